@@ -24,7 +24,7 @@
 
 use super::*;
 
-use bosminer_config::CLIENT_URL_JAVA_SCRIPT_REGEX;
+use bosminer_config::{ClientPolicy, GroupDescriptor, CLIENT_URL_JAVA_SCRIPT_REGEX};
 
 const DESCRIPTION_CAUTION_OVERCLOCKING: &'static str =
     "Caution: Overclocking may damage your device. Proceed at your own risk!";
@@ -124,6 +124,51 @@ pub fn for_backend() -> serde_json::Value {
                                 "span": 3
                             }
                         ],
+                        [
+                            "policy",
+                            {
+                                "type": "enum",
+                                "label": "Pool Policy",
+                                "values": [
+                                    {
+                                        "key": ClientPolicy::Failover.to_string(),
+                                        "label": "Failover"
+                                    },
+                                    {
+                                        "key": ClientPolicy::RoundRobin.to_string(),
+                                        "label": "Round Robin"
+                                    },
+                                    {
+                                        "key": ClientPolicy::LoadBalance.to_string(),
+                                        "label": "Load Balance"
+                                    }
+                                ],
+                                "default": GroupDescriptor::DEFAULT_CLIENT_POLICY.to_string(),
+                                "span": 4
+                            }
+                        ],
+                        [
+                            "return_to_primary_delay",
+                            {
+                                "type": "number",
+                                "label": "Return to Primary Delay",
+                                "unit": "s",
+                                "min": 0,
+                                "default": GroupDescriptor::DEFAULT_RETURN_TO_PRIMARY_DELAY.as_secs(),
+                                "span": 4
+                            }
+                        ],
+                        [
+                            "rotation_period",
+                            {
+                                "type": "number",
+                                "label": "Rotation Period",
+                                "unit": "s",
+                                "min": 1,
+                                "default": GroupDescriptor::DEFAULT_ROTATION_PERIOD.as_secs(),
+                                "span": 4
+                            }
+                        ],
                         [
                             "pool",
                             {
@@ -160,7 +205,7 @@ pub fn for_backend() -> serde_json::Value {
                                                 "type": "string",
                                                 "label": "Username",
                                                 "min_length": 1,
-                                                "span": 7
+                                                "span": 4
                                             }
                                         ],
                                         [
@@ -171,6 +216,16 @@ pub fn for_backend() -> serde_json::Value {
                                                 "default": null,
                                                 "span": 5
                                             }
+                                        ],
+                                        [
+                                            "quota",
+                                            {
+                                                "type": "number",
+                                                "label": "Quota",
+                                                "min": 1,
+                                                "default": 1,
+                                                "span": 3
+                                            }
                                         ]
                                     ]
                                 }
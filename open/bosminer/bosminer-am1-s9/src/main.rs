@@ -170,6 +170,7 @@ async fn main() {
                 url: url.to_string(),
                 user: user_info.user.to_string(),
                 password: user_info.password.map(|v| v.to_string()),
+                quota: None,
             }]),
         };
 
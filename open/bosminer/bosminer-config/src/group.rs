@@ -22,7 +22,11 @@
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+use std::convert::TryFrom;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Debug)]
 #[serde(deny_unknown_fields)]
 pub enum LoadBalanceStrategy {
     #[serde(rename = "quota")]
@@ -49,6 +53,165 @@ impl LoadBalanceStrategy {
     }
 }
 
+/// Day of the week used in group schedules
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Weekday {
+    Mon,
+    Tue,
+    Wed,
+    Thu,
+    Fri,
+    Sat,
+    Sun,
+}
+
+impl Weekday {
+    pub const ALL: [Weekday; 7] = [
+        Self::Mon,
+        Self::Tue,
+        Self::Wed,
+        Self::Thu,
+        Self::Fri,
+        Self::Sat,
+        Self::Sun,
+    ];
+
+    /// Returns the day before this one
+    pub fn previous(self) -> Self {
+        let index = Self::ALL
+            .iter()
+            .position(|weekday| *weekday == self)
+            .expect("BUG: missing weekday");
+        Self::ALL[(index + Self::ALL.len() - 1) % Self::ALL.len()]
+    }
+}
+
+/// Time of day in seconds since midnight written as `HH:MM` in the configuration, `24:00` can be
+/// used as the end of a window
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(into = "String", try_from = "String")]
+pub struct TimeOfDay(u32);
+
+impl TimeOfDay {
+    const SECONDS_PER_DAY: u32 = 24 * 60 * 60;
+    pub const MIDNIGHT: TimeOfDay = TimeOfDay(0);
+    pub const END_OF_DAY: TimeOfDay = TimeOfDay(Self::SECONDS_PER_DAY);
+
+    pub fn from_hm(hours: u32, minutes: u32) -> Option<Self> {
+        match (hours, minutes) {
+            (0..=23, 0..=59) | (24, 0) => Some(Self(hours * 60 * 60 + minutes * 60)),
+            _ => None,
+        }
+    }
+
+    pub fn as_secs(&self) -> u32 {
+        self.0
+    }
+}
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, String> {
+        let mut parts = value.splitn(2, ':');
+        let hours = parts.next().and_then(|hours| hours.parse().ok());
+        let minutes = parts.next().and_then(|minutes| minutes.parse().ok());
+        match (hours, minutes) {
+            (Some(hours), Some(minutes)) => Self::from_hm(hours, minutes),
+            _ => None,
+        }
+        .ok_or_else(|| format!("invalid time of day '{}', expected HH:MM", value))
+    }
+}
+
+impl From<TimeOfDay> for String {
+    fn from(value: TimeOfDay) -> Self {
+        format!("{:02}:{:02}", value.0 / 3600, value.0 / 60 % 60)
+    }
+}
+
+/// Point in the week that group schedules are evaluated at, the schedules follow UTC
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct ScheduleTime {
+    pub weekday: Weekday,
+    pub time_of_day: TimeOfDay,
+}
+
+impl From<SystemTime> for ScheduleTime {
+    fn from(time: SystemTime) -> Self {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or_default();
+        let days = secs / u64::from(TimeOfDay::SECONDS_PER_DAY);
+        Self {
+            // 1st January 1970 was Thursday
+            weekday: Weekday::ALL[((days + 3) % 7) as usize],
+            time_of_day: TimeOfDay((secs % u64::from(TimeOfDay::SECONDS_PER_DAY)) as u32),
+        }
+    }
+}
+
+/// Time window with its own load balance strategy. The window starts at `start` and ends before
+/// `end` on the listed `days` (every day when there are none). A window with `end` before `start`
+/// continues past midnight, the part past midnight applies on the days following the listed ones.
+// NOTE: `#[serde(deny_unknown_fields)]` cannot be used due to flatten strategy but the error is
+// caught in the `LoadBalanceStrategy`
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScheduleWindow {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub days: Option<Vec<Weekday>>,
+    pub start: TimeOfDay,
+    pub end: TimeOfDay,
+    #[serde(flatten)]
+    pub strategy: LoadBalanceStrategy,
+}
+
+impl ScheduleWindow {
+    pub fn contains(&self, time: ScheduleTime) -> bool {
+        let day_matches = |weekday| {
+            self.days
+                .as_ref()
+                .map_or(true, |days| days.contains(&weekday))
+        };
+        if self.start <= self.end {
+            self.start <= time.time_of_day
+                && time.time_of_day < self.end
+                && day_matches(time.weekday)
+        } else if self.start <= time.time_of_day {
+            day_matches(time.weekday)
+        } else {
+            // The window has started the day before
+            time.time_of_day < self.end && day_matches(time.weekday.previous())
+        }
+    }
+}
+
+/// Policy that selects which client of a group generates work for the group
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ClientPolicy {
+    /// The first running client in the order of priority is used, the group returns to a client
+    /// with higher priority once it has been running for the return-to-primary delay
+    Failover,
+    /// Running clients take turns, each client generates work for the rotation period multiplied
+    /// by its quota
+    RoundRobin,
+    /// Generated work (hashrate) is split between running clients in the ratio of their quotas
+    LoadBalance,
+}
+
+impl fmt::Display for ClientPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Failover => write!(f, "failover"),
+            Self::RoundRobin => write!(f, "round_robin"),
+            Self::LoadBalance => write!(f, "load_balance"),
+        }
+    }
+}
+
 /// Contains basic information about group
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(deny_unknown_fields)]
@@ -59,12 +222,29 @@ pub struct Descriptor {
     #[serde(flatten)]
     #[serde(skip_serializing_if = "Option::is_none")]
     strategy: Option<LoadBalanceStrategy>,
+    #[serde(rename = "policy")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_policy: Option<ClientPolicy>,
+    /// Delay in seconds before failover policy switches back to a recovered client with higher
+    /// priority
+    #[serde(skip_serializing_if = "Option::is_none")]
+    return_to_primary_delay: Option<u64>,
+    /// Period in seconds that round robin policy keeps a client with quota 1 active
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rotation_period: Option<u64>,
+    /// Windows that replace the load balance strategy at some times of day, the first matching
+    /// window applies
+    #[serde(skip_serializing_if = "Option::is_none")]
+    schedule: Option<Vec<ScheduleWindow>>,
 }
 
 impl Descriptor {
     pub const DEFAULT_NAME: &'static str = "Default";
     pub const DEFAULT_INDEX: usize = 0;
     pub const DEFAULT_QUOTA: usize = 1;
+    pub const DEFAULT_CLIENT_POLICY: ClientPolicy = ClientPolicy::Failover;
+    pub const DEFAULT_RETURN_TO_PRIMARY_DELAY: Duration = Duration::from_secs(0);
+    pub const DEFAULT_ROTATION_PERIOD: Duration = Duration::from_secs(600);
 
     pub fn new<T>(name: String, private: bool, strategy: T) -> Self
     where
@@ -74,15 +254,44 @@ impl Descriptor {
             name,
             private,
             strategy: strategy.into(),
+            client_policy: None,
+            return_to_primary_delay: None,
+            rotation_period: None,
+            schedule: None,
         }
     }
 
+    /// Follows `schedule` instead of the static load balance strategy
+    pub fn with_schedule(mut self, schedule: Vec<ScheduleWindow>) -> Self {
+        self.schedule = Some(schedule);
+        self
+    }
+
     pub fn strategy(&self) -> LoadBalanceStrategy {
         self.strategy
             .clone()
             .unwrap_or(LoadBalanceStrategy::Quota(Self::DEFAULT_QUOTA))
     }
 
+    /// Returns strategy of the first schedule window that contains `time` or the static strategy
+    /// when there is no such window
+    pub fn strategy_at(&self, time: ScheduleTime) -> LoadBalanceStrategy {
+        self.schedule
+            .iter()
+            .flatten()
+            .find(|window| window.contains(time))
+            .map(|window| window.strategy.clone())
+            .unwrap_or_else(|| self.strategy())
+    }
+
+    /// Times of day when the schedule windows start or end
+    pub fn schedule_boundaries(&self) -> impl Iterator<Item = TimeOfDay> + '_ {
+        self.schedule
+            .iter()
+            .flatten()
+            .flat_map(|window| vec![window.start, window.end])
+    }
+
     pub fn get_quota(&self) -> Option<usize> {
         match self.strategy() {
             LoadBalanceStrategy::Quota(value) => Some(value),
@@ -95,6 +304,22 @@ impl Descriptor {
             .as_ref()
             .and_then(|strategy| strategy.get_fixed_share_ratio())
     }
+
+    pub fn client_policy(&self) -> ClientPolicy {
+        self.client_policy.unwrap_or(Self::DEFAULT_CLIENT_POLICY)
+    }
+
+    pub fn return_to_primary_delay(&self) -> Duration {
+        self.return_to_primary_delay
+            .map(Duration::from_secs)
+            .unwrap_or(Self::DEFAULT_RETURN_TO_PRIMARY_DELAY)
+    }
+
+    pub fn rotation_period(&self) -> Duration {
+        self.rotation_period
+            .map(Duration::from_secs)
+            .unwrap_or(Self::DEFAULT_ROTATION_PERIOD)
+    }
 }
 
 impl Default for Descriptor {
@@ -103,6 +328,10 @@ impl Default for Descriptor {
             name: Self::DEFAULT_NAME.to_string(),
             private: false,
             strategy: None,
+            client_policy: None,
+            return_to_primary_delay: None,
+            rotation_period: None,
+            schedule: None,
         }
     }
 }
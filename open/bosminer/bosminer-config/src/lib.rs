@@ -30,8 +30,10 @@ pub use client::Protocol as ClientProtocol;
 pub use client::UserInfo as ClientUserInfo;
 pub use client::URL_JAVA_SCRIPT_REGEX as CLIENT_URL_JAVA_SCRIPT_REGEX;
 
+pub use group::ClientPolicy;
 pub use group::Descriptor as GroupDescriptor;
 pub use group::LoadBalanceStrategy;
+pub use group::{ScheduleTime, ScheduleWindow, TimeOfDay, Weekday};
 
 // reexport common crates
 pub use clap;
@@ -48,6 +50,9 @@ pub struct PoolConfig {
     pub user: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub password: Option<String>,
+    /// Share of the group work for round robin and load balance client policies
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quota: Option<usize>,
 }
 
 // NOTE: `#[serde(deny_unknown_fields)]` cannot be used due to flatten descriptor but the error is
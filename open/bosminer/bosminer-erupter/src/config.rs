@@ -55,14 +55,16 @@ impl Backend {
 
     pub async fn init_client(self) {
         if let Some(client_descriptor) = self.client_descriptor {
-            let group = self
-                .client_manager
-                .expect("BUG: missing client manager")
-                .create_or_get_default_group()
-                .await;
+            let client_manager = self.client_manager.expect("BUG: missing client manager");
+            let group = client_manager.create_or_get_default_group().await;
 
             group
-                .push_client(client::Handle::new(client_descriptor, None, None))
+                .push_client(client::Handle::new(
+                    client_descriptor,
+                    None,
+                    client_manager.get_backend_registry(),
+                    None,
+                ))
                 .await;
         }
     }
@@ -138,6 +138,8 @@ fn impl_derive_work_solver_node(
         member_start_time,
         member_last_share,
         member_best_share,
+        member_accepted,
+        member_rejected,
         member_valid_network_diff,
         member_valid_job_diff,
         member_valid_backend_diff,
@@ -157,6 +159,8 @@ fn impl_derive_mining_stats(ast: &DeriveInput, derive_name: &str) -> proc_macro2
     let start_time = find_member(&fields, "member_start_time");
     let last_share = find_member(&fields, "member_last_share");
     let best_share = find_member(&fields, "member_best_share");
+    let accepted = find_member(&fields, "member_accepted");
+    let rejected = find_member(&fields, "member_rejected");
     let valid_network_diff = find_member(&fields, "member_valid_network_diff");
     let valid_job_diff = find_member(&fields, "member_valid_job_diff");
     let valid_backend_diff = find_member(&fields, "member_valid_backend_diff");
@@ -179,6 +183,16 @@ fn impl_derive_mining_stats(ast: &DeriveInput, derive_name: &str) -> proc_macro2
                 &self.#best_share
             }
 
+            #[inline]
+            fn accepted(&self) -> &stats::Meter {
+                &self.#accepted
+            }
+
+            #[inline]
+            fn rejected(&self) -> &stats::Meter {
+                &self.#rejected
+            }
+
             #[inline]
             fn valid_network_diff(&self) -> &stats::Meter {
                 &self.#valid_network_diff
@@ -215,6 +229,9 @@ fn impl_derive_mining_stats(ast: &DeriveInput, derive_name: &str) -> proc_macro2
         member_accepted,
         member_rejected,
         member_stale,
+        member_reject_reasons,
+        member_discarded,
+        member_share_latency,
         member_valid_network_diff,
         member_valid_job_diff,
         member_valid_backend_diff,
@@ -240,9 +257,10 @@ fn impl_derive_client_stats(
     let valid_jobs = find_member(&fields, "member_valid_jobs");
     let invalid_jobs = find_member(&fields, "member_invalid_jobs");
     let generated_work = find_member(&fields, "member_generated_work");
-    let accepted = find_member(&fields, "member_accepted");
-    let rejected = find_member(&fields, "member_rejected");
     let stale = find_member(&fields, "member_stale");
+    let reject_reasons = find_member(&fields, "member_reject_reasons");
+    let discarded = find_member(&fields, "member_discarded");
+    let share_latency = find_member(&fields, "member_share_latency");
 
     stream.extend(quote! {
         impl#generics stats::Client for #name#generics {
@@ -262,18 +280,23 @@ fn impl_derive_client_stats(
             }
 
             #[inline]
-            fn accepted(&self) -> &stats::Meter {
-                &self.#accepted
+            fn stale(&self) -> &stats::Meter {
+                &self.#stale
             }
 
             #[inline]
-            fn rejected(&self) -> &stats::Meter {
-                &self.#rejected
+            fn reject_reasons(&self) -> &stats::RejectReasons {
+                &self.#reject_reasons
             }
 
             #[inline]
-            fn stale(&self) -> &stats::Meter {
-                &self.#stale
+            fn discarded(&self) -> &stats::CounterU64 {
+                &self.#discarded
+            }
+
+            #[inline]
+            fn share_latency(&self) -> &stats::ShareLatency {
+                &self.#share_latency
             }
         }
     });
@@ -289,6 +312,8 @@ fn impl_derive_client_stats(
         member_generated_work,
         member_last_share,
         member_best_share,
+        member_accepted,
+        member_rejected,
         member_valid_network_diff,
         member_valid_job_diff,
         member_valid_backend_diff,
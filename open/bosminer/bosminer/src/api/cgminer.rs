@@ -34,7 +34,7 @@ use crate::version;
 use ii_cgminer_api::support::ValueExt as _;
 use ii_cgminer_api::{command, json, response};
 
-use bosminer_config::{ClientDescriptor, ClientUserInfo};
+use bosminer_config::{ClientDescriptor, ClientPolicy, ClientUserInfo};
 
 use std::future::Future;
 use std::net::SocketAddr;
@@ -84,9 +84,12 @@ impl Handler {
         let accepted = client_stats.accepted().take_snapshot().await;
         let rejected = client_stats.rejected().take_snapshot().await;
         let stale = client_stats.stale().take_snapshot().await;
+        let reject_reasons = client_stats.reject_reasons();
+        let discarded = client_stats.discarded().take_snapshot();
         let last_share = client_stats.last_share().take_snapshot().await;
         let valid_backend_diff = client_stats.valid_backend_diff().take_snapshot().await;
         let best_share = client_stats.best_share().take_snapshot();
+        let health = client.health().await;
 
         let last_share_time = last_share
             .as_ref()
@@ -120,7 +123,9 @@ impl Handler {
             | sync::Status::Starting
             | sync::Status::Stopping
             | sync::Status::Restarting
-            | sync::Status::Stopped => (response::PoolStatus::Alive, false),
+            | sync::Status::Destroying
+            | sync::Status::Stopped
+            | sync::Status::Destroyed => (response::PoolStatus::Alive, false),
             sync::Status::Failing
             | sync::Status::Declining
             | sync::Status::Retrying
@@ -137,16 +142,14 @@ impl Handler {
             status,
             // The pools are sorted by its priority
             priority: idx as i32,
-            // TODO: get actual value from client
-            quota: 1,
+            quota: client.quota() as i32,
             // TODO: get actual value from client?
             long_poll: response::Bool::N,
             getworks: *valid_jobs as u32,
             accepted: accepted.solutions,
             rejected: rejected.solutions,
             works: *generated_work as i32,
-            // TODO: BOSminer does not account this information
-            discarded: 0,
+            discarded: *discarded as u32,
             stale: stale.solutions as u32,
             // TODO: account failures
             get_failures: 0,
@@ -178,6 +181,23 @@ impl Handler {
             current_block_version,
             // TODO: get actual value from client
             asic_boost: true,
+            health_score: health.score * 100.0,
+            demotion_reason: health
+                .demotion
+                .map(|reason| reason.to_string())
+                .unwrap_or_default(),
+            rejected_duplicate: *reject_reasons
+                .get(stats::RejectReason::Duplicate)
+                .take_snapshot(),
+            rejected_low_difficulty: *reject_reasons
+                .get(stats::RejectReason::LowDifficulty)
+                .take_snapshot(),
+            rejected_invalid_job: *reject_reasons
+                .get(stats::RejectReason::InvalidJob)
+                .take_snapshot(),
+            rejected_other: *reject_reasons
+                .get(stats::RejectReason::Other)
+                .take_snapshot(),
         }
     }
 
@@ -193,6 +213,8 @@ impl Handler {
         let work_solver_stats = work_solver.work_solver_stats();
         let last_work_time = work_solver_stats.last_work_time().take_snapshot().await;
         let last_share = mining_stats.last_share().take_snapshot().await;
+        let accepted = mining_stats.accepted().take_snapshot().await;
+        let rejected = mining_stats.rejected().take_snapshot().await;
         let valid_job_diff = mining_stats.valid_job_diff().take_snapshot().await;
         let valid_backend_diff = mining_stats.valid_backend_diff().take_snapshot().await;
         let error_backend_diff = mining_stats.error_backend_diff().take_snapshot().await;
@@ -216,6 +238,17 @@ impl Handler {
         } else {
             0.0
         };
+        let rejected_shares = rejected.shares.as_f64();
+        let backend_rejected_ratio = if backend_valid_solutions != 0 {
+            rejected_shares / backend_valid_solutions as f64 * 100.0
+        } else {
+            0.0
+        };
+        let utility = if elapsed.as_secs() != 0 {
+            accepted.solutions as f64 / elapsed.as_secs() as f64
+        } else {
+            accepted.solutions as f64
+        } * 60.0;
 
         response::Asc {
             idx: idx as i32,
@@ -241,27 +274,21 @@ impl Handler {
             mhs_15m: valid_backend_diff
                 .to_mega_hashes(*INTERVAL_15M, now)
                 .into_f64(),
-            // TODO: BOSminer does not account this information
-            accepted: 0,
-            // TODO: BOSminer does not account this information
-            rejected: 0,
+            accepted: accepted.solutions as i32,
+            rejected: rejected.solutions as i32,
             hardware_errors: backend_error_solutions as i32,
-            // TODO: BOSminer does not account accepted
-            utility: 0.0,
-            // TODO: BOSminer does not account accepted
+            utility,
+            // TODO: BOSminer does not account pool of the last share
             last_share_pool: -1,
             last_share_time,
             total_mega_hashes,
             diff1_work: backend_valid_solutions,
-            // TODO: BOSminer does not account accepted
-            difficulty_accepted: 0.0,
-            // TODO: BOSminer does not account rejected
-            difficulty_rejected: 0.0,
+            difficulty_accepted: accepted.shares.as_f64(),
+            difficulty_rejected: rejected_shares,
             last_share_difficulty,
             last_valid_work: last_work_time,
             device_hardware_ratio: backend_error_ratio,
-            // TODO: BOSminer does not account rejected
-            device_rejected_ratio: 0.0,
+            device_rejected_ratio: backend_rejected_ratio,
             device_elapsed: elapsed.as_secs(),
             hardware_error_mhs_15m: error_backend_diff
                 .to_mega_hashes(*INTERVAL_15M, now)
@@ -397,6 +424,23 @@ impl Handler {
 
         ClientDescriptor::create(url, &ClientUserInfo::new(user, password), true).map_err(|_| ())
     }
+
+    /// CGMiner has a single strategy for all pools so the policy of the default group is reported
+    async fn get_multipool_strategy(&self) -> response::MultipoolStrategy {
+        let client_policy = self
+            .core
+            .get_client_manager()
+            .get_default_group()
+            .await
+            .map(|group| group.descriptor.client_policy())
+            .unwrap_or(ClientPolicy::Failover);
+        match client_policy {
+            ClientPolicy::Failover => response::MultipoolStrategy::Failover,
+            // Round robin of BOSminer switches clients after a time slice like CGMiner 'Rotate'
+            ClientPolicy::RoundRobin => response::MultipoolStrategy::Rotate,
+            ClientPolicy::LoadBalance => response::MultipoolStrategy::LoadBalance,
+        }
+    }
 }
 
 #[async_trait::async_trait]
@@ -455,6 +499,7 @@ impl command::Handler for Handler {
         let mut pools_rejected_shares = 0.0;
         let mut pools_stale = 0;
         let mut pools_stale_shares = 0.0;
+        let mut pools_discarded = 0;
 
         for client in self.get_clients().await {
             let client_stats = client.stats();
@@ -463,6 +508,7 @@ impl command::Handler for Handler {
             let accepted = client_stats.accepted().take_snapshot().await;
             let rejected = client_stats.rejected().take_snapshot().await;
             let stale = client_stats.stale().take_snapshot().await;
+            let discarded = client_stats.discarded().take_snapshot();
 
             pools_valid_jobs += *valid_jobs as u64;
             pools_accepted += accepted.solutions;
@@ -471,6 +517,7 @@ impl command::Handler for Handler {
             pools_rejected_shares += rejected.shares.as_f64();
             pools_stale += stale.solutions;
             pools_stale_shares += stale.shares.as_f64();
+            pools_discarded += *discarded;
         }
 
         let pools_all_solutions = pools_accepted + pools_rejected + pools_stale;
@@ -520,8 +567,7 @@ impl command::Handler for Handler {
             rejected: pools_rejected,
             hardware_errors: backend_error_solutions as i32,
             utility: pools_utility,
-            // TODO: BOSminer does not account this information
-            discarded: 0,
+            discarded: pools_discarded as i64,
             stale: pools_stale,
             // TODO: BOSminer does not account this information
             get_failures: 0,
@@ -549,8 +595,7 @@ impl command::Handler for Handler {
             asc_count: self.core.get_work_solvers().await.len() as i32,
             pga_count: 0,
             pool_count: self.get_clients().await.len() as i32,
-            // TODO: get actual multi-pool strategy
-            strategy: response::MultipoolStrategy::Failover,
+            strategy: self.get_multipool_strategy().await,
             log_interval: DEFAULT_LOG_INTERVAL as i32,
             device_code: String::new(),
             // TODO: detect underlying operation system
@@ -616,15 +661,13 @@ impl command::Handler for Handler {
             .get_client_descriptor(parameter)
             .map_err(|_| response::ErrorCode::InvalidAddPoolDetails(parameter.to_string()))?;
 
-        let group = self
-            .core
-            .get_client_manager()
-            .create_or_get_default_group()
-            .await;
+        let client_manager = self.core.get_client_manager();
+        let group = client_manager.create_or_get_default_group().await;
         let client = group
             .push_client(client::Handle::new(
                 client_descriptor.clone(),
                 self.core.backend_info.clone(),
+                client_manager.get_backend_registry(),
                 None,
             ))
             .await;
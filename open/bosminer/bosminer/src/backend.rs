@@ -131,6 +131,40 @@ impl Registry {
     pub async fn lock_work_solvers<'a>(&'a self) -> MutexGuard<'a, Vec<Arc<dyn node::WorkSolver>>> {
         self.work_solvers.lock().await
     }
+
+    /// Return nominal hashrate of the whole backend. The root hub is asked first and when it is
+    /// not able to provide it then the hashrate is aggregated from all registered work solvers.
+    pub async fn get_nominal_hashrate(&self) -> Option<ii_bitcoin::HashesUnit> {
+        let root_hub = self.lock_root_hub().await.clone();
+        if let Some(root_hub) = root_hub {
+            if let Some(nominal_hashrate) = root_hub.get_nominal_hashrate().await {
+                return Some(nominal_hashrate);
+            }
+        }
+
+        let work_solvers: Vec<_> = self.lock_work_solvers().await.iter().cloned().collect();
+        let mut total_hashrate: Option<u128> = None;
+        for work_solver in work_solvers {
+            if let Some(nominal_hashrate) = work_solver.get_nominal_hashrate().await {
+                *total_hashrate.get_or_insert(0) += nominal_hashrate.into_hashes().into_u128();
+            }
+        }
+        total_hashrate.map(Into::into)
+    }
+
+    /// Return amount of work generated by all registered work solvers
+    pub async fn get_generated_work(&self) -> u64 {
+        self.lock_work_solvers()
+            .await
+            .iter()
+            .map(|work_solver| {
+                *work_solver
+                    .work_solver_stats()
+                    .generated_work()
+                    .take_snapshot()
+            })
+            .sum()
+    }
 }
 
 #[async_trait]
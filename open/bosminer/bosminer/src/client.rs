@@ -23,13 +23,16 @@
 //! This module contains common functionality related to mining protocol client and allows
 //! executing a specific type of mining protocol client instance.
 
+pub mod health;
 mod scheduler;
 
 // Sub-modules with client implementation
 pub mod drain;
+pub mod stratum_v1;
 pub mod stratum_v2;
 pub mod stratum_v2_channels;
 
+use crate::backend;
 use crate::error;
 use crate::hal;
 use crate::job;
@@ -42,17 +45,19 @@ use crate::work;
 pub use scheduler::JobExecutor;
 
 use bosminer_config::{
-    ClientDescriptor, ClientProtocol, ClientUserInfo, GroupConfig, GroupDescriptor,
-    LoadBalanceStrategy,
+    ClientDescriptor, ClientProtocol, ClientUserInfo, GroupConfig, GroupDescriptor, ScheduleTime,
+    TimeOfDay, Weekday,
 };
 
 use futures::channel::mpsc;
 use futures::lock::Mutex;
 use ii_async_compat::futures;
 
+use std::iter;
 use std::slice;
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::{Arc, Weak};
+use std::time;
 
 #[derive(Debug)]
 pub struct Handle {
@@ -62,14 +67,22 @@ pub struct Handle {
     enabled: AtomicBool,
     engine_sender: Arc<work::EngineSender>,
     solution_sender: mpsc::UnboundedSender<work::Solution>,
+    /// Share of the group work for client policies that split the work between clients
+    quota: usize,
+    health: Mutex<health::Tracker>,
 }
 
 impl Handle {
+    pub const DEFAULT_QUOTA: usize = 1;
+
+    /// `backend_registry` - provides nominal hashrate of the miner, the client reports its share
+    /// to the pool
     /// `channel` - endpoints for 2 channels so that stratum V2 client can communicate with an
     /// external client that implements some protocol extension
     pub fn new(
         descriptor: ClientDescriptor,
         backend_info: Option<hal::BackendInfo>,
+        backend_registry: Weak<backend::Registry>,
         channel: Option<(
             stratum_v2::ExtensionChannelToStratumReceiver,
             stratum_v2::ExtensionChannelFromStratumSender,
@@ -80,6 +93,8 @@ impl Handle {
         let engine_sender = Arc::new(work::EngineSender::new(None));
 
         let job_solver = job::Solver::new(engine_sender.clone(), solution_receiver);
+        // Descriptor is shared with stratum V2 clients, they update it on server redirects
+        let shared_descriptor = Arc::new(Mutex::new(descriptor.clone()));
         let node: Arc<dyn node::Client> = match &descriptor.protocol {
             ClientProtocol::Drain => {
                 assert!(
@@ -93,34 +108,60 @@ impl Handle {
                     channel.is_none(),
                     "BUG: protocol 'Stratum V1' does not support channel"
                 );
-                Arc::new(stratum_v2_channels::StratumClient::new(
-                    stratum_v2_channels::ConnectionDetails::from_descriptor(&descriptor),
+                Arc::new(stratum_v1::StratumClient::new(
+                    stratum_v1::ConnectionDetails::from_descriptor(&descriptor),
                     job_solver,
                 ))
             }
             ClientProtocol::StratumV2(_) => Arc::new(stratum_v2::StratumClient::new(
                 stratum_v2::ConnectionDetails::from_descriptor(&descriptor),
+                shared_descriptor.clone(),
                 backend_info,
+                backend_registry,
                 job_solver,
                 channel,
             )),
             ClientProtocol::StratumV2Insecure => Arc::new(stratum_v2::StratumClient::new(
                 stratum_v2::ConnectionDetails::from_descriptor(&descriptor),
+                shared_descriptor.clone(),
                 backend_info,
+                backend_registry,
                 job_solver,
                 channel,
             )),
         };
 
+        let max_job_age = match &descriptor.protocol {
+            ClientProtocol::StratumV2(_) | ClientProtocol::StratumV2Insecure => {
+                health::Tracker::MAX_V2_JOB_AGE
+            }
+            _ => health::Tracker::MAX_JOB_AGE,
+        };
+
         Self {
-            descriptor: Arc::new(Mutex::new(descriptor)),
+            descriptor: shared_descriptor,
             node,
             enabled: AtomicBool::new(false),
             engine_sender,
             solution_sender,
+            quota: Self::DEFAULT_QUOTA,
+            health: Mutex::new(health::Tracker::new().with_max_job_age(max_job_age)),
         }
     }
 
+    /// Sets share of the group work for client policies that split the work between clients.
+    /// The quota must be positive.
+    pub fn with_quota(mut self, quota: usize) -> Self {
+        assert!(quota > 0, "BUG: client quota must be positive");
+        self.quota = quota;
+        self
+    }
+
+    #[inline]
+    pub fn quota(&self) -> usize {
+        self.quota
+    }
+
     #[inline]
     pub async fn descriptor(&self) -> ClientDescriptor {
         self.descriptor.lock().await.clone()
@@ -195,6 +236,27 @@ impl Handle {
         }
     }
 
+    /// Destroys the client gracefully, the running client submits solutions that have been already
+    /// found and waits for the responses. Returns after the client has been destroyed.
+    async fn destroy(&self) {
+        // Disable the client without stopping it to keep the scheduler away from it
+        self.enabled.store(false, Ordering::Relaxed);
+
+        // Status changes of the client are no longer reported to the group
+        let event_monitor = event::Monitor::new();
+        let mut event_receiver = event_monitor.subscribe();
+        self.set_event_sender(event_monitor.publish());
+        if self.node.status().initiate_destroying() {
+            // Interrupt the client task that finishes the destruction
+            self.node.stop();
+            while self.status() != crate::sync::Status::Destroyed {
+                // The event monitor is still alive so waiting cannot fail
+                let _ = event_receiver.wait_for_event().await;
+            }
+        }
+        self.take_event_sender();
+    }
+
     /// Check if current state of the client is enabled
     #[inline]
     pub fn is_enabled(&self) -> bool {
@@ -250,6 +312,31 @@ impl Handle {
     pub(crate) async fn get_last_job(&self) -> Option<Arc<dyn job::Bitcoin>> {
         self.node.get_last_job().await
     }
+
+    /// Evaluates health of the client from its current statistics
+    pub(crate) async fn update_health(&self, now: time::Instant) -> health::Status {
+        let client_stats = self.stats();
+        let rejected = client_stats.rejected().take_snapshot().await;
+        let stale = client_stats.stale().take_snapshot().await;
+        let share_latency = client_stats.share_latency().take_snapshot().await;
+        let sample = health::Sample {
+            accepted: client_stats.accepted().take_snapshot().await.solutions,
+            rejected: rejected.solutions + stale.solutions,
+            valid_jobs: *client_stats.valid_jobs().take_snapshot(),
+            responses: share_latency.responses,
+            total_latency: share_latency.total,
+        };
+        self.health
+            .lock()
+            .await
+            .update(sample, self.is_running(), now)
+            .clone()
+    }
+
+    #[inline]
+    pub async fn health(&self) -> health::Status {
+        self.health.lock().await.status().clone()
+    }
 }
 
 impl Drop for Handle {
@@ -341,20 +428,23 @@ impl Group {
         client_handle
     }
 
+    /// Removes the client from the group and destroys it. Returns after the client has submitted
+    /// the remaining solutions and it is gone.
     pub async fn remove_client_at(&self, index: usize) -> Result<Arc<Handle>, error::Client> {
-        let mut scheduler_client_handles = self.scheduler_client_handles.lock().await;
-        if index >= scheduler_client_handles.len() {
-            Err(error::Client::Missing)
-        } else {
-            let client_handle = scheduler_client_handles.remove(index).client_handle;
-            // Immediately notify about client removal from the group
-            self.event_sender.notify();
-            // Remove event sender not to notify about removed client status changes
-            client_handle.take_event_sender();
-            // Immediately disable client to force scheduler to select another client
-            let _ = client_handle.try_disable();
-            Ok(client_handle)
-        }
+        let client_handle = {
+            let mut scheduler_client_handles = self.scheduler_client_handles.lock().await;
+            if index >= scheduler_client_handles.len() {
+                return Err(error::Client::Missing);
+            }
+            scheduler_client_handles.remove(index).client_handle
+        };
+        // Immediately notify about client removal from the group to force scheduler to select
+        // another client
+        self.event_sender.notify();
+        // The group is not locked while the client is being destroyed so the scheduler can
+        // switch to another client in the meantime
+        client_handle.destroy().await;
+        Ok(client_handle)
     }
 
     /// Changes the position of a client within the group
@@ -412,19 +502,23 @@ impl Group {
 pub struct GroupRegistry {
     list: Vec<scheduler::GroupHandle>,
     event_monitor: event::Monitor,
-    total_quota: usize,
-    fixed_share_ratio_count: usize,
-    total_fixed_share_ratio: f64,
+    /// Time that the group schedules follow
+    clock: Arc<dyn scheduler::Clock>,
+    /// Time when the group schedules have been applied the last time
+    schedule_time: ScheduleTime,
 }
 
 impl GroupRegistry {
     pub fn new(event_monitor: event::Monitor) -> Self {
+        Self::with_clock(event_monitor, Arc::new(scheduler::SystemClock))
+    }
+
+    fn with_clock(event_monitor: event::Monitor, clock: Arc<dyn scheduler::Clock>) -> Self {
         Self {
             list: vec![],
             event_monitor,
-            total_quota: 0,
-            fixed_share_ratio_count: 0,
-            total_fixed_share_ratio: 0.0,
+            schedule_time: clock.now().into(),
+            clock,
         }
     }
 
@@ -457,27 +551,15 @@ impl GroupRegistry {
         descriptor: GroupDescriptor,
         midstate_count: usize,
     ) -> Result<Arc<Group>, error::Client> {
-        match descriptor.strategy() {
-            LoadBalanceStrategy::Quota(quota) => {
-                self.total_quota += quota;
-            }
-            LoadBalanceStrategy::FixedShareRatio(fixed_share_ratio) => {
-                if self.is_empty() {
-                    Err(error::Client::OnlyFixedShareRatio)?;
-                } else if self.total_fixed_share_ratio + fixed_share_ratio >= 1.0 {
-                    Err(error::Client::FixedShareRatioOverflow)?;
-                }
-                self.fixed_share_ratio_count += 1;
-                self.total_fixed_share_ratio += fixed_share_ratio;
-            }
-        }
+        self.check_strategies(&descriptor)?;
 
         let group_handle = Arc::new(Group::new(
             descriptor,
             self.event_monitor.publish(),
             midstate_count,
         ));
-        let scheduler_group_handle = scheduler::GroupHandle::new(group_handle.clone());
+        let scheduler_group_handle =
+            scheduler::GroupHandle::new(group_handle.clone(), self.schedule_time);
         self.list.push(scheduler_group_handle);
         self.recalculate_quotas(true);
 
@@ -513,18 +595,87 @@ impl GroupRegistry {
         None
     }
 
+    /// Checks that the groups together with a new group described by `descriptor` leave some
+    /// share ratio for groups with quota at any time of the week. The strategies of the groups
+    /// change only at boundaries of their schedule windows so it is sufficient to check them.
+    fn check_strategies(&self, descriptor: &GroupDescriptor) -> Result<(), error::Client> {
+        let descriptors: Vec<_> = self
+            .list
+            .iter()
+            .map(|scheduler_group_handle| &scheduler_group_handle.group_handle.descriptor)
+            .chain(iter::once(descriptor))
+            .collect();
+        let mut boundaries: Vec<_> = iter::once(TimeOfDay::MIDNIGHT)
+            .chain(
+                descriptors
+                    .iter()
+                    .flat_map(|descriptor| descriptor.schedule_boundaries()),
+            )
+            .filter(|time_of_day| *time_of_day < TimeOfDay::END_OF_DAY)
+            .collect();
+        boundaries.sort();
+        boundaries.dedup();
+
+        for &weekday in Weekday::ALL.iter() {
+            for &time_of_day in boundaries.iter() {
+                let time = ScheduleTime {
+                    weekday,
+                    time_of_day,
+                };
+                let fixed_share_ratios: Vec<_> = descriptors
+                    .iter()
+                    .filter_map(|descriptor| descriptor.strategy_at(time).get_fixed_share_ratio())
+                    .collect();
+                if fixed_share_ratios.len() == descriptors.len() {
+                    Err(error::Client::OnlyFixedShareRatio)?;
+                } else if fixed_share_ratios.iter().sum::<f64>() >= 1.0 {
+                    Err(error::Client::FixedShareRatioOverflow)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Switches the groups to strategies of their schedule windows that are active now
+    fn apply_schedule(&mut self) {
+        let schedule_time = self.clock.now().into();
+        self.schedule_time = schedule_time;
+
+        let mut strategy_changed = false;
+        for scheduler_group_handle in self.list.iter_mut() {
+            strategy_changed |= scheduler_group_handle.update_strategy(schedule_time);
+        }
+        if strategy_changed {
+            self.recalculate_quotas(true);
+        }
+    }
+
     fn recalculate_quotas(&mut self, reset_generated_work: bool) {
+        if self.is_empty() {
+            return;
+        }
+
+        let total_quota: usize = self
+            .list
+            .iter()
+            .filter_map(|scheduler_group_handle| scheduler_group_handle.get_quota())
+            .sum();
+        let total_fixed_share_ratio: f64 = self
+            .list
+            .iter()
+            .filter_map(|scheduler_group_handle| scheduler_group_handle.get_fixed_share_ratio())
+            .sum();
         assert!(
-            self.total_fixed_share_ratio < 1.0 && self.fixed_share_ratio_count < self.count(),
+            total_fixed_share_ratio < 1.0
+                && self
+                    .list
+                    .iter()
+                    .any(|scheduler_group_handle| !scheduler_group_handle.has_fixed_share_ratio()),
             "BUG: no share ratio left for common groups"
         );
 
-        if self.is_empty() {
-            return;
-        }
         // Precalculate remaining share ratio normalized per 1 quota unit
-        let share_ratio_per_quota_unit =
-            (1.0 - self.total_fixed_share_ratio) / self.total_quota as f64;
+        let share_ratio_per_quota_unit = (1.0 - total_fixed_share_ratio) / total_quota as f64;
 
         // Update all groups with newly calculated share ratio.
         // Also reset generated work to prevent switching all future work to new group because
@@ -533,12 +684,17 @@ impl GroupRegistry {
             if reset_generated_work {
                 scheduler_group_handle.reset_generated_work();
             }
-            if !scheduler_group_handle.has_fixed_share_ratio() {
-                scheduler_group_handle.share_ratio = share_ratio_per_quota_unit
-                    * scheduler_group_handle
-                        .get_quota()
-                        .expect("BUG: missing group quota") as f64;
-            }
+            scheduler_group_handle.share_ratio = match scheduler_group_handle
+                .get_fixed_share_ratio()
+            {
+                Some(fixed_share_ratio) => fixed_share_ratio,
+                None => {
+                    share_ratio_per_quota_unit
+                        * scheduler_group_handle
+                            .get_quota()
+                            .expect("BUG: missing group quota") as f64
+                }
+            };
         }
     }
 }
@@ -548,15 +704,18 @@ pub struct Manager {
     group_registry: Arc<Mutex<GroupRegistry>>,
     event_monitor: event::Monitor,
     midstate_count: usize,
+    // NOTE: the registry is owned by the miner core, keep only weak reference to it
+    backend_registry: Weak<backend::Registry>,
 }
 
 impl Manager {
-    pub fn new(midstate_count: usize) -> Self {
+    pub fn new(midstate_count: usize, backend_registry: &Arc<backend::Registry>) -> Self {
         let event_monitor = event::Monitor::new();
         Self {
             group_registry: Arc::new(Mutex::new(GroupRegistry::new(event_monitor.clone()))),
             event_monitor,
             midstate_count,
+            backend_registry: Arc::downgrade(backend_registry),
         }
     }
 
@@ -583,7 +742,20 @@ impl Manager {
                             pool_config.enabled.unwrap_or(default_pool_enabled),
                         )
                         .map_err(|e| e.to_string())?;
-                        let client_handle = Handle::new(descriptor, backend_info.cloned(), None);
+                        let quota = match pool_config.quota {
+                            Some(0) => {
+                                Err(format!("Pool '{}' quota must be positive", pool_config.url))?
+                            }
+                            Some(quota) => quota,
+                            None => Handle::DEFAULT_QUOTA,
+                        };
+                        let client_handle = Handle::new(
+                            descriptor,
+                            backend_info.cloned(),
+                            self.backend_registry.clone(),
+                            None,
+                        )
+                        .with_quota(quota);
                         group.push_client(client_handle).await;
                     }
                 }
@@ -630,4 +802,70 @@ impl Manager {
     pub async fn get_groups(&self) -> Vec<Arc<Group>> {
         self.group_registry.lock().await.get_groups()
     }
+
+    /// Backend registry that is passed to new clients as a source of nominal hashrate
+    #[inline]
+    pub fn get_backend_registry(&self) -> Weak<backend::Registry> {
+        self.backend_registry.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::node::ClientStats as _;
+    use crate::sync;
+    use crate::test_utils;
+
+    use ii_async_compat::tokio;
+
+    #[tokio::test]
+    async fn test_remove_active_client() {
+        let client_manager = Manager::new(1, &Arc::new(backend::Registry::new()));
+        let group = client_manager.create_or_get_default_group().await;
+
+        let descriptor = ClientDescriptor::create(
+            "drain://localhost",
+            &ClientUserInfo::new("user", None),
+            true,
+        )
+        .expect("BUG: cannot create client descriptor");
+        let client_handle = group
+            .push_client(Handle::new(
+                descriptor,
+                None,
+                client_manager.get_backend_registry(),
+                None,
+            ))
+            .await;
+        while !client_handle.is_running() {
+            tokio::time::delay_for(time::Duration::from_millis(10)).await;
+        }
+
+        // solutions of test blocks are issued by the removed client so that they are accounted
+        // to it instead of the test client shared with other tests
+        let client_stats = client_handle.node.client_stats();
+        let accepted = client_stats.accepted().take_snapshot().await.solutions;
+
+        // solutions that are still queued when the client is removed have to be accounted too
+        for block in test_utils::TEST_BLOCKS.iter() {
+            let origin = Arc::downgrade(&client_handle.node);
+            client_handle
+                .solution_sender
+                .unbounded_send(test_utils::TestClientBlock::new(block, origin).into())
+                .expect("BUG: cannot send solution");
+        }
+        let removed_client = group
+            .remove_client_at(0)
+            .await
+            .expect("BUG: cannot remove client");
+
+        assert!(Arc::ptr_eq(&removed_client, &client_handle));
+        assert_eq!(client_handle.status(), sync::Status::Destroyed);
+        assert!(group.is_empty().await);
+        assert_eq!(
+            client_stats.accepted().take_snapshot().await.solutions - accepted,
+            test_utils::TEST_BLOCKS.len() as u64
+        );
+    }
 }
@@ -267,10 +267,7 @@ impl Client {
 
     async fn account_solution(&self, solution: work::Solution) {
         let now = std::time::Instant::now();
-        self.stats
-            .accepted
-            .account_solution(&solution.job_target(), now)
-            .await;
+        stats::account_accepted(&solution.path(), &solution.job_target(), now).await;
     }
 
     async fn main_loop(self: Arc<Self>) -> error::Result<()> {
@@ -314,6 +311,13 @@ impl Client {
                 _ = stop_receiver.next() => {}
             }
 
+            if self.status.is_destroying() {
+                // Account solutions that have been found before the client started destroying
+                let mut solution_receiver = self.solution_receiver.lock().await;
+                while let Some(solution) = solution_receiver.try_receive().await {
+                    self.account_solution(solution).await;
+                }
+            }
             // Invalidate current job to stop working on it
             self.job_sender.lock().await.invalidate();
 
@@ -0,0 +1,358 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Health of clients that accept the connection but don't provide any useful service, e.g.
+//! reject most of the shares or stop sending new jobs. The scheduler temporarily skips clients
+//! that have been demoted due to bad health.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::time;
+
+/// Reason why a client has been temporarily demoted
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DemotionReason {
+    /// Too many shares have been rejected by the remote server
+    RejectRatio,
+    /// The remote server hasn't sent any new job for too long
+    JobAge,
+    /// The remote server responds to submitted shares too slowly
+    ShareLatency,
+}
+
+impl fmt::Display for DemotionReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::RejectRatio => write!(f, "High reject ratio"),
+            Self::JobAge => write!(f, "Stale job"),
+            Self::ShareLatency => write!(f, "Slow share responses"),
+        }
+    }
+}
+
+/// Client statistics that the health is evaluated from, all values are cumulative
+#[derive(Debug, Clone, Default)]
+pub struct Sample {
+    /// Shares accepted by the remote server
+    pub accepted: u64,
+    /// Shares rejected by the remote server including the stale ones
+    pub rejected: u64,
+    /// Valid jobs received from the remote server
+    pub valid_jobs: usize,
+    /// Number of responses to submitted shares
+    pub responses: u64,
+    /// Total time between finding the shares and receiving the responses
+    pub total_latency: time::Duration,
+}
+
+/// Health of a client evaluated from the recent statistics
+#[derive(Debug, Clone, PartialEq)]
+pub struct Status {
+    /// Score between 0 (useless client) and 1 (healthy client)
+    pub score: f64,
+    /// Ratio of rejected shares when there are enough responses to evaluate it
+    pub reject_ratio: Option<f64>,
+    /// Time since the last job has been received while the client has been running
+    pub job_age: Option<time::Duration>,
+    /// Mean time between finding shares and receiving the responses
+    pub share_latency: Option<time::Duration>,
+    /// The reason of the demotion when the client is demoted
+    pub demotion: Option<DemotionReason>,
+}
+
+impl Status {
+    #[inline]
+    pub fn is_demoted(&self) -> bool {
+        self.demotion.is_some()
+    }
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Self {
+            score: 1.0,
+            reject_ratio: None,
+            job_age: None,
+            share_latency: None,
+            demotion: None,
+        }
+    }
+}
+
+/// Tracks health of a single client from periodic samples of its statistics
+#[derive(Debug)]
+pub struct Tracker {
+    /// Samples within the measurement window, the first one is the base of the window
+    samples: VecDeque<(time::Instant, Sample)>,
+    /// Time when the last new job has been received or when the measurement has started
+    last_job_time: Option<time::Instant>,
+    last_valid_jobs: usize,
+    /// The client is demoted when it hasn't received any new job for this period
+    max_job_age: time::Duration,
+    /// Time when the demoted client is retried
+    demoted_until: Option<time::Instant>,
+    status: Status,
+}
+
+impl Tracker {
+    /// The reject ratio and share latency are evaluated from statistics of this period
+    pub const MEASUREMENT_WINDOW: time::Duration = time::Duration::from_secs(10 * 60);
+    /// The reject ratio is evaluated only when there are at least this many responses in the
+    /// measurement window
+    pub const MIN_RESPONSES: u64 = 20;
+    pub const MAX_REJECT_RATIO: f64 = 0.5;
+    /// Default job age limit that suits servers which send new jobs regularly (stratum V1)
+    pub const MAX_JOB_AGE: time::Duration = time::Duration::from_secs(5 * 60);
+    /// Stratum V2 servers may send new jobs only along with a new prevhash, i.e. once per block.
+    /// Block interval rarely exceeds one hour.
+    pub const MAX_V2_JOB_AGE: time::Duration = time::Duration::from_secs(60 * 60);
+    pub const MAX_SHARE_LATENCY: time::Duration = time::Duration::from_secs(10);
+    /// Demoted client is skipped for this period and then it is measured again from scratch
+    pub const DEMOTION_PERIOD: time::Duration = time::Duration::from_secs(5 * 60);
+
+    pub fn new() -> Self {
+        Self {
+            samples: VecDeque::new(),
+            last_job_time: None,
+            last_valid_jobs: 0,
+            max_job_age: Self::MAX_JOB_AGE,
+            demoted_until: None,
+            status: Default::default(),
+        }
+    }
+
+    /// Sets how long the client may go without a new job before it is demoted
+    pub fn with_max_job_age(mut self, max_job_age: time::Duration) -> Self {
+        self.max_job_age = max_job_age;
+        self
+    }
+
+    #[inline]
+    pub fn status(&self) -> &Status {
+        &self.status
+    }
+
+    /// Evaluates health of the client from a new `sample` of its statistics. The health is
+    /// measured only while the client is running.
+    pub fn update(&mut self, sample: Sample, running: bool, now: time::Instant) -> &Status {
+        if let Some(demoted_until) = self.demoted_until {
+            if now >= demoted_until {
+                // Retry the client with new measurement
+                self.demoted_until = None;
+                self.status.demotion = None;
+                self.samples.clear();
+                self.last_job_time = None;
+            }
+        }
+
+        if running {
+            if self.last_job_time.is_none() || sample.valid_jobs != self.last_valid_jobs {
+                self.last_job_time = Some(now);
+            }
+            self.samples.push_back((now, sample.clone()));
+            while self.samples.len() > 1
+                && now.saturating_duration_since(self.samples[1].0) >= Self::MEASUREMENT_WINDOW
+            {
+                self.samples.pop_front();
+            }
+        } else {
+            self.samples.clear();
+            self.last_job_time = None;
+        }
+        self.last_valid_jobs = sample.valid_jobs;
+
+        self.evaluate(now);
+        &self.status
+    }
+
+    fn evaluate(&mut self, now: time::Instant) {
+        let (reject_ratio, share_latency) = match (self.samples.front(), self.samples.back()) {
+            (Some((_, first)), Some((_, last))) => {
+                let accepted = last.accepted - first.accepted;
+                let rejected = last.rejected - first.rejected;
+                let shares = accepted + rejected;
+                let responses = last.responses - first.responses;
+                (
+                    Some(rejected as f64 / shares as f64).filter(|_| shares >= Self::MIN_RESPONSES),
+                    Some(responses)
+                        .filter(|responses| *responses > 0)
+                        .map(|responses| {
+                            (last.total_latency - first.total_latency) / responses as u32
+                        }),
+                )
+            }
+            _ => (None, None),
+        };
+        let job_age = self
+            .last_job_time
+            .map(|last_job_time| now.saturating_duration_since(last_job_time));
+
+        let scores = [
+            reject_ratio.map(|reject_ratio| 1.0 - reject_ratio / Self::MAX_REJECT_RATIO),
+            job_age.map(|job_age| 1.0 - job_age.as_secs_f64() / self.max_job_age.as_secs_f64()),
+            share_latency.map(|share_latency| {
+                1.0 - share_latency.as_secs_f64() / Self::MAX_SHARE_LATENCY.as_secs_f64()
+            }),
+        ];
+        let score = scores
+            .iter()
+            .flatten()
+            .fold(1.0, |score: f64, metric_score| score.min(*metric_score))
+            .max(0.0);
+
+        if self.demoted_until.is_none() {
+            self.status.demotion =
+                if reject_ratio.map_or(false, |ratio| ratio > Self::MAX_REJECT_RATIO) {
+                    Some(DemotionReason::RejectRatio)
+                } else if job_age.map_or(false, |job_age| job_age > self.max_job_age) {
+                    Some(DemotionReason::JobAge)
+                } else if share_latency.map_or(false, |latency| latency > Self::MAX_SHARE_LATENCY) {
+                    Some(DemotionReason::ShareLatency)
+                } else {
+                    None
+                };
+            if self.status.demotion.is_some() {
+                self.demoted_until = Some(now + Self::DEMOTION_PERIOD);
+            }
+        }
+
+        self.status.score = score;
+        self.status.reject_ratio = reject_ratio;
+        self.status.job_age = job_age;
+        self.status.share_latency = share_latency;
+    }
+}
+
+impl Default for Tracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample(accepted: u64, rejected: u64, valid_jobs: usize) -> Sample {
+        Sample {
+            accepted,
+            rejected,
+            valid_jobs,
+            responses: accepted + rejected,
+            total_latency: time::Duration::from_millis(100 * (accepted + rejected)),
+        }
+    }
+
+    #[test]
+    fn test_reject_ratio_demotion() {
+        let start = time::Instant::now();
+        let mut tracker = Tracker::new();
+
+        assert_eq!(tracker.update(sample(0, 0, 1), true, start).score, 1.0);
+        // Too few responses to evaluate the reject ratio
+        let now = start + time::Duration::from_secs(10);
+        assert_eq!(
+            tracker.update(sample(2, 8, 1), true, now).reject_ratio,
+            None
+        );
+        let now = start + time::Duration::from_secs(20);
+        let status = tracker.update(sample(10, 10, 2), true, now).clone();
+        assert_eq!(status.reject_ratio, Some(0.5));
+        assert_eq!(status.share_latency, Some(time::Duration::from_millis(100)));
+        assert!(!status.is_demoted());
+        let now = start + time::Duration::from_secs(30);
+        let status = tracker.update(sample(10, 20, 3), true, now).clone();
+        assert_eq!(status.demotion, Some(DemotionReason::RejectRatio));
+        assert_eq!(status.score, 0.0);
+
+        // The client stays demoted for the whole period and then it is measured again
+        let now = now + Tracker::DEMOTION_PERIOD - time::Duration::from_secs(1);
+        assert!(tracker.update(sample(10, 20, 4), true, now).is_demoted());
+        let now = now + time::Duration::from_secs(1);
+        let status = tracker.update(sample(10, 20, 5), true, now);
+        assert!(!status.is_demoted());
+        assert_eq!(status.reject_ratio, None);
+    }
+
+    #[test]
+    fn test_job_age_demotion() {
+        let start = time::Instant::now();
+        let mut tracker = Tracker::new();
+
+        // Job age is not measured while the client isn't running
+        assert_eq!(tracker.update(sample(0, 0, 1), false, start).job_age, None);
+        let now = start + Tracker::MAX_JOB_AGE;
+        assert_eq!(
+            tracker.update(sample(0, 0, 1), true, now).job_age,
+            Some(time::Duration::from_secs(0))
+        );
+        let now = now + Tracker::MAX_JOB_AGE;
+        let status = tracker.update(sample(0, 0, 1), true, now);
+        assert_eq!(status.score, 0.0);
+        assert!(!status.is_demoted());
+        let now = now + time::Duration::from_secs(1);
+        assert_eq!(
+            tracker.update(sample(0, 0, 1), true, now).demotion,
+            Some(DemotionReason::JobAge)
+        );
+    }
+
+    #[test]
+    fn test_v2_job_age() {
+        let start = time::Instant::now();
+        let mut tracker = Tracker::new().with_max_job_age(Tracker::MAX_V2_JOB_AGE);
+
+        // New jobs arrive along with new prevhash only, the second block takes 30 minutes
+        let mut now = start;
+        for (valid_jobs, block_interval) in [(1, 10), (2, 30), (3, 5)].iter() {
+            let block_end = now + time::Duration::from_secs(block_interval * 60);
+            while now < block_end {
+                let status = tracker.update(sample(0, 0, *valid_jobs), true, now);
+                assert!(!status.is_demoted(), "Demoted at {:?}", now - start);
+                now += time::Duration::from_secs(30);
+            }
+        }
+
+        // No job for the whole limit
+        let now = now + Tracker::MAX_V2_JOB_AGE;
+        assert_eq!(
+            tracker.update(sample(0, 0, 3), true, now).demotion,
+            Some(DemotionReason::JobAge)
+        );
+    }
+
+    #[test]
+    fn test_share_latency_demotion() {
+        let start = time::Instant::now();
+        let mut tracker = Tracker::new();
+
+        tracker.update(sample(0, 0, 1), true, start);
+        let mut slow_sample = sample(0, 0, 1);
+        slow_sample.accepted = 2;
+        slow_sample.responses = 2;
+        slow_sample.total_latency = time::Duration::from_secs(30);
+        let now = start + time::Duration::from_secs(10);
+        let status = tracker.update(slow_sample, true, now);
+        assert_eq!(status.share_latency, Some(time::Duration::from_secs(15)));
+        assert_eq!(status.demotion, Some(DemotionReason::ShareLatency));
+    }
+}
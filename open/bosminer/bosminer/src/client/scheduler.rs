@@ -24,6 +24,8 @@ use crate::client;
 use crate::sync::event;
 use crate::work;
 
+use bosminer_config::{ClientPolicy, GroupDescriptor, LoadBalanceStrategy, ScheduleTime};
+
 use futures::channel::mpsc;
 use futures::lock::{Mutex, MutexGuard};
 use ii_async_compat::{futures, FutureExt};
@@ -31,6 +33,47 @@ use ii_async_compat::{futures, FutureExt};
 use std::sync::Arc;
 use std::time;
 
+/// Source of the wall-clock time that the group schedules follow
+pub trait Clock: Send + Sync {
+    fn now(&self) -> time::SystemTime;
+}
+
+/// Clock of the operating system
+#[derive(Debug, Copy, Clone)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> time::SystemTime {
+        time::SystemTime::now()
+    }
+}
+
+/// Scheduling state of a client that the client policies decide on
+#[derive(Debug, Clone, PartialEq)]
+struct ClientState {
+    /// Time since the client has been running without interruption
+    running_since: Option<time::Instant>,
+    /// Share of the group work
+    quota: usize,
+    /// Work generated by the client since it has been added to the group
+    generated_work: u64,
+    /// The client has been temporarily demoted due to bad health
+    demoted: bool,
+}
+
+impl ClientState {
+    #[inline]
+    fn is_running(&self) -> bool {
+        self.running_since.is_some()
+    }
+
+    fn running_for(&self, now: time::Instant) -> time::Duration {
+        self.running_since
+            .map(|running_since| now.saturating_duration_since(running_since))
+            .unwrap_or_default()
+    }
+}
+
 /// This struct cannot be shared and it is possible to use mutable references. However, the
 /// client handle is shared object with interior mutability scheduler::ClientHandle. It solves
 /// many synchronization problems.
@@ -38,12 +81,26 @@ use std::time;
 pub struct ClientHandle {
     pub client_handle: Arc<client::Handle>,
     last_generated_work: u64,
+    state: ClientState,
+    /// Time when the scheduler asked for stopping the client
+    stop_requested: Option<time::Instant>,
 }
 
 impl ClientHandle {
+    /// Client that is no longer needed is kept running for this period so that the scheduler can
+    /// switch back to it without reconnecting
+    const STOP_GRACE_PERIOD: time::Duration = time::Duration::from_secs(60);
+
     pub fn new(client_handle: Arc<client::Handle>) -> Self {
         Self {
             last_generated_work: Self::get_generated_work(&client_handle),
+            state: ClientState {
+                running_since: None,
+                quota: client_handle.quota(),
+                generated_work: 0,
+                demoted: false,
+            },
+            stop_requested: None,
             client_handle,
         }
     }
@@ -54,7 +111,8 @@ impl ClientHandle {
     }
 
     #[inline]
-    fn try_start(&self) -> Result<(), ()> {
+    fn try_start(&mut self) -> Result<(), ()> {
+        self.stop_requested = None;
         if self.client_handle.is_enabled() {
             self.client_handle.start();
             Ok(())
@@ -63,17 +121,28 @@ impl ClientHandle {
         }
     }
 
-    #[inline]
-    fn try_delayed_stop(&self) -> Result<(), ()> {
-        // TODO: Implement delay before actual stopping
+    /// Stops the client once the scheduler hasn't needed it for the whole grace period
+    fn try_delayed_stop(&mut self, now: time::Instant) -> Result<(), ()> {
         if self.client_handle.is_enabled() {
-            self.client_handle.stop();
+            let stop_requested = *self.stop_requested.get_or_insert(now);
+            if now.saturating_duration_since(stop_requested) >= Self::STOP_GRACE_PERIOD {
+                self.client_handle.stop();
+            }
             Ok(())
         } else {
             Err(())
         }
     }
 
+    /// Keeps the client running, it is started when it is not running
+    fn keep_running(&mut self) {
+        if self.is_running() {
+            self.stop_requested = None;
+        } else {
+            let _ = self.try_start();
+        }
+    }
+
     fn get_generated_work(client_handle: &Arc<client::Handle>) -> u64 {
         *client_handle
             .node
@@ -93,6 +162,18 @@ impl ClientHandle {
         self.last_generated_work = next_generated_work;
         delta
     }
+
+    /// Updates scheduling state of the client and returns work generated since the last update
+    async fn update_state(&mut self, now: time::Instant) -> u64 {
+        let generated_work_delta = self.get_delta_and_update_generated_work();
+        self.state.generated_work += generated_work_delta;
+        self.state.running_since = match self.is_running() {
+            true => self.state.running_since.or(Some(now)),
+            false => None,
+        };
+        self.state.demoted = self.client_handle.update_health(now).await.is_demoted();
+        generated_work_delta
+    }
 }
 
 impl PartialEq for ClientHandle {
@@ -101,11 +182,131 @@ impl PartialEq for ClientHandle {
     }
 }
 
+/// Selects the active client of a group according to the group `ClientPolicy`
+#[derive(Debug, Clone)]
+enum ClientSelector {
+    Failover {
+        return_to_primary_delay: time::Duration,
+    },
+    RoundRobin {
+        rotation_period: time::Duration,
+        /// Time when the active client has been selected
+        rotation_start: Option<time::Instant>,
+    },
+    LoadBalance,
+}
+
+impl ClientSelector {
+    fn new(descriptor: &GroupDescriptor) -> Self {
+        match descriptor.client_policy() {
+            ClientPolicy::Failover => Self::Failover {
+                return_to_primary_delay: descriptor.return_to_primary_delay(),
+            },
+            ClientPolicy::RoundRobin => Self::RoundRobin {
+                rotation_period: descriptor.rotation_period(),
+                rotation_start: None,
+            },
+            ClientPolicy::LoadBalance => Self::LoadBalance,
+        }
+    }
+
+    /// Failover keeps only clients with higher priority than the active one running, the other
+    /// policies need all clients running to be able to switch between them
+    fn keeps_all_clients_running(&self) -> bool {
+        match self {
+            Self::Failover { .. } => false,
+            Self::RoundRobin { .. } | Self::LoadBalance => true,
+        }
+    }
+
+    /// Returns index of the client that should generate work for the group. The `active` client
+    /// is the one that has been selected in the previous round.
+    fn select(
+        &mut self,
+        clients: &[ClientState],
+        active: Option<usize>,
+        now: time::Instant,
+    ) -> Option<usize> {
+        let active = active.filter(|&index| {
+            clients
+                .get(index)
+                .map_or(false, |client| client.is_running())
+        });
+        match self {
+            Self::Failover {
+                return_to_primary_delay,
+            } => clients.iter().enumerate().position(|(index, client)| {
+                // Client with higher priority replaces the active client only after it has been
+                // running for the whole return-to-primary delay
+                client.is_running()
+                    && (active.map_or(true, |active| active == index)
+                        || client.running_for(now) >= *return_to_primary_delay)
+            }),
+            Self::RoundRobin {
+                rotation_period,
+                rotation_start,
+            } => {
+                if let (Some(active), Some(start)) = (active, *rotation_start) {
+                    let time_slice = *rotation_period * clients[active].quota as u32;
+                    if now.saturating_duration_since(start) < time_slice {
+                        return Some(active);
+                    }
+                }
+                // Take the next running client after the active one
+                let first = active.map_or(0, |active| active + 1);
+                let next = (first..first + clients.len())
+                    .map(|index| index % clients.len())
+                    .find(|&index| clients[index].is_running());
+                *rotation_start = next.map(|_| now);
+                next
+            }
+            Self::LoadBalance => {
+                let running = || clients.iter().filter(|client| client.is_running());
+                let total_quota: usize = running().map(|client| client.quota).sum();
+                let total_generated_work: u64 = running().map(|client| client.generated_work).sum();
+                // Select the client that is the furthest below its share of the work
+                let mut next = None;
+                for (index, client) in clients.iter().enumerate() {
+                    if !client.is_running() {
+                        continue;
+                    }
+                    let work_ratio = client.generated_work as f64
+                        / total_generated_work.max(1) as f64
+                        - client.quota as f64 / total_quota as f64;
+                    match next {
+                        Some((_, min_work_ratio)) if min_work_ratio <= work_ratio => {}
+                        _ => next = Some((index, work_ratio)),
+                    }
+                }
+                next.map(|(index, _)| index)
+            }
+        }
+    }
+}
+
+/// Demoted clients are treated as if they weren't running so that the client policies skip them.
+/// They are used only when there is no other running client.
+fn skip_demoted_clients(client_states: &mut [ClientState]) {
+    if client_states
+        .iter()
+        .any(|client_state| client_state.is_running() && !client_state.demoted)
+    {
+        for client_state in client_states.iter_mut() {
+            if client_state.demoted {
+                client_state.running_since = None;
+            }
+        }
+    }
+}
+
 /// Private client handle with internal information which shouldn't be leaked
 #[derive(Debug, Clone)]
 pub struct GroupHandle {
     pub group_handle: Arc<client::Group>,
     active_client: Option<Arc<client::Handle>>,
+    client_selector: ClientSelector,
+    /// Strategy of the schedule window that is currently active
+    strategy: LoadBalanceStrategy,
     generated_work: u64,
     /// Current ratio of hashrate that this group has been allocated to. This number
     /// changes based on newly added/removed groups.
@@ -113,14 +314,14 @@ pub struct GroupHandle {
 }
 
 impl GroupHandle {
-    pub fn new(group_handle: Arc<client::Group>) -> Self {
+    pub fn new(group_handle: Arc<client::Group>, time: ScheduleTime) -> Self {
+        let strategy = group_handle.descriptor.strategy_at(time);
         Self {
             active_client: None,
+            client_selector: ClientSelector::new(&group_handle.descriptor),
+            share_ratio: strategy.get_fixed_share_ratio().unwrap_or_default(),
+            strategy,
             generated_work: 0,
-            share_ratio: group_handle
-                .descriptor
-                .get_fixed_share_ratio()
-                .unwrap_or_default(),
             group_handle,
         }
     }
@@ -132,37 +333,59 @@ impl GroupHandle {
 
     #[inline]
     pub fn has_fixed_share_ratio(&self) -> bool {
-        self.group_handle
-            .descriptor
-            .get_fixed_share_ratio()
-            .is_some()
+        self.get_fixed_share_ratio().is_some()
+    }
+
+    #[inline]
+    pub fn get_fixed_share_ratio(&self) -> Option<f64> {
+        self.strategy.get_fixed_share_ratio()
     }
 
     #[inline]
     pub fn get_quota(&self) -> Option<usize> {
-        self.group_handle.descriptor.get_quota()
+        self.strategy.get_quota()
+    }
+
+    /// Switches to the strategy of the schedule window that contains `time` and returns whether
+    /// the strategy has changed
+    pub fn update_strategy(&mut self, time: ScheduleTime) -> bool {
+        let strategy = self.group_handle.descriptor.strategy_at(time);
+        if self.strategy == strategy {
+            return false;
+        }
+        self.strategy = strategy;
+        true
     }
 
-    async fn update_status(&mut self) {
+    async fn update_status(&mut self, now: time::Instant) {
         let mut scheduler_client_handles = self.group_handle.scheduler_client_handles.lock().await;
         let mut generated_work_delta = 0;
 
-        self.active_client = None;
-        for scheduler_client_handle in scheduler_client_handles.iter_mut() {
-            generated_work_delta += scheduler_client_handle.get_delta_and_update_generated_work();
-            match self.active_client {
-                None => {
-                    if scheduler_client_handle.is_running() {
-                        self.active_client = Some(scheduler_client_handle.client_handle.clone());
-                    } else {
-                        let _ = scheduler_client_handle.try_start();
-                    }
-                }
-                Some(_) => {
-                    let _ = scheduler_client_handle.try_delayed_stop();
+        let mut client_states = Vec::with_capacity(scheduler_client_handles.len());
+        let mut active_index = None;
+        for (index, scheduler_client_handle) in scheduler_client_handles.iter_mut().enumerate() {
+            generated_work_delta += scheduler_client_handle.update_state(now).await;
+            client_states.push(scheduler_client_handle.state.clone());
+            if self.active_client.as_ref() == Some(&scheduler_client_handle.client_handle) {
+                active_index = Some(index);
+            }
+        }
+        skip_demoted_clients(&mut client_states);
+
+        let selected_index = self
+            .client_selector
+            .select(&client_states, active_index, now);
+        let keeps_all_clients_running = self.client_selector.keeps_all_clients_running();
+        for (index, scheduler_client_handle) in scheduler_client_handles.iter_mut().enumerate() {
+            match selected_index {
+                Some(selected_index) if index > selected_index && !keeps_all_clients_running => {
+                    let _ = scheduler_client_handle.try_delayed_stop(now);
                 }
+                _ => scheduler_client_handle.keep_running(),
             }
         }
+        self.active_client =
+            selected_index.map(|index| scheduler_client_handles[index].client_handle.clone());
 
         self.generated_work += generated_work_delta;
     }
@@ -264,10 +487,12 @@ impl JobDispatcher {
         if group_registry.is_empty() {
             return None;
         }
+        group_registry.apply_schedule();
 
+        let now = time::Instant::now();
         let mut total_generated_work = 0;
         for scheduler_group_handle in group_registry.iter_mut() {
-            scheduler_group_handle.update_status().await;
+            scheduler_group_handle.update_status(now).await;
             total_generated_work += scheduler_group_handle.generated_work;
         }
 
@@ -396,3 +621,220 @@ impl JobExecutor {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::error;
+
+    use bosminer_config::{ScheduleWindow, TimeOfDay};
+    use ii_async_compat::tokio;
+
+    fn client_state(running_for: Option<u64>, quota: usize, now: time::Instant) -> ClientState {
+        ClientState {
+            running_since: running_for.map(|secs| now - time::Duration::from_secs(secs)),
+            quota,
+            generated_work: 0,
+            demoted: false,
+        }
+    }
+
+    #[test]
+    fn test_failover_return_to_primary() {
+        let now = time::Instant::now();
+        let mut selector = ClientSelector::Failover {
+            return_to_primary_delay: time::Duration::from_secs(300),
+        };
+        let mut clients = vec![client_state(None, 1, now), client_state(Some(600), 1, now)];
+
+        // Only the backup client is running
+        assert_eq!(selector.select(&clients, None, now), Some(1));
+        // Recovered primary client has to be running for the whole delay
+        clients[0] = client_state(Some(10), 1, now);
+        assert_eq!(selector.select(&clients, Some(1), now), Some(1));
+        clients[0] = client_state(Some(300), 1, now);
+        assert_eq!(selector.select(&clients, Some(1), now), Some(0));
+        // The delay doesn't apply when the active client fails
+        clients[0] = client_state(Some(10), 1, now);
+        clients[1] = client_state(None, 1, now);
+        assert_eq!(selector.select(&clients, Some(1), now), Some(0));
+        clients[0] = client_state(None, 1, now);
+        assert_eq!(selector.select(&clients, Some(0), now), None);
+    }
+
+    #[test]
+    fn test_round_robin_quota() {
+        let start = time::Instant::now();
+        let mut selector = ClientSelector::RoundRobin {
+            rotation_period: time::Duration::from_secs(60),
+            rotation_start: None,
+        };
+        let clients = vec![
+            client_state(Some(0), 2, start),
+            client_state(None, 1, start),
+            client_state(Some(0), 1, start),
+        ];
+
+        assert_eq!(selector.select(&clients, None, start), Some(0));
+        // The first client has quota for two rotation periods
+        let now = start + time::Duration::from_secs(90);
+        assert_eq!(selector.select(&clients, Some(0), now), Some(0));
+        // Client that isn't running is skipped
+        let now = start + time::Duration::from_secs(120);
+        assert_eq!(selector.select(&clients, Some(0), now), Some(2));
+        let now = start + time::Duration::from_secs(150);
+        assert_eq!(selector.select(&clients, Some(2), now), Some(2));
+        let now = start + time::Duration::from_secs(180);
+        assert_eq!(selector.select(&clients, Some(2), now), Some(0));
+    }
+
+    #[test]
+    fn test_load_balance_generated_work() {
+        let now = time::Instant::now();
+        let mut selector = ClientSelector::LoadBalance;
+        let mut clients = vec![client_state(Some(0), 3, now), client_state(Some(0), 1, now)];
+
+        assert_eq!(selector.select(&clients, None, now), Some(0));
+        clients[0].generated_work = 70;
+        clients[1].generated_work = 30;
+        assert_eq!(selector.select(&clients, Some(0), now), Some(0));
+        clients[0].generated_work = 80;
+        clients[1].generated_work = 20;
+        assert_eq!(selector.select(&clients, Some(0), now), Some(1));
+        // Work is not balanced with clients that aren't running
+        clients[1].running_since = None;
+        assert_eq!(selector.select(&clients, Some(1), now), Some(0));
+    }
+
+    struct FakeClock(std::sync::Mutex<time::SystemTime>);
+
+    impl FakeClock {
+        /// Starts on Monday 00:00 UTC
+        fn new() -> Self {
+            Self(std::sync::Mutex::new(
+                time::UNIX_EPOCH + time::Duration::from_secs(4 * 24 * 60 * 60),
+            ))
+        }
+
+        fn advance(&self, hours: u64) {
+            *self.0.lock().expect("BUG: poisoned lock") +=
+                time::Duration::from_secs(hours * 60 * 60);
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> time::SystemTime {
+            *self.0.lock().expect("BUG: poisoned lock")
+        }
+    }
+
+    fn customer_window(start: u32, end: u32, fixed_share_ratio: f64) -> ScheduleWindow {
+        ScheduleWindow {
+            days: None,
+            start: TimeOfDay::from_hm(start, 0).expect("BUG: invalid time of day"),
+            end: TimeOfDay::from_hm(end, 0).expect("BUG: invalid time of day"),
+            strategy: LoadBalanceStrategy::FixedShareRatio(fixed_share_ratio),
+        }
+    }
+
+    fn group_registry(clock: Arc<FakeClock>) -> client::GroupRegistry {
+        let mut group_registry = client::GroupRegistry::with_clock(event::Monitor::new(), clock);
+        group_registry
+            .create_group(
+                GroupDescriptor::new("pool".to_string(), false, LoadBalanceStrategy::Quota(1)),
+                1,
+            )
+            .expect("BUG: cannot create group");
+        group_registry
+    }
+
+    fn share_ratios(group_registry: &client::GroupRegistry) -> Vec<f64> {
+        group_registry
+            .iter()
+            .map(|scheduler_group_handle| scheduler_group_handle.share_ratio)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_share_ratio() {
+        let clock = Arc::new(FakeClock::new());
+        let mut group_registry = group_registry(clock.clone());
+        group_registry
+            .create_group(
+                GroupDescriptor::new(
+                    "customer".to_string(),
+                    false,
+                    LoadBalanceStrategy::FixedShareRatio(0.3),
+                )
+                .with_schedule(vec![customer_window(8, 20, 0.6)]),
+                1,
+            )
+            .expect("BUG: cannot create group");
+        let group_registry = Arc::new(Mutex::new(group_registry));
+        let dispatcher = JobDispatcher::new(work::EngineSender::new(None), group_registry.clone());
+
+        // Groups without clients don't provide any client but the schedule is followed
+        assert!(dispatcher.select_client(0).await.is_none());
+        assert_eq!(share_ratios(&*group_registry.lock().await), vec![0.7, 0.3]);
+        clock.advance(8);
+        assert!(dispatcher.select_client(0).await.is_none());
+        assert_eq!(share_ratios(&*group_registry.lock().await), vec![0.4, 0.6]);
+        clock.advance(11);
+        assert!(dispatcher.select_client(0).await.is_none());
+        assert_eq!(share_ratios(&*group_registry.lock().await), vec![0.4, 0.6]);
+        clock.advance(1);
+        assert!(dispatcher.select_client(0).await.is_none());
+        assert_eq!(share_ratios(&*group_registry.lock().await), vec![0.7, 0.3]);
+    }
+
+    #[test]
+    fn test_scheduled_share_ratio_overflow() {
+        let clock = Arc::new(FakeClock::new());
+        let mut group_registry = group_registry(clock);
+        group_registry
+            .create_group(
+                GroupDescriptor::new(
+                    "customer1".to_string(),
+                    false,
+                    LoadBalanceStrategy::FixedShareRatio(0.3),
+                ),
+                1,
+            )
+            .expect("BUG: cannot create group");
+
+        // The window overflows in the evening even though the group fits now
+        let result = group_registry.create_group(
+            GroupDescriptor::new(
+                "customer2".to_string(),
+                false,
+                LoadBalanceStrategy::FixedShareRatio(0.1),
+            )
+            .with_schedule(vec![customer_window(22, 2, 0.7)]),
+            1,
+        );
+        assert_eq!(result.err(), Some(error::Client::FixedShareRatioOverflow));
+    }
+
+    #[test]
+    fn test_skip_demoted_clients() {
+        let now = time::Instant::now();
+        let mut selector = ClientSelector::Failover {
+            return_to_primary_delay: time::Duration::from_secs(0),
+        };
+        let mut clients = vec![
+            client_state(Some(60), 1, now),
+            client_state(Some(60), 1, now),
+        ];
+
+        // Demoted primary client is skipped while the backup client is healthy
+        clients[0].demoted = true;
+        let mut client_states = clients.clone();
+        skip_demoted_clients(&mut client_states);
+        assert_eq!(selector.select(&client_states, Some(0), now), Some(1));
+        // Demoted client is still better than no client
+        clients[1].demoted = true;
+        let mut client_states = clients.clone();
+        skip_demoted_clients(&mut client_states);
+        assert_eq!(selector.select(&client_states, Some(1), now), Some(0));
+    }
+}
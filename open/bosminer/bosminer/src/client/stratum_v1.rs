@@ -0,0 +1,1183 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Native Stratum V1 client. The client talks to the upstream directly over JSON-RPC and
+//! negotiates version rolling via `mining.configure` (BIP310).
+
+use ii_logging::macros::*;
+
+use crate::error;
+use crate::job;
+use crate::node;
+use crate::stats;
+use crate::sync;
+use crate::version;
+use crate::work;
+
+use failure::ResultExt;
+
+use ii_bitcoin::HashTrait;
+
+use bosminer_config::{ClientDescriptor, ClientProtocol};
+use bosminer_macros::ClientNode;
+
+use async_trait::async_trait;
+use futures::channel::mpsc;
+use futures::lock::Mutex;
+use futures::stream::{SplitSink, SplitStream};
+use ii_async_compat::prelude::*;
+use ii_async_compat::select;
+
+use std::collections::HashMap;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::net::ToSocketAddrs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex as StdMutex;
+use std::sync::{Arc, Weak};
+use std::time;
+
+use ii_stratum::v1::messages::{
+    BooleanResult, Configure, ExtranonceSubscribe, JobId, Notify, SetDifficulty, SetExtranonce,
+    SetVersionMask, Submit, Subscribe, SubscribeResult, VersionRolling,
+};
+use ii_stratum::v1::rpc::{Request, RequestPayload, Rpc, StratumError, StratumResult};
+use ii_stratum::v1::{self, build_message_from_frame, Handler, MessageId};
+use ii_wire::Connection;
+
+/// Error code that pools use for rejecting shares of jobs that are no longer valid
+const STALE_SHARE_ERROR_CODE: i32 = 21;
+/// Error code that pools use for rejecting shares that have already been submitted
+const DUPLICATE_SHARE_ERROR_CODE: i32 = 22;
+/// Error code that pools use for rejecting shares that don't meet the pool target
+const LOW_DIFFICULTY_SHARE_ERROR_CODE: i32 = 23;
+
+/// Determines the reason of share rejection from the error code sent by the pool
+fn reject_reason(error_code: i32) -> stats::RejectReason {
+    match error_code {
+        STALE_SHARE_ERROR_CODE => stats::RejectReason::Stale,
+        DUPLICATE_SHARE_ERROR_CODE => stats::RejectReason::Duplicate,
+        LOW_DIFFICULTY_SHARE_ERROR_CODE => stats::RejectReason::LowDifficulty,
+        _ => stats::RejectReason::Other,
+    }
+}
+
+type FramedSink = SplitSink<v1::Framed, <v1::Framing as ii_wire::Framing>::Tx>;
+type FramedStream = SplitStream<v1::Framed>;
+
+#[derive(Debug, Clone)]
+pub struct ConnectionDetails {
+    pub user: String,
+    pub password: Option<String>,
+    pub host: String,
+    pub port: u16,
+    pub fragment: Option<String>,
+}
+
+impl ConnectionDetails {
+    pub fn from_descriptor(descriptor: &ClientDescriptor) -> Self {
+        Self {
+            user: descriptor.user.clone(),
+            password: descriptor.password.clone(),
+            host: descriptor.host.clone(),
+            port: descriptor.port(),
+            fragment: descriptor.fragment.clone(),
+        }
+    }
+
+    fn get_host_and_port(&self) -> String {
+        format!("{}:{}", self.host, self.port)
+    }
+
+    fn try_enable_xnsub(&self) -> bool {
+        self.host.find(".nicehash.com").is_some()
+            || self
+                .fragment
+                .as_ref()
+                .and_then(|fragment| fragment.find("xnsub"))
+                .is_some()
+    }
+}
+
+/// Extranonce space assigned by the upstream either upon subscription or later on via
+/// `mining.set_extranonce`
+#[derive(Debug, Clone)]
+struct Extranonce {
+    extranonce_1: Vec<u8>,
+    extranonce_2_size: usize,
+    /// Value of the extranonce 2 that will be assigned to the next job
+    next_extranonce_2: u64,
+}
+
+impl Extranonce {
+    fn new(extranonce_1: &v1::ExtraNonce1, extranonce_2_size: usize) -> Self {
+        Self {
+            extranonce_1: extranonce_1.0.as_ref().clone(),
+            extranonce_2_size,
+            next_extranonce_2: 0,
+        }
+    }
+
+    /// Generate extranonce 2 for a new job. The nonce range together with version rolling
+    /// provides enough search space for a single miner so the extranonce 2 is rolled only when
+    /// the upstream doesn't allow version rolling (`version_mask` is 0). The counter is
+    /// serialized as little endian and it is either truncated or padded with zeros to fit the
+    /// extranonce 2 size.
+    fn next_extranonce_2(&mut self, version_mask: u32) -> Vec<u8> {
+        let mut extranonce_2 = vec![0; self.extranonce_2_size];
+        if version_mask == 0 {
+            let counter = self.next_extranonce_2.to_le_bytes();
+            self.next_extranonce_2 = self.next_extranonce_2.wrapping_add(1);
+
+            let len = self.extranonce_2_size.min(counter.len());
+            extranonce_2[..len].copy_from_slice(&counter[..len]);
+        }
+        extranonce_2
+    }
+}
+
+/// Build coinbase transaction from job notification `notify` and the extranonces and calculate
+/// merkle root of the block
+fn calculate_merkle_root(
+    notify: &Notify,
+    extranonce_1: &[u8],
+    extranonce_2: &[u8],
+) -> ii_bitcoin::DHash {
+    let mut coinbase = Vec::with_capacity(
+        notify.coin_base_1().len()
+            + extranonce_1.len()
+            + extranonce_2.len()
+            + notify.coin_base_2().len(),
+    );
+    coinbase.extend_from_slice(notify.coin_base_1());
+    coinbase.extend_from_slice(extranonce_1);
+    coinbase.extend_from_slice(extranonce_2);
+    coinbase.extend_from_slice(notify.coin_base_2());
+
+    notify.merkle_branch().iter().fold(
+        ii_bitcoin::DHash::hash(&coinbase),
+        |merkle_root, tx_hash| {
+            let mut node = Vec::with_capacity(64);
+            node.extend_from_slice(&merkle_root.into_inner());
+            node.extend_from_slice(tx_hash.as_ref());
+            ii_bitcoin::DHash::hash(&node)
+        },
+    )
+}
+
+#[derive(Debug, Clone)]
+pub struct StratumJob {
+    client: Weak<StratumClient>,
+    id: String,
+    extranonce_2: Vec<u8>,
+    version: u32,
+    version_mask: u32,
+    prev_hash: ii_bitcoin::DHash,
+    merkle_root: ii_bitcoin::DHash,
+    time: u32,
+    bits: u32,
+    target: ii_bitcoin::Target,
+    /// The job is valid until the client receives a notification with `clean_jobs` flag
+    clean_jobs_generation: u64,
+}
+
+impl StratumJob {
+    fn new(
+        client: &Arc<StratumClient>,
+        notify: &Notify,
+        extranonce: &mut Extranonce,
+        version_mask: u32,
+        target: ii_bitcoin::Target,
+    ) -> error::Result<Self> {
+        let extranonce_2 = extranonce.next_extranonce_2(version_mask);
+        let prev_hash = ii_bitcoin::DHash::from_slice(notify.prev_hash())
+            .map_err(|_| format!("Invalid prev hash in job '{}'", notify.job_id()))?;
+
+        Ok(Self {
+            client: Arc::downgrade(client),
+            id: notify.job_id().to_string(),
+            merkle_root: calculate_merkle_root(notify, &extranonce.extranonce_1, &extranonce_2),
+            extranonce_2,
+            version: notify.version(),
+            version_mask,
+            prev_hash,
+            time: notify.time(),
+            bits: notify.bits(),
+            target,
+            clean_jobs_generation: client.clean_jobs_generation.load(Ordering::Relaxed),
+        })
+    }
+}
+
+impl job::Bitcoin for StratumJob {
+    fn origin(&self) -> Weak<dyn node::Client> {
+        self.client.clone()
+    }
+
+    fn version(&self) -> u32 {
+        self.version
+    }
+
+    fn version_mask(&self) -> u32 {
+        self.version_mask
+    }
+
+    fn previous_hash(&self) -> &ii_bitcoin::DHash {
+        &self.prev_hash
+    }
+
+    fn merkle_root(&self) -> &ii_bitcoin::DHash {
+        &self.merkle_root
+    }
+
+    fn time(&self) -> u32 {
+        self.time
+    }
+
+    fn bits(&self) -> u32 {
+        self.bits
+    }
+
+    fn target(&self) -> ii_bitcoin::Target {
+        self.target
+    }
+
+    fn is_valid(&self) -> bool {
+        self.client.upgrade().map_or(false, |client| {
+            client.clean_jobs_generation.load(Ordering::Relaxed) == self.clean_jobs_generation
+        })
+    }
+}
+
+/// Requests sent to the upstream that are waiting for a response
+enum PendingRequest {
+    Configure,
+    Subscribe,
+    ExtranonceSubscribe,
+    Authorize,
+    Submit(Box<work::Solution>),
+}
+
+/// Helper task for `StratumClient` that implements Stratum V1 visitor which processes incoming
+/// messages from remote server. It also keeps track of all requests so that each response can be
+/// paired with the original request.
+struct StratumEventHandler {
+    client: Arc<StratumClient>,
+    user: String,
+    next_request_id: u32,
+    pending_requests: HashMap<u32, PendingRequest>,
+    /// Version bits that the upstream allowed for rolling
+    version_mask: u32,
+    extranonce: Option<Extranonce>,
+    /// Mining target for the next job that is to be solved
+    current_target: ii_bitcoin::Target,
+    /// Latest job notification, it is needed when the job arrives before the extranonce is known
+    last_notify: Option<Notify>,
+    authorized: bool,
+    /// Error that terminates the session, it is checked after processing of each message
+    error: Option<error::Error>,
+}
+
+impl StratumEventHandler {
+    fn new(client: Arc<StratumClient>, user: String) -> Self {
+        Self {
+            client,
+            user,
+            next_request_id: 0,
+            pending_requests: HashMap::new(),
+            version_mask: 0,
+            extranonce: None,
+            current_target: Default::default(),
+            last_notify: None,
+            authorized: false,
+            error: None,
+        }
+    }
+
+    /// Mining can start once the user is authorized and the extranonce space is known
+    fn is_ready(&self) -> bool {
+        self.authorized && self.extranonce.is_some()
+    }
+
+    /// Builds a request from `method` with a unique ID and registers it for pairing with the
+    /// response
+    fn build_request<M>(&mut self, method: M, pending_request: PendingRequest) -> Rpc
+    where
+        M: TryInto<RequestPayload, Error = ii_stratum::error::Error>,
+    {
+        let payload = method
+            .try_into()
+            .expect("BUG: cannot convert V1 method into a request");
+        let id = self.next_request_id;
+        self.next_request_id = self.next_request_id.wrapping_add(1);
+        self.pending_requests.insert(id, pending_request);
+
+        Request {
+            id: Some(id),
+            payload,
+        }
+        .into()
+    }
+
+    fn build_submit(&mut self, solution: work::Solution) -> Rpc {
+        let job: &StratumJob = solution.job();
+        let submit = Submit::new(
+            self.user.clone(),
+            JobId::from_str(&job.id),
+            &job.extranonce_2,
+            solution.time(),
+            solution.nonce(),
+            // Only the rolled bits are submitted as per BIP310
+            solution.version() & job.version_mask,
+        );
+        self.build_request(submit, PendingRequest::Submit(Box::new(solution)))
+    }
+
+    /// Some submitted solutions are still waiting for the response
+    fn has_pending_submits(&self) -> bool {
+        self.pending_requests.values().any(|request| match request {
+            PendingRequest::Submit(_) => true,
+            _ => false,
+        })
+    }
+
+    fn take_pending_request(&mut self, id: &MessageId) -> Option<PendingRequest> {
+        let request = id.and_then(|id| self.pending_requests.remove(&id));
+        if request.is_none() {
+            warn!("Stratum: response for unknown request id={:?}", id);
+        }
+        request
+    }
+
+    async fn update_job(&mut self, notify: &Notify) {
+        let extranonce = match self.extranonce.as_mut() {
+            Some(extranonce) => extranonce,
+            None => return,
+        };
+        match StratumJob::new(
+            &self.client,
+            notify,
+            extranonce,
+            self.version_mask,
+            self.current_target,
+        ) {
+            Ok(job) => {
+                let job = Arc::new(job);
+                self.client.update_last_job(job.clone()).await;
+                self.client.job_sender.lock().await.send(job);
+            }
+            Err(e) => warn!("Stratum: cannot build job: {}", e),
+        }
+    }
+
+    fn update_target(&mut self, difficulty: f32) {
+        // Fractional difficulty cannot be represented, it is rounded up so that every share meets
+        // the pool target. The value is saturated as casting of an out of range float is undefined.
+        let difficulty = f64::from(difficulty)
+            .max(1.0)
+            .ceil()
+            .min(f64::from(std::u32::MAX));
+        let new_target = ii_bitcoin::Target::from_pool_difficulty(difficulty as usize);
+        info!(
+            "Stratum: changing target to {} diff={}",
+            new_target,
+            new_target.get_difficulty()
+        );
+        self.current_target = new_target;
+    }
+
+    fn handle_configure_result(&mut self, payload: &StratumResult) {
+        let version_rolling = payload.0["version-rolling"].as_bool() == Some(true);
+        let mask = payload.0["version-rolling.mask"]
+            .as_str()
+            .and_then(|mask| u32::from_str_radix(mask, 16).ok());
+        match mask {
+            Some(mask) if version_rolling => {
+                self.version_mask = mask & ii_stratum::BIP320_N_VERSION_MASK;
+                info!("Stratum: version rolling mask={:08x}", self.version_mask);
+            }
+            _ => info!("Stratum: upstream doesn't support version rolling"),
+        }
+    }
+
+    async fn handle_subscribe_result(&mut self, payload: &StratumResult) {
+        match SubscribeResult::try_from(payload) {
+            Ok(subscribe_result) => {
+                self.extranonce = Some(Extranonce::new(
+                    subscribe_result.extra_nonce_1(),
+                    subscribe_result.extra_nonce_2_size(),
+                ));
+                // Start mining the job that has been received before the subscription finished
+                if let Some(notify) = self.last_notify.clone() {
+                    self.update_job(&notify).await;
+                }
+            }
+            Err(e) => self.error = Some(format!("Malformed subscribe result: {}", e).into()),
+        }
+    }
+
+    fn handle_authorize_result(&mut self, payload: &StratumResult) {
+        match BooleanResult::try_from(payload) {
+            Ok(BooleanResult(true)) => self.authorized = true,
+            _ => self.error = Some(format!("User '{}' has not been authorized", self.user).into()),
+        }
+    }
+
+    async fn account_response(&self, solution: &work::Solution, now: time::Instant) {
+        self.client
+            .client_stats
+            .share_latency
+            .account_response(solution.timestamp(), now)
+            .await;
+    }
+
+    async fn account_accepted(&self, solution: &work::Solution) {
+        info!(
+            "Stratum: accepted solution with nonce={:08x}",
+            solution.nonce()
+        );
+        let now = time::Instant::now();
+        self.account_response(solution, now).await;
+        stats::account_accepted(&solution.path(), solution.job_target(), now).await;
+    }
+
+    async fn account_rejected(&self, solution: &work::Solution, error: Option<&StratumError>) {
+        info!(
+            "Stratum: rejected solution with nonce={:08x} ({:?})",
+            solution.nonce(),
+            error
+        );
+        let now = time::Instant::now();
+        self.account_response(solution, now).await;
+        let reason = error.map_or(stats::RejectReason::Other, |StratumError(code, ..)| {
+            reject_reason(*code)
+        });
+        self.client.client_stats.reject_reasons.account(reason);
+        if reason == stats::RejectReason::Stale {
+            self.client
+                .client_stats
+                .stale
+                .account_solution(solution.job_target(), now)
+                .await;
+        } else {
+            stats::account_rejected(&solution.path(), solution.job_target(), now).await;
+        }
+    }
+}
+
+#[async_trait]
+impl Handler for StratumEventHandler {
+    async fn visit_stratum_result(&mut self, id: &MessageId, payload: &StratumResult) {
+        match self.take_pending_request(id) {
+            Some(PendingRequest::Configure) => self.handle_configure_result(payload),
+            Some(PendingRequest::Subscribe) => self.handle_subscribe_result(payload).await,
+            Some(PendingRequest::ExtranonceSubscribe) => {
+                info!("Stratum: extranonce subscription result: {:?}", payload)
+            }
+            Some(PendingRequest::Authorize) => self.handle_authorize_result(payload),
+            Some(PendingRequest::Submit(solution)) => match BooleanResult::try_from(payload) {
+                Ok(BooleanResult(true)) => self.account_accepted(&solution).await,
+                _ => self.account_rejected(&solution, None).await,
+            },
+            None => {}
+        }
+    }
+
+    async fn visit_stratum_error(&mut self, id: &MessageId, payload: &StratumError) {
+        match self.take_pending_request(id) {
+            Some(PendingRequest::Configure) => {
+                info!("Stratum: version rolling refused: {:?}", payload);
+            }
+            Some(PendingRequest::Subscribe) => {
+                self.error = Some(format!("Subscribe error: {:?}", payload).into())
+            }
+            Some(PendingRequest::ExtranonceSubscribe) => {
+                info!("Stratum: extranonce subscription refused: {:?}", payload)
+            }
+            Some(PendingRequest::Authorize) => {
+                self.error = Some(format!("Authorize error: {:?}", payload).into())
+            }
+            Some(PendingRequest::Submit(solution)) => {
+                self.account_rejected(&solution, Some(payload)).await
+            }
+            None => {}
+        }
+    }
+
+    async fn visit_set_extranonce(&mut self, _id: &MessageId, payload: &SetExtranonce) {
+        // The new extranonce is used starting with the next job
+        self.extranonce = Some(Extranonce::new(
+            payload.extra_nonce_1(),
+            payload.extra_nonce_2_size(),
+        ));
+    }
+
+    async fn visit_set_difficulty(&mut self, _id: &MessageId, payload: &SetDifficulty) {
+        self.update_target(payload.value());
+    }
+
+    async fn visit_notify(&mut self, _id: &MessageId, payload: &Notify) {
+        if payload.clean_jobs() {
+            // All previous jobs are invalid from now on
+            self.client
+                .clean_jobs_generation
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        self.last_notify = Some(payload.clone());
+        self.update_job(payload).await;
+    }
+
+    async fn visit_set_version_mask(&mut self, _id: &MessageId, payload: &SetVersionMask) {
+        self.version_mask = payload.value() & ii_stratum::BIP320_N_VERSION_MASK;
+        info!(
+            "Stratum: upstream changed version rolling mask={:08x}",
+            self.version_mask
+        );
+    }
+}
+
+#[derive(Debug, ClientNode)]
+pub struct StratumClient {
+    connection_details: StdMutex<ConnectionDetails>,
+    #[member_status]
+    status: sync::StatusMonitor,
+    #[member_client_stats]
+    client_stats: stats::BasicClient,
+    stop_sender: mpsc::Sender<()>,
+    stop_receiver: Mutex<mpsc::Receiver<()>>,
+    last_job: Mutex<Option<Arc<StratumJob>>>,
+    /// Incremented with each job notification that has `clean_jobs` flag set
+    clean_jobs_generation: AtomicU64,
+    job_sender: Mutex<job::Sender>,
+    solution_receiver: Mutex<job::SolutionReceiver>,
+}
+
+impl StratumClient {
+    const CONNECTION_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+    const EVENT_TIMEOUT: time::Duration = time::Duration::from_secs(60);
+    const SEND_TIMEOUT: time::Duration = time::Duration::from_secs(2);
+    /// Maximal time for submitting the remaining solutions when the client is being destroyed
+    const DRAIN_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+    pub fn new(connection_details: ConnectionDetails, solver: job::Solver) -> Self {
+        let (stop_sender, stop_receiver) = mpsc::channel(1);
+        Self {
+            connection_details: StdMutex::new(connection_details),
+            status: Default::default(),
+            client_stats: Default::default(),
+            stop_sender,
+            stop_receiver: Mutex::new(stop_receiver),
+            last_job: Mutex::new(None),
+            clean_jobs_generation: AtomicU64::new(0),
+            job_sender: Mutex::new(solver.job_sender),
+            solution_receiver: Mutex::new(solver.solution_receiver),
+        }
+    }
+
+    fn connection_details(&self) -> ConnectionDetails {
+        self.connection_details
+            .lock()
+            .expect("BUG: cannot lock connection details")
+            .clone()
+    }
+
+    async fn update_last_job(&self, job: Arc<StratumJob>) {
+        self.last_job.lock().await.replace(job);
+    }
+
+    async fn send_request(connection_tx: &mut FramedSink, request: Rpc) -> error::Result<()> {
+        let frame: v1::Frame = request.try_into()?;
+        match connection_tx.send(frame).timeout(Self::SEND_TIMEOUT).await {
+            Ok(Ok(_)) => Ok(()),
+            Ok(Err(e)) => Err(e.into()),
+            Err(_) => Err("Cannot send message due to timeout")?,
+        }
+    }
+
+    /// Unsupported or malformed messages are only logged as pools commonly send various
+    /// proprietary notifications
+    async fn handle_frame(
+        &self,
+        frame: v1::Frame,
+        event_handler: &mut StratumEventHandler,
+    ) -> error::Result<()> {
+        match build_message_from_frame(frame) {
+            Ok(event_msg) => event_msg.accept(event_handler).await,
+            Err(e) => info!("Stratum: ignoring message: {}", e),
+        }
+        match event_handler.error.take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    async fn connect(&self, connection_details: &ConnectionDetails) -> error::Result<v1::Framed> {
+        let socket_addr = connection_details
+            .get_host_and_port()
+            .to_socket_addrs()
+            .context("Invalid server address")?
+            // TODO: this is not correct as it always only attempts to ever connect to the first
+            //  IP address from the resolved set
+            .next()
+            .ok_or("Cannot resolve any IP address")?;
+
+        let connection = Connection::<v1::Framing>::connect(&socket_addr)
+            .await
+            .context("Cannot connect to stratum server")?;
+
+        Ok(connection.into_inner())
+    }
+
+    /// Negotiates version rolling, subscribes for jobs and authorizes the user. The requests are
+    /// pipelined and the session is initialized once the user is authorized and extranonce is
+    /// known.
+    async fn init_session(
+        &self,
+        connection_details: &ConnectionDetails,
+        connection_rx: &mut FramedStream,
+        connection_tx: &mut FramedSink,
+        event_handler: &mut StratumEventHandler,
+    ) -> error::Result<()> {
+        let mut configure = Configure::new();
+        configure.add_feature(VersionRolling::new(
+            ii_stratum::BIP320_N_VERSION_MASK,
+            ii_stratum::BIP320_N_VERSION_MAX_BITS,
+        ))?;
+        let subscribe = Subscribe(
+            Some(format!("{}/{}", crate::SIGNATURE, *version::STRING)),
+            None,
+            Some(connection_details.get_host_and_port()),
+            None,
+        );
+        let authorize = v1::messages::Authorize(
+            connection_details.user.clone(),
+            connection_details.password.clone().unwrap_or_default(),
+        );
+
+        let mut requests = vec![
+            event_handler.build_request(configure, PendingRequest::Configure),
+            event_handler.build_request(subscribe, PendingRequest::Subscribe),
+        ];
+        if connection_details.try_enable_xnsub() {
+            requests.push(
+                event_handler
+                    .build_request(ExtranonceSubscribe(), PendingRequest::ExtranonceSubscribe),
+            );
+        }
+        requests.push(event_handler.build_request(authorize, PendingRequest::Authorize));
+        for request in requests {
+            Self::send_request(connection_tx, request)
+                .await
+                .context("Cannot send stratum session request")?;
+        }
+
+        while !event_handler.is_ready() {
+            let frame = connection_rx
+                .next()
+                .await
+                .ok_or("The remote stratum server was disconnected prematurely")??;
+            self.handle_frame(frame, event_handler).await?;
+        }
+        Ok(())
+    }
+
+    /// Connect to the upstream and initialize the session. The session is provided only when the
+    /// client has successfully transitioned to the running state.
+    async fn start_session(
+        self: Arc<Self>,
+    ) -> Option<(FramedStream, FramedSink, StratumEventHandler)> {
+        let connection_details = self.connection_details();
+        let host_and_port = connection_details.get_host_and_port();
+        let user = connection_details.user.clone();
+
+        let framed_connection = match self
+            .connect(&connection_details)
+            .timeout(Self::CONNECTION_TIMEOUT)
+            .await
+        {
+            Ok(Ok(framed_connection)) => framed_connection,
+            Ok(Err(e)) => {
+                info!(
+                    "Failed to connect to {}, user={} {:?}",
+                    host_and_port, user, e
+                );
+                self.status.initiate_failing();
+                return None;
+            }
+            Err(_) => {
+                info!("Connection to {} timed out, user={}", host_and_port, user);
+                self.status.initiate_failing();
+                return None;
+            }
+        };
+
+        let (mut connection_tx, mut connection_rx) = framed_connection.split();
+        let mut event_handler = StratumEventHandler::new(self.clone(), user.clone());
+        match self
+            .init_session(
+                &connection_details,
+                &mut connection_rx,
+                &mut connection_tx,
+                &mut event_handler,
+            )
+            .timeout(Self::CONNECTION_TIMEOUT)
+            .await
+        {
+            Ok(Ok(())) => {
+                if self.status.initiate_running() {
+                    return Some((connection_rx, connection_tx, event_handler));
+                }
+            }
+            Ok(Err(e)) => {
+                info!(
+                    "Failed to initialize session at {}, user={} {:?}",
+                    host_and_port, user, e
+                );
+                self.status.initiate_failing();
+            }
+            Err(_) => {
+                info!(
+                    "Session initialization at {} timed out, user={}",
+                    host_and_port, user
+                );
+                self.status.initiate_failing();
+            }
+        }
+        None
+    }
+
+    async fn main_loop(
+        &self,
+        mut connection_rx: FramedStream,
+        mut connection_tx: FramedSink,
+        mut event_handler: StratumEventHandler,
+        stop_receiver: &mut mpsc::Receiver<()>,
+    ) -> error::Result<()> {
+        let mut solution_receiver = self.solution_receiver.lock().await;
+
+        while !self.status.is_shutting_down() {
+            select! {
+                frame = connection_rx.next().timeout(Self::EVENT_TIMEOUT).fuse() => {
+                    match frame {
+                        Ok(Some(frame)) => self.handle_frame(frame?, &mut event_handler).await?,
+                        Ok(None) | Err(_) => {
+                            Err("The remote stratum server was disconnected prematurely")?;
+                        }
+                    }
+                },
+                solution = solution_receiver.receive().fuse() => {
+                    match solution {
+                        Some(solution) => {
+                            let request = event_handler.build_submit(solution);
+                            Self::send_request(&mut connection_tx, request)
+                                .await
+                                .context("Cannot send submit to stratum server")?;
+                        }
+                        None => {
+                            // TODO: initiate Destroying and remove error
+                            Err("Standard application shutdown")?;
+                        }
+                    }
+                },
+                _ = stop_receiver.next() => break,
+            }
+        }
+
+        if self.status.is_destroying() {
+            match self
+                .drain(
+                    &mut connection_rx,
+                    &mut connection_tx,
+                    &mut event_handler,
+                    &mut solution_receiver,
+                )
+                .timeout(Self::DRAIN_TIMEOUT)
+                .await
+            {
+                Ok(result) => result?,
+                Err(_) => info!("Stratum: some submits haven't been answered before destroying"),
+            }
+        }
+        Ok(())
+    }
+
+    /// Submits solutions that have been found before the client started destroying and waits for
+    /// the responses to all pending submits
+    async fn drain(
+        &self,
+        connection_rx: &mut FramedStream,
+        connection_tx: &mut FramedSink,
+        event_handler: &mut StratumEventHandler,
+        solution_receiver: &mut job::SolutionReceiver,
+    ) -> error::Result<()> {
+        while let Some(solution) = solution_receiver.try_receive().await {
+            let request = event_handler.build_submit(solution);
+            Self::send_request(connection_tx, request)
+                .await
+                .context("Cannot send submit to stratum server")?;
+        }
+        while event_handler.has_pending_submits() {
+            match connection_rx.next().await {
+                Some(frame) => self.handle_frame(frame?, event_handler).await?,
+                None => Err("The remote stratum server was disconnected prematurely")?,
+            }
+        }
+        Ok(())
+    }
+
+    async fn run(self: Arc<Self>, stop_receiver: &mut mpsc::Receiver<()>) {
+        // The session can be stopped at any time during its initialization. Once it is running,
+        // the stop request is handled by the main loop.
+        let session = select! {
+            session = self.clone().start_session().fuse() => session,
+            _ = stop_receiver.next() => None,
+        };
+        if let Some((connection_rx, connection_tx, event_handler)) = session {
+            if let Err(e) = self
+                .main_loop(connection_rx, connection_tx, event_handler, stop_receiver)
+                .await
+            {
+                info!("Stratum session terminated: {:?}", e);
+                self.status.initiate_failing();
+            }
+        }
+    }
+
+    async fn main_task(self: Arc<Self>) {
+        // Flush all obsolete solutions from previous run
+        self.solution_receiver.lock().await.flush();
+
+        loop {
+            let mut stop_receiver = self.stop_receiver.lock().await;
+            self.clone().run(&mut stop_receiver).await;
+
+            // Invalidate current job to stop working on it
+            self.job_sender.lock().await.invalidate();
+            // Flush all unprocessed solutions to empty buffer
+            self.solution_receiver.lock().await.flush();
+
+            if self.status.can_stop() {
+                // NOTE: it is not safe to add here any code!
+                // The reason is that at this point the main task can be executed in parallel again
+                break;
+            }
+            // Restarting
+        }
+    }
+}
+
+#[async_trait]
+impl node::Client for StratumClient {
+    fn start(self: Arc<Self>) {
+        tokio::spawn(self.clone().main_task());
+    }
+
+    fn stop(&self) {
+        if let Err(e) = self.stop_sender.clone().try_send(()) {
+            assert!(e.is_full(), "BUG: Unexpected error in stop sender: {}", e);
+        }
+    }
+
+    async fn get_last_job(&self) -> Option<Arc<dyn job::Bitcoin>> {
+        self.last_job
+            .lock()
+            .await
+            .as_ref()
+            .map(|job| job.clone() as Arc<dyn job::Bitcoin>)
+    }
+
+    /// The new connection details are used for the next connection attempt
+    fn change_connection_details(&self, descriptor: &bosminer_config::ClientDescriptor) {
+        *self
+            .connection_details
+            .lock()
+            .expect("BUG: cannot lock connection details") =
+            ConnectionDetails::from_descriptor(descriptor);
+    }
+}
+
+impl fmt::Display for StratumClient {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let connection_details = self.connection_details();
+        write!(
+            f,
+            "{}://{}@{}",
+            ClientProtocol::SCHEME_STRATUM_V1,
+            connection_details.host,
+            connection_details.user
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::TEST_BLOCKS;
+    use crate::work;
+    use ii_bitcoin::FromHex;
+    use ii_stratum::test_utils::v1 as test_utils;
+    use ii_stratum::v1::rpc::{Response, ResponsePayload};
+    use ii_stratum::v1::HexBytes;
+    use std::str::FromStr;
+
+    fn build_event_handler() -> StratumEventHandler {
+        let (_, solution_receiver) = mpsc::unbounded();
+        let solver = job::Solver::new(Arc::new(work::EngineSender::new(None)), solution_receiver);
+        let client = StratumClient::new(
+            ConnectionDetails {
+                user: "user".to_string(),
+                password: None,
+                host: "localhost".to_string(),
+                port: 3333,
+                fragment: None,
+            },
+            solver,
+        );
+        StratumEventHandler::new(Arc::new(client), "user".to_string())
+    }
+
+    fn build_extranonce_1(extranonce_1: &str) -> v1::ExtraNonce1 {
+        v1::ExtraNonce1(HexBytes::try_from(extranonce_1).expect("BUG: invalid extranonce 1"))
+    }
+
+    fn build_result(result: &str) -> StratumResult {
+        let response = format!(r#"{{"id":0,"error":null,"result":{}}}"#, result);
+        match Rpc::from_str(&response) {
+            Ok(Rpc::Response(Response {
+                payload:
+                    ResponsePayload {
+                        result: Some(result),
+                        ..
+                    },
+                ..
+            })) => result,
+            _ => panic!("BUG: invalid result"),
+        }
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        let subscribe_result = test_utils::build_subscribe_ok_result();
+        let mut extranonce = Extranonce::new(
+            subscribe_result.extra_nonce_1(),
+            subscribe_result.extra_nonce_2_size(),
+        );
+        let extranonce_2 = extranonce.next_extranonce_2(ii_stratum::BIP320_N_VERSION_MASK);
+        let merkle_root = calculate_merkle_root(
+            &test_utils::build_mining_notify(),
+            &extranonce.extranonce_1,
+            &extranonce_2,
+        );
+
+        assert_eq!(
+            ii_bitcoin::DHash::from_hex(test_utils::MINING_NOTIFY_MERKLE_ROOT)
+                .expect("BUG: invalid merkle root"),
+            merkle_root
+        );
+    }
+
+    #[test]
+    fn test_next_extranonce_2() {
+        let mut extranonce = Extranonce::new(&build_extranonce_1("01020304"), 4);
+        // extranonce 2 is not rolled when the upstream allows version rolling
+        let version_mask = ii_stratum::BIP320_N_VERSION_MASK;
+        assert_eq!(extranonce.next_extranonce_2(version_mask), vec![0, 0, 0, 0]);
+        assert_eq!(extranonce.next_extranonce_2(version_mask), vec![0, 0, 0, 0]);
+
+        assert_eq!(extranonce.next_extranonce_2(0), vec![0, 0, 0, 0]);
+        assert_eq!(extranonce.next_extranonce_2(0), vec![1, 0, 0, 0]);
+
+        // the counter is truncated to the extranonce 2 size
+        let mut extranonce = Extranonce::new(&build_extranonce_1("01020304"), 2);
+        extranonce.next_extranonce_2 = 0x10203;
+        assert_eq!(extranonce.next_extranonce_2(0), vec![0x03, 0x02]);
+
+        // and padded with zeros when the extranonce 2 is larger than the counter
+        let mut extranonce = Extranonce::new(&build_extranonce_1("01020304"), 10);
+        extranonce.next_extranonce_2 = 0x10203;
+        assert_eq!(
+            extranonce.next_extranonce_2(0),
+            vec![0x03, 0x02, 0x01, 0, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_configure_result() {
+        let mut event_handler = build_event_handler();
+        event_handler.handle_configure_result(&build_result(
+            r#"{"version-rolling":true,"version-rolling.mask":"1fffe000"}"#,
+        ));
+        assert_eq!(event_handler.version_mask, 0x1fffe000);
+
+        // bits outside of BIP320 are never rolled
+        event_handler.handle_configure_result(&build_result(
+            r#"{"version-rolling":true,"version-rolling.mask":"ffffffff"}"#,
+        ));
+        assert_eq!(
+            event_handler.version_mask,
+            ii_stratum::BIP320_N_VERSION_MASK
+        );
+
+        let mut event_handler = build_event_handler();
+        event_handler.handle_configure_result(&build_result(
+            r#"{"version-rolling":false,"version-rolling.mask":"1fffe000"}"#,
+        ));
+        assert_eq!(event_handler.version_mask, 0);
+    }
+
+    #[tokio::test]
+    async fn test_set_extranonce() {
+        let mut event_handler = build_event_handler();
+        event_handler
+            .visit_set_extranonce(&None, &SetExtranonce(build_extranonce_1("cafebabe"), 8))
+            .await;
+
+        let extranonce = event_handler
+            .extranonce
+            .as_ref()
+            .expect("BUG: missing extranonce");
+        assert_eq!(extranonce.extranonce_1, vec![0xca, 0xfe, 0xba, 0xbe]);
+        assert_eq!(extranonce.extranonce_2_size, 8);
+    }
+
+    #[tokio::test]
+    async fn test_set_difficulty() {
+        let mut event_handler = build_event_handler();
+        event_handler
+            .visit_set_difficulty(&None, &test_utils::build_set_difficulty())
+            .await;
+        assert_eq!(
+            event_handler.current_target,
+            ii_bitcoin::Target::from_pool_difficulty(4)
+        );
+
+        // fractional difficulty is rounded up
+        event_handler
+            .visit_set_difficulty(&None, &SetDifficulty([1.5]))
+            .await;
+        assert_eq!(
+            event_handler.current_target,
+            ii_bitcoin::Target::from_pool_difficulty(2)
+        );
+
+        // difficulty below 1 is mined at difficulty 1
+        event_handler
+            .visit_set_difficulty(&None, &SetDifficulty([0.5]))
+            .await;
+        assert_eq!(
+            event_handler.current_target,
+            ii_bitcoin::Target::from_pool_difficulty(1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_clean_jobs() {
+        let mut event_handler = build_event_handler();
+        let subscribe_result = test_utils::build_subscribe_ok_result();
+        event_handler.extranonce = Some(Extranonce::new(
+            subscribe_result.extra_nonce_1(),
+            subscribe_result.extra_nonce_2_size(),
+        ));
+        let client = event_handler.client.clone();
+        let last_job = || async {
+            client
+                .last_job
+                .lock()
+                .await
+                .clone()
+                .expect("BUG: missing job")
+        };
+
+        let notify = test_utils::build_mining_notify();
+        assert!(!notify.clean_jobs());
+        event_handler.visit_notify(&None, &notify).await;
+        let first_job = last_job().await;
+        event_handler.visit_notify(&None, &notify).await;
+        let second_job = last_job().await;
+        assert!(job::Bitcoin::is_valid(&*first_job));
+        assert!(job::Bitcoin::is_valid(&*second_job));
+
+        let clean_jobs_notify =
+            match Rpc::from_str(&test_utils::MINING_NOTIFY_JSON.replace(",false]", ",true]")) {
+                Ok(Rpc::Request(request)) => {
+                    Notify::try_from(request).expect("BUG: cannot build notify")
+                }
+                _ => panic!("BUG: invalid notify"),
+            };
+        event_handler.visit_notify(&None, &clean_jobs_notify).await;
+        let third_job = last_job().await;
+        assert!(!job::Bitcoin::is_valid(&*first_job));
+        assert!(!job::Bitcoin::is_valid(&*second_job));
+        assert!(job::Bitcoin::is_valid(&*third_job));
+    }
+
+    #[tokio::test]
+    async fn test_submit_response_pairing() {
+        let mut event_handler = build_event_handler();
+        for id in 1..=2 {
+            event_handler.pending_requests.insert(
+                id,
+                PendingRequest::Submit(Box::new((&TEST_BLOCKS[0]).into())),
+            );
+        }
+        let client = event_handler.client.clone();
+        let client_stats = &client.client_stats;
+        let responses =
+            || async move { client_stats.share_latency.take_snapshot().await.responses };
+        let low_difficulty = || {
+            *client_stats
+                .reject_reasons
+                .get(stats::RejectReason::LowDifficulty)
+                .take_snapshot()
+        };
+
+        // The second submit is rejected first
+        event_handler
+            .visit_stratum_error(
+                &Some(2),
+                &StratumError(
+                    LOW_DIFFICULTY_SHARE_ERROR_CODE,
+                    "Low difficulty".to_string(),
+                    None,
+                ),
+            )
+            .await;
+        assert_eq!(responses().await, 1);
+        assert_eq!(low_difficulty(), 1);
+        assert!(event_handler.pending_requests.contains_key(&1));
+        assert!(!event_handler.pending_requests.contains_key(&2));
+        assert!(event_handler.has_pending_submits());
+
+        event_handler
+            .visit_stratum_result(&Some(1), &build_result("true"))
+            .await;
+        assert_eq!(responses().await, 2);
+        assert_eq!(low_difficulty(), 1);
+        assert!(!event_handler.has_pending_submits());
+
+        // Responses to unknown requests are ignored
+        event_handler
+            .visit_stratum_result(&Some(1), &build_result("true"))
+            .await;
+        assert_eq!(responses().await, 2);
+    }
+}
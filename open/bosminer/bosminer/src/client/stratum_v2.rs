@@ -25,6 +25,7 @@ pub mod telemetry;
 
 use ii_logging::macros::*;
 
+use crate::backend;
 use crate::error;
 use crate::hal;
 use crate::job;
@@ -43,6 +44,7 @@ use bosminer_macros::ClientNode;
 use async_trait::async_trait;
 use futures::channel::mpsc;
 use futures::lock::Mutex;
+use futures::stream::{SplitSink, SplitStream};
 use ii_async_compat::prelude::*;
 use ii_async_compat::select;
 
@@ -54,10 +56,12 @@ use std::sync::{Arc, Weak};
 use std::time;
 
 use ii_stratum::v2::messages::{
-    NewMiningJob, OpenStandardMiningChannel, OpenStandardMiningChannelError,
-    OpenStandardMiningChannelSuccess, SetNewPrevHash, SetTarget, SetupConnection,
-    SetupConnectionError, SetupConnectionSuccess, SubmitSharesError, SubmitSharesStandard,
-    SubmitSharesSuccess,
+    CloseChannel, NewExtendedMiningJob, NewMiningJob, OpenExtendedMiningChannel,
+    OpenExtendedMiningChannelError, OpenExtendedMiningChannelSuccess, OpenStandardMiningChannel,
+    OpenStandardMiningChannelError, OpenStandardMiningChannelSuccess, Reconnect,
+    SetExtranoncePrefix, SetGroupChannel, SetNewPrevHash, SetTarget, SetupConnection,
+    SetupConnectionError, SetupConnectionSuccess, SubmitSharesError, SubmitSharesExtended,
+    SubmitSharesStandard, SubmitSharesSuccess, UpdateChannel, UpdateChannelError,
 };
 use ii_stratum::v2::types::*;
 use ii_stratum::v2::{
@@ -71,6 +75,26 @@ use std::collections::HashMap;
 // TODO: move it to the stratum crate
 const VERSION_MASK: u32 = 0x1fffe000;
 
+/// Error code of shares that belong to a job that is no longer valid
+const STALE_SHARE_ERROR_CODE: &str = "stale-share";
+/// Error code of shares that have already been submitted
+const DUPLICATE_SHARE_ERROR_CODE: &str = "duplicate-share";
+/// Error code of shares that don't meet the channel target
+const LOW_DIFFICULTY_ERROR_CODE: &str = "difficulty-too-low";
+/// Error code of shares that reference an unknown job
+const INVALID_JOB_ERROR_CODE: &str = "invalid-job-id";
+
+/// Determines the reason of share rejection from the error code sent by the remote server
+pub(crate) fn reject_reason(code: &str) -> stats::RejectReason {
+    match code {
+        STALE_SHARE_ERROR_CODE => stats::RejectReason::Stale,
+        DUPLICATE_SHARE_ERROR_CODE => stats::RejectReason::Duplicate,
+        LOW_DIFFICULTY_ERROR_CODE => stats::RejectReason::LowDifficulty,
+        INVALID_JOB_ERROR_CODE => stats::RejectReason::InvalidJob,
+        _ => stats::RejectReason::Other,
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct ConnectionDetails {
     /// TODO temporary field that denotes the protocol, it will be replaced by a `Connector`
@@ -79,6 +103,7 @@ pub struct ConnectionDetails {
     pub user: String,
     pub host: String,
     pub port: u16,
+    pub fragment: Option<String>,
 }
 
 impl ConnectionDetails {
@@ -88,12 +113,138 @@ impl ConnectionDetails {
             user: descriptor.user.clone(),
             host: descriptor.host.clone(),
             port: descriptor.port(),
+            fragment: descriptor.fragment.clone(),
         }
     }
 
     fn get_host_and_port(&self) -> String {
         format!("{}:{}", self.host, self.port)
     }
+
+    /// Extended channel is requested by URL fragment, e.g.:
+    /// `stratum2+tcp+insecure://v2.stratum.slushpool.com#extended`
+    fn use_extended_channel(&self) -> bool {
+        self.fragment.as_deref() == Some("extended")
+    }
+}
+
+/// Extranonce space assigned to an extended channel by the upstream. The client rolls the part
+/// that follows `extranonce_prefix` on its own.
+#[derive(Debug, Clone)]
+struct ExtendedChannel {
+    extranonce_prefix: Vec<u8>,
+    extranonce_size: usize,
+    /// Value of the extranonce that will be assigned to the next job
+    next_extranonce: u64,
+}
+
+impl ExtendedChannel {
+    /// Size of the extranonce space that the client needs for rolling. It is small enough for
+    /// a translation proxy that keeps one byte of the common 4 byte V1 extranonce 2 for itself.
+    const MIN_EXTRANONCE_SIZE: u16 = 2;
+
+    fn new(success_msg: &OpenExtendedMiningChannelSuccess) -> Self {
+        Self {
+            extranonce_prefix: success_msg.extranonce_prefix.to_vec(),
+            extranonce_size: success_msg.extranonce_size as usize,
+            next_extranonce: 0,
+        }
+    }
+
+    /// Generate unique extranonce for a new job. The counter is serialized as little endian and
+    /// it is either truncated or padded with zeros to fit the negotiated extranonce size.
+    fn roll_extranonce(&mut self) -> Bytes0_32 {
+        let counter = self.next_extranonce.to_le_bytes();
+        self.next_extranonce = self.next_extranonce.wrapping_add(1);
+
+        let mut extranonce = vec![0; self.extranonce_size];
+        let len = self.extranonce_size.min(counter.len());
+        extranonce[..len].copy_from_slice(&counter[..len]);
+        Bytes0_32::from_vec(extranonce)
+    }
+}
+
+/// Mining channel negotiated with the upstream during initialization of the mining session
+#[derive(Debug, Clone, Default)]
+struct MiningChannel {
+    id: u32,
+    /// Group channel that this channel is a member of. Messages sent to the group channel apply
+    /// to this channel, too.
+    group_id: Option<u32>,
+    init_target: ii_bitcoin::Target,
+    /// Extranonce space of the channel when it has been opened as extended
+    extended: Option<ExtendedChannel>,
+    /// Nominal hashrate (in h/s) that has been reported to the upstream for this channel
+    nominal_hashrate: f32,
+    /// Generated work at the time of the last nominal hashrate report
+    work_snapshot: Option<WorkSnapshot>,
+}
+
+impl MiningChannel {
+    /// Checks whether a message for `channel_id` is intended for this channel
+    fn is_addressed_by(&self, channel_id: u32) -> bool {
+        channel_id == self.id || self.group_id == Some(channel_id)
+    }
+
+    /// Determines whether `nominal_hashrate` differs so much from the reported one that the
+    /// upstream should be notified
+    fn is_nominal_hashrate_changed(&self, nominal_hashrate: f32) -> bool {
+        let reported_hashrate = self.nominal_hashrate as f64;
+        if reported_hashrate <= 0.0 {
+            return nominal_hashrate > 0.0;
+        }
+        (nominal_hashrate as f64 - reported_hashrate).abs() / reported_hashrate
+            > StratumClient::NOMINAL_HASHRATE_CHANGE_THRESHOLD
+    }
+}
+
+/// Amount of work generated for a client and for the whole miner at some point in time
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct WorkSnapshot {
+    client: u64,
+    total: u64,
+}
+
+impl WorkSnapshot {
+    /// Part of the miner work that has been generated for the client since `previous` snapshot.
+    /// There is no share when the miner has not generated any work in the meantime.
+    fn client_share_since(&self, previous: &WorkSnapshot) -> Option<f64> {
+        let total = self.total.saturating_sub(previous.total);
+        if total == 0 {
+            return None;
+        }
+        let client = self.client.saturating_sub(previous.client);
+        Some((client as f64 / total as f64).min(1.0))
+    }
+}
+
+/// Build coinbase transaction from extended job `job_msg` and the full extranonce
+/// (`extranonce_prefix` + `extranonce`) and calculate merkle root of the block
+fn calculate_merkle_root(
+    job_msg: &NewExtendedMiningJob,
+    extranonce_prefix: &[u8],
+    extranonce: &[u8],
+) -> ii_bitcoin::DHash {
+    let mut coinbase = Vec::with_capacity(
+        job_msg.coinbase_tx_prefix.len()
+            + extranonce_prefix.len()
+            + extranonce.len()
+            + job_msg.coinbase_tx_suffix.len(),
+    );
+    coinbase.extend_from_slice(&job_msg.coinbase_tx_prefix);
+    coinbase.extend_from_slice(extranonce_prefix);
+    coinbase.extend_from_slice(extranonce);
+    coinbase.extend_from_slice(&job_msg.coinbase_tx_suffix);
+
+    job_msg.merkle_path.iter().fold(
+        ii_bitcoin::DHash::hash(&coinbase),
+        |merkle_root, tx_hash| {
+            let mut node = [0u8; 64];
+            node[..32].copy_from_slice(&merkle_root.into_inner());
+            node[32..].copy_from_slice(tx_hash.as_ref());
+            ii_bitcoin::DHash::hash(&node)
+        },
+    )
 }
 
 #[derive(Debug, Clone)]
@@ -107,6 +258,8 @@ pub struct StratumJob {
     time: u32,
     bits: u32,
     target: ii_bitcoin::Target,
+    /// Extranonce rolled by the client when the job belongs to an extended channel
+    extranonce: Option<Bytes0_32>,
 }
 
 impl StratumJob {
@@ -128,6 +281,30 @@ impl StratumJob {
             time: prevhash_msg.min_ntime,
             bits: prevhash_msg.nbits,
             target,
+            extranonce: None,
+        }
+    }
+
+    pub fn new_extended(
+        client: Arc<StratumClient>,
+        job_msg: &NewExtendedMiningJob,
+        prevhash_msg: &SetNewPrevHash,
+        target: ii_bitcoin::Target,
+        extranonce_prefix: &[u8],
+        extranonce: Bytes0_32,
+    ) -> Self {
+        Self {
+            client: Arc::downgrade(&client),
+            id: job_msg.job_id,
+            channel_id: job_msg.channel_id,
+            version: job_msg.version,
+            prev_hash: ii_bitcoin::DHash::from_slice(prevhash_msg.prev_hash.as_ref())
+                .expect("BUG: Stratum: incorrect size of prev hash"),
+            merkle_root: calculate_merkle_root(job_msg, extranonce_prefix, &extranonce),
+            time: prevhash_msg.min_ntime,
+            bits: prevhash_msg.nbits,
+            target,
+            extranonce: Some(extranonce),
         }
     }
 }
@@ -174,6 +351,13 @@ impl job::Bitcoin for StratumJob {
     }
 }
 
+/// Connection to the upstream along with the mining channel that has been opened on it
+type MiningSession = (
+    SplitStream<v2::Framed>,
+    Arc<Mutex<SplitSink<v2::Framed, <Framing as ii_wire::Framing>::Tx>>>,
+    MiningChannel,
+);
+
 /// Queue that contains pairs of solution and its assigned sequence number. It is our responsibility
 /// to keep the sequence number monotonic so that we as a stratum V2 client can easily process bulk
 /// acknowledgements. The sequence number type has been selected as u32 to match
@@ -185,18 +369,42 @@ type SolutionQueue = Mutex<VecDeque<(work::Solution, u32)>>;
 struct StratumEventHandler {
     client: Arc<StratumClient>,
     all_jobs: HashMap<u32, NewMiningJob>,
+    all_extended_jobs: HashMap<u32, NewExtendedMiningJob>,
     current_prevhash_msg: Option<SetNewPrevHash>,
     /// Mining target for the next job that is to be solved
     current_target: ii_bitcoin::Target,
+    channel: MiningChannel,
+    /// Redirection requested by the upstream, it is processed once the current frame is handled
+    reconnect: Option<Reconnect>,
 }
 
 impl StratumEventHandler {
-    pub fn new(client: Arc<StratumClient>, current_target: ii_bitcoin::Target) -> Self {
+    pub fn new(client: Arc<StratumClient>, channel: MiningChannel) -> Self {
         Self {
             client,
             all_jobs: Default::default(),
+            all_extended_jobs: Default::default(),
             current_prevhash_msg: None,
-            current_target,
+            current_target: channel.init_target,
+            channel,
+            reconnect: None,
+        }
+    }
+
+    /// Messages for other channels may be received when the upstream shares the connection
+    /// among multiple channels
+    fn is_for_other_channel(&self, channel_id: u32, msg_name: &str) -> bool {
+        if self.channel.is_addressed_by(channel_id) {
+            false
+        } else {
+            trace!(
+                "Stratum: ignoring {} for channel {} (own channel: {}, group: {:?})",
+                msg_name,
+                channel_id,
+                self.channel.id,
+                self.channel.group_id
+            );
+            true
         }
     }
 
@@ -216,6 +424,34 @@ impl StratumEventHandler {
         self.client.job_sender.lock().await.send(job);
     }
 
+    /// Convert new extended mining job message into StratumJob with a freshly rolled extranonce
+    /// and send it down the line for solving.
+    async fn update_extended_job(&mut self, job_msg: &NewExtendedMiningJob) {
+        let extended_channel = match self.channel.extended.as_mut() {
+            Some(extended_channel) => extended_channel,
+            None => {
+                warn!(
+                    "Stratum: ignoring extended job {} on a standard channel",
+                    job_msg.job_id
+                );
+                return;
+            }
+        };
+        let extranonce = extended_channel.roll_extranonce();
+        let job = Arc::new(StratumJob::new_extended(
+            self.client.clone(),
+            job_msg,
+            self.current_prevhash_msg
+                .as_ref()
+                .expect("TODO: no prevhash"),
+            self.current_target,
+            &extended_channel.extranonce_prefix,
+            extranonce,
+        ));
+        self.client.update_last_job(job.clone()).await;
+        self.client.job_sender.lock().await.send(job);
+    }
+
     fn update_target(&mut self, value: Uint256Bytes) {
         let new_target: ii_bitcoin::Target = value.into();
         info!(
@@ -229,16 +465,17 @@ impl StratumEventHandler {
     async fn process_accepted_shares(&self, success_msg: &SubmitSharesSuccess) {
         let now = std::time::Instant::now();
         while let Some((solution, seq_num)) = self.client.solutions.lock().await.pop_front() {
+            self.client
+                .client_stats
+                .share_latency
+                .account_response(solution.timestamp(), now)
+                .await;
             info!(
                 "Stratum: accepted solution #{} with nonce={:08x}",
                 seq_num,
                 solution.nonce()
             );
-            self.client
-                .client_stats
-                .accepted
-                .account_solution(&solution.job_target(), now)
-                .await;
+            stats::account_accepted(&solution.path(), &solution.job_target(), now).await;
             if success_msg.last_seq_num == seq_num {
                 // all accepted solutions have been found
                 return;
@@ -253,17 +490,28 @@ impl StratumEventHandler {
     async fn process_rejected_shares(&self, error_msg: &SubmitSharesError) {
         let now = std::time::Instant::now();
         while let Some((solution, seq_num)) = self.client.solutions.lock().await.pop_front() {
+            self.client
+                .client_stats
+                .share_latency
+                .account_response(solution.timestamp(), now)
+                .await;
             if error_msg.seq_num == seq_num {
                 info!(
                     "Stratum: rejected solution #{} with nonce={:08x}!",
                     seq_num,
                     solution.nonce()
                 );
-                self.client
-                    .client_stats
-                    .rejected
-                    .account_solution(&solution.job_target(), now)
-                    .await;
+                let reason = reject_reason(&error_msg.code.to_string());
+                self.client.client_stats.reject_reasons.account(reason);
+                if reason == stats::RejectReason::Stale {
+                    self.client
+                        .client_stats
+                        .stale
+                        .account_solution(&solution.job_target(), now)
+                        .await;
+                } else {
+                    stats::account_rejected(&solution.path(), &solution.job_target(), now).await;
+                }
                 // the rejected solution has been found
                 return;
             } else {
@@ -274,11 +522,7 @@ impl StratumEventHandler {
                     seq_num,
                     solution.nonce()
                 );
-                self.client
-                    .client_stats
-                    .accepted
-                    .account_solution(&solution.job_target(), now)
-                    .await;
+                stats::account_accepted(&solution.path(), &solution.job_target(), now).await;
                 warn!(
                     "Stratum: the solution #{} precedes rejected solution #{}!",
                     seq_num, error_msg.seq_num
@@ -308,6 +552,9 @@ impl Handler for StratumEventHandler {
     //      - flush all other jobs
 
     async fn visit_new_mining_job(&mut self, _header: &Header, job_msg: &NewMiningJob) {
+        if self.is_for_other_channel(job_msg.channel_id, "NewMiningJob") {
+            return;
+        }
         // all jobs since last `prevmsg` have to be stored in job table
         self.all_jobs.insert(job_msg.job_id, job_msg.clone());
         // TODO: close connection when maximal capacity of `all_jobs` has been reached
@@ -324,9 +571,46 @@ impl Handler for StratumEventHandler {
         }
     }
 
+    async fn visit_new_extended_mining_job(
+        &mut self,
+        _header: &Header,
+        job_msg: &NewExtendedMiningJob,
+    ) {
+        if self.is_for_other_channel(job_msg.channel_id, "NewExtendedMiningJob") {
+            return;
+        }
+        // Extended jobs follow the same rules as standard jobs, see `visit_new_mining_job`
+        self.all_extended_jobs
+            .insert(job_msg.job_id, job_msg.clone());
+
+        if !job_msg.future_job && self.current_prevhash_msg.is_some() {
+            self.update_extended_job(job_msg).await;
+        }
+    }
+
     async fn visit_set_new_prev_hash(&mut self, _header: &Header, prevhash_msg: &SetNewPrevHash) {
+        // prevhash is typically broadcast to the whole group channel
+        if self.is_for_other_channel(prevhash_msg.channel_id, "SetNewPrevHash") {
+            return;
+        }
         self.current_prevhash_msg.replace(prevhash_msg.clone());
 
+        // extended channel has its own job table
+        if self.channel.extended.is_some() {
+            let mut future_job_msg = self
+                .all_extended_jobs
+                .remove(&prevhash_msg.job_id)
+                .expect("TODO: requested job ID not found");
+            // all other jobs are now invalid
+            self.all_extended_jobs.clear();
+            future_job_msg.future_job = false;
+            self.all_extended_jobs
+                .insert(future_job_msg.job_id, future_job_msg.clone());
+
+            self.update_extended_job(&future_job_msg).await;
+            return;
+        }
+
         // find the future job with ID referenced in prevhash_msg
         let (_, mut future_job_msg) = self
             .all_jobs
@@ -346,9 +630,62 @@ impl Handler for StratumEventHandler {
     }
 
     async fn visit_set_target(&mut self, _header: &Header, target_msg: &SetTarget) {
+        if self.is_for_other_channel(target_msg.channel_id, "SetTarget") {
+            return;
+        }
         self.update_target(target_msg.max_target);
     }
 
+    async fn visit_reconnect(&mut self, _header: &Header, reconnect_msg: &Reconnect) {
+        self.reconnect.replace(reconnect_msg.clone());
+    }
+
+    async fn visit_set_group_channel(&mut self, _header: &Header, group_msg: &SetGroupChannel) {
+        if group_msg.channel_ids.contains(&self.channel.id) {
+            info!(
+                "Stratum: channel {} moved to group channel {}",
+                self.channel.id, group_msg.group_channel_id
+            );
+            self.channel.group_id = Some(group_msg.group_channel_id);
+        }
+    }
+
+    async fn visit_set_extranonce_prefix(
+        &mut self,
+        _header: &Header,
+        prefix_msg: &SetExtranoncePrefix,
+    ) {
+        if self.is_for_other_channel(prefix_msg.channel_id, "SetExtranoncePrefix") {
+            return;
+        }
+        match self.channel.extended.as_mut() {
+            // The new prefix is used starting with the next job
+            Some(extended_channel) => {
+                info!(
+                    "Stratum: changing extranonce prefix to {:x?}",
+                    prefix_msg.extranonce_prefix
+                );
+                extended_channel.extranonce_prefix = prefix_msg.extranonce_prefix.to_vec();
+            }
+            None => warn!("Stratum: ignoring extranonce prefix for a standard channel"),
+        }
+    }
+
+    async fn visit_update_channel_error(
+        &mut self,
+        _header: &Header,
+        error_msg: &UpdateChannelError,
+    ) {
+        if self.is_for_other_channel(error_msg.channel_id, "UpdateChannelError") {
+            return;
+        }
+        warn!(
+            "Stratum: upstream rejected nominal hashrate update of channel {}: {}",
+            error_msg.channel_id,
+            error_msg.code.to_string()
+        );
+    }
+
     async fn visit_submit_shares_success(
         &mut self,
         _header: &Header,
@@ -423,30 +760,50 @@ where
         }
     }
 
+    /// Store solution with sequence number for future server acknowledge
+    async fn push_solution(&self, solution: work::Solution, seq_num: u32) {
+        self.client
+            .solutions
+            .lock()
+            .await
+            .push_back((solution, seq_num));
+    }
+
     async fn process_solution(&mut self, solution: work::Solution) -> error::Result<()> {
         let job: &StratumJob = solution.job();
 
         let seq_num = self.seq_num;
         self.seq_num = self.seq_num.wrapping_add(1);
 
-        let share_msg = SubmitSharesStandard {
-            channel_id: job.channel_id,
-            seq_num,
-            job_id: job.id,
-            nonce: solution.nonce(),
-            ntime: solution.time(),
-            version: solution.version(),
+        let send_result = match job.extranonce.clone() {
+            Some(extranonce) => {
+                let share_msg = SubmitSharesExtended {
+                    channel_id: job.channel_id,
+                    seq_num,
+                    job_id: job.id,
+                    nonce: solution.nonce(),
+                    ntime: solution.time(),
+                    version: solution.version(),
+                    extranonce,
+                };
+                self.push_solution(solution, seq_num).await;
+                StratumClient::send_msg(&self.connection_tx, share_msg).await
+            }
+            None => {
+                let share_msg = SubmitSharesStandard {
+                    channel_id: job.channel_id,
+                    seq_num,
+                    job_id: job.id,
+                    nonce: solution.nonce(),
+                    ntime: solution.time(),
+                    version: solution.version(),
+                };
+                self.push_solution(solution, seq_num).await;
+                StratumClient::send_msg(&self.connection_tx, share_msg).await
+            }
         };
-        // store solution with sequence number for future server acknowledge
-        self.client
-            .solutions
-            .lock()
-            .await
-            .push_back((solution, seq_num));
         // send solutions back to the stratum server
-        StratumClient::send_msg(&self.connection_tx, share_msg)
-            .await
-            .context("Cannot send submit to stratum server")?;
+        send_result.context("Cannot send submit to stratum server")?;
         // the response is handled in a separate task
         Ok(())
     }
@@ -454,7 +811,7 @@ where
 
 struct StratumConnectionHandler {
     client: Arc<StratumClient>,
-    init_target: ii_bitcoin::Target,
+    channel: MiningChannel,
     status: Option<error::Result<()>>,
 }
 
@@ -462,7 +819,7 @@ impl StratumConnectionHandler {
     pub fn new(client: Arc<StratumClient>) -> Self {
         Self {
             client,
-            init_target: Default::default(),
+            channel: Default::default(),
             status: None,
         }
     }
@@ -511,23 +868,41 @@ impl StratumConnectionHandler {
         R: FrameStream,
         S: FrameSink,
     {
-        let channel_msg = OpenStandardMiningChannel {
-            req_id: 10, // TODO? come up with request ID sequencing
-            user: self
-                .client
-                .connection_details()
-                .user
-                .clone()
-                .try_into()
-                .expect("BUG: cannot convert 'OpenStandardMiningChannel::user'"),
-            nominal_hashrate: 1e9,
-            // Maximum bitcoin target is 0xffff << 208 (= difficulty 1 share)
-            max_target: ii_bitcoin::Target::default().into(),
-        };
-
-        StratumClient::send_msg(&connection_tx, channel_msg)
+        let connection_details = self.client.connection_details();
+        let user: Str1_255 = connection_details
+            .user
+            .clone()
+            .try_into()
+            .expect("BUG: cannot convert 'OpenMiningChannel::user'");
+        // The share of the client is not known before it starts receiving work so the hashrate of
+        // the whole miner is announced and it is corrected later with `UpdateChannel`
+        let nominal_hashrate = self
+            .client
+            .get_miner_nominal_hashrate()
             .await
-            .context("Cannot send stratum open channel")?;
+            .unwrap_or(StratumClient::DEFAULT_NOMINAL_HASHRATE);
+        // Maximum bitcoin target is 0xffff << 208 (= difficulty 1 share)
+        let max_target: Uint256Bytes = ii_bitcoin::Target::default().into();
+
+        if connection_details.use_extended_channel() {
+            let channel_msg = OpenExtendedMiningChannel {
+                req_id: 10, // TODO? come up with request ID sequencing
+                user,
+                nominal_hashrate,
+                max_target,
+                min_extranonce_size: ExtendedChannel::MIN_EXTRANONCE_SIZE,
+            };
+            StratumClient::send_msg(&connection_tx, channel_msg).await
+        } else {
+            let channel_msg = OpenStandardMiningChannel {
+                req_id: 10, // TODO? come up with request ID sequencing
+                user,
+                nominal_hashrate,
+                max_target,
+            };
+            StratumClient::send_msg(&connection_tx, channel_msg).await
+        }
+        .context("Cannot send stratum open channel")?;
         let frame = connection_rx
             .next()
             .await
@@ -536,6 +911,8 @@ impl StratumConnectionHandler {
 
         self.status = None;
         response_msg.accept(self).await;
+        self.channel.nominal_hashrate = nominal_hashrate;
+        self.channel.work_snapshot = self.client.take_work_snapshot().await;
         self.status
             .take()
             .unwrap_or(Err("Unexpected response for stratum open channel".into()))
@@ -569,12 +946,12 @@ impl StratumConnectionHandler {
         Ok(client_framed_stream)
     }
 
-    /// Starts mining session and provides the initial target negotiated by the upstream endpoint
+    /// Starts mining session and provides the channel negotiated by the upstream endpoint
     async fn init_mining_session<R, S>(
         mut self,
         connection_rx: &mut R,
         connection_tx: Arc<Mutex<S>>,
-    ) -> error::Result<ii_bitcoin::Target>
+    ) -> error::Result<MiningChannel>
     where
         R: FrameStream,
         S: FrameSink,
@@ -586,7 +963,7 @@ impl StratumConnectionHandler {
             .await
             .context("Cannot open stratum channel")?;
 
-        Ok(self.init_target)
+        Ok(self.channel)
     }
 }
 
@@ -614,7 +991,19 @@ impl Handler for StratumConnectionHandler {
         _header: &Header,
         success_msg: &OpenStandardMiningChannelSuccess,
     ) {
-        self.init_target = success_msg.target.into();
+        self.channel = MiningChannel {
+            id: success_msg.channel_id,
+            // Group channel ID 0 means that the channel is not a member of any group
+            group_id: if success_msg.group_channel_id != 0 {
+                Some(success_msg.group_channel_id)
+            } else {
+                None
+            },
+            init_target: success_msg.target.into(),
+            extended: None,
+            nominal_hashrate: Default::default(),
+            work_snapshot: None,
+        };
         self.status = Ok(()).into();
     }
 
@@ -626,6 +1015,44 @@ impl Handler for StratumConnectionHandler {
         self.status =
             Err(format!("Open channel error: {}", error_msg.code.to_string()).into()).into();
     }
+
+    async fn visit_open_extended_mining_channel_success(
+        &mut self,
+        _header: &Header,
+        success_msg: &OpenExtendedMiningChannelSuccess,
+    ) {
+        if success_msg.extranonce_size < ExtendedChannel::MIN_EXTRANONCE_SIZE {
+            self.status = Err(format!(
+                "Open extended channel error: insufficient extranonce size {}",
+                success_msg.extranonce_size
+            )
+            .into())
+            .into();
+            return;
+        }
+        self.channel = MiningChannel {
+            id: success_msg.channel_id,
+            group_id: None,
+            init_target: success_msg.target.into(),
+            extended: Some(ExtendedChannel::new(success_msg)),
+            nominal_hashrate: Default::default(),
+            work_snapshot: None,
+        };
+        self.status = Ok(()).into();
+    }
+
+    async fn visit_open_extended_mining_channel_error(
+        &mut self,
+        _header: &Header,
+        error_msg: &OpenExtendedMiningChannelError,
+    ) {
+        self.status = Err(format!(
+            "Open extended channel error: {}",
+            error_msg.code.to_string()
+        )
+        .into())
+        .into();
+    }
 }
 
 /// Messages to control the extension channel
@@ -652,7 +1079,16 @@ pub type ExtensionChannelFromStratumSender = mpsc::Sender<ExtensionChannelMsg>;
 #[derive(Debug, ClientNode)]
 pub struct StratumClient {
     connection_details: Arc<StdMutex<ConnectionDetails>>,
+    /// Descriptor shared with the client handle. It is updated when the upstream redirects the
+    /// client so that the API reports the endpoint that is actually used.
+    descriptor: Arc<Mutex<ClientDescriptor>>,
+    /// Descriptor configured by the user that has been replaced by a redirection
+    configured_descriptor: StdMutex<Option<ClientDescriptor>>,
     backend_info: Option<hal::BackendInfo>,
+    /// Source of the nominal hashrate of the whole miner. The client reports only its share to the
+    /// upstream.
+    // NOTE: the registry is owned by the miner core, keep only weak reference to it
+    backend_registry: Weak<backend::Registry>,
     #[member_status]
     status: sync::StatusMonitor,
     #[member_client_stats]
@@ -676,6 +1112,16 @@ impl StratumClient {
     const CONNECTION_TIMEOUT: time::Duration = time::Duration::from_secs(5);
     const EVENT_TIMEOUT: time::Duration = time::Duration::from_secs(150);
     const SEND_TIMEOUT: time::Duration = time::Duration::from_secs(2);
+    /// Maximal time for submitting the remaining solutions when the client is being destroyed
+    const DRAIN_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+    /// Period of checking whether the nominal hashrate of the miner has changed
+    const NOMINAL_HASHRATE_CHECK_INTERVAL: time::Duration = time::Duration::from_secs(60);
+    /// Relative change of nominal hashrate that is reported to the upstream with `UpdateChannel`
+    const NOMINAL_HASHRATE_CHANGE_THRESHOLD: f64 = 0.1;
+    /// Nominal hashrate announced when opening a channel before the backend is able to provide it
+    const DEFAULT_NOMINAL_HASHRATE: f32 = 1e9;
+    /// Reason code of `CloseChannel` sent when the client is stopped (e.g. the pool is disabled)
+    const CLOSE_CHANNEL_REASON: &'static str = "client-stopped";
 
     /// Start a task that plays a dummy role for both communication channels that the stratum
     /// client uses to talk to stratum extension.
@@ -715,7 +1161,9 @@ impl StratumClient {
 
     pub fn new(
         connection_details: ConnectionDetails,
+        descriptor: Arc<Mutex<ClientDescriptor>>,
         backend_info: Option<hal::BackendInfo>,
+        backend_registry: Weak<backend::Registry>,
         solver: job::Solver,
         channel: Option<(
             ExtensionChannelToStratumReceiver,
@@ -738,7 +1186,10 @@ impl StratumClient {
 
         Self {
             connection_details: Arc::new(StdMutex::new(connection_details)),
+            descriptor,
+            configured_descriptor: StdMutex::new(None),
             backend_info,
+            backend_registry,
             status: Default::default(),
             client_stats: Default::default(),
             stop_sender: stop_sender,
@@ -759,10 +1210,151 @@ impl StratumClient {
             .clone()
     }
 
+    fn set_connection_details(&self, descriptor: &ClientDescriptor) {
+        *self
+            .connection_details
+            .lock()
+            .expect("BUG: cannot lock connection details") =
+            ConnectionDetails::from_descriptor(descriptor);
+    }
+
     async fn update_last_job(&self, job: Arc<StratumJob>) {
         self.last_job.lock().await.replace(job);
     }
 
+    /// Switch the client to the endpoint requested by the upstream and restart the session. The
+    /// configured descriptor is put aside so that it can be restored once the redirected session
+    /// ends.
+    async fn redirect(&self, reconnect_msg: &Reconnect) {
+        // NOTE: Keep descriptor locked to synchronize descriptor changes with the client handle
+        let mut descriptor = self.descriptor.lock().await;
+
+        let mut redirected_descriptor = descriptor.clone();
+        let new_host = reconnect_msg.new_host.to_string();
+        if !new_host.is_empty() {
+            redirected_descriptor.host = new_host;
+        }
+        redirected_descriptor.port = Some(reconnect_msg.new_port);
+        info!(
+            "Stratum: upstream redirects client from {} to {}",
+            descriptor.get_url(true, true, false),
+            redirected_descriptor.get_url(true, true, false)
+        );
+
+        self.configured_descriptor
+            .lock()
+            .expect("BUG: cannot lock configured descriptor")
+            .get_or_insert_with(|| descriptor.clone());
+        self.set_connection_details(&redirected_descriptor);
+        *descriptor = redirected_descriptor;
+
+        // Stopping followed by starting results in restarting of the main task
+        if self.status.initiate_stopping() {
+            self.status.initiate_starting();
+        }
+    }
+
+    /// Let the upstream know that the channel is not going to be used anymore so that it can
+    /// release it immediately
+    async fn close_channel<S>(&self, connection_tx: &Arc<Mutex<S>>, channel: &MiningChannel)
+    where
+        S: FrameSink,
+    {
+        let close_msg = CloseChannel {
+            channel_id: channel.id,
+            reason_code: Self::CLOSE_CHANNEL_REASON
+                .try_into()
+                .expect("BUG: cannot convert 'CloseChannel::reason_code'"),
+        };
+        if let Err(e) = Self::send_msg(connection_tx, close_msg).await {
+            info!("Stratum: cannot close channel {}: {:?}", channel.id, e);
+        }
+    }
+
+    /// Fall back to the configured endpoint when the redirected session is over
+    async fn restore_configured_descriptor(&self) {
+        let mut descriptor = self.descriptor.lock().await;
+
+        let configured_descriptor = self
+            .configured_descriptor
+            .lock()
+            .expect("BUG: cannot lock configured descriptor")
+            .take();
+        if let Some(configured_descriptor) = configured_descriptor {
+            info!(
+                "Stratum: redirected session to {} ended, falling back to {}",
+                descriptor.get_url(true, true, false),
+                configured_descriptor.get_url(true, true, false)
+            );
+            self.set_connection_details(&configured_descriptor);
+            *descriptor = configured_descriptor;
+        }
+    }
+
+    /// Nominal hashrate of the whole miner in h/s
+    async fn get_miner_nominal_hashrate(&self) -> Option<f32> {
+        self.backend_registry
+            .upgrade()?
+            .get_nominal_hashrate()
+            .await
+            .map(|nominal_hashrate| nominal_hashrate.into_hashes().into_f64() as f32)
+    }
+
+    /// Current amount of work generated for this client and for the whole miner
+    async fn take_work_snapshot(&self) -> Option<WorkSnapshot> {
+        let backend_registry = self.backend_registry.upgrade()?;
+        Some(WorkSnapshot {
+            client: *self.client_stats.generated_work.take_snapshot(),
+            total: backend_registry.get_generated_work().await,
+        })
+    }
+
+    /// Notify the upstream when the share of the miner nominal hashrate that belongs to this
+    /// client has changed meaningfully since the last report (e.g. after the backend has changed
+    /// frequency of hash chains or the scheduler has moved work between clients)
+    async fn update_nominal_hashrate<S>(
+        &self,
+        connection_tx: &Arc<Mutex<S>>,
+        channel: &mut MiningChannel,
+    ) -> error::Result<()>
+    where
+        S: FrameSink,
+    {
+        let work_snapshot = match self.take_work_snapshot().await {
+            Some(work_snapshot) => work_snapshot,
+            None => return Ok(()),
+        };
+        let client_share = match channel
+            .work_snapshot
+            .replace(work_snapshot)
+            .and_then(|previous| work_snapshot.client_share_since(&previous))
+        {
+            Some(client_share) => client_share,
+            None => return Ok(()),
+        };
+        let nominal_hashrate = match self.get_miner_nominal_hashrate().await {
+            Some(nominal_hashrate) => (nominal_hashrate as f64 * client_share) as f32,
+            None => return Ok(()),
+        };
+        if !channel.is_nominal_hashrate_changed(nominal_hashrate) {
+            return Ok(());
+        }
+        info!(
+            "Stratum: updating nominal hashrate of channel {} from {} h/s to {} h/s",
+            channel.id, channel.nominal_hashrate, nominal_hashrate
+        );
+        let update_msg = UpdateChannel {
+            channel_id: channel.id,
+            nominal_hashrate,
+            max_target: ii_bitcoin::Target::default().into(),
+        };
+        Self::send_msg(connection_tx, update_msg)
+            .await
+            .context("Cannot send update channel to stratum server")?;
+        channel.nominal_hashrate = nominal_hashrate;
+        Ok(())
+    }
+
     /// Send a message down a specified Tx Sink
     /// TODO: temporarily, this became an associated method so that we don't have to generalize
     ///  with type parameters the full StratumClient struct. Once this is done, we will use the
@@ -830,6 +1422,7 @@ impl StratumClient {
         mut connection_rx: R,
         connection_tx: Arc<Mutex<S>>,
         mut event_handler: StratumEventHandler,
+        stop_receiver: &mut mpsc::Receiver<()>,
     ) -> error::Result<()>
     where
         R: FrameStream,
@@ -852,11 +1445,18 @@ impl StratumClient {
                 })
                 .expect("BUG: stratum extension channel not available for start");
         }
+        let mut next_hashrate_check = time::Instant::now() + Self::NOMINAL_HASHRATE_CHECK_INTERVAL;
         while !self.status.is_shutting_down() {
             select! {
                 frame = connection_rx.next().timeout(Self::EVENT_TIMEOUT).fuse() => {
                     match frame {
-                        Ok(Some(frame)) => self.handle_frame(frame?, &mut event_handler).await?,
+                        Ok(Some(frame)) => {
+                            self.handle_frame(frame?, &mut event_handler).await?;
+                            if let Some(reconnect_msg) = event_handler.reconnect.take() {
+                                self.redirect(&reconnect_msg).await;
+                                return Ok(());
+                            }
+                        }
                         Ok(None) | Err(_) => {
                             Err("The remote stratum server was disconnected prematurely")?;
                         }
@@ -877,6 +1477,57 @@ impl StratumClient {
                         }
                     }
                 }
+                _ = stop_receiver.next() => break,
+                _ = tokio::time::delay_until(next_hashrate_check.into()).fuse() => {
+                    self.update_nominal_hashrate(&connection_tx, &mut event_handler.channel)
+                        .await?;
+                    next_hashrate_check =
+                        time::Instant::now() + Self::NOMINAL_HASHRATE_CHECK_INTERVAL;
+                }
+            }
+        }
+
+        if self.status.is_destroying() {
+            match self
+                .drain(
+                    &mut connection_rx,
+                    &mut event_handler,
+                    &mut solution_handler,
+                    &mut solution_receiver,
+                )
+                .timeout(Self::DRAIN_TIMEOUT)
+                .await
+            {
+                Ok(result) => result?,
+                Err(_) => info!("Stratum: some submits haven't been answered before destroying"),
+            }
+        }
+        // Close the channel explicitly instead of just dropping the connection
+        self.close_channel(&connection_tx, &event_handler.channel)
+            .await;
+        Ok(())
+    }
+
+    /// Submits solutions that have been found before the client started destroying and waits for
+    /// the responses to all pending submits
+    async fn drain<R, S>(
+        &self,
+        connection_rx: &mut R,
+        event_handler: &mut StratumEventHandler,
+        solution_handler: &mut StratumSolutionHandler<S>,
+        solution_receiver: &mut job::SolutionReceiver,
+    ) -> error::Result<()>
+    where
+        R: FrameStream,
+        S: FrameSink,
+    {
+        while let Some(solution) = solution_receiver.try_receive().await {
+            solution_handler.process_solution(solution).await?;
+        }
+        while !self.solutions.lock().await.is_empty() {
+            match connection_rx.next().await {
+                Some(frame) => self.handle_frame(frame?, event_handler).await?,
+                None => Err("The remote stratum server was disconnected prematurely")?,
             }
         }
         Ok(())
@@ -886,24 +1537,27 @@ impl StratumClient {
         self: Arc<Self>,
         connection_rx: R,
         connection_tx: Arc<Mutex<S>>,
-        init_target: ii_bitcoin::Target,
+        channel: MiningChannel,
+        stop_receiver: &mut mpsc::Receiver<()>,
     ) where
         R: FrameStream,
         S: FrameSink,
     {
-        let event_handler = StratumEventHandler::new(self.clone(), init_target);
+        let event_handler = StratumEventHandler::new(self.clone(), channel);
         // TODO consider changing main_loop to accept Arc<Self> and build the solution_handler
         //  along with solution handler communication channels inside of the main_loop.
         let client = self.clone();
         if let Err(_) = client
-            .main_loop(connection_rx, connection_tx, event_handler)
+            .main_loop(connection_rx, connection_tx, event_handler, stop_receiver)
             .await
         {
             self.status.initiate_failing();
         }
     }
 
-    async fn run(self: Arc<Self>) {
+    /// Connect to the upstream and open a mining channel. The session is provided only when the
+    /// client has successfully transitioned to the running state.
+    async fn start_session(self: Arc<Self>) -> Option<MiningSession> {
         let connection_handler = StratumConnectionHandler::new(self.clone());
         let connection_details = connection_handler.client.connection_details();
         let host_and_port = connection_details.get_host_and_port();
@@ -925,11 +1579,9 @@ impl StratumClient {
                     .map_err(|_| {
                         error::ErrorKind::General("Init mining session timeout".to_string()).into()
                     }) {
-                    Ok(Ok(init_target)) => {
+                    Ok(Ok(channel)) => {
                         if self.status.initiate_running() {
-                            self.clone()
-                                .run_job_solver(framed_stream, framed_sink, init_target)
-                                .await;
+                            return Some((framed_stream, framed_sink, channel));
                         }
                     }
                     Ok(Err(e)) | Err(e) => {
@@ -951,18 +1603,32 @@ impl StratumClient {
                 self.status.initiate_failing()
             }
         }
+        None
+    }
+
+    async fn run(self: Arc<Self>, stop_receiver: &mut mpsc::Receiver<()>) {
+        // The session can be stopped at any time during its initialization. Once it is running,
+        // the stop request is handled by the main loop.
+        let session = select! {
+            session = self.clone().start_session().fuse() => session,
+            _ = stop_receiver.next() => None,
+        };
+        if let Some((connection_rx, connection_tx, channel)) = session {
+            self.run_job_solver(connection_rx, connection_tx, channel, stop_receiver)
+                .await;
+        }
     }
 
     async fn main_task(self: Arc<Self>) {
-        // TODO: Count as a discarded solution?
         // Flush all obsolete solutions from previous run
         self.solution_receiver.lock().await.flush();
 
         loop {
             let mut stop_receiver = self.stop_receiver.lock().await;
-            select! {
-                _ = self.clone().run().fuse() => {}
-                _ = stop_receiver.next() => {}
+            self.clone().run(&mut stop_receiver).await;
+            // Only a restart requested by a redirection continues with the redirected endpoint
+            if self.status.status() != sync::Status::Restarting {
+                self.restore_configured_descriptor().await;
             }
 
             // Notify the other end that uses the extension channel that it should restart its
@@ -985,7 +1651,6 @@ impl StratumClient {
             // Invalidate current job to stop working on it
             self.job_sender.lock().await.invalidate();
             // Flush all unprocessed solutions to empty buffer
-            // TODO: Count as a discarded solution?
             self.solution_receiver.lock().await.flush();
             self.solutions.lock().await.clear();
 
@@ -1025,11 +1690,12 @@ impl node::Client for StratumClient {
 
     /// Build new connection details from the specified `descriptor`
     fn change_connection_details(&self, descriptor: &bosminer_config::ClientDescriptor) {
-        *self
-            .connection_details
+        // Descriptor provided by the user overrides any redirection
+        self.configured_descriptor
             .lock()
-            .expect("BUG: cannot lock connection details") =
-            ConnectionDetails::from_descriptor(descriptor);
+            .expect("BUG: cannot lock configured descriptor")
+            .take();
+        self.set_connection_details(descriptor);
     }
 }
 
@@ -1043,3 +1709,119 @@ impl fmt::Display for StratumClient {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ii_bitcoin::FromHex;
+    use ii_stratum::test_utils;
+
+    #[test]
+    fn test_use_extended_channel() {
+        let mut connection_details = ConnectionDetails {
+            protocol: ClientProtocol::StratumV2Insecure,
+            user: "user".to_string(),
+            host: "localhost".to_string(),
+            port: 3336,
+            fragment: None,
+        };
+        assert!(!connection_details.use_extended_channel());
+        connection_details.fragment = Some("extended".to_string());
+        assert!(connection_details.use_extended_channel());
+        connection_details.fragment = Some("notextended".to_string());
+        assert!(!connection_details.use_extended_channel());
+    }
+
+    #[test]
+    fn test_roll_extranonce() {
+        let mut success_msg = test_utils::v2::build_open_extended_channel_success();
+        let mut extended_channel = ExtendedChannel::new(&success_msg);
+        assert_eq!(extended_channel.roll_extranonce().as_ref(), &[0, 0, 0, 0]);
+        assert_eq!(extended_channel.roll_extranonce().as_ref(), &[1, 0, 0, 0]);
+
+        // the counter is truncated to the negotiated extranonce size
+        success_msg.extranonce_size = ExtendedChannel::MIN_EXTRANONCE_SIZE;
+        let mut extended_channel = ExtendedChannel::new(&success_msg);
+        extended_channel.next_extranonce = 0x10203;
+        assert_eq!(extended_channel.roll_extranonce().as_ref(), &[0x03, 0x02]);
+
+        // and padded with zeros when the extranonce is larger than the counter
+        success_msg.extranonce_size = 10;
+        let mut extended_channel = ExtendedChannel::new(&success_msg);
+        extended_channel.next_extranonce = 0x10203;
+        assert_eq!(
+            extended_channel.roll_extranonce().as_ref(),
+            &[0x03, 0x02, 0x01, 0, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        // the extended test job is built from the V1 test job so the full extranonce consists of
+        // V1 extranonce 1 and zeroed extranonce 2
+        let subscribe_result = test_utils::v1::build_subscribe_ok_result();
+        let extranonce_2 = vec![0; subscribe_result.extra_nonce_2_size()];
+        let merkle_root = calculate_merkle_root(
+            &test_utils::v2::build_new_extended_mining_job(),
+            subscribe_result.extra_nonce_1().0.as_ref(),
+            &extranonce_2,
+        );
+
+        assert_eq!(
+            ii_bitcoin::DHash::from_hex(test_utils::v1::MINING_NOTIFY_MERKLE_ROOT)
+                .expect("BUG: invalid merkle root"),
+            merkle_root
+        );
+    }
+
+    #[test]
+    fn test_reject_reason() {
+        assert_eq!(reject_reason("stale-share"), stats::RejectReason::Stale);
+        assert_eq!(
+            reject_reason("difficulty-too-low"),
+            stats::RejectReason::LowDifficulty
+        );
+        assert_eq!(
+            reject_reason("invalid-job-id"),
+            stats::RejectReason::InvalidJob
+        );
+        assert_eq!(
+            reject_reason("duplicate-share"),
+            stats::RejectReason::Duplicate
+        );
+        // generic rejection of the translation proxy
+        assert_eq!(reject_reason("share-rejected"), stats::RejectReason::Other);
+        assert_eq!(reject_reason("unknown"), stats::RejectReason::Other);
+    }
+
+    #[test]
+    fn test_nominal_hashrate_changed() {
+        let mut channel = MiningChannel::default();
+        // Nothing has been reported yet
+        assert!(!channel.is_nominal_hashrate_changed(0.0));
+        assert!(channel.is_nominal_hashrate_changed(1e12));
+
+        channel.nominal_hashrate = 1e12;
+        assert!(!channel.is_nominal_hashrate_changed(1e12));
+        assert!(!channel.is_nominal_hashrate_changed(1.05e12));
+        assert!(!channel.is_nominal_hashrate_changed(0.95e12));
+        assert!(channel.is_nominal_hashrate_changed(1.2e12));
+        assert!(channel.is_nominal_hashrate_changed(0.8e12));
+        assert!(channel.is_nominal_hashrate_changed(0.0));
+    }
+
+    #[test]
+    fn test_work_snapshot_client_share() {
+        let previous = WorkSnapshot {
+            client: 10,
+            total: 100,
+        };
+        let snapshot = |client, total| WorkSnapshot { client, total };
+
+        // The miner has not generated any work
+        assert_eq!(snapshot(10, 100).client_share_since(&previous), None);
+        assert_eq!(snapshot(10, 200).client_share_since(&previous), Some(0.0));
+        assert_eq!(snapshot(35, 200).client_share_since(&previous), Some(0.25));
+        assert_eq!(snapshot(110, 200).client_share_since(&previous), Some(1.0));
+    }
+}
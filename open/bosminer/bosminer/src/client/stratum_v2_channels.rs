@@ -24,6 +24,7 @@
 
 use ii_logging::macros::*;
 
+use crate::client;
 use crate::error;
 use crate::job;
 use crate::node;
@@ -234,16 +235,17 @@ impl StratumEventHandler {
     async fn process_accepted_shares(&self, success_msg: &SubmitSharesSuccess) {
         let now = std::time::Instant::now();
         while let Some((solution, seq_num)) = self.client.solutions.lock().await.pop_front() {
+            self.client
+                .client_stats
+                .share_latency
+                .account_response(solution.timestamp(), now)
+                .await;
             info!(
                 "Stratum: accepted solution #{} with nonce={:08x}",
                 seq_num,
                 solution.nonce()
             );
-            self.client
-                .client_stats
-                .accepted
-                .account_solution(&solution.job_target(), now)
-                .await;
+            stats::account_accepted(&solution.path(), &solution.job_target(), now).await;
             if success_msg.last_seq_num == seq_num {
                 // all accepted solutions have been found
                 return;
@@ -258,17 +260,28 @@ impl StratumEventHandler {
     async fn process_rejected_shares(&self, error_msg: &SubmitSharesError) {
         let now = std::time::Instant::now();
         while let Some((solution, seq_num)) = self.client.solutions.lock().await.pop_front() {
+            self.client
+                .client_stats
+                .share_latency
+                .account_response(solution.timestamp(), now)
+                .await;
             if error_msg.seq_num == seq_num {
                 info!(
                     "Stratum: rejected solution #{} with nonce={:08x}!",
                     seq_num,
                     solution.nonce()
                 );
-                self.client
-                    .client_stats
-                    .rejected
-                    .account_solution(&solution.job_target(), now)
-                    .await;
+                let reason = client::stratum_v2::reject_reason(&error_msg.code.to_string());
+                self.client.client_stats.reject_reasons.account(reason);
+                if reason == stats::RejectReason::Stale {
+                    self.client
+                        .client_stats
+                        .stale
+                        .account_solution(&solution.job_target(), now)
+                        .await;
+                } else {
+                    stats::account_rejected(&solution.path(), &solution.job_target(), now).await;
+                }
                 // the rejected solution has been found
                 return;
             } else {
@@ -279,11 +292,7 @@ impl StratumEventHandler {
                     seq_num,
                     solution.nonce()
                 );
-                self.client
-                    .client_stats
-                    .accepted
-                    .account_solution(&solution.job_target(), now)
-                    .await;
+                stats::account_accepted(&solution.path(), &solution.job_target(), now).await;
                 warn!(
                     "Stratum: the solution #{} precedes rejected solution #{}!",
                     seq_num, error_msg.seq_num
@@ -756,6 +765,7 @@ impl StratumClient {
                 if self.status.initiate_running() {
                     let options = V2ToV1TranslationOptions {
                         try_enable_xnsub: self.connection_details.try_enable_xnsub(),
+                        ..Default::default()
                     };
                     let (translation_handler, v2_translation_rx, v2_translation_tx) =
                         TranslationHandler::new(v1_framed_connection, options);
@@ -773,7 +783,6 @@ impl StratumClient {
     }
 
     async fn main_task(self: Arc<Self>) {
-        // TODO: Count as a discarded solution?
         // Flush all obsolete solutions from previous run
         self.solution_receiver.lock().await.flush();
 
@@ -787,7 +796,6 @@ impl StratumClient {
             // Invalidate current job to stop working on it
             self.job_sender.lock().await.invalidate();
             // Flush all unprocessed solutions to empty buffer
-            // TODO: Count as a discarded solution?
             self.solution_receiver.lock().await.flush();
             self.solutions.lock().await.clear();
 
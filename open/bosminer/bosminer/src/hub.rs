@@ -106,7 +106,7 @@ impl Core {
         let (engine_sender, engine_receiver) = work::engine_channel(EventHandler);
         let (solution_sender, solution_receiver) = mpsc::unbounded();
 
-        let client_manager = client::Manager::new(midstate_count);
+        let client_manager = client::Manager::new(midstate_count, backend_registry);
         let job_executor = Arc::new(client::JobExecutor::new(
             frontend.clone(),
             engine_sender,
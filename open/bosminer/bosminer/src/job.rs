@@ -186,35 +186,59 @@ impl SolutionReceiver {
         );
     }
 
+    /// Accounts the solution in statistics and provides it only when it should be submitted
+    async fn filter_solution(solution: work::Solution) -> Option<work::Solution> {
+        let path = solution.path();
+        let time = solution.timestamp();
+        let hash = solution.hash();
+        let job_target = solution.job_target();
+
+        // compare block hash for given solution with all targets
+        // TODO: create tests for solution validation with all difficulty variants
+        assert!(&solution.network_target() <= job_target);
+        if hash.meets(&solution.network_target()) {
+            stats::account_valid_solution(&path, &solution, time, DiffTargetType::Network).await;
+        } else if hash.meets(&job_target) {
+            stats::account_valid_solution(&path, &solution, time, DiffTargetType::Job).await;
+        } else if hash.meets(solution.backend_target()) {
+            stats::account_valid_solution(&path, &solution, time, DiffTargetType::Backend).await;
+            // skip submitting the solution as we've only met backend difficulty
+            return None;
+        } else {
+            stats::account_error_backend_diff(&path, &solution.backend_target(), time).await;
+            // skip submitting the solution as this is a backend error
+            return None;
+        }
+
+        if solution.has_valid_job() {
+            Self::trace_share(&solution, &job_target);
+            return Some(solution);
+        }
+        Self::account_discarded(&solution);
+        None
+    }
+
+    /// Accounts a share that is not going to be submitted to the client that has issued its job
+    fn account_discarded(solution: &work::Solution) {
+        if let Some(origin) = solution.origin().upgrade() {
+            origin.client_stats().discarded().inc();
+        }
+    }
+
     pub async fn receive(&mut self) -> Option<work::Solution> {
         while let Some(solution) = self.solution_channel.next().await {
-            let path = solution.path();
-            let time = solution.timestamp();
-            let hash = solution.hash();
-            let job_target = solution.job_target();
-
-            // compare block hash for given solution with all targets
-            // TODO: create tests for solution validation with all difficulty variants
-            assert!(&solution.network_target() <= job_target);
-            if hash.meets(&solution.network_target()) {
-                stats::account_valid_solution(&path, &solution, time, DiffTargetType::Network)
-                    .await;
-            } else if hash.meets(&job_target) {
-                stats::account_valid_solution(&path, &solution, time, DiffTargetType::Job).await;
-            } else if hash.meets(solution.backend_target()) {
-                stats::account_valid_solution(&path, &solution, time, DiffTargetType::Backend)
-                    .await;
-                // skip submitting the solution as we've only met backend difficulty
-                continue;
-            } else {
-                stats::account_error_backend_diff(&path, &solution.backend_target(), time).await;
-                // skip submitting the solution as this is a backend error
-                continue;
+            if let Some(solution) = Self::filter_solution(solution).await {
+                return Some(solution);
             }
+        }
+        None
+    }
 
-            if solution.has_valid_job() {
-                // TODO: Account solution to Discard meter
-                Self::trace_share(&solution, &job_target);
+    /// Receive already buffered solution without blocking. This is used for submitting the
+    /// remaining solutions before the client is destroyed.
+    pub async fn try_receive(&mut self) -> Option<work::Solution> {
+        while let Ok(Some(solution)) = self.solution_channel.try_next() {
+            if let Some(solution) = Self::filter_solution(solution).await {
                 return Some(solution);
             }
         }
@@ -226,6 +250,11 @@ impl SolutionReceiver {
     /// TODO: We should review this regularly as there may be extensions in the mining protocol that
     /// may allow resume a mining session
     pub fn flush(&mut self) {
-        while let Ok(Some(_)) = self.solution_channel.try_next() {}
+        while let Ok(Some(solution)) = self.solution_channel.try_next() {
+            // solutions that don't meet the job target would have never been submitted anyway
+            if solution.hash().meets(solution.job_target()) {
+                Self::account_discarded(&solution);
+            }
+        }
     }
 }
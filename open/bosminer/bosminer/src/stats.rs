@@ -393,6 +393,79 @@ impl Default for Timestamp {
     }
 }
 
+#[derive(Debug, Clone, Default)]
+pub struct ShareLatencySnapshot {
+    /// Number of responses to submitted shares
+    pub responses: u64,
+    /// Total time between finding the shares and receiving the responses
+    pub total: time::Duration,
+}
+
+#[derive(Debug)]
+pub struct ShareLatency {
+    inner: Mutex<ShareLatencySnapshot>,
+}
+
+impl ShareLatency {
+    pub async fn take_snapshot(&self) -> Snapshot<ShareLatencySnapshot> {
+        Snapshot::new(self.inner.lock().await.clone())
+    }
+
+    /// Accounts response of the remote server to a share found at `solution_time`
+    pub(crate) async fn account_response(&self, solution_time: time::Instant, time: time::Instant) {
+        let mut share_latency = self.inner.lock().await;
+        share_latency.responses += 1;
+        share_latency.total += time.saturating_duration_since(solution_time);
+    }
+}
+
+impl Default for ShareLatency {
+    fn default() -> Self {
+        Self {
+            inner: Mutex::new(Default::default()),
+        }
+    }
+}
+
+/// Reason of share rejection reported by remote server
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The share belongs to a job that is no longer valid
+    Stale,
+    Duplicate,
+    LowDifficulty,
+    InvalidJob,
+    /// The reason is unknown or it has not been reported
+    Other,
+}
+
+/// Number of shares rejected by remote server for each `RejectReason`
+#[derive(Debug, Default)]
+pub struct RejectReasons {
+    stale: CounterU64,
+    duplicate: CounterU64,
+    low_difficulty: CounterU64,
+    invalid_job: CounterU64,
+    other: CounterU64,
+}
+
+impl RejectReasons {
+    pub fn get(&self, reason: RejectReason) -> &CounterU64 {
+        match reason {
+            RejectReason::Stale => &self.stale,
+            RejectReason::Duplicate => &self.duplicate,
+            RejectReason::LowDifficulty => &self.low_difficulty,
+            RejectReason::InvalidJob => &self.invalid_job,
+            RejectReason::Other => &self.other,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn account(&self, reason: RejectReason) {
+        self.get(reason).inc();
+    }
+}
+
 pub trait UnixTime {
     fn get_unix_time(&self) -> Result<u32, String>;
 }
@@ -411,6 +484,10 @@ pub trait Mining: Send + Sync {
     /// Information about last valid share with at least job difficulty
     fn last_share(&self) -> &LastShare;
     fn best_share(&self) -> &BestShare;
+    /// Shares accepted by remote server
+    fn accepted(&self) -> &Meter;
+    /// Shares rejected by remote server for other reason than stale job
+    fn rejected(&self) -> &Meter;
     /// Statistics for all valid blocks on network difficulty
     fn valid_network_diff(&self) -> &Meter;
     /// Statistics for all valid jobs on job/pool difficulty
@@ -428,12 +505,15 @@ pub trait Client: Mining {
     fn invalid_jobs(&self) -> &CounterUsize;
     /// Number of work generated from jobs by rolling or with extra nonce
     fn generated_work(&self) -> &CounterU64;
-    /// Shares accepted by remote server
-    fn accepted(&self) -> &Meter;
-    /// Shares rejected by remote server
-    fn rejected(&self) -> &Meter;
     /// Valid shares rejected by remote server or discarded due to some error
     fn stale(&self) -> &Meter;
+    /// Number of rejected shares (including stale ones) broken down by the reason of rejection
+    fn reject_reasons(&self) -> &RejectReasons;
+    /// Number of valid shares that have never been submitted to remote server because their job
+    /// had been invalidated in the meantime
+    fn discarded(&self) -> &CounterU64;
+    /// Time between finding shares and responses of remote server
+    fn share_latency(&self) -> &ShareLatency;
 }
 
 pub trait WorkSolver: Mining {
@@ -451,6 +531,10 @@ pub struct BasicMining {
     pub last_share: LastShare,
     #[member_best_share]
     pub best_share: BestShare,
+    #[member_accepted]
+    pub accepted: Meter,
+    #[member_rejected]
+    pub rejected: Meter,
     #[member_valid_network_diff]
     pub valid_network_diff: Meter,
     #[member_valid_job_diff]
@@ -467,6 +551,8 @@ impl BasicMining {
             start_time,
             last_share: Default::default(),
             best_share: Default::default(),
+            accepted: Meter::new(&intervals),
+            rejected: Meter::new(&intervals),
             valid_network_diff: Meter::new(&intervals),
             valid_job_diff: Meter::new(&intervals),
             valid_backend_diff: Meter::new(&intervals),
@@ -501,6 +587,12 @@ pub struct BasicClient {
     pub rejected: stats::Meter,
     #[member_stale]
     pub stale: stats::Meter,
+    #[member_reject_reasons]
+    pub reject_reasons: RejectReasons,
+    #[member_discarded]
+    pub discarded: CounterU64,
+    #[member_share_latency]
+    pub share_latency: ShareLatency,
     #[member_valid_network_diff]
     pub valid_network_diff: Meter,
     #[member_valid_job_diff]
@@ -523,6 +615,9 @@ impl BasicClient {
             accepted: Meter::new(&intervals),
             rejected: Meter::new(&intervals),
             stale: Default::default(),
+            reject_reasons: Default::default(),
+            discarded: Default::default(),
+            share_latency: Default::default(),
             valid_network_diff: Meter::new(&intervals),
             valid_job_diff: Meter::new(&intervals),
             valid_backend_diff: Meter::new(&intervals),
@@ -549,6 +644,10 @@ pub struct BasicWorkSolver {
     pub last_share: LastShare,
     #[member_best_share]
     pub best_share: BestShare,
+    #[member_accepted]
+    pub accepted: Meter,
+    #[member_rejected]
+    pub rejected: Meter,
     #[member_valid_network_diff]
     pub valid_network_diff: Meter,
     #[member_valid_job_diff]
@@ -567,6 +666,8 @@ impl BasicWorkSolver {
             best_share: Default::default(),
             last_work_time: Default::default(),
             generated_work: Default::default(),
+            accepted: Meter::new(&intervals),
+            rejected: Meter::new(&intervals),
             valid_network_diff: Meter::new(&intervals),
             valid_job_diff: Meter::new(&intervals),
             valid_backend_diff: Meter::new(&intervals),
@@ -605,6 +706,8 @@ account_impl!(account_valid_network_diff, valid_network_diff);
 account_impl!(account_valid_job_diff, valid_job_diff);
 account_impl!(account_valid_backend_diff, valid_backend_diff);
 account_impl!(account_error_backend_diff, error_backend_diff);
+account_impl!(account_accepted, accepted);
+account_impl!(account_rejected, rejected);
 
 /// Describes which difficulty target a particular solution has met.
 /// It also determines in which statistics a particular solution should be accounted.
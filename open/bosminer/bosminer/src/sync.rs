@@ -40,10 +40,10 @@ pub enum Status {
     Declining,
     Restarting,
     Recovering,
-    // TODO: Destroying
+    Destroying,
     Stopped,
     Failed,
-    // TODO: Destroyed
+    Destroyed,
 }
 
 impl fmt::Display for Status {
@@ -113,6 +113,8 @@ impl StatusMonitor {
                 | Status::Running
                 | Status::Restarting
                 | Status::Recovering => break,
+                // Destroyed client cannot be started again
+                Status::Destroying | Status::Destroyed => break,
             };
             // Try it again because another task change the state
         }
@@ -127,7 +129,11 @@ impl StatusMonitor {
         loop {
             let previous = status;
             match status {
-                Status::Created | Status::Stopped | Status::Failing | Status::Failed => {
+                Status::Created
+                | Status::Stopped
+                | Status::Failing
+                | Status::Failed
+                | Status::Destroyed => {
                     panic!("BUG: 'report_fail': unexpected state '{:?}'", status)
                 }
                 Status::Starting | Status::Retrying => {
@@ -141,9 +147,11 @@ impl StatusMonitor {
                     }
                 }
                 Status::Running => break,
-                Status::Stopping | Status::Declining | Status::Restarting | Status::Recovering => {
-                    return false
-                }
+                Status::Stopping
+                | Status::Declining
+                | Status::Restarting
+                | Status::Recovering
+                | Status::Destroying => return false,
             }
             // Try it again because another task change the state
         }
@@ -162,8 +170,10 @@ impl StatusMonitor {
                 | Status::Stopping
                 | Status::Failing
                 | Status::Declining
+                | Status::Destroying
                 | Status::Stopped
-                | Status::Failed => break,
+                | Status::Failed
+                | Status::Destroyed => break,
                 // Client is currently started
                 Status::Starting | Status::Running | Status::Restarting => {
                     status =
@@ -197,7 +207,7 @@ impl StatusMonitor {
         loop {
             let previous = status;
             match status {
-                Status::Created | Status::Stopped | Status::Failed => {
+                Status::Created | Status::Stopped | Status::Failed | Status::Destroyed => {
                     panic!("BUG: 'report_fail': unexpected state '{:?}'", status)
                 }
                 Status::Running | Status::Stopping => {
@@ -218,17 +228,64 @@ impl StatusMonitor {
                         break;
                     }
                 }
-                Status::Failing | Status::Declining | Status::Restarting | Status::Recovering => {
-                    break
+                // Failure of destroying client is not reported, the client is going away anyway
+                Status::Failing
+                | Status::Declining
+                | Status::Restarting
+                | Status::Recovering
+                | Status::Destroying => break,
+            };
+            // Try it again because another task change the state
+        }
+    }
+
+    /// Initiates graceful destruction of the client. Returns `true` when the destruction has to be
+    /// finished by the running client task and `false` when the client has been destroyed
+    /// immediately or it is already being destroyed.
+    pub fn initiate_destroying(&self) -> bool {
+        let mut status = self.status();
+
+        loop {
+            let previous = status;
+            match status {
+                // Client is not running at all
+                Status::Created | Status::Stopped | Status::Failed => {
+                    status =
+                        self.status
+                            .compare_and_swap(status, Status::Destroyed, Ordering::Relaxed);
+                    if status == previous {
+                        self.notify();
+                        break;
+                    }
                 }
+                // Client task is running and it finishes the destruction
+                Status::Starting
+                | Status::Retrying
+                | Status::Running
+                | Status::Stopping
+                | Status::Failing
+                | Status::Declining
+                | Status::Restarting
+                | Status::Recovering => {
+                    status =
+                        self.status
+                            .compare_and_swap(status, Status::Destroying, Ordering::Relaxed);
+                    if status == previous {
+                        // Destroying has been initiated successfully
+                        return true;
+                    }
+                }
+                Status::Destroying | Status::Destroyed => break,
             };
             // Try it again because another task change the state
         }
+
+        false
     }
 
     pub fn is_shutting_down(&self) -> bool {
         match self.status() {
-            Status::Stopping | Status::Failing | Status::Declining => true,
+            Status::Stopping | Status::Failing | Status::Declining | Status::Destroying => true,
             Status::Created
             | Status::Starting
             | Status::Running
@@ -236,10 +293,16 @@ impl StatusMonitor {
             | Status::Restarting
             | Status::Recovering
             | Status::Stopped
-            | Status::Failed => false,
+            | Status::Failed
+            | Status::Destroyed => false,
         }
     }
 
+    #[inline]
+    pub fn is_destroying(&self) -> bool {
+        self.status() == Status::Destroying
+    }
+
     pub fn can_stop(&self) -> bool {
         let mut status = self.status();
 
@@ -251,7 +314,8 @@ impl StatusMonitor {
                 | Status::Retrying
                 | Status::Running
                 | Status::Stopped
-                | Status::Failed => panic!("BUG: 'can_stop': unexpected state '{:?}'", status),
+                | Status::Failed
+                | Status::Destroyed => panic!("BUG: 'can_stop': unexpected state '{:?}'", status),
                 Status::Stopping => {
                     // Try to change state to `Stopped`
                     status =
@@ -272,6 +336,16 @@ impl StatusMonitor {
                         return true;
                     }
                 }
+                Status::Destroying => {
+                    // Try to change state to `Destroyed`
+                    status =
+                        self.status
+                            .compare_and_swap(status, Status::Destroyed, Ordering::Relaxed);
+                    if status == previous {
+                        self.notify();
+                        return true;
+                    }
+                }
                 Status::Declining => {
                     // Try to change state to `Failed`
                     status =
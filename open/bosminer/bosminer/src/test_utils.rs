@@ -164,6 +164,61 @@ impl job::Bitcoin for TestBlock {
     }
 }
 
+/// Test block with a job that has been issued by `origin` instead of the common test client so
+/// that the solutions are accounted to a client of its own
+#[derive(Debug)]
+pub struct TestClientBlock {
+    test_block: TestBlock,
+    origin: Weak<dyn node::Client>,
+}
+
+impl TestClientBlock {
+    pub fn new(test_block: &TestBlock, origin: Weak<dyn node::Client>) -> Self {
+        Self {
+            test_block: *test_block,
+            origin,
+        }
+    }
+}
+
+impl job::Bitcoin for TestClientBlock {
+    fn origin(&self) -> Weak<dyn node::Client> {
+        self.origin.clone()
+    }
+
+    fn version(&self) -> u32 {
+        self.test_block.version()
+    }
+
+    fn version_mask(&self) -> u32 {
+        self.test_block.version_mask()
+    }
+
+    fn previous_hash(&self) -> &ii_bitcoin::DHash {
+        self.test_block.previous_hash()
+    }
+
+    fn merkle_root(&self) -> &ii_bitcoin::DHash {
+        self.test_block.merkle_root()
+    }
+
+    fn time(&self) -> u32 {
+        self.test_block.time()
+    }
+
+    fn bits(&self) -> u32 {
+        self.test_block.bits()
+    }
+
+    fn target(&self) -> ii_bitcoin::Target {
+        self.test_block.target()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.test_block.is_valid()
+    }
+}
+
 /// Trait used for `TestBlock` customization
 pub trait TestBlockBuilder {
     /// Modify job target
@@ -234,6 +289,18 @@ impl From<&TestBlock> for work::Solution {
     }
 }
 
+impl From<TestClientBlock> for work::Solution {
+    fn from(test_client_block: TestClientBlock) -> Self {
+        let test_block = test_client_block.test_block;
+        let mid = work::Midstate {
+            version: test_block.version(),
+            state: test_block.midstate,
+        };
+        let work = work::Assignment::new(Arc::new(test_client_block), vec![mid], test_block.time());
+        Self::new(work, TestSolution::new(&test_block), None)
+    }
+}
+
 impl From<TestBlock> for work::Assignment {
     fn from(test_block: TestBlock) -> Self {
         (&test_block).into()
@@ -413,6 +413,20 @@ pub struct Pool {
     // Follows attribute extensions
     #[serde(rename = "AsicBoost")]
     pub asic_boost: bool,
+    #[serde(rename = "Health Score")]
+    pub health_score: Percent,
+    /// Reason why the scheduler temporarily skips the pool, empty for healthy pools
+    #[serde(rename = "Demotion Reason")]
+    pub demotion_reason: String,
+    /// Rejected shares broken down by the reason, stale shares are reported in `stale`
+    #[serde(rename = "Rejected Duplicate")]
+    pub rejected_duplicate: u64,
+    #[serde(rename = "Rejected Low Difficulty")]
+    pub rejected_low_difficulty: u64,
+    #[serde(rename = "Rejected Invalid Job")]
+    pub rejected_invalid_job: u64,
+    #[serde(rename = "Rejected Other")]
+    pub rejected_other: u64,
 }
 
 #[derive(Serialize, PartialEq, Clone, Debug)]
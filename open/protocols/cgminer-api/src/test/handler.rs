@@ -69,6 +69,12 @@ impl command::Handler for BasicTest {
                 current_block_height: 0,
                 current_block_version: 0,
                 asic_boost: false,
+                health_score: 100.0,
+                demotion_reason: "".to_string(),
+                rejected_duplicate: 0,
+                rejected_low_difficulty: 0,
+                rejected_invalid_job: 0,
+                rejected_other: 0,
             }],
         })
     }
@@ -22,15 +22,33 @@
 
 use async_trait::async_trait;
 use bitcoin_hashes::{hex::FromHex, sha256d, Hash};
+use std::convert::TryFrom;
 use std::fmt::Debug;
 use uint;
 
+use ii_async_compat::prelude::*;
 use ii_logging::macros::*;
 
+pub mod job_negotiation;
+
 use crate::test_utils::common::*;
 use crate::test_utils::v1;
 use crate::v2::{framing, messages::*, types::*, Handler};
 
+/// Receives the next frame from `frame_rx` and deserializes it as message `M`. Tests use it to
+/// verify messages generated by the component under test.
+pub async fn receive_message<M, S>(frame_rx: &mut S) -> M
+where
+    S: Stream<Item = framing::Frame> + Unpin,
+    M: TryFrom<framing::Frame, Error = crate::error::Error>,
+{
+    let frame = frame_rx
+        .next()
+        .await
+        .expect("At least 1 message was expected");
+    M::try_from(frame).expect("Unexpected message")
+}
+
 /// Message payload visitor that compares the payload of the visited message (e.g. after
 /// deserialization test) with the payload built.
 /// This handler should be used in tests to verify that serialization and deserialization yield the
@@ -92,6 +110,90 @@ impl Handler for TestIdentityHandler {
         self.visit_and_check(header, payload, build_new_mining_job);
     }
 
+    async fn visit_open_extended_mining_channel(
+        &mut self,
+        header: &framing::Header,
+        payload: &OpenExtendedMiningChannel,
+    ) {
+        self.visit_and_check(header, payload, build_open_extended_channel);
+    }
+
+    async fn visit_open_extended_mining_channel_success(
+        &mut self,
+        header: &framing::Header,
+        payload: &OpenExtendedMiningChannelSuccess,
+    ) {
+        self.visit_and_check(header, payload, build_open_extended_channel_success);
+    }
+
+    async fn visit_new_extended_mining_job(
+        &mut self,
+        header: &framing::Header,
+        payload: &NewExtendedMiningJob,
+    ) {
+        self.visit_and_check(header, payload, build_new_extended_mining_job);
+    }
+
+    async fn visit_set_group_channel(
+        &mut self,
+        header: &framing::Header,
+        payload: &SetGroupChannel,
+    ) {
+        self.visit_and_check(header, payload, build_set_group_channel);
+    }
+
+    async fn visit_update_channel(&mut self, header: &framing::Header, payload: &UpdateChannel) {
+        self.visit_and_check(header, payload, build_update_channel);
+    }
+
+    async fn visit_update_channel_error(
+        &mut self,
+        header: &framing::Header,
+        payload: &UpdateChannelError,
+    ) {
+        self.visit_and_check(header, payload, build_update_channel_error);
+    }
+
+    async fn visit_close_channel(&mut self, header: &framing::Header, payload: &CloseChannel) {
+        self.visit_and_check(header, payload, build_close_channel);
+    }
+
+    async fn visit_set_custom_mining_job(
+        &mut self,
+        header: &framing::Header,
+        payload: &SetCustomMiningJob,
+    ) {
+        self.visit_and_check(header, payload, build_set_custom_mining_job);
+    }
+
+    async fn visit_set_custom_mining_job_success(
+        &mut self,
+        header: &framing::Header,
+        payload: &SetCustomMiningJobSuccess,
+    ) {
+        self.visit_and_check(header, payload, build_set_custom_mining_job_success);
+    }
+
+    async fn visit_set_custom_mining_job_error(
+        &mut self,
+        header: &framing::Header,
+        payload: &SetCustomMiningJobError,
+    ) {
+        self.visit_and_check(header, payload, build_set_custom_mining_job_error);
+    }
+
+    async fn visit_reconnect(&mut self, header: &framing::Header, payload: &Reconnect) {
+        self.visit_and_check(header, payload, build_reconnect);
+    }
+
+    async fn visit_submit_shares_extended(
+        &mut self,
+        header: &framing::Header,
+        payload: &SubmitSharesExtended,
+    ) {
+        self.visit_and_check(header, payload, build_submit_shares_extended);
+    }
+
     async fn visit_set_new_prev_hash(
         &mut self,
         header: &framing::Header,
@@ -143,6 +245,9 @@ pub fn build_setup_connection_success() -> SetupConnectionSuccess {
     }
 }
 
+/// Group channel that contains the standard test channel
+pub const GROUP_CHANNEL_ID: u32 = std::u32::MAX;
+
 pub fn build_open_channel() -> OpenStandardMiningChannel {
     OpenStandardMiningChannel {
         req_id: 10,
@@ -171,6 +276,14 @@ pub fn build_open_channel_success() -> OpenStandardMiningChannelSuccess {
     }
 }
 
+/// Standard test channel that has been made a member of the test group channel
+pub fn build_open_channel_success_with_group() -> OpenStandardMiningChannelSuccess {
+    OpenStandardMiningChannelSuccess {
+        group_channel_id: GROUP_CHANNEL_ID,
+        ..build_open_channel_success()
+    }
+}
+
 /// TODO: see test_utils::v1::MINING_NOTIFY_JSON that defines a stratum v1 job.
 /// The merkle root below has been calculated by the integration test and cannot be trusted...
 /// We need a V1 mining job with verified merkle root that is to be copied
@@ -200,6 +313,14 @@ pub fn build_set_new_prev_hash() -> SetNewPrevHash {
     }
 }
 
+/// New prevhash that is broadcast to the whole test group channel
+pub fn build_set_new_prev_hash_for_group() -> SetNewPrevHash {
+    SetNewPrevHash {
+        channel_id: GROUP_CHANNEL_ID,
+        ..build_set_new_prev_hash()
+    }
+}
+
 pub fn build_submit_shares() -> SubmitSharesStandard {
     // Use the mining job to provide sensible information for the share submit
     let mining_job = build_new_mining_job();
@@ -213,3 +334,143 @@ pub fn build_submit_shares() -> SubmitSharesStandard {
         version: MINING_WORK_VERSION,
     }
 }
+
+/// Extranonce prefix assigned by the upstream to the extended test channel
+pub const EXTENDED_CHANNEL_EXTRANONCE_PREFIX: &[u8] = &[0x6c, 0x6f, 0x01, 0x00];
+/// Extranonce space left for the device on the extended test channel
+pub const EXTENDED_CHANNEL_EXTRANONCE_SIZE: u16 = 4;
+
+pub fn build_open_extended_channel() -> OpenExtendedMiningChannel {
+    OpenExtendedMiningChannel {
+        req_id: 11,
+        user: USER_CREDENTIALS.try_into().unwrap(),
+        nominal_hashrate: 1e9,
+        max_target: ii_bitcoin::Target::default().into(),
+        min_extranonce_size: EXTENDED_CHANNEL_EXTRANONCE_SIZE,
+    }
+}
+
+pub fn build_open_extended_channel_success() -> OpenExtendedMiningChannelSuccess {
+    let standard = build_open_channel_success();
+
+    OpenExtendedMiningChannelSuccess {
+        req_id: 11,
+        channel_id: 1,
+        target: standard.target,
+        extranonce_size: EXTENDED_CHANNEL_EXTRANONCE_SIZE,
+        extranonce_prefix: Bytes0_32::from_slice(EXTENDED_CHANNEL_EXTRANONCE_PREFIX),
+    }
+}
+
+/// Extended job built from the V1 test job, the coinbase is split at the extranonce position
+pub fn build_new_extended_mining_job() -> NewExtendedMiningJob {
+    let v1_job = v1::build_mining_notify();
+    let merkle_path = v1_job
+        .merkle_branch()
+        .iter()
+        .map(|branch| {
+            let mut hash = [0u8; 32];
+            hash.copy_from_slice(branch.as_ref().as_slice());
+            Uint256Bytes(hash)
+        })
+        .collect::<Vec<_>>();
+
+    NewExtendedMiningJob {
+        channel_id: 1,
+        job_id: 0,
+        future_job: true,
+        version: MINING_WORK_VERSION,
+        version_rolling_allowed: true,
+        merkle_path: Seq0_255::from_vec(merkle_path),
+        coinbase_tx_prefix: Bytes0_64k::from_slice(v1_job.coin_base_1()),
+        coinbase_tx_suffix: Bytes0_64k::from_slice(v1_job.coin_base_2()),
+    }
+}
+
+pub fn build_submit_shares_extended() -> SubmitSharesExtended {
+    let mining_job = build_new_extended_mining_job();
+
+    SubmitSharesExtended {
+        channel_id: mining_job.channel_id,
+        seq_num: 0,
+        job_id: mining_job.job_id,
+        nonce: MINING_WORK_NONCE,
+        ntime: MINING_WORK_NTIME,
+        version: MINING_WORK_VERSION,
+        extranonce: Bytes0_32::from_slice(&[0; EXTENDED_CHANNEL_EXTRANONCE_SIZE as usize]),
+    }
+}
+
+pub fn build_set_group_channel() -> SetGroupChannel {
+    SetGroupChannel {
+        group_channel_id: GROUP_CHANNEL_ID,
+        channel_ids: Seq0_64k::from_vec(vec![0, 1, 2]),
+    }
+}
+
+pub fn build_update_channel() -> UpdateChannel {
+    let open_channel = build_open_channel();
+
+    UpdateChannel {
+        channel_id: 0,
+        nominal_hashrate: 13.5e12,
+        max_target: open_channel.max_target,
+    }
+}
+
+pub fn build_update_channel_error() -> UpdateChannelError {
+    UpdateChannelError {
+        channel_id: 0,
+        code: "max-target-out-of-range".try_into().unwrap(),
+    }
+}
+
+pub fn build_reconnect() -> Reconnect {
+    Reconnect {
+        new_host: Str0_255::from_str("v2.eu.stratum.slushpool.com"),
+        new_port: 3336,
+    }
+}
+
+pub fn build_close_channel() -> CloseChannel {
+    CloseChannel {
+        channel_id: 0,
+        reason_code: "client-stopped".try_into().unwrap(),
+    }
+}
+
+/// Custom job equivalent to the committed job from the job negotiation test utils
+pub fn build_set_custom_mining_job() -> SetCustomMiningJob {
+    let commit = job_negotiation::build_commit_mining_job();
+    let prev_hash = build_set_new_prev_hash();
+
+    SetCustomMiningJob {
+        channel_id: 1,
+        req_id: 12,
+        mining_job_token: job_negotiation::build_commit_mining_job_success().new_mining_job_token,
+        version: commit.version,
+        prev_hash: commit.prev_hash,
+        min_ntime: prev_hash.min_ntime,
+        nbits: commit.nbits,
+        merkle_path: commit.merkle_path,
+        coinbase_tx_prefix: commit.coinbase_tx_prefix,
+        coinbase_tx_suffix: commit.coinbase_tx_suffix,
+        future_job: false,
+    }
+}
+
+pub fn build_set_custom_mining_job_success() -> SetCustomMiningJobSuccess {
+    SetCustomMiningJobSuccess {
+        channel_id: 1,
+        req_id: 12,
+        job_id: 0,
+    }
+}
+
+pub fn build_set_custom_mining_job_error() -> SetCustomMiningJobError {
+    SetCustomMiningJobError {
+        channel_id: 1,
+        req_id: 12,
+        code: "invalid-mining-job-token".try_into().unwrap(),
+    }
+}
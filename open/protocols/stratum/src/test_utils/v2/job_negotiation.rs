@@ -0,0 +1,279 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Test utilities for the job negotiation extension including an in-process pool stand-in that
+//! allows testing the whole custom job flow offline
+
+use async_trait::async_trait;
+use std::collections::{HashMap, HashSet};
+use std::convert::{TryFrom, TryInto};
+
+use ii_logging::macros::*;
+
+use super::{build_new_extended_mining_job, build_set_new_prev_hash, TestIdentityHandler};
+use crate::error::Error;
+use crate::test_utils::common::*;
+use crate::v2::job_negotiation::{self, messages::*};
+use crate::v2::{self, extensions, framing, messages::*, types::*};
+
+/// Coinbase outputs size limit advertised by the `TestPool`
+pub const COINBASE_OUTPUT_MAX_ADDITIONAL_SIZE: u32 = 100;
+/// Error code for unknown or already used mining job tokens
+pub const INVALID_TOKEN_CODE: &str = "invalid-mining-job-token";
+/// Error code for a custom job that doesn't match the committed one
+pub const INVALID_JOB_CODE: &str = "invalid-job-param-value";
+
+#[async_trait]
+impl job_negotiation::Handler for TestIdentityHandler {
+    async fn visit_allocate_mining_job_token(
+        &mut self,
+        header: &framing::Header,
+        payload: &AllocateMiningJobToken,
+    ) {
+        self.visit_and_check(header, payload, build_allocate_mining_job_token);
+    }
+
+    async fn visit_allocate_mining_job_token_success(
+        &mut self,
+        header: &framing::Header,
+        payload: &AllocateMiningJobTokenSuccess,
+    ) {
+        self.visit_and_check(header, payload, build_allocate_mining_job_token_success);
+    }
+
+    async fn visit_commit_mining_job(
+        &mut self,
+        header: &framing::Header,
+        payload: &CommitMiningJob,
+    ) {
+        self.visit_and_check(header, payload, build_commit_mining_job);
+    }
+
+    async fn visit_commit_mining_job_success(
+        &mut self,
+        header: &framing::Header,
+        payload: &CommitMiningJobSuccess,
+    ) {
+        self.visit_and_check(header, payload, build_commit_mining_job_success);
+    }
+
+    async fn visit_commit_mining_job_error(
+        &mut self,
+        header: &framing::Header,
+        payload: &CommitMiningJobError,
+    ) {
+        self.visit_and_check(header, payload, build_commit_mining_job_error);
+    }
+}
+
+/// Tokens are issued by the `TestPool` as little endian encoded sequence numbers
+fn build_token(seq_num: u32) -> Bytes0_255 {
+    Bytes0_255::from_slice(&seq_num.to_le_bytes())
+}
+
+pub fn build_allocate_mining_job_token() -> AllocateMiningJobToken {
+    AllocateMiningJobToken {
+        req_id: 20,
+        user_identifier: USER_CREDENTIALS.try_into().unwrap(),
+    }
+}
+
+/// First token issued by a fresh `TestPool`
+pub fn build_allocate_mining_job_token_success() -> AllocateMiningJobTokenSuccess {
+    AllocateMiningJobTokenSuccess {
+        req_id: 20,
+        mining_job_token: build_token(0),
+        coinbase_output_max_additional_size: COINBASE_OUTPUT_MAX_ADDITIONAL_SIZE,
+        async_mining_allowed: true,
+    }
+}
+
+/// Commits the extended test job on top of the test prevhash
+pub fn build_commit_mining_job() -> CommitMiningJob {
+    let job = build_new_extended_mining_job();
+    let prev_hash = build_set_new_prev_hash();
+
+    CommitMiningJob {
+        req_id: 21,
+        mining_job_token: build_allocate_mining_job_token_success().mining_job_token,
+        version: job.version,
+        prev_hash: prev_hash.prev_hash,
+        nbits: prev_hash.nbits,
+        merkle_path: job.merkle_path,
+        coinbase_tx_prefix: job.coinbase_tx_prefix,
+        coinbase_tx_suffix: job.coinbase_tx_suffix,
+    }
+}
+
+pub fn build_commit_mining_job_success() -> CommitMiningJobSuccess {
+    CommitMiningJobSuccess {
+        req_id: 21,
+        new_mining_job_token: build_token(1),
+    }
+}
+
+pub fn build_commit_mining_job_error() -> CommitMiningJobError {
+    CommitMiningJobError {
+        req_id: 21,
+        code: INVALID_TOKEN_CODE.try_into().unwrap(),
+    }
+}
+
+/// Pool stand-in that implements the pool side of job negotiation and of custom job setup on a
+/// mining channel. Each token can be used only once. Responses are collected in the order of
+/// processed requests and are available via `take_responses()`.
+#[derive(Default)]
+pub struct TestPool {
+    next_token: u32,
+    next_job_id: u32,
+    allocated_tokens: HashSet<Vec<u8>>,
+    /// Committed jobs indexed by the token that has been issued upon the commit
+    committed_jobs: HashMap<Vec<u8>, CommitMiningJob>,
+    responses: Vec<framing::Frame>,
+}
+
+impl TestPool {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Dispatches `frame` either to the job negotiation or to the mining protocol handler
+    pub async fn handle_frame(&mut self, frame: framing::Frame) {
+        if frame.header.extension_type == extensions::JOB_NEGOTIATION {
+            let message = job_negotiation::messages::build_message_from_frame(frame)
+                .expect("BUG: cannot build job negotiation message");
+            message.accept(self).await;
+        } else {
+            let message =
+                v2::build_message_from_frame(frame).expect("BUG: cannot build mining message");
+            message.accept(self).await;
+        }
+    }
+
+    /// Provides all responses generated so far
+    pub fn take_responses(&mut self) -> Vec<framing::Frame> {
+        std::mem::replace(&mut self.responses, Vec::new())
+    }
+
+    fn issue_token(&mut self) -> Bytes0_255 {
+        let token = build_token(self.next_token);
+        self.next_token += 1;
+        token
+    }
+
+    fn respond<T>(&mut self, response: T)
+    where
+        framing::Frame: TryFrom<T, Error = Error>,
+    {
+        self.responses.push(
+            response
+                .try_into()
+                .expect("BUG: cannot build response frame"),
+        );
+    }
+
+    fn matches_committed_job(job: &SetCustomMiningJob, committed_job: &CommitMiningJob) -> bool {
+        job.version == committed_job.version
+            && job.prev_hash == committed_job.prev_hash
+            && job.nbits == committed_job.nbits
+            && job.merkle_path == committed_job.merkle_path
+            && job.coinbase_tx_prefix == committed_job.coinbase_tx_prefix
+            && job.coinbase_tx_suffix == committed_job.coinbase_tx_suffix
+    }
+}
+
+#[async_trait]
+impl job_negotiation::Handler for TestPool {
+    async fn visit_allocate_mining_job_token(
+        &mut self,
+        _header: &framing::Header,
+        payload: &AllocateMiningJobToken,
+    ) {
+        let mining_job_token = self.issue_token();
+        self.allocated_tokens
+            .insert(mining_job_token.as_ref().to_vec());
+        self.respond(AllocateMiningJobTokenSuccess {
+            req_id: payload.req_id,
+            mining_job_token,
+            coinbase_output_max_additional_size: COINBASE_OUTPUT_MAX_ADDITIONAL_SIZE,
+            async_mining_allowed: true,
+        });
+    }
+
+    async fn visit_commit_mining_job(
+        &mut self,
+        _header: &framing::Header,
+        payload: &CommitMiningJob,
+    ) {
+        if !self
+            .allocated_tokens
+            .remove(payload.mining_job_token.as_ref())
+        {
+            trace!("TestPool: unknown token in {:?}", payload);
+            self.respond(CommitMiningJobError {
+                req_id: payload.req_id,
+                code: INVALID_TOKEN_CODE.try_into().unwrap(),
+            });
+            return;
+        }
+        let new_mining_job_token = self.issue_token();
+        self.committed_jobs
+            .insert(new_mining_job_token.as_ref().to_vec(), payload.clone());
+        self.respond(CommitMiningJobSuccess {
+            req_id: payload.req_id,
+            new_mining_job_token,
+        });
+    }
+}
+
+#[async_trait]
+impl v2::Handler for TestPool {
+    async fn visit_set_custom_mining_job(
+        &mut self,
+        _header: &framing::Header,
+        payload: &SetCustomMiningJob,
+    ) {
+        let code = match self
+            .committed_jobs
+            .remove(payload.mining_job_token.as_ref())
+        {
+            Some(committed_job) if Self::matches_committed_job(payload, &committed_job) => {
+                let job_id = self.next_job_id;
+                self.next_job_id += 1;
+                self.respond(SetCustomMiningJobSuccess {
+                    channel_id: payload.channel_id,
+                    req_id: payload.req_id,
+                    job_id,
+                });
+                return;
+            }
+            Some(_) => INVALID_JOB_CODE,
+            None => INVALID_TOKEN_CODE,
+        };
+        trace!("TestPool: rejecting {:?} ({})", payload, code);
+        self.respond(SetCustomMiningJobError {
+            channel_id: payload.channel_id,
+            req_id: payload.req_id,
+            code: code.try_into().unwrap(),
+        });
+    }
+}
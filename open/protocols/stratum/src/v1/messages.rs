@@ -389,6 +389,38 @@ pub struct Notify(
 
 // TODO consider making the attributes return new type references, it would be less prone to typos
 impl Notify {
+    /// Builds a job notification, `prev_hash` is expected in the internal byte order as
+    /// provided by `prev_hash()`
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        job_id: JobId,
+        prev_hash: &[u8],
+        coin_base_1: &[u8],
+        coin_base_2: &[u8],
+        merkle_branch: &[&[u8]],
+        version: u32,
+        bits: u32,
+        time: u32,
+        clean_jobs: bool,
+    ) -> Self {
+        Self(
+            job_id,
+            PrevHash(prev_hash.into()),
+            CoinBase1(HexBytes(coin_base_1.into())),
+            CoinBase2(HexBytes(coin_base_2.into())),
+            MerkleBranch(
+                merkle_branch
+                    .iter()
+                    .map(|tx_hash| HexBytes(tx_hash.to_vec()))
+                    .collect(),
+            ),
+            Version(HexU32Be(version)),
+            Bits(HexU32Be(bits)),
+            Time(HexU32Be(time)),
+            clean_jobs,
+        )
+    }
+
     pub fn job_id(&self) -> &str {
         &(self.0).0
     }
@@ -85,3 +85,25 @@ fn test_subscribe_malformed_result_json() {
         Rpc::Request(_) => (),
     }
 }
+
+#[test]
+fn test_build_notify() {
+    let expected_notify = build_mining_notify();
+    let merkle_branch: Vec<&[u8]> = expected_notify
+        .merkle_branch()
+        .iter()
+        .map(|tx_hash| tx_hash.as_ref().as_slice())
+        .collect();
+    let notify = Notify::new(
+        JobId::from_str(expected_notify.job_id()),
+        expected_notify.prev_hash(),
+        expected_notify.coin_base_1(),
+        expected_notify.coin_base_2(),
+        &merkle_branch,
+        expected_notify.version(),
+        expected_notify.bits(),
+        expected_notify.time(),
+        expected_notify.clean_jobs(),
+    );
+    assert_eq!(expected_notify, notify, "Notify mismatch");
+}
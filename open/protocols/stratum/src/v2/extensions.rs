@@ -26,3 +26,5 @@
 pub const BASE: u16 = 0x0000;
 /// Telemetry extension
 pub const TELEMETRY: u16 = 0x0001;
+/// Job negotiation extension
+pub const JOB_NEGOTIATION: u16 = 0x0002;
@@ -0,0 +1,206 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Job negotiation extension allows a miner to construct its own mining jobs. The miner
+//! allocates a mining job token from the pool, commits the job it intends to mine and finally
+//! uses the token returned by the commit for setting up the custom job on its mining channel
+//! (see `v2::messages::SetCustomMiningJob`)
+
+pub mod messages;
+
+use super::framing;
+
+use async_trait::async_trait;
+
+/// Protocol associates the job negotiation handler with it
+pub struct Protocol;
+impl crate::Protocol for Protocol {
+    type Handler = dyn Handler;
+    type Header = framing::Header;
+}
+
+/// Specifies all job negotiation messages to be visited
+#[async_trait]
+pub trait Handler: 'static + Send {
+    async fn visit_allocate_mining_job_token(
+        &mut self,
+        _header: &framing::Header,
+        _payload: &messages::AllocateMiningJobToken,
+    ) {
+    }
+
+    async fn visit_allocate_mining_job_token_success(
+        &mut self,
+        _header: &framing::Header,
+        _payload: &messages::AllocateMiningJobTokenSuccess,
+    ) {
+    }
+
+    async fn visit_commit_mining_job(
+        &mut self,
+        _header: &framing::Header,
+        _payload: &messages::CommitMiningJob,
+    ) {
+    }
+
+    async fn visit_commit_mining_job_success(
+        &mut self,
+        _header: &framing::Header,
+        _payload: &messages::CommitMiningJobSuccess,
+    ) {
+    }
+
+    async fn visit_commit_mining_job_error(
+        &mut self,
+        _header: &framing::Header,
+        _payload: &messages::CommitMiningJobError,
+    ) {
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::messages::*;
+    use super::*;
+    use crate::test_utils::v2::job_negotiation::*;
+    use crate::test_utils::v2::*;
+    use crate::v2::messages::{
+        SetCustomMiningJob, SetCustomMiningJobError, SetCustomMiningJobSuccess,
+    };
+
+    use ii_async_compat::tokio;
+    use std::convert::{TryFrom, TryInto};
+
+    #[tokio::test]
+    async fn test_build_message_from_frame() {
+        let frames: Vec<framing::Frame> = vec![
+            build_allocate_mining_job_token()
+                .try_into()
+                .expect("Cannot create test frame"),
+            build_allocate_mining_job_token_success()
+                .try_into()
+                .expect("Cannot create test frame"),
+            build_commit_mining_job()
+                .try_into()
+                .expect("Cannot create test frame"),
+            build_commit_mining_job_success()
+                .try_into()
+                .expect("Cannot create test frame"),
+            build_commit_mining_job_error()
+                .try_into()
+                .expect("Cannot create test frame"),
+        ];
+
+        for frame in frames {
+            let message =
+                build_message_from_frame(frame).expect("Message payload deserialization failed");
+            message.accept(&mut TestIdentityHandler).await;
+        }
+    }
+
+    #[test]
+    fn test_reject_foreign_extension() {
+        let frame: framing::Frame = build_setup_connection()
+            .try_into()
+            .expect("Cannot create test frame");
+        assert!(build_message_from_frame(frame).is_err());
+    }
+
+    /// Sends `request` to the `pool` and returns its only response
+    async fn request<T>(pool: &mut TestPool, request: T) -> framing::Frame
+    where
+        framing::Frame: TryFrom<T, Error = crate::error::Error>,
+    {
+        pool.handle_frame(request.try_into().expect("Cannot create test frame"))
+            .await;
+        let mut responses = pool.take_responses();
+        assert_eq!(responses.len(), 1, "Expected exactly one response");
+        responses.pop().unwrap()
+    }
+
+    /// Negotiates the test job with the pool and sets it up on the mining channel
+    #[tokio::test]
+    async fn test_custom_job_flow() {
+        let mut pool = TestPool::new();
+
+        let response = request(&mut pool, build_allocate_mining_job_token()).await;
+        assert_eq!(
+            AllocateMiningJobTokenSuccess::try_from(response).expect("Unexpected response"),
+            build_allocate_mining_job_token_success()
+        );
+
+        let response = request(&mut pool, build_commit_mining_job()).await;
+        assert_eq!(
+            CommitMiningJobSuccess::try_from(response).expect("Unexpected response"),
+            build_commit_mining_job_success()
+        );
+
+        let response = request(&mut pool, build_set_custom_mining_job()).await;
+        assert_eq!(
+            response.header.msg_type,
+            crate::v2::messages::MessageType::SetCustomMiningJobSuccess as framing::MsgType
+        );
+        assert_eq!(
+            SetCustomMiningJobSuccess::try_from(response).expect("Unexpected response"),
+            build_set_custom_mining_job_success()
+        );
+
+        // The token issued upon commit is consumed by the custom job
+        let response = request(&mut pool, build_set_custom_mining_job()).await;
+        assert_eq!(
+            SetCustomMiningJobError::try_from(response).expect("Unexpected response"),
+            build_set_custom_mining_job_error()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_custom_job_invalid_token() {
+        let mut pool = TestPool::new();
+
+        // Committing a job without allocating the token first
+        let response = request(&mut pool, build_commit_mining_job()).await;
+        assert_eq!(
+            response.header.msg_type,
+            MessageType::CommitMiningJobError as framing::MsgType
+        );
+        assert_eq!(
+            CommitMiningJobError::try_from(response).expect("Unexpected response"),
+            build_commit_mining_job_error()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_custom_job_mismatch() {
+        let mut pool = TestPool::new();
+
+        request(&mut pool, build_allocate_mining_job_token()).await;
+        request(&mut pool, build_commit_mining_job()).await;
+
+        let job = SetCustomMiningJob {
+            version: build_commit_mining_job().version ^ 1,
+            ..build_set_custom_mining_job()
+        };
+        let response = request(&mut pool, job).await;
+        let error = SetCustomMiningJobError::try_from(response).expect("Unexpected response");
+        assert_eq!(error.code.to_string(), INVALID_JOB_CODE);
+    }
+}
@@ -0,0 +1,165 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+#[cfg(not(feature = "v2json"))]
+use crate::v2::serialization;
+use crate::{
+    error::{Error, Result},
+    v2::{error, extensions, framing, job_negotiation::Protocol, types::*},
+    AnyPayload, Message,
+};
+use async_trait::async_trait;
+use packed_struct::prelude::*;
+use packed_struct_codegen::PrimitiveEnum_u8;
+use serde;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+use ii_logging::macros::*;
+
+#[cfg(feature = "v2json")]
+use serde_json as serialization;
+
+/// Generates conversion for job negotiation protocol messages (extension 2)
+macro_rules! impl_job_negotiation_message_conversion {
+    ($message:tt, $is_channel_msg:expr, $handler_fn:tt) => {
+        impl_standalone_message_conversion!(
+            extensions::JOB_NEGOTIATION,
+            $message,
+            $is_channel_msg,
+            $handler_fn
+        );
+    };
+}
+
+/// All message recognized by the protocol
+#[derive(PrimitiveEnum_u8, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum MessageType {
+    AllocateMiningJobToken = 0x00,
+    AllocateMiningJobTokenSuccess = 0x01,
+    CommitMiningJob = 0x02,
+    CommitMiningJobSuccess = 0x03,
+    CommitMiningJobError = 0x04,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AllocateMiningJobToken {
+    pub req_id: u32,
+    pub user_identifier: Str0_255,
+}
+
+/// `coinbase_output_max_additional_size` limits the size of coinbase outputs that the miner may
+/// add to the custom job
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AllocateMiningJobTokenSuccess {
+    pub req_id: u32,
+    pub mining_job_token: Bytes0_255,
+    pub coinbase_output_max_additional_size: u32,
+    pub async_mining_allowed: bool,
+}
+
+/// Announces the job that the miner intends to mine with the allocated `mining_job_token`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CommitMiningJob {
+    pub req_id: u32,
+    pub mining_job_token: Bytes0_255,
+    pub version: u32,
+    pub prev_hash: Uint256Bytes,
+    pub nbits: u32,
+    pub merkle_path: Seq0_255<Uint256Bytes>,
+    pub coinbase_tx_prefix: Bytes0_64k,
+    pub coinbase_tx_suffix: Bytes0_64k,
+}
+
+/// `new_mining_job_token` replaces the allocated token and identifies the committed job in
+/// `SetCustomMiningJob`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CommitMiningJobSuccess {
+    pub req_id: u32,
+    pub new_mining_job_token: Bytes0_255,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CommitMiningJobError {
+    pub req_id: u32,
+    pub code: Str0_32,
+}
+
+impl_job_negotiation_message_conversion!(
+    AllocateMiningJobToken,
+    false,
+    visit_allocate_mining_job_token
+);
+impl_job_negotiation_message_conversion!(
+    AllocateMiningJobTokenSuccess,
+    false,
+    visit_allocate_mining_job_token_success
+);
+impl_job_negotiation_message_conversion!(CommitMiningJob, false, visit_commit_mining_job);
+impl_job_negotiation_message_conversion!(
+    CommitMiningJobSuccess,
+    false,
+    visit_commit_mining_job_success
+);
+impl_job_negotiation_message_conversion!(
+    CommitMiningJobError,
+    false,
+    visit_commit_mining_job_error
+);
+
+/// Consumes `frame` and produces a Message object based on the payload type
+pub fn build_message_from_frame(frame: framing::Frame) -> Result<Message<Protocol>> {
+    trace!(
+        "V2: building job negotiation message from frame {:x?}",
+        frame
+    );
+
+    if frame.header.extension_type != extensions::JOB_NEGOTIATION {
+        return Err(error::ErrorKind::UnknownMessage(
+            format!(
+                "Unexpected extension type, full header: {:x?}",
+                frame.header
+            )
+            .into(),
+        )
+        .into());
+    }
+    // Job negotiation frames always carry serialized payload (see
+    // `impl_standalone_message_conversion`), the header is copied before the frame is consumed
+    let header = frame.header.clone();
+    let payload: Box<dyn AnyPayload<Protocol>> = match MessageType::from_primitive(
+        frame.header.msg_type,
+    )
+    .ok_or(error::ErrorKind::UnknownMessage(
+        format!("Unexpected payload type, full header: {:x?}", frame.header).into(),
+    ))? {
+        MessageType::AllocateMiningJobToken => Box::new(AllocateMiningJobToken::try_from(frame)?),
+        MessageType::AllocateMiningJobTokenSuccess => {
+            Box::new(AllocateMiningJobTokenSuccess::try_from(frame)?)
+        }
+        MessageType::CommitMiningJob => Box::new(CommitMiningJob::try_from(frame)?),
+        MessageType::CommitMiningJobSuccess => Box::new(CommitMiningJobSuccess::try_from(frame)?),
+        MessageType::CommitMiningJobError => Box::new(CommitMiningJobError::try_from(frame)?),
+    };
+
+    Ok(Message { header, payload })
+}
@@ -83,3 +83,59 @@ macro_rules! impl_message_conversion {
         }
     };
 }
+
+/// Generates conversion for a message of an extension that defines its own `Protocol` (and
+/// therefore its own handler). Frames can only carry lazily serialized payloads of the base
+/// protocol, the message is thus serialized right away when building the frame.
+/// The parameters have the same meaning as for `impl_message_conversion`
+#[macro_export]
+macro_rules! impl_standalone_message_conversion {
+    ($extension_id:expr, $message:tt, $is_channel_msg:expr, $handler_fn:tt) => {
+        impl TryFrom<$message> for framing::Frame {
+            type Error = Error;
+
+            fn try_from(m: $message) -> Result<Self> {
+                let payload = serialization::to_vec(&m)?;
+                Ok(framing::Frame::from_serialized_payload(
+                    $is_channel_msg,
+                    $extension_id,
+                    MessageType::$message as framing::MsgType,
+                    ii_async_compat::bytes::BytesMut::from(&payload[..]),
+                ))
+            }
+        }
+
+        impl TryFrom<&[u8]> for $message {
+            type Error = Error;
+
+            fn try_from(msg: &[u8]) -> Result<Self> {
+                serialization::from_slice(msg).map_err(Into::into)
+            }
+        }
+
+        impl TryFrom<framing::Frame> for $message {
+            type Error = Error;
+
+            fn try_from(frame: framing::Frame) -> Result<Self> {
+                let (_header, payload) = frame.split();
+                let payload = payload.into_bytes_mut()?;
+                Self::try_from(&payload[..])
+            }
+        }
+
+        #[async_trait]
+        impl AnyPayload<Protocol> for $message {
+            async fn accept(
+                &self,
+                header: &<Protocol as crate::Protocol>::Header,
+                handler: &mut <Protocol as crate::Protocol>::Handler,
+            ) {
+                handler.$handler_fn(header, self).await;
+            }
+
+            fn serialize_to_writer(&self, writer: &mut dyn std::io::Write) -> Result<()> {
+                serialization::to_writer(writer, self).map_err(Into::into)
+            }
+        }
+    };
+}
@@ -78,7 +78,7 @@ pub enum MessageType {
     SetTarget = 0x21,
     SetCustomMiningJob = 0x22,
     SetCustomMiningJobSuccess = 0x23,
-    SetCustomMiningError = 0x24,
+    SetCustomMiningJobError = 0x24,
     Reconnect = 0x25,
     SetGroupChannel = 0x26,
 }
@@ -134,12 +134,60 @@ pub struct OpenStandardMiningChannelError {
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
-pub struct UpdateChannel;
+pub struct OpenExtendedMiningChannel {
+    pub req_id: u32,
+    pub user: Str1_255,
+    pub nominal_hashrate: f32,
+    pub max_target: Uint256Bytes,
+    /// Minimum size of extranonce space the device needs for rolling on its own
+    pub min_extranonce_size: u16,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OpenExtendedMiningChannelSuccess {
+    pub req_id: u32,
+    pub channel_id: u32,
+    /// Initial target for mining
+    pub target: Uint256Bytes,
+    /// Size of the extranonce space (excluding the prefix) available to the device
+    pub extranonce_size: u16,
+    pub extranonce_prefix: Bytes0_32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct OpenExtendedMiningChannelError {
+    pub req_id: u32,
+    pub code: Str0_32,
+}
+
+/// Notifies the upstream about a change of the nominal hashrate of the channel
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UpdateChannel {
+    pub channel_id: u32,
+    pub nominal_hashrate: f32,
+    pub max_target: Uint256Bytes,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UpdateChannelError {
+    pub channel_id: u32,
+    pub code: Str0_32,
+}
 
+/// Closes the channel, `reason_code` describes why the channel is no longer used
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
-pub struct UpdateChannelError;
+pub struct CloseChannel {
+    pub channel_id: u32,
+    pub reason_code: Str0_32,
+}
 
-pub struct CloseChannel;
+/// Changes extranonce prefix of an extended channel, the new prefix applies to jobs sent after
+/// this message
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SetExtranoncePrefix {
+    pub channel_id: u32,
+    pub extranonce_prefix: Bytes0_32,
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct SubmitSharesStandard {
@@ -152,6 +200,20 @@ pub struct SubmitSharesStandard {
     pub version: u32,
 }
 
+/// Share submitted on an extended channel, `extranonce` is the part rolled by the device, i.e.
+/// without the channel extranonce prefix
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SubmitSharesExtended {
+    pub channel_id: u32,
+    pub seq_num: u32,
+    pub job_id: u32,
+
+    pub nonce: u32,
+    pub ntime: u32,
+    pub version: u32,
+    pub extranonce: Bytes0_32,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct SubmitSharesSuccess {
     pub channel_id: u32,
@@ -176,7 +238,19 @@ pub struct NewMiningJob {
     pub merkle_root: Uint256Bytes,
 }
 
-pub struct NewExtendedMiningJob;
+/// Mining job for extended channels. The coinbase transaction is assembled as:
+/// `coinbase_tx_prefix + extranonce_prefix + extranonce + coinbase_tx_suffix`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct NewExtendedMiningJob {
+    pub channel_id: u32,
+    pub job_id: u32,
+    pub future_job: bool,
+    pub version: u32,
+    pub version_rolling_allowed: bool,
+    pub merkle_path: Seq0_255<Uint256Bytes>,
+    pub coinbase_tx_prefix: Bytes0_64k,
+    pub coinbase_tx_suffix: Bytes0_64k,
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct SetNewPrevHash {
@@ -189,9 +263,46 @@ pub struct SetNewPrevHash {
     //pub signature: ??,
 }
 
-pub struct SetCustomMiningJob;
-pub struct SetCustomMiningJobSuccess;
-pub struct Reconnect;
+/// Custom job that has been negotiated with the pool via the job negotiation extension. The
+/// `mining_job_token` is the token obtained upon committing the job (see
+/// `job_negotiation::messages::CommitMiningJobSuccess`).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SetCustomMiningJob {
+    pub channel_id: u32,
+    pub req_id: u32,
+    pub mining_job_token: Bytes0_255,
+    pub version: u32,
+    pub prev_hash: Uint256Bytes,
+    pub min_ntime: u32,
+    pub nbits: u32,
+    pub merkle_path: Seq0_255<Uint256Bytes>,
+    pub coinbase_tx_prefix: Bytes0_64k,
+    pub coinbase_tx_suffix: Bytes0_64k,
+    pub future_job: bool,
+}
+
+/// The pool accepted the custom job, shares for it are to be submitted with `job_id`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SetCustomMiningJobSuccess {
+    pub channel_id: u32,
+    pub req_id: u32,
+    pub job_id: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SetCustomMiningJobError {
+    pub channel_id: u32,
+    pub req_id: u32,
+    pub code: Str0_32,
+}
+
+/// Instructs the downstream to reconnect to a different endpoint. Empty `new_host` means that the
+/// current host is to be kept and only the port changes.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Reconnect {
+    pub new_host: Str0_255,
+    pub new_port: u16,
+}
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct SetTarget {
@@ -199,7 +310,13 @@ pub struct SetTarget {
     pub max_target: Uint256Bytes,
 }
 
-pub struct SetGroupChannel;
+/// Adds the listed standard channels into a group channel. Jobs and new prevhash messages sent
+/// to the group channel apply to all of its member channels.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SetGroupChannel {
+    pub group_channel_id: u32,
+    pub channel_ids: Seq0_64k<u32>,
+}
 
 impl_base_message_conversion!(SetupConnection, false, visit_setup_connection);
 impl_base_message_conversion!(
@@ -223,11 +340,43 @@ impl_base_message_conversion!(
     false,
     visit_open_standard_mining_channel_error
 );
+impl_base_message_conversion!(
+    OpenExtendedMiningChannel,
+    false,
+    visit_open_extended_mining_channel
+);
+impl_base_message_conversion!(
+    OpenExtendedMiningChannelSuccess,
+    false,
+    visit_open_extended_mining_channel_success
+);
+impl_base_message_conversion!(
+    OpenExtendedMiningChannelError,
+    false,
+    visit_open_extended_mining_channel_error
+);
 impl_base_message_conversion!(UpdateChannel, true, visit_update_channel);
 impl_base_message_conversion!(UpdateChannelError, true, visit_update_channel_error);
+impl_base_message_conversion!(CloseChannel, true, visit_close_channel);
 impl_base_message_conversion!(SubmitSharesStandard, true, visit_submit_shares_standard);
+impl_base_message_conversion!(SubmitSharesExtended, true, visit_submit_shares_extended);
 impl_base_message_conversion!(SubmitSharesSuccess, true, visit_submit_shares_success);
 impl_base_message_conversion!(SubmitSharesError, true, visit_submit_shares_error);
 impl_base_message_conversion!(NewMiningJob, true, visit_new_mining_job);
+impl_base_message_conversion!(NewExtendedMiningJob, true, visit_new_extended_mining_job);
+impl_base_message_conversion!(SetExtranoncePrefix, true, visit_set_extranonce_prefix);
 impl_base_message_conversion!(SetNewPrevHash, true, visit_set_new_prev_hash);
 impl_base_message_conversion!(SetTarget, true, visit_set_target);
+impl_base_message_conversion!(SetCustomMiningJob, true, visit_set_custom_mining_job);
+impl_base_message_conversion!(
+    SetCustomMiningJobSuccess,
+    true,
+    visit_set_custom_mining_job_success
+);
+impl_base_message_conversion!(
+    SetCustomMiningJobError,
+    true,
+    visit_set_custom_mining_job_error
+);
+impl_base_message_conversion!(SetGroupChannel, false, visit_set_group_channel);
+impl_base_message_conversion!(Reconnect, false, visit_reconnect);
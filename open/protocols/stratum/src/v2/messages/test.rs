@@ -55,3 +55,18 @@ fn test_serialize_setup_connection() {
         serialized_message
     );
 }
+
+/// Verify that all variable length fields of the extended job survive serialization
+#[test]
+fn test_new_extended_mining_job_round_trip() {
+    let message = build_new_extended_mining_job();
+    let mut writer = bytes::BytesMut::new().writer();
+    message
+        .serialize_to_writer(&mut writer)
+        .expect("Cannot serialize message");
+    let serialized_message = writer.into_inner();
+
+    let deserialized =
+        NewExtendedMiningJob::try_from(&serialized_message[..]).expect("Deserialization failed");
+    assert_eq!(message, deserialized, "Deserialization is not correct");
+}
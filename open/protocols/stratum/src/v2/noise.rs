@@ -82,14 +82,27 @@ pub fn generate_keypair() -> Result<StaticKeypair> {
 pub struct Initiator {
     stage: usize,
     handshake_state: HandshakeState,
-    /// Public key that the Initiatior will use to construct a 'Certificate' on the fly from
+    /// Public keys that the Initiatior will use to construct a 'Certificate' on the fly from
     /// the SignatureNoiseMessage and of the static public key of the `Responder` and will verify
-    /// the authenticity of the static public key of the Responder
-    authority_public_key: ed25519_dalek::PublicKey,
+    /// the authenticity of the static public key of the Responder. The Responder is authentic
+    /// when its certificate has been signed by any of the keys.
+    authority_public_keys: Vec<ed25519_dalek::PublicKey>,
 }
 
 impl Initiator {
     pub fn new(authority_public_key: ed25519_dalek::PublicKey) -> Self {
+        Self::with_authority_public_keys(vec![authority_public_key])
+    }
+
+    /// Builds Initiator that accepts certificates signed by any of `authority_public_keys`, e.g.
+    /// while the authority of the Responder is being rotated
+    pub fn with_authority_public_keys(
+        authority_public_keys: Vec<ed25519_dalek::PublicKey>,
+    ) -> Self {
+        assert!(
+            !authority_public_keys.is_empty(),
+            "BUG: no authority public key provided"
+        );
         let params: NoiseParams = PARAMS.parse().expect("BUG: cannot parse noise parameters");
 
         // Initialize our initiator using a builder.
@@ -101,7 +114,7 @@ impl Initiator {
         Self {
             stage: 0,
             handshake_state,
-            authority_public_key,
+            authority_public_keys,
         }
     }
 
@@ -130,14 +143,19 @@ impl Initiator {
         let signature_noise_message =
             auth::SignatureNoiseMessage::try_from(&signature_noise_message[..])?;
 
-        let certificate = auth::Certificate::from_noise_message(
-            signature_noise_message,
-            remote_static_key,
-            self.authority_public_key,
-        );
-        certificate
-            .validate()
-            .context("Validation of certificate")?;
+        let mut result = Ok(());
+        for authority_public_key in self.authority_public_keys.iter() {
+            let certificate = auth::Certificate::from_noise_message(
+                signature_noise_message.clone(),
+                remote_static_key.clone(),
+                *authority_public_key,
+            );
+            result = certificate.validate();
+            if result.is_ok() {
+                break;
+            }
+        }
+        result.context("Validation of certificate")?;
 
         Ok(())
     }
@@ -342,9 +360,16 @@ pub(crate) mod test {
         let (signature_noise_message, authority_keypair, static_keypair) =
             build_serialized_signature_noise_message_and_keypairs();
 
-        let mut initiator = Initiator::new(authority_keypair.public);
+        let initiator = Initiator::new(authority_keypair.public);
+        let responder = Responder::new(&static_keypair, signature_noise_message);
+        run_handshake(initiator, responder)
+    }
 
-        let mut responder = Responder::new(&static_keypair, signature_noise_message);
+    /// Performs all handshake steps between `initiator` and `responder`
+    fn run_handshake(
+        mut initiator: Initiator,
+        mut responder: Responder,
+    ) -> (TransportMode, TransportMode) {
         let mut initiator_in_msg: Option<handshake::Message> = None;
 
         // Verify that responder expects to receive the first message
@@ -433,6 +458,56 @@ pub(crate) mod test {
         assert_eq!(&message[..], &decrypted_msg, "Messages don't match");
     }
 
+    /// Verifies that initiator accepts certificate signed by any of its authority keys
+    #[test]
+    fn test_handshake_multiple_authority_keys() {
+        let (signature_noise_message, authority_keypair, static_keypair) =
+            build_serialized_signature_noise_message_and_keypairs();
+        let other_authority_keypair = ed25519_dalek::Keypair::generate(&mut rand::rngs::OsRng {});
+
+        let initiator = Initiator::with_authority_public_keys(vec![
+            other_authority_keypair.public,
+            authority_keypair.public,
+        ]);
+        let responder = Responder::new(&static_keypair, signature_noise_message);
+        run_handshake(initiator, responder);
+    }
+
+    /// Verifies that initiator refuses certificate that none of its authority keys has signed
+    #[test]
+    fn test_handshake_unknown_authority_key() {
+        let (signature_noise_message, _authority_keypair, static_keypair) =
+            build_serialized_signature_noise_message_and_keypairs();
+        let other_authority_keypair = ed25519_dalek::Keypair::generate(&mut rand::rngs::OsRng {});
+
+        let mut initiator =
+            Initiator::with_authority_public_keys(vec![other_authority_keypair.public]);
+        let mut responder = Responder::new(&static_keypair, signature_noise_message);
+        responder
+            .step(None, BytesMut::new())
+            .expect("BUG: responder failed in the first step");
+        let initiator_out_msg = match initiator
+            .step(None, BytesMut::new())
+            .expect("BUG: initiator failed in the first step")
+        {
+            handshake::StepResult::ExpectReply(msg) => msg,
+            _ => panic!("BUG: initiator didn't provide the first message"),
+        };
+        let responder_out_msg = match responder
+            .step(Some(initiator_out_msg), BytesMut::new())
+            .expect("BUG: responder failed")
+        {
+            handshake::StepResult::NoMoreReply(msg) => msg,
+            _ => panic!("BUG: responder didn't provide its reply"),
+        };
+        assert!(
+            initiator
+                .step(Some(responder_out_msg), BytesMut::new())
+                .is_err(),
+            "Certificate of unknown authority must be refused"
+        );
+    }
+
     fn bind_test_server() -> Option<(ii_wire::Server, ii_wire::Address)> {
         const ADDR: &'static str = "127.0.0.1";
         const MIN_PORT: u16 = 9999;
@@ -261,6 +261,10 @@ impl Certificate {
         signed_part.verify_expiration(SystemTime::now())
     }
 
+    pub fn signed_part_header(&self) -> &SignedPartHeader {
+        &self.signed_part_header
+    }
+
     pub fn from_noise_message(
         signature_noise_message: SignatureNoiseMessage,
         pubkey: StaticPublicKey,
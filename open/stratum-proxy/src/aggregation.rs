@@ -0,0 +1,275 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Aggregation of downstream V2 connections onto a limited number of upstream V1 sessions. All
+//! channels of a session share its extranonce 2 space, each channel mines in its own partition
+//! derived from the channel ID.
+
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::time;
+
+use futures::channel::mpsc;
+use futures::pin_mut;
+
+use ii_async_compat::prelude::*;
+use ii_async_compat::select;
+use ii_logging::macros::*;
+use ii_stratum::v2;
+
+use crate::error::Result;
+use crate::limits::{ConnectionLimits, DownstreamMonitor};
+use crate::server::ConnTranslation;
+use crate::translation::{DownstreamId, V2ToV1Translation};
+use crate::upstream::{UpstreamConnection, UpstreamConnector, V1Upstream};
+
+/// Events that downstream connections deliver to the shared session
+enum SessionEvent {
+    /// New downstream connection along with the queue for its outgoing frames and its peer
+    /// address
+    Attach(DownstreamId, mpsc::Sender<v2::Frame>, SocketAddr),
+    /// Frame received via downstream connection
+    Frame(DownstreamId, v2::Frame),
+    /// Downstream connection has been closed
+    Detach(DownstreamId),
+}
+
+/// Upstream V1 session shared by multiple downstream connections. The session fails over to
+/// another upstream server when the upstream is lost, all downstream connections of the session
+/// are closed when no server is available.
+struct SharedSession {
+    /// Index of the session in the pool, used for logging only
+    index: usize,
+    upstream_connector: UpstreamConnector,
+    event_rx: mpsc::Receiver<SessionEvent>,
+}
+
+impl SharedSession {
+    /// All channels of the session submit their shares via a single upstream connection
+    const MAX_UPSTREAM_CHANNEL_SIZE: usize = 1000;
+
+    async fn run(mut self) {
+        loop {
+            let v1_conn = match self.upstream_connector.connect().await {
+                Ok(v1_conn) => v1_conn,
+                Err(e) => {
+                    info!("Shared session {}: {}", self.index, e);
+                    continue;
+                }
+            };
+            match self.run_session(v1_conn).await {
+                // The pool has been dropped
+                Ok(()) => break,
+                Err(e) => info!("Shared session {} terminated: {}", self.index, e),
+            }
+        }
+    }
+
+    async fn run_session(&mut self, v1_conn: UpstreamConnection) -> Result<()> {
+        info!(
+            "Shared session {} established with upstream V1 {}",
+            self.index, v1_conn.peer_addr
+        );
+        let (mut v1, v1_translation_tx) = V1Upstream::new(
+            self.upstream_connector.clone(),
+            v1_conn,
+            Self::MAX_UPSTREAM_CHANNEL_SIZE,
+        );
+        // All downstream connections of the session are released along with the translation
+        let mut translation = V2ToV1Translation::new_shared(
+            v1_translation_tx,
+            self.upstream_connector.translation_options(),
+        );
+        translation.set_upstream_addr(v1.peer_addr);
+
+        // Submits that are not confirmed by the upstream in time are periodically reported,
+        // the difficulty of idle channels is adjusted at the same time
+        let mut submit_timeout_check =
+            tokio::time::interval(V2ToV1Translation::V1_SUBMIT_TIMEOUT / 2);
+
+        loop {
+            select! {
+                v1_frame = v1.conn_rx.next().timeout(ConnTranslation::V1_UPSTREAM_TIMEOUT).fuse() => {
+                    v1.handle_frame(&mut translation, v1_frame).await?;
+                },
+                // Send out frames translated into V1
+                v1_frame = v1.translation_rx.next().fuse() => {
+                    v1.send_frame(&mut translation, v1_frame).await?;
+                },
+                // More preferred upstream server has recovered
+                v1_conn = v1.recovery_rx.select_next_some() => {
+                    v1.switch(&mut translation, v1_conn);
+                },
+                event = self.event_rx.next().fuse() => {
+                    match event {
+                        Some(event) => Self::handle_event(&mut translation, event).await,
+                        None => return Ok(()),
+                    }
+                },
+                _ = submit_timeout_check.tick().fuse() => {
+                    translation.report_timed_out_submits(time::Instant::now());
+                    translation.retarget_channels(time::Instant::now());
+                },
+            }
+        }
+    }
+
+    async fn handle_event(translation: &mut V2ToV1Translation, event: SessionEvent) {
+        match event {
+            SessionEvent::Attach(downstream_id, v2_tx, peer_addr) => {
+                translation.add_downstream(downstream_id, v2_tx);
+                translation.set_downstream_peer_addr(downstream_id, peer_addr);
+            }
+            SessionEvent::Frame(downstream_id, v2_frame) => {
+                Self::v2_handle_frame(translation, downstream_id, v2_frame).await
+            }
+            SessionEvent::Detach(downstream_id) => translation.remove_downstream(downstream_id),
+        }
+    }
+
+    async fn v2_handle_frame(
+        translation: &mut V2ToV1Translation,
+        downstream_id: DownstreamId,
+        frame: v2::Frame,
+    ) {
+        match frame.header.extension_type {
+            v2::extensions::BASE => match v2::build_message_from_frame(frame) {
+                Ok(event_msg) => {
+                    translation
+                        .accept_downstream_message(downstream_id, event_msg)
+                        .await
+                }
+                Err(e) => info!("Downstream {}: malformed message: {}", downstream_id, e),
+            },
+            // Report any other extension down the line
+            _ => warn!("Unsupported extension frame: {:x?} ", frame),
+        }
+    }
+}
+
+/// Pool of upstream V1 sessions, downstream connections are assigned to the sessions in a round
+/// robin fashion
+pub struct SessionPool {
+    /// Event queues of the shared sessions
+    sessions: Vec<mpsc::Sender<SessionEvent>>,
+    /// Limits of the downstream connections joining the sessions
+    connection_limits: ConnectionLimits,
+    next_session: AtomicUsize,
+    next_downstream_id: AtomicU32,
+}
+
+impl SessionPool {
+    const MAX_EVENT_CHANNEL_SIZE: usize = 1000;
+    const MAX_DOWNSTREAM_CHANNEL_SIZE: usize = 10;
+
+    /// Starts `session_count` sessions with the upstream servers of `upstream_connector`
+    pub fn new(upstream_connector: UpstreamConnector, session_count: usize) -> Self {
+        assert!(session_count > 0, "BUG: no upstream sessions");
+        let sessions = (0..session_count)
+            .map(|index| {
+                let (event_tx, event_rx) = mpsc::channel(Self::MAX_EVENT_CHANNEL_SIZE);
+                let session = SharedSession {
+                    index,
+                    upstream_connector: upstream_connector.clone(),
+                    event_rx,
+                };
+                tokio::spawn(session.run());
+                event_tx
+            })
+            .collect();
+
+        Self {
+            sessions,
+            connection_limits: upstream_connector.connection_limits(),
+            next_session: AtomicUsize::new(0),
+            next_downstream_id: AtomicU32::new(0),
+        }
+    }
+
+    /// Serves downstream connection `v2_conn` until it is closed by the peer or by the shared
+    /// session
+    pub async fn handle_downstream(
+        &self,
+        v2_conn: v2::Framed,
+        v2_peer_addr: SocketAddr,
+    ) -> Result<()> {
+        let downstream_id = self.next_downstream_id.fetch_add(1, Ordering::Relaxed);
+        let session_index = self.next_session.fetch_add(1, Ordering::Relaxed) % self.sessions.len();
+        let mut event_tx = self.sessions[session_index].clone();
+        info!(
+            "Downstream {} (peer: {}) joins shared session {}",
+            downstream_id, v2_peer_addr, session_index
+        );
+
+        let (v2_tx, v2_rx) = mpsc::channel(Self::MAX_DOWNSTREAM_CHANNEL_SIZE);
+        event_tx
+            .send(SessionEvent::Attach(downstream_id, v2_tx, v2_peer_addr))
+            .await
+            .map_err(|_| "Shared session terminated")?;
+
+        let v2_monitor = DownstreamMonitor::new(&self.connection_limits, v2_peer_addr);
+        let result = Self::forward_frames(
+            downstream_id,
+            v2_conn,
+            v2_peer_addr,
+            v2_monitor,
+            v2_rx,
+            &mut event_tx,
+        )
+        .await;
+        // The session may have been terminated already
+        let _ = event_tx.send(SessionEvent::Detach(downstream_id)).await;
+        result
+    }
+
+    /// Delivers frames from the downstream connection to the session and vice versa. The
+    /// session closes the connection by dropping the sender of `v2_rx`.
+    async fn forward_frames(
+        downstream_id: DownstreamId,
+        v2_conn: v2::Framed,
+        v2_peer_addr: SocketAddr,
+        mut v2_monitor: DownstreamMonitor,
+        v2_rx: mpsc::Receiver<v2::Frame>,
+        event_tx: &mut mpsc::Sender<SessionEvent>,
+    ) -> Result<()> {
+        let (v2_conn_tx, mut v2_conn_rx) = v2_conn.split();
+        let v2_send_task = ConnTranslation::v2_send_task(v2_conn_tx, v2_rx, v2_peer_addr).fuse();
+        pin_mut!(v2_send_task);
+
+        loop {
+            select! {
+                result = v2_send_task => return result,
+                v2_frame = v2_monitor.next_frame(&mut v2_conn_rx).fuse() => {
+                    match v2_frame? {
+                        Some(v2_frame) => event_tx
+                            .send(SessionEvent::Frame(downstream_id, v2_frame?))
+                            .await
+                            .map_err(|_| "Shared session terminated")?,
+                        None => {
+                            Err(format!("V2 client disconnected ({:?})", v2_peer_addr))?;
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,589 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Configuration of the proxy. The proxy is configured either by the command line options alone
+//! or by a TOML configuration file that may declare several listeners, e.g.:
+//!
+//! ```toml
+//! metrics_listen = "0.0.0.0:9090"
+//!
+//! [[listener]]
+//! listen = "0.0.0.0:3336"
+//! v1_listen = "0.0.0.0:3333"
+//! upstream = ["pool.example.com:3333", "backup.example.com:3333"]
+//! certificate_file = "server.cert"
+//! secret_key_file = "server.key"
+//! aggregate = 4
+//!
+//! [listener.translation]
+//! try_enable_xnsub = true
+//! vardiff_shares_per_minute = 20
+//!
+//! [[listener]]
+//! listen = "0.0.0.0:3337"
+//! upstream = ["v2.example.com:3336"]
+//! upstream_protocol = "v2"
+//! upstream_authority_keys = ["fw4SfogGgTvMsWz8G4Rp7a6Hsm1y4eUYNzSNJmKuuhPkCFz9G"]
+//! insecure = true
+//! ```
+//!
+//! Options specified on the command line override the values in the file for all listeners,
+//! the listen addresses can be overridden only when the file declares a single listener. Relative
+//! paths in the file are relative to the working directory of the proxy.
+
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::iter;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use ii_stratum::v2;
+use ii_wire::Address;
+
+use crate::error::{Error, Result, ResultExt};
+use crate::frontend::{self, read_from_file, Args, UpstreamProtocol};
+use crate::routing::RoutingRules;
+use crate::vardiff::VarDiffOptions;
+
+#[cfg(test)]
+mod test;
+
+/// Default address for incoming Stratum V2 connections
+const DEFAULT_LISTEN_ADDRESS: (&str, u16) = ("localhost", 3336);
+
+/// Translation options of a listener as they are written in the configuration file
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct TranslationFileConfig {
+    try_enable_xnsub: Option<bool>,
+    vardiff_shares_per_minute: Option<f64>,
+    vardiff_min_difficulty: Option<u32>,
+    vardiff_retarget_interval: Option<u64>,
+}
+
+/// Listener as it is written in the configuration file
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct ListenerFileConfig {
+    listen: Option<String>,
+    v1_listen: Option<String>,
+    upstream: Option<Vec<String>>,
+    upstream_protocol: Option<String>,
+    upstream_authority_keys: Option<Vec<String>>,
+    insecure_upstream: Option<bool>,
+    insecure: Option<bool>,
+    certificate_file: Option<PathBuf>,
+    secret_key_file: Option<PathBuf>,
+    aggregate: Option<usize>,
+    routing_rules: Option<PathBuf>,
+    proxy_protocol: Option<bool>,
+    #[serde(default)]
+    translation: TranslationFileConfig,
+}
+
+/// Contents of the configuration file
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    metrics_listen: Option<String>,
+    #[serde(default)]
+    listener: Vec<ListenerFileConfig>,
+}
+
+fn parse_address(addr: &str, descr: &str) -> Result<Address> {
+    addr.parse::<Address>()
+        .map_err(|_| format!("Invalid {} address: {}", descr, addr).into())
+}
+
+/// Converts a command line flag to a setting that overrides the file only when the flag is present
+fn flag(present: bool) -> Option<bool> {
+    match present {
+        true => Some(true),
+        false => None,
+    }
+}
+
+/// Settings of a listener that haven't been validated yet, missing settings take their default
+/// values during validation
+#[derive(Debug, Default)]
+struct ListenerSettings {
+    listen_address: Option<Address>,
+    v1_listen_address: Option<Address>,
+    upstream_addresses: Option<Vec<Address>>,
+    upstream_protocol: Option<UpstreamProtocol>,
+    upstream_authority_public_keys: Option<Vec<v2::noise::auth::EncodedEd25519PublicKey>>,
+    insecure_upstream: Option<bool>,
+    insecure: Option<bool>,
+    certificate_file: Option<PathBuf>,
+    secret_key_file: Option<PathBuf>,
+    upstream_sessions: Option<usize>,
+    routing_rules_file: Option<PathBuf>,
+    proxy_protocol: Option<bool>,
+    try_enable_xnsub: Option<bool>,
+    vardiff_shares_per_minute: Option<f64>,
+    vardiff_min_difficulty: Option<u32>,
+    vardiff_retarget_interval: Option<u64>,
+}
+
+impl ListenerSettings {
+    /// Replaces settings with those present in `overrides`. The security settings are replaced
+    /// as a whole so that e.g. `insecure` from the command line disables the certificate
+    /// configured in the file.
+    fn override_with(self, overrides: &Self) -> Self {
+        let security_overridden = overrides.insecure.is_some()
+            || overrides.certificate_file.is_some()
+            || overrides.secret_key_file.is_some();
+        let (insecure, certificate_file, secret_key_file) = match security_overridden {
+            true => (
+                overrides.insecure,
+                overrides.certificate_file.clone(),
+                overrides.secret_key_file.clone(),
+            ),
+            false => (self.insecure, self.certificate_file, self.secret_key_file),
+        };
+        Self {
+            listen_address: overrides.listen_address.clone().or(self.listen_address),
+            v1_listen_address: overrides
+                .v1_listen_address
+                .clone()
+                .or(self.v1_listen_address),
+            upstream_addresses: overrides
+                .upstream_addresses
+                .clone()
+                .or(self.upstream_addresses),
+            upstream_protocol: overrides.upstream_protocol.or(self.upstream_protocol),
+            upstream_authority_public_keys: overrides
+                .upstream_authority_public_keys
+                .clone()
+                .or(self.upstream_authority_public_keys),
+            insecure_upstream: overrides.insecure_upstream.or(self.insecure_upstream),
+            insecure,
+            certificate_file,
+            secret_key_file,
+            upstream_sessions: overrides.upstream_sessions.or(self.upstream_sessions),
+            routing_rules_file: overrides
+                .routing_rules_file
+                .clone()
+                .or(self.routing_rules_file),
+            proxy_protocol: overrides.proxy_protocol.or(self.proxy_protocol),
+            try_enable_xnsub: overrides.try_enable_xnsub.or(self.try_enable_xnsub),
+            vardiff_shares_per_minute: overrides
+                .vardiff_shares_per_minute
+                .or(self.vardiff_shares_per_minute),
+            vardiff_min_difficulty: overrides
+                .vardiff_min_difficulty
+                .or(self.vardiff_min_difficulty),
+            vardiff_retarget_interval: overrides
+                .vardiff_retarget_interval
+                .or(self.vardiff_retarget_interval),
+        }
+    }
+}
+
+impl From<&Args> for ListenerSettings {
+    fn from(args: &Args) -> Self {
+        Self {
+            listen_address: args.listen_address.clone(),
+            v1_listen_address: args.v1_listen_address.clone(),
+            upstream_addresses: Some(args.upstream_addresses.clone())
+                .filter(|addrs| !addrs.is_empty()),
+            upstream_protocol: args.upstream_protocol,
+            upstream_authority_public_keys: Some(args.upstream_authority_public_keys.clone())
+                .filter(|keys| !keys.is_empty()),
+            insecure_upstream: flag(args.insecure_upstream),
+            insecure: flag(args.insecure),
+            certificate_file: args.certificate_file.clone(),
+            secret_key_file: args.secret_key_file.clone(),
+            upstream_sessions: args.upstream_sessions,
+            routing_rules_file: args.routing_rules_file.clone(),
+            proxy_protocol: flag(args.proxy_protocol),
+            try_enable_xnsub: None,
+            vardiff_shares_per_minute: args.vardiff_shares_per_minute,
+            vardiff_min_difficulty: args.vardiff_min_difficulty,
+            vardiff_retarget_interval: args.vardiff_retarget_interval,
+        }
+    }
+}
+
+impl TryFrom<ListenerFileConfig> for ListenerSettings {
+    type Error = Error;
+
+    fn try_from(config: ListenerFileConfig) -> Result<Self> {
+        let parse_addresses = |addrs: Vec<String>| {
+            addrs
+                .iter()
+                .map(|addr| parse_address(addr, "upstream"))
+                .collect::<Result<Vec<_>>>()
+        };
+        Ok(Self {
+            listen_address: config
+                .listen
+                .map(|addr| parse_address(&addr, "listen"))
+                .transpose()?,
+            v1_listen_address: config
+                .v1_listen
+                .map(|addr| parse_address(&addr, "V1 listen"))
+                .transpose()?,
+            upstream_addresses: config.upstream.map(parse_addresses).transpose()?,
+            upstream_protocol: config
+                .upstream_protocol
+                .map(|protocol| protocol.parse())
+                .transpose()?,
+            upstream_authority_public_keys: config
+                .upstream_authority_keys
+                .map(|keys| {
+                    keys.iter()
+                        .map(|key| frontend::parse_authority_public_key(key))
+                        .collect::<Result<Vec<_>>>()
+                })
+                .transpose()?,
+            insecure_upstream: config.insecure_upstream,
+            insecure: config.insecure,
+            certificate_file: config.certificate_file,
+            secret_key_file: config.secret_key_file,
+            upstream_sessions: config.aggregate,
+            routing_rules_file: config.routing_rules,
+            proxy_protocol: config.proxy_protocol,
+            try_enable_xnsub: config.translation.try_enable_xnsub,
+            vardiff_shares_per_minute: config.translation.vardiff_shares_per_minute,
+            vardiff_min_difficulty: config.translation.vardiff_min_difficulty,
+            vardiff_retarget_interval: config.translation.vardiff_retarget_interval,
+        })
+    }
+}
+
+/// Parsed configuration file, the listeners are validated only after the command line options
+/// are applied
+#[derive(Debug)]
+pub struct ConfigFile {
+    metrics_listen_address: Option<Address>,
+    listeners: Vec<ListenerSettings>,
+}
+
+impl TryFrom<String> for ConfigFile {
+    type Error = Error;
+
+    fn try_from(config: String) -> Result<Self> {
+        let config: FileConfig = toml::from_str(&config).context("Invalid configuration file")?;
+        let metrics_listen_address = config
+            .metrics_listen
+            .map(|addr| parse_address(&addr, "metrics listen"))
+            .transpose()?;
+        let listeners = config
+            .listener
+            .into_iter()
+            .enumerate()
+            .map(|(index, listener)| {
+                ListenerSettings::try_from(listener)
+                    .context(format!("Listener #{}", index + 1))
+                    .map_err(Into::into)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self {
+            metrics_listen_address,
+            listeners,
+        })
+    }
+}
+
+/// How downstream connections of a listener are secured
+#[derive(Clone, PartialEq, Debug)]
+pub enum Security {
+    /// All services are provided unencrypted
+    Insecure,
+    /// Noise handshake authenticated by the certificate signed with the secret key
+    Certificate {
+        certificate_file: PathBuf,
+        secret_key_file: PathBuf,
+    },
+}
+
+/// Validated configuration of a single listener
+#[derive(Clone, Debug)]
+pub struct ListenerConfig {
+    /// Address for incoming Stratum V2 connections
+    pub listen_address: Address,
+    /// Optional address for incoming Stratum V1 connections
+    pub v1_listen_address: Option<Address>,
+    /// Upstream servers in the order of preference
+    pub upstream_addresses: Vec<Address>,
+    pub upstream_protocol: UpstreamProtocol,
+    /// Keys that authenticate the upstream V2 servers, there is none when the upstream is
+    /// insecure or speaks V1
+    pub upstream_authority_public_keys: Vec<v2::noise::auth::EncodedEd25519PublicKey>,
+    pub security: Security,
+    /// Number of shared upstream sessions when the connections are aggregated
+    pub upstream_sessions: Option<usize>,
+    pub routing_rules_file: Option<PathBuf>,
+    /// Expect PROXY protocol header at the start of each downstream connection
+    pub proxy_protocol: bool,
+    /// Try to send `extranonce.subscribe` to the upstream V1 servers
+    pub try_enable_xnsub: bool,
+    pub vardiff: Option<VarDiffOptions>,
+}
+
+impl ListenerConfig {
+    /// Optionally read routing rules
+    /// Return:
+    ///  - None - when no rules file is configured
+    ///  - parsed `RoutingRules` otherwise
+    pub async fn read_routing_rules(&self) -> Result<Option<RoutingRules>> {
+        match self.routing_rules_file.as_ref() {
+            Some(routing_rules_file) => Ok(Some(
+                read_from_file::<RoutingRules>(Some(routing_rules_file), "Routing rules").await?,
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Optionally read certificate and secret keypair
+    /// Return:
+    ///  - None - when the listener is insecure
+    ///  - build `Certificate` + `StaticSecretKeyFormat` pair otherwise
+    pub async fn read_certificate_secret_key_pair(
+        &self,
+    ) -> Result<
+        Option<(
+            v2::noise::auth::Certificate,
+            v2::noise::auth::StaticSecretKeyFormat,
+        )>,
+    > {
+        let certificate_key_pair = match &self.security {
+            Security::Insecure => None,
+            Security::Certificate {
+                certificate_file,
+                secret_key_file,
+            } => {
+                let certificate = read_from_file::<v2::noise::auth::Certificate>(
+                    Some(certificate_file),
+                    "Certificate",
+                )
+                .await?;
+                let secret_key = read_from_file::<v2::noise::auth::StaticSecretKeyFormat>(
+                    Some(secret_key_file),
+                    "Secret key",
+                )
+                .await?;
+                Some((certificate, secret_key))
+            }
+        };
+
+        Ok(certificate_key_pair)
+    }
+
+    /// Provides paths of the certificate and of the secret key to be watched for changes,
+    /// there is nothing to watch when the listener is insecure
+    pub fn certificate_files(&self) -> Option<(PathBuf, PathBuf)> {
+        match &self.security {
+            Security::Insecure => None,
+            Security::Certificate {
+                certificate_file,
+                secret_key_file,
+            } => Some((certificate_file.clone(), secret_key_file.clone())),
+        }
+    }
+
+    /// Addresses that the listener binds
+    fn addresses(&self) -> impl Iterator<Item = &Address> {
+        iter::once(&self.listen_address).chain(self.v1_listen_address.iter())
+    }
+}
+
+impl TryFrom<ListenerSettings> for ListenerConfig {
+    type Error = Error;
+
+    fn try_from(settings: ListenerSettings) -> Result<Self> {
+        let upstream_protocol = settings.upstream_protocol.unwrap_or(UpstreamProtocol::V1);
+        let upstream_addresses = match settings.upstream_addresses {
+            Some(addrs) if !addrs.is_empty() => addrs,
+            _ => Err("No upstream server specified")?,
+        };
+        let security = match (
+            settings.insecure.unwrap_or(false),
+            settings.certificate_file,
+            settings.secret_key_file,
+        ) {
+            (true, None, None) => Security::Insecure,
+            (true, _, _) => Err("Insecure listener cannot have a certificate or a secret key")?,
+            (false, Some(certificate_file), Some(secret_key_file)) => Security::Certificate {
+                certificate_file,
+                secret_key_file,
+            },
+            (false, None, None) => {
+                Err("Certificate and secret key are required unless the listener is insecure")?
+            }
+            (false, None, Some(_)) => Err("Secret key is configured without a certificate")?,
+            (false, Some(_), None) => Err("Certificate is configured without a secret key")?,
+        };
+        let upstream_authority_public_keys = match (
+            upstream_protocol,
+            settings.insecure_upstream.unwrap_or(false),
+            settings.upstream_authority_public_keys,
+        ) {
+            (UpstreamProtocol::V1, _, _) | (UpstreamProtocol::V2, true, _) => vec![],
+            (UpstreamProtocol::V2, false, Some(keys)) if !keys.is_empty() => keys,
+            (UpstreamProtocol::V2, false, _) => Err(
+                "Upstream authority key is required unless the upstream is configured as insecure",
+            )?,
+        };
+        if settings.upstream_sessions == Some(0) {
+            Err("At least one upstream session is required for aggregation")?;
+        }
+        let vardiff = match settings.vardiff_shares_per_minute {
+            Some(shares_per_minute) if shares_per_minute > 0.0 => {
+                let default = VarDiffOptions::default();
+                Some(VarDiffOptions {
+                    shares_per_minute,
+                    min_difficulty: settings
+                        .vardiff_min_difficulty
+                        .unwrap_or(default.min_difficulty),
+                    retarget_interval: settings
+                        .vardiff_retarget_interval
+                        .map(Duration::from_secs)
+                        .unwrap_or(default.retarget_interval),
+                })
+            }
+            Some(_) => Err("Variable difficulty share rate must be positive")?,
+            None if settings.vardiff_min_difficulty.is_some()
+                || settings.vardiff_retarget_interval.is_some() =>
+            {
+                Err("Variable difficulty is configured without a share rate")?
+            }
+            None => None,
+        };
+        let try_enable_xnsub = settings.try_enable_xnsub.unwrap_or(false);
+
+        if upstream_protocol == UpstreamProtocol::V2 {
+            if settings.upstream_sessions.is_some() {
+                Err("Aggregation is not supported with V2 upstream servers")?;
+            }
+            if settings.routing_rules_file.is_some() {
+                Err("Routing rules are not supported with V2 upstream servers")?;
+            }
+            if vardiff.is_some() {
+                Err("Variable difficulty is not supported with V2 upstream servers")?;
+            }
+            if try_enable_xnsub {
+                Err("Extranonce subscription is not supported with V2 upstream servers")?;
+            }
+        }
+
+        Ok(Self {
+            listen_address: settings
+                .listen_address
+                .unwrap_or_else(|| DEFAULT_LISTEN_ADDRESS.into()),
+            v1_listen_address: settings.v1_listen_address,
+            upstream_addresses,
+            upstream_protocol,
+            upstream_authority_public_keys,
+            security,
+            upstream_sessions: settings.upstream_sessions,
+            routing_rules_file: settings.routing_rules_file,
+            proxy_protocol: settings.proxy_protocol.unwrap_or(false),
+            try_enable_xnsub,
+            vardiff,
+        })
+    }
+}
+
+/// Validated configuration of the whole proxy
+#[derive(Debug)]
+pub struct ProxyConfig {
+    /// Optional address of the metrics service
+    pub metrics_listen_address: Option<Address>,
+    pub listeners: Vec<ListenerConfig>,
+}
+
+impl ProxyConfig {
+    /// Builds the configuration from the command line options and from the configuration file
+    /// if there is one
+    pub async fn read(args: &Args) -> Result<Self> {
+        let config_file = match args.config_file.as_ref() {
+            Some(config_file) => {
+                Some(read_from_file::<ConfigFile>(Some(config_file), "Configuration file").await?)
+            }
+            None => None,
+        };
+        Self::new(config_file, args)
+    }
+
+    /// Applies command line options in `args` to all listeners of `config_file`, the options
+    /// alone configure a single listener when there is no file
+    pub fn new(config_file: Option<ConfigFile>, args: &Args) -> Result<Self> {
+        let (metrics_listen_address, listeners) = match config_file {
+            Some(config_file) => {
+                if config_file.listeners.is_empty() {
+                    Err("Configuration file declares no listener")?;
+                }
+                (config_file.metrics_listen_address, config_file.listeners)
+            }
+            None => (None, vec![ListenerSettings::default()]),
+        };
+        if listeners.len() > 1
+            && (args.listen_address.is_some() || args.v1_listen_address.is_some())
+        {
+            Err("Listen addresses cannot be overridden when multiple listeners are configured")?;
+        }
+
+        let overrides = ListenerSettings::from(args);
+        let listeners = listeners
+            .into_iter()
+            .enumerate()
+            .map(|(index, listener)| {
+                ListenerConfig::try_from(listener.override_with(&overrides))
+                    .context(format!("Listener #{}", index + 1))
+                    .map_err(Into::into)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        for (index, listener) in listeners.iter().enumerate() {
+            for addr in listener.addresses() {
+                let other_index = listeners[..index]
+                    .iter()
+                    .position(|other| other.addresses().any(|other_addr| other_addr == addr));
+                if let Some(other_index) = other_index {
+                    Err(format!(
+                        "Listener #{}: address {} is already used by listener #{}",
+                        index + 1,
+                        addr,
+                        other_index + 1
+                    ))?;
+                }
+            }
+            if listener.v1_listen_address.as_ref() == Some(&listener.listen_address) {
+                Err(format!(
+                    "Listener #{}: V1 and V2 connections cannot share address {}",
+                    index + 1,
+                    listener.listen_address
+                ))?;
+            }
+        }
+
+        Ok(Self {
+            metrics_listen_address: args
+                .metrics_listen_address
+                .clone()
+                .or(metrics_listen_address),
+            listeners,
+        })
+    }
+}
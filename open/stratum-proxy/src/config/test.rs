@@ -0,0 +1,239 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use super::*;
+
+use structopt::StructOpt;
+
+const AUTHORITY_KEY: &str = "fw4SfogGgTvMsWz8G4Rp7a6Hsm1y4eUYNzSNJmKuuhPkCFz9G";
+
+const CONFIG: &str = r#"
+metrics_listen = "0.0.0.0:9090"
+
+[[listener]]
+listen = "0.0.0.0:3336"
+v1_listen = "0.0.0.0:3333"
+upstream = ["pool.example.com:3333", "backup.example.com:3333"]
+certificate_file = "server.cert"
+secret_key_file = "server.key"
+aggregate = 4
+
+[listener.translation]
+try_enable_xnsub = true
+vardiff_shares_per_minute = 20
+
+[[listener]]
+listen = "0.0.0.0:3337"
+upstream = ["v2.example.com:3336"]
+upstream_protocol = "v2"
+upstream_authority_keys = ["fw4SfogGgTvMsWz8G4Rp7a6Hsm1y4eUYNzSNJmKuuhPkCFz9G"]
+insecure = true
+"#;
+
+fn build_args(args: &[&str]) -> Args {
+    Args::from_iter_safe(iter::once("stratum-proxy").chain(args.iter().cloned()))
+        .expect("BUG: invalid arguments")
+}
+
+fn build_config(config: &str, args: &[&str]) -> Result<ProxyConfig> {
+    let config_file = ConfigFile::try_from(config.to_string())?;
+    ProxyConfig::new(Some(config_file), &build_args(args))
+}
+
+/// Adds listener settings to the minimal configuration file
+fn build_listener_config(settings: &str) -> Result<ProxyConfig> {
+    let config = format!(
+        "[[listener]]\nupstream = [\"pool.example.com:3333\"]\n{}",
+        settings
+    );
+    build_config(&config, &["--config", "proxy.toml"])
+}
+
+#[test]
+fn test_config_file() {
+    let config = build_config(CONFIG, &["--config", "proxy.toml"]).expect("BUG: invalid config");
+    assert_eq!(
+        config.metrics_listen_address,
+        Some(Address("0.0.0.0".to_string(), 9090))
+    );
+    assert_eq!(config.listeners.len(), 2);
+
+    let listener = &config.listeners[0];
+    assert_eq!(
+        listener.listen_address,
+        Address("0.0.0.0".to_string(), 3336)
+    );
+    assert_eq!(
+        listener.v1_listen_address,
+        Some(Address("0.0.0.0".to_string(), 3333))
+    );
+    assert_eq!(
+        listener.upstream_addresses,
+        vec![
+            Address("pool.example.com".to_string(), 3333),
+            Address("backup.example.com".to_string(), 3333),
+        ]
+    );
+    assert_eq!(listener.upstream_protocol, UpstreamProtocol::V1);
+    assert_eq!(
+        listener.security,
+        Security::Certificate {
+            certificate_file: "server.cert".into(),
+            secret_key_file: "server.key".into(),
+        }
+    );
+    assert_eq!(listener.upstream_sessions, Some(4));
+    assert!(listener.try_enable_xnsub);
+    let vardiff = listener.vardiff.expect("BUG: missing vardiff");
+    assert_eq!(vardiff.shares_per_minute, 20.0);
+    assert_eq!(
+        vardiff.min_difficulty,
+        VarDiffOptions::default().min_difficulty
+    );
+
+    let listener = &config.listeners[1];
+    assert_eq!(listener.upstream_protocol, UpstreamProtocol::V2);
+    assert_eq!(listener.security, Security::Insecure);
+    assert_eq!(listener.upstream_authority_public_keys.len(), 1);
+    assert_eq!(
+        listener.upstream_authority_public_keys[0].to_string(),
+        AUTHORITY_KEY
+    );
+    assert!(!listener.try_enable_xnsub);
+    assert!(listener.vardiff.is_none());
+}
+
+#[test]
+fn test_command_line_only() {
+    let config = ProxyConfig::new(
+        None,
+        &build_args(&["--upstream", "pool.example.com:3333", "--insecure"]),
+    )
+    .expect("BUG: invalid config");
+    assert_eq!(config.listeners.len(), 1);
+    let listener = &config.listeners[0];
+    assert_eq!(listener.listen_address, DEFAULT_LISTEN_ADDRESS.into());
+    assert_eq!(listener.upstream_protocol, UpstreamProtocol::V1);
+    assert_eq!(listener.security, Security::Insecure);
+    assert!(listener.vardiff.is_none());
+}
+
+#[test]
+fn test_command_line_overrides() {
+    let config = build_config(
+        CONFIG,
+        &[
+            "--config",
+            "proxy.toml",
+            "--metrics-listen",
+            "localhost:9091",
+            "--upstream",
+            "other.example.com:3333",
+            "--insecure",
+            "--proxy-protocol",
+        ],
+    )
+    .expect("BUG: invalid config");
+    assert_eq!(
+        config.metrics_listen_address,
+        Some(Address("localhost".to_string(), 9091))
+    );
+    for listener in config.listeners.iter() {
+        assert_eq!(
+            listener.upstream_addresses,
+            vec![Address("other.example.com".to_string(), 3333)]
+        );
+        assert_eq!(listener.security, Security::Insecure);
+        assert!(listener.proxy_protocol);
+    }
+    // Values that are not overridden are kept
+    assert_eq!(config.listeners[0].upstream_sessions, Some(4));
+
+    // Switching all listeners to V2 upstream conflicts with aggregation of the first one
+    assert!(build_config(
+        CONFIG,
+        &["--config", "proxy.toml", "--upstream-protocol", "v2"]
+    )
+    .is_err());
+    // Listen address is ambiguous with multiple listeners
+    assert!(build_config(
+        CONFIG,
+        &["--config", "proxy.toml", "--listen", "localhost:3336"]
+    )
+    .is_err());
+
+    let config = build_listener_config("insecure = true\n").expect("BUG: invalid config");
+    assert_eq!(config.listeners.len(), 1);
+}
+
+#[test]
+fn test_invalid_config() {
+    // Unknown options and malformed values
+    assert!(ConfigFile::try_from("[[listener]]\nlisten_address = \"a:1\"".to_string()).is_err());
+    assert!(ConfigFile::try_from("[[listener]]\nlisten = \"localhost\"".to_string()).is_err());
+    assert!(ConfigFile::try_from("[[listener]]\nupstream_protocol = \"v3\"".to_string()).is_err());
+    assert!(
+        ConfigFile::try_from("[[listener]]\nupstream_authority_keys = [\"abc\"]".to_string())
+            .is_err()
+    );
+    assert!(build_config("", &["--config", "proxy.toml"]).is_err());
+
+    // Missing or conflicting security
+    assert!(build_listener_config("").is_err());
+    assert!(build_listener_config("certificate_file = \"server.cert\"\n").is_err());
+    assert!(build_listener_config(
+        "insecure = true\ncertificate_file = \"server.cert\"\nsecret_key_file = \"server.key\"\n"
+    )
+    .is_err());
+
+    // Invalid upstream and translation options
+    assert!(build_config(
+        "[[listener]]\nupstream = []\ninsecure = true\n",
+        &["--config", "proxy.toml"]
+    )
+    .is_err());
+    assert!(build_listener_config("insecure = true\naggregate = 0\n").is_err());
+    assert!(build_listener_config(
+        "insecure = true\n[listener.translation]\nvardiff_shares_per_minute = 0\n"
+    )
+    .is_err());
+    assert!(build_listener_config(
+        "insecure = true\n[listener.translation]\nvardiff_min_difficulty = 8\n"
+    )
+    .is_err());
+    assert!(build_listener_config("insecure = true\nupstream_protocol = \"v2\"\n").is_err());
+    assert!(build_listener_config(
+        "insecure = true\nupstream_protocol = \"v2\"\ninsecure_upstream = true\n\
+         [listener.translation]\ntry_enable_xnsub = true\n"
+    )
+    .is_err());
+
+    // Listeners sharing an address
+    assert!(build_config(
+        "[[listener]]\nlisten = \"localhost:3336\"\nupstream = [\"pool.example.com:3333\"]\n\
+         insecure = true\n\
+         [[listener]]\nv1_listen = \"localhost:3336\"\nlisten = \"localhost:3337\"\n\
+         upstream = [\"pool.example.com:3333\"]\ninsecure = true\n",
+        &["--config", "proxy.toml"]
+    )
+    .is_err());
+}
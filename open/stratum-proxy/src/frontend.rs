@@ -22,6 +22,8 @@
 
 use std::convert::TryFrom;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 use structopt::StructOpt;
 use tokio::{fs::File, io::AsyncReadExt};
 
@@ -29,28 +31,220 @@ use ii_async_compat::tokio;
 use ii_stratum::v2;
 use ii_wire::Address;
 
-use crate::error::{Result, ResultExt};
+use crate::error::{Error, Result, ResultExt};
+use crate::limits::ConnectionLimits;
+
+/// Protocol spoken by the upstream servers
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum UpstreamProtocol {
+    /// Downstream V2 connections are translated to V1
+    V1,
+    /// Downstream V2 connections are relayed to V2
+    V2,
+}
+
+impl FromStr for UpstreamProtocol {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "v1" => Ok(UpstreamProtocol::V1),
+            "v2" => Ok(UpstreamProtocol::V2),
+            _ => Err(format!("Unknown upstream protocol: {}", s).into()),
+        }
+    }
+}
+
+pub(crate) fn parse_authority_public_key(
+    key: &str,
+) -> Result<v2::noise::auth::EncodedEd25519PublicKey> {
+    v2::noise::auth::EncodedEd25519PublicKey::try_from(key.to_string())
+        .context("Cannot parse upstream authority public key")
+        .map_err(Into::into)
+}
 
 #[derive(StructOpt, Debug)]
-#[structopt(name = "stratum-proxy", about = "Stratum V2->V1 translating proxy.")]
+#[structopt(
+    name = "stratum-proxy",
+    about = "Stratum V2 proxy that translates to V1 or relays to V2 upstream servers."
+)]
 pub struct Args {
+    /// Configuration file
+    #[structopt(
+        long = "config",
+        name = "CONFIG_FILE",
+        parse(from_os_str),
+        help = "TOML file that declares one or more listeners, each with its own upstream \
+                servers, security and translation options. Options specified on the command line \
+                override the values in the file for all listeners."
+    )]
+    pub config_file: Option<PathBuf>,
+
     /// Listen address
     #[structopt(
         short = "l",
         long = "listen",
-        default_value = "localhost:3336",
-        help = "Address to listen on for incoming Stratum V2 connections"
+        help = "Address to listen on for incoming Stratum V2 connections [default: \
+                localhost:3336]"
+    )]
+    pub listen_address: Option<Address>,
+
+    /// Listen address for V1 miners
+    #[structopt(
+        long = "v1-listen",
+        name = "ADDR",
+        help = "Address to listen on for incoming Stratum V1 connections. Each V1 connection \
+                gets its own upstream session, V1 connections are not aggregated."
+    )]
+    pub v1_listen_address: Option<Address>,
+
+    /// Listen address of the metrics service
+    #[structopt(
+        long = "metrics-listen",
+        name = "METRICS_ADDR",
+        help = "Address to listen on for Prometheus metrics requests, the metrics are served \
+                over plain HTTP on /metrics"
     )]
-    pub listen_address: Address,
+    pub metrics_listen_address: Option<Address>,
 
-    /// Remote V1 endpoint where to connect to
+    /// Remote endpoints where to connect to in the order of preference
     #[structopt(
         short = "u",
-        long = "v1-upstream",
+        long = "upstream",
+        visible_alias = "v1-upstream",
         name = "HOSTNAME:PORT",
-        help = "Address of the upstream Stratum V1 server that the proxy connects to"
+        required_unless = "CONFIG_FILE",
+        number_of_values = 1,
+        help = "Address of the upstream Stratum server that the proxy connects to. Repeat the \
+                option to specify backup servers in the order of preference, the proxy fails \
+                over to them when the primary server is not available."
     )]
-    pub upstream_address: Address,
+    pub upstream_addresses: Vec<Address>,
+
+    /// Protocol of the upstream servers
+    #[structopt(
+        long = "upstream-protocol",
+        possible_values = &["v1", "v2"],
+        help = "Protocol of the upstream servers, V2 connections are relayed to V2 upstream \
+                servers with remapped channel and request IDs [default: v1]"
+    )]
+    pub upstream_protocol: Option<UpstreamProtocol>,
+
+    /// Authority public keys that sign certificates of the upstream V2 servers
+    #[structopt(
+        long = "upstream-authority-key",
+        name = "BASE58_KEY",
+        number_of_values = 1,
+        parse(try_from_str = parse_authority_public_key),
+        help = "Authority public key that the upstream V2 servers are authenticated with during \
+                the noise handshake. Repeat the option to accept certificates signed by any of \
+                the keys, e.g. while the upstream authority is being rotated."
+    )]
+    pub upstream_authority_public_keys: Vec<v2::noise::auth::EncodedEd25519PublicKey>,
+
+    #[structopt(
+        long,
+        help = "Disable noise protocol handshake with the upstream V2 servers"
+    )]
+    pub insecure_upstream: bool,
+
+    /// Number of shared upstream sessions
+    #[structopt(
+        long = "aggregate",
+        name = "SESSIONS",
+        help = "Multiplex all downstream connections onto the specified number of upstream V1 \
+                sessions instead of opening an upstream session for each downstream connection"
+    )]
+    pub upstream_sessions: Option<usize>,
+
+    /// Routing rules file
+    #[structopt(
+        long = "routing-rules",
+        name = "RULES_FILE",
+        parse(from_os_str),
+        help = "TOML file with rules that rewrite users of the channels to upstream accounts and \
+                worker names and that may route them to other upstream V1 servers. Channels of \
+                users that don't match any rule are refused."
+    )]
+    pub routing_rules_file: Option<PathBuf>,
+
+    /// Share rate that variable difficulty keeps channels at
+    #[structopt(
+        long = "vardiff-shares-per-minute",
+        name = "RATE",
+        help = "Enable variable difficulty that adjusts difficulty of each channel to keep the \
+                specified share rate. Channels never get higher difficulty than the upstream \
+                session, shares below the upstream difficulty are not submitted upstream."
+    )]
+    pub vardiff_shares_per_minute: Option<f64>,
+
+    /// Minimum difficulty of channels with variable difficulty
+    #[structopt(
+        long = "vardiff-min-difficulty",
+        name = "DIFFICULTY",
+        help = "Variable difficulty never lowers difficulty of a channel below this value \
+                [default: 1]"
+    )]
+    pub vardiff_min_difficulty: Option<u32>,
+
+    /// Retarget interval of variable difficulty
+    #[structopt(
+        long = "vardiff-retarget-interval",
+        name = "SECONDS",
+        help = "How long the share rate of a channel is measured before its difficulty is \
+                adjusted [default: 60]"
+    )]
+    pub vardiff_retarget_interval: Option<u64>,
+
+    /// Maximum number of downstream connections
+    #[structopt(
+        long = "max-connections",
+        name = "CONNECTIONS",
+        help = "Refuse downstream connections of both protocols above this number"
+    )]
+    pub max_connections: Option<usize>,
+
+    /// Maximum number of downstream connections from a single IP address
+    #[structopt(
+        long = "max-connections-per-ip",
+        name = "IP_CONNECTIONS",
+        help = "Refuse downstream connections from a single IP address above this number"
+    )]
+    pub max_connections_per_ip: Option<usize>,
+
+    /// Noise handshake timeout
+    #[structopt(
+        long = "handshake-timeout",
+        name = "HANDSHAKE_SECONDS",
+        default_value = "10",
+        help = "Drop V2 connections that don't complete the noise handshake within this time"
+    )]
+    pub handshake_timeout: u64,
+
+    /// Idle timeout of V2 connections
+    #[structopt(
+        long = "idle-timeout",
+        name = "IDLE_SECONDS",
+        default_value = "60",
+        help = "Drop V2 connections that don't send any message within this time"
+    )]
+    pub idle_timeout: u64,
+
+    /// Maximum message rate of V2 connections
+    #[structopt(
+        long = "max-message-rate",
+        name = "MESSAGES_PER_SECOND",
+        help = "Drop V2 connections that send more messages per second than this rate, short \
+                bursts of up to one second worth of messages are tolerated"
+    )]
+    pub max_message_rate: Option<u32>,
+
+    #[structopt(
+        long,
+        help = "Expect HAProxy PROXY protocol header (version 1 or 2) at the start of each \
+                downstream connection, e.g. when running behind a TCP load balancer"
+    )]
+    pub proxy_protocol: bool,
 
     #[structopt(
         long,
@@ -59,45 +253,34 @@ pub struct Args {
     pub insecure: bool,
 
     /// Certificate file
-    #[structopt(short = "c", long, parse(from_os_str), required_unless("insecure"))]
+    #[structopt(
+        short = "c",
+        long,
+        parse(from_os_str),
+        required_unless_one(&["insecure", "CONFIG_FILE"])
+    )]
     pub certificate_file: Option<PathBuf>,
 
     /// Secret key as counter part of the public key in the configured public certificate
-    #[structopt(short = "s", long, parse(from_os_str), required_unless("insecure"))]
+    #[structopt(
+        short = "s",
+        long,
+        parse(from_os_str),
+        required_unless_one(&["insecure", "CONFIG_FILE"])
+    )]
     pub secret_key_file: Option<PathBuf>,
 }
 
 impl Args {
-    /// Optionally read certificate and secret keypair
-    /// Return:
-    ///  - None - when `insecure` is true
-    ///  - build `Certificate` + `StaticSecretKeyFormat` pair otherwise
-    pub async fn read_certificate_secret_key_pair(
-        &self,
-    ) -> Result<
-        Option<(
-            v2::noise::auth::Certificate,
-            v2::noise::auth::StaticSecretKeyFormat,
-        )>,
-    > {
-        let certificate_key_pair = match self.insecure {
-            true => None,
-            false => {
-                let certificate = read_from_file::<v2::noise::auth::Certificate>(
-                    self.certificate_file.as_ref(),
-                    "Certificate",
-                )
-                .await?;
-                let secret_key = read_from_file::<v2::noise::auth::StaticSecretKeyFormat>(
-                    self.secret_key_file.as_ref(),
-                    "Secret key",
-                )
-                .await?;
-                Some((certificate, secret_key))
-            }
-        };
-
-        Ok(certificate_key_pair)
+    /// Provides limits of the downstream connections
+    pub fn connection_limits(&self) -> ConnectionLimits {
+        ConnectionLimits {
+            max_connections: self.max_connections,
+            max_connections_per_ip: self.max_connections_per_ip,
+            handshake_timeout: Duration::from_secs(self.handshake_timeout),
+            idle_timeout: Duration::from_secs(self.idle_timeout),
+            max_message_rate: self.max_message_rate,
+        }
     }
 }
 
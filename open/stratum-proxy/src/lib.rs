@@ -27,8 +27,18 @@
 // the default recursion limit if more complex statements are used
 #![recursion_limit = "256"]
 
+pub mod aggregation;
+pub mod config;
 pub mod error;
 pub mod frontend;
+pub mod limits;
+pub mod metrics;
+pub mod passthrough;
+pub mod proxy_protocol;
+pub mod routing;
 pub mod server;
 pub mod translation;
+pub mod upstream;
 pub mod util;
+pub mod v1_downstream;
+pub mod vardiff;
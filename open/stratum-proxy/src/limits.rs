@@ -0,0 +1,241 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Limits protect the proxy from downstream peers that open too many connections, that never
+//! complete the noise handshake, that stay connected without sending anything or that flood the
+//! proxy with messages. Connections exceeding any of the limits are refused or dropped and
+//! counted in the metrics.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::stream::{Stream, StreamExt};
+
+use ii_async_compat::futures;
+use ii_async_compat::prelude::*;
+
+use crate::error::Result;
+use crate::metrics;
+
+#[cfg(test)]
+mod test;
+
+/// Limits of downstream connections
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ConnectionLimits {
+    /// Maximum number of open downstream connections, unlimited when not set
+    pub max_connections: Option<usize>,
+    /// Maximum number of open downstream connections from a single IP address
+    pub max_connections_per_ip: Option<usize>,
+    /// Noise handshake with a downstream peer has to complete within this time
+    pub handshake_timeout: Duration,
+    /// V2 connection is dropped when no message arrives within this time
+    pub idle_timeout: Duration,
+    /// Maximum number of messages per second that a V2 connection may send, short bursts of up
+    /// to one second worth of messages are tolerated
+    pub max_message_rate: Option<u32>,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            max_connections: None,
+            max_connections_per_ip: None,
+            handshake_timeout: Duration::from_secs(10),
+            idle_timeout: Duration::from_secs(60),
+            max_message_rate: None,
+        }
+    }
+}
+
+#[derive(Default, Debug)]
+struct ConnectionCounts {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Counts open downstream connections in total and per IP address
+#[derive(Debug)]
+pub struct ConnectionTracker {
+    limits: ConnectionLimits,
+    counts: Mutex<ConnectionCounts>,
+}
+
+impl ConnectionTracker {
+    pub fn new(limits: ConnectionLimits) -> Self {
+        Self {
+            limits,
+            counts: Mutex::new(ConnectionCounts::default()),
+        }
+    }
+
+    /// Registers a new connection from `peer_ip` unless it would exceed the limits
+    /// Return:
+    ///  - permit that keeps the connection registered until the permit is dropped
+    ///  - reason of the rejection otherwise
+    // TODO: Convert to take self: &Arc<Self> once this is stabilized
+    // cf. https://github.com/rust-lang/rust/issues/44874
+    pub fn try_acquire(
+        self: Arc<Self>,
+        peer_ip: IpAddr,
+    ) -> std::result::Result<ConnectionPermit, &'static str> {
+        {
+            let mut counts = self
+                .counts
+                .lock()
+                .expect("BUG: connection counts lock poisoned");
+            if self
+                .limits
+                .max_connections
+                .map_or(false, |max_connections| counts.total >= max_connections)
+            {
+                return Err(metrics::MAX_CONNECTIONS_REASON);
+            }
+            let ip_count = counts.per_ip.get(&peer_ip).cloned().unwrap_or_default();
+            if self
+                .limits
+                .max_connections_per_ip
+                .map_or(false, |max_connections| ip_count >= max_connections)
+            {
+                return Err(metrics::MAX_CONNECTIONS_PER_IP_REASON);
+            }
+            counts.total += 1;
+            counts.per_ip.insert(peer_ip, ip_count + 1);
+        }
+        Ok(ConnectionPermit {
+            tracker: self,
+            peer_ip,
+        })
+    }
+
+    fn release(&self, peer_ip: IpAddr) {
+        let mut counts = self
+            .counts
+            .lock()
+            .expect("BUG: connection counts lock poisoned");
+        counts.total = counts.total.saturating_sub(1);
+        if let Some(ip_count) = counts.per_ip.get_mut(&peer_ip) {
+            *ip_count -= 1;
+            if *ip_count == 0 {
+                counts.per_ip.remove(&peer_ip);
+            }
+        }
+    }
+}
+
+/// Open downstream connection registered with `ConnectionTracker`
+#[derive(Debug)]
+pub struct ConnectionPermit {
+    tracker: Arc<ConnectionTracker>,
+    peer_ip: IpAddr,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        self.tracker.release(self.peer_ip);
+    }
+}
+
+/// Token bucket that refills at the configured rate and holds one second worth of tokens
+#[derive(Debug)]
+pub struct MessageRateLimiter {
+    rate: f64,
+    tokens: f64,
+    last_update: Instant,
+}
+
+impl MessageRateLimiter {
+    pub fn new(rate: u32, now: Instant) -> Self {
+        let rate = f64::from(rate.max(1));
+        Self {
+            rate,
+            tokens: rate,
+            last_update: now,
+        }
+    }
+
+    /// Consumes a token for a message that has arrived at `now`
+    /// Return:
+    ///  - false when the message exceeds the rate
+    pub fn check(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_update);
+        self.last_update = now;
+        self.tokens = (self.tokens + elapsed.as_secs_f64() * self.rate).min(self.rate);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+}
+
+/// Enforces the idle timeout and the message rate of a V2 downstream connection
+#[derive(Debug)]
+pub struct DownstreamMonitor {
+    idle_timeout: Duration,
+    rate_limiter: Option<MessageRateLimiter>,
+    peer_addr: SocketAddr,
+}
+
+impl DownstreamMonitor {
+    pub fn new(limits: &ConnectionLimits, peer_addr: SocketAddr) -> Self {
+        Self {
+            idle_timeout: limits.idle_timeout,
+            rate_limiter: limits
+                .max_message_rate
+                .map(|rate| MessageRateLimiter::new(rate, Instant::now())),
+            peer_addr,
+        }
+    }
+
+    /// Awaits the next frame of the downstream connection `stream`. It is an error when nothing
+    /// arrives within the idle timeout or when the peer exceeds the message rate.
+    pub async fn next_frame<S>(&mut self, stream: &mut S) -> Result<Option<S::Item>>
+    where
+        S: Stream + Unpin,
+    {
+        let frame = match stream.next().timeout(self.idle_timeout).await {
+            Ok(frame) => frame,
+            Err(_) => {
+                metrics::record_downstream_rejection("v2", metrics::IDLE_TIMEOUT_REASON);
+                Err(format!(
+                    "V2 peer idle for {:?} ({:?})",
+                    self.idle_timeout, self.peer_addr
+                ))?
+            }
+        };
+        if frame.is_some() {
+            if let Some(rate_limiter) = self.rate_limiter.as_mut() {
+                if !rate_limiter.check(Instant::now()) {
+                    metrics::record_downstream_rejection("v2", metrics::MESSAGE_RATE_REASON);
+                    Err(format!(
+                        "V2 peer exceeded message rate ({:?})",
+                        self.peer_addr
+                    ))?;
+                }
+            }
+        }
+        Ok(frame)
+    }
+}
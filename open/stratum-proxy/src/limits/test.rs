@@ -0,0 +1,119 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use std::str::FromStr;
+
+use ii_async_compat::tokio;
+
+use super::*;
+
+fn build_limits() -> ConnectionLimits {
+    ConnectionLimits {
+        max_connections: Some(3),
+        max_connections_per_ip: Some(2),
+        handshake_timeout: Duration::from_secs(10),
+        idle_timeout: Duration::from_millis(50),
+        max_message_rate: Some(2),
+    }
+}
+
+/// Verifies that connections above the global and the per IP limit are refused and that
+/// dropped permits release their slots
+#[test]
+fn test_connection_tracker() {
+    let tracker = Arc::new(ConnectionTracker::new(build_limits()));
+    let ip1 = IpAddr::from_str("10.0.0.1").expect("BUG: invalid address");
+    let ip2 = IpAddr::from_str("10.0.0.2").expect("BUG: invalid address");
+
+    let permit1 = tracker
+        .clone()
+        .try_acquire(ip1)
+        .expect("Connection refused");
+    let _permit2 = tracker
+        .clone()
+        .try_acquire(ip1)
+        .expect("Connection refused");
+    assert_eq!(
+        tracker.clone().try_acquire(ip1).err(),
+        Some(metrics::MAX_CONNECTIONS_PER_IP_REASON)
+    );
+    let _permit3 = tracker
+        .clone()
+        .try_acquire(ip2)
+        .expect("Connection refused");
+    assert_eq!(
+        tracker.clone().try_acquire(ip2).err(),
+        Some(metrics::MAX_CONNECTIONS_REASON)
+    );
+
+    drop(permit1);
+    tracker
+        .try_acquire(ip1)
+        .expect("Released connection slot not available");
+}
+
+/// Verifies that burst of one second worth of messages is tolerated and that the limiter
+/// refills at the configured rate
+#[test]
+fn test_message_rate_limiter() {
+    let start = Instant::now();
+    let mut limiter = MessageRateLimiter::new(2, start);
+    assert!(limiter.check(start));
+    assert!(limiter.check(start));
+    assert!(!limiter.check(start));
+
+    let now = start + Duration::from_millis(500);
+    assert!(limiter.check(now));
+    assert!(!limiter.check(now));
+}
+
+/// Verifies that silent peer is dropped after the idle timeout
+#[tokio::test]
+async fn test_downstream_monitor_idle_timeout() {
+    let peer_addr = SocketAddr::from_str("10.0.0.1:3336").expect("BUG: invalid address");
+    let mut monitor = DownstreamMonitor::new(&build_limits(), peer_addr);
+    let mut stream = futures::stream::pending::<u32>();
+    assert!(monitor.next_frame(&mut stream).await.is_err());
+}
+
+/// Verifies that peer exceeding the message rate is dropped
+#[tokio::test]
+async fn test_downstream_monitor_message_rate() {
+    let peer_addr = SocketAddr::from_str("10.0.0.1:3336").expect("BUG: invalid address");
+    let mut monitor = DownstreamMonitor::new(&build_limits(), peer_addr);
+    let mut stream = futures::stream::iter(vec![1, 2, 3]);
+    assert_eq!(
+        monitor
+            .next_frame(&mut stream)
+            .await
+            .expect("Frame dropped"),
+        Some(1)
+    );
+    assert_eq!(
+        monitor
+            .next_frame(&mut stream)
+            .await
+            .expect("Frame dropped"),
+        Some(2)
+    );
+    assert!(monitor.next_frame(&mut stream).await.is_err());
+}
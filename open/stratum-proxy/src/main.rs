@@ -20,20 +20,153 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
-//! Simple proxy that translates V2 protocol from clients to V1 protocol and connects to a
-//! requested pool
+//! Simple proxy that translates V2 protocol from clients to V1 protocol or relays it to V2 and
+//! connects to a requested pool
 
 use std::cell::RefCell;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
 use structopt::StructOpt;
 
 use ctrlc;
 
-use ii_async_compat::tokio;
+use ii_async_compat::{
+    futures::{self, future},
+    tokio,
+};
+use ii_stratum::v2;
 use ii_stratum_proxy::{
+    config::{ListenerConfig, ProxyConfig},
     error::{Result, ResultExt},
-    frontend::Args,
-    server,
+    frontend::{Args, UpstreamProtocol},
+    limits::ConnectionLimits,
+    metrics, passthrough, server,
+    upstream::{UpstreamConnection, UpstreamConnector},
+    v1_downstream,
 };
+use ii_wire::Address;
+
+/// Spawns the server, V1 connections are accepted when `v1_listen_address` is configured.
+/// The certificate is reloaded whenever `certificate_files` change.
+/// Return:
+///  - handle of the server task
+///  - channel that tells the server to shut down
+fn spawn_server<FN, FT>(
+    server: server::ProxyServer<FN>,
+    certificate_files: Option<(PathBuf, PathBuf)>,
+    v1_listen_address: Option<Address>,
+    v1_upstream_mode: v1_downstream::UpstreamMode,
+) -> Result<(
+    tokio::task::JoinHandle<()>,
+    futures::channel::mpsc::Sender<()>,
+)>
+where
+    FT: Future<Output = Result<()>> + Send + 'static,
+    FN: Fn(v2::Framed, SocketAddr, UpstreamConnection, UpstreamConnector) -> FT
+        + Send
+        + Sync
+        + 'static,
+{
+    let server = match v1_listen_address {
+        Some(v1_listen_address) => server
+            .listen_v1(v1_listen_address, v1_upstream_mode)
+            .context("Cannot bind the V1 server")?,
+        None => server,
+    };
+    let server = match certificate_files {
+        Some((certificate_file, secret_key_file)) => server
+            .reload_certificate(certificate_file, secret_key_file)
+            .context("Cannot watch the certificate")?,
+        None => server,
+    };
+    let quit = server.quit_channel();
+    Ok((tokio::spawn(server.run()), quit))
+}
+
+/// Binds and spawns the server of a single listener
+async fn start_listener(
+    listener: ListenerConfig,
+    connection_limits: ConnectionLimits,
+) -> Result<(
+    tokio::task::JoinHandle<()>,
+    futures::channel::mpsc::Sender<()>,
+)> {
+    let certificate_secret_key_pair = listener.read_certificate_secret_key_pair().await?;
+    let certificate_files = listener.certificate_files();
+    let routing_rules = listener.read_routing_rules().await?;
+
+    match listener.upstream_protocol {
+        UpstreamProtocol::V1 => {
+            let server = server::ProxyServer::listen(
+                listener.listen_address,
+                listener.upstream_addresses,
+                server::handle_connection,
+                certificate_secret_key_pair,
+            )
+            .context("Cannot bind the server")?
+            .limit_connections(connection_limits);
+            let server = match listener.proxy_protocol {
+                true => server.accept_proxy_protocol(),
+                false => server,
+            };
+            let server = match listener.try_enable_xnsub {
+                true => server.extranonce_subscribe(),
+                false => server,
+            };
+            let server = match routing_rules {
+                Some(routing_rules) => server.route(routing_rules),
+                None => server,
+            };
+            let server = match listener.vardiff {
+                Some(vardiff_options) => server.vardiff(vardiff_options),
+                None => server,
+            };
+            let server = match listener.upstream_sessions {
+                Some(session_count) => server.aggregate(session_count),
+                None => server,
+            };
+            spawn_server(
+                server,
+                certificate_files,
+                listener.v1_listen_address,
+                v1_downstream::UpstreamMode::V1,
+            )
+        }
+        UpstreamProtocol::V2 => {
+            let authority_public_keys = listener.upstream_authority_public_keys;
+            let server = server::ProxyServer::listen(
+                listener.listen_address,
+                listener.upstream_addresses,
+                {
+                    let authority_public_keys = authority_public_keys.clone();
+                    move |v2_conn, v2_peer_addr, upstream_conn, upstream_connector| {
+                        passthrough::handle_connection(
+                            v2_conn,
+                            v2_peer_addr,
+                            upstream_conn,
+                            upstream_connector,
+                            authority_public_keys.clone(),
+                        )
+                    }
+                },
+                certificate_secret_key_pair,
+            )
+            .context("Cannot bind the server")?
+            .limit_connections(connection_limits);
+            let server = match listener.proxy_protocol {
+                true => server.accept_proxy_protocol(),
+                false => server,
+            };
+            spawn_server(
+                server,
+                certificate_files,
+                listener.v1_listen_address,
+                v1_downstream::UpstreamMode::V2(authority_public_keys),
+            )
+        }
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -42,24 +175,33 @@ async fn main() -> Result<()> {
         ii_logging::setup_for_app(ii_logging::LoggingConfig::ASYNC_LOGGER_DRAIN_CHANNEL_SIZE);
 
     let args = Args::from_args();
+    let config = ProxyConfig::read(&args).await?;
+    let connection_limits = args.connection_limits();
 
-    let certificate_secret_key_pair = args.read_certificate_secret_key_pair().await?;
-
-    let server = server::ProxyServer::listen(
-        args.listen_address,
-        args.upstream_address,
-        server::handle_connection,
-        certificate_secret_key_pair,
-    )
-    .context("Cannot bind the server")?;
+    if let Some(metrics_listen_address) = config.metrics_listen_address.clone() {
+        let metrics_server = metrics::MetricsServer::bind(metrics_listen_address)
+            .context("Cannot bind the metrics server")?;
+        tokio::spawn(metrics_server.run());
+    }
 
-    let quit = RefCell::new(server.quit_channel());
+    let mut server_tasks = Vec::with_capacity(config.listeners.len());
+    let mut quit_channels = Vec::with_capacity(config.listeners.len());
+    for listener in config.listeners {
+        let (server_task, quit) = start_listener(listener, connection_limits).await?;
+        server_tasks.push(server_task);
+        quit_channels.push(quit);
+    }
+    let quit_channels = RefCell::new(quit_channels);
     ctrlc::set_handler(move || {
-        // Received SIGINT, tell the server task to shut down:
-        let _ = quit.try_borrow_mut().map(|mut quit| quit.try_send(()));
+        // Received SIGINT, tell all server tasks to shut down:
+        let _ = quit_channels.try_borrow_mut().map(|mut quit_channels| {
+            for quit in quit_channels.iter_mut() {
+                let _ = quit.try_send(());
+            }
+        });
     })
     .expect("Could not set SIGINT handler");
 
-    server.run().await;
+    future::join_all(server_tasks).await;
     Ok(())
 }
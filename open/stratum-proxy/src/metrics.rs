@@ -0,0 +1,292 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Prometheus metrics of the proxy. The metrics are collected regardless of the configuration,
+//! `MetricsServer` exports them in the text exposition format over plain HTTP.
+//!
+//! Share metrics are collected for shares translated onto V1 upstream sessions, they are
+//! labeled with the upstream server address and the user of the channel.
+
+use std::net::SocketAddr;
+use std::time;
+
+use lazy_static::lazy_static;
+use prometheus::{
+    core::Collector, Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts,
+    TextEncoder,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use ii_async_compat::prelude::*;
+use ii_async_compat::tokio;
+use ii_logging::macros::*;
+use ii_wire::{Address, Server};
+
+use crate::error::Result;
+
+#[cfg(test)]
+mod test;
+
+lazy_static! {
+    /// Downstream connections accepted by the proxy, labeled by the protocol of the listener
+    pub static ref DOWNSTREAM_CONNECTIONS_TOTAL: IntCounterVec = register(IntCounterVec::new(
+        Opts::new(
+            "stratum_proxy_downstream_connections_total",
+            "Number of accepted downstream connections",
+        ),
+        &["protocol"],
+    ));
+    pub static ref DOWNSTREAM_CONNECTIONS: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new(
+            "stratum_proxy_downstream_connections",
+            "Number of currently connected downstream connections",
+        ),
+        &["protocol"],
+    ));
+    /// Downstream connections refused or dropped by the proxy because they have exceeded
+    /// the configured limits, labeled by the protocol of the listener and by the limit
+    pub static ref DOWNSTREAM_REJECTIONS_TOTAL: IntCounterVec = register(IntCounterVec::new(
+        Opts::new(
+            "stratum_proxy_downstream_rejections_total",
+            "Number of downstream connections rejected due to connection limits",
+        ),
+        &["protocol", "reason"],
+    ));
+    /// Failed noise handshakes, labeled by the peer that the proxy has attempted to handshake
+    /// with
+    pub static ref HANDSHAKE_FAILURES_TOTAL: IntCounterVec = register(IntCounterVec::new(
+        Opts::new("stratum_proxy_handshake_failures_total", "Number of failed noise handshakes"),
+        &["peer"],
+    ));
+    pub static ref UPSTREAM_SESSIONS: IntGaugeVec = register(IntGaugeVec::new(
+        Opts::new(
+            "stratum_proxy_upstream_sessions",
+            "Number of currently open V1 upstream sessions",
+        ),
+        &["upstream"],
+    ));
+    pub static ref SHARES_ACCEPTED_TOTAL: IntCounterVec = register(IntCounterVec::new(
+        Opts::new(
+            "stratum_proxy_shares_accepted_total",
+            "Number of shares accepted by the upstream",
+        ),
+        &["upstream", "user"],
+    ));
+    /// Rejected shares, the reason distinguishes shares rejected by the upstream from shares
+    /// that have timed out or that have been lost along with the upstream session. Shares
+    /// rejected by the proxy itself are labeled with the error code sent downstream.
+    pub static ref SHARES_REJECTED_TOTAL: IntCounterVec = register(IntCounterVec::new(
+        Opts::new(
+            "stratum_proxy_shares_rejected_total",
+            "Number of shares that haven't been accepted by the upstream",
+        ),
+        &["upstream", "user", "reason"],
+    ));
+    pub static ref SUBMIT_LATENCY_SECONDS: HistogramVec = register(HistogramVec::new(
+        HistogramOpts::new(
+            "stratum_proxy_submit_latency_seconds",
+            "Time between sending a share upstream and receiving its result",
+        )
+        .buckets(vec![
+            0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+        ]),
+        &["upstream"],
+    ));
+}
+
+/// Registers `metric` with the default registry, the metric definitions are static and they are
+/// expected to be valid
+fn register<T>(metric: prometheus::Result<T>) -> T
+where
+    T: Collector + Clone + 'static,
+{
+    let metric = metric.expect("BUG: invalid metric");
+    prometheus::register(Box::new(metric.clone())).expect("BUG: cannot register metric");
+    metric
+}
+
+/// Reasons of rejected shares
+pub const SHARE_REJECTED_REASON: &str = "rejected";
+pub const SHARE_TIMEOUT_REASON: &str = "timeout";
+pub const SHARE_UPSTREAM_LOST_REASON: &str = "upstream-lost";
+
+/// Reasons of rejected downstream connections
+pub const MAX_CONNECTIONS_REASON: &str = "max-connections";
+pub const MAX_CONNECTIONS_PER_IP_REASON: &str = "max-connections-per-ip";
+pub const HANDSHAKE_TIMEOUT_REASON: &str = "handshake-timeout";
+pub const IDLE_TIMEOUT_REASON: &str = "idle-timeout";
+pub const MESSAGE_RATE_REASON: &str = "message-rate";
+
+/// Label of the upstream server, empty when the upstream is not known yet
+pub fn upstream_label(upstream_addr: Option<SocketAddr>) -> String {
+    upstream_addr
+        .map(|upstream_addr| upstream_addr.to_string())
+        .unwrap_or_default()
+}
+
+/// Records result of a share submitted to `upstream` by `user` at `submit_time`
+pub fn record_share_result(
+    upstream: &str,
+    user: &str,
+    submit_time: time::Instant,
+    rejected_reason: Option<&str>,
+) {
+    SUBMIT_LATENCY_SECONDS
+        .with_label_values(&[upstream])
+        .observe(duration_seconds(submit_time.elapsed()));
+    match rejected_reason {
+        Some(reason) => SHARES_REJECTED_TOTAL
+            .with_label_values(&[upstream, user, reason])
+            .inc(),
+        None => SHARES_ACCEPTED_TOTAL
+            .with_label_values(&[upstream, user])
+            .inc(),
+    }
+}
+
+/// Records downstream connection of `protocol` that has been refused or dropped because it has
+/// exceeded the limit described by `reason`
+pub fn record_downstream_rejection(protocol: &str, reason: &str) {
+    DOWNSTREAM_REJECTIONS_TOTAL
+        .with_label_values(&[protocol, reason])
+        .inc();
+}
+
+/// Records share of `user` that the proxy has rejected with `reason` without submitting it to
+/// `upstream`
+pub fn record_share_rejected_locally(upstream: &str, user: &str, reason: &str) {
+    SHARES_REJECTED_TOTAL
+        .with_label_values(&[upstream, user, reason])
+        .inc();
+}
+
+fn duration_seconds(duration: time::Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9
+}
+
+/// HTTP server that exports the metrics on `METRICS_PATH`
+pub struct MetricsServer {
+    server: Server,
+    listen_addr: Address,
+}
+
+impl MetricsServer {
+    const METRICS_PATH: &'static str = "/metrics";
+    /// Only the request line is interpreted, the rest of the request is ignored
+    const MAX_REQUEST_SIZE: usize = 4096;
+    const REQUEST_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+    pub fn bind(listen_addr: Address) -> Result<Self> {
+        let server = Server::bind(&listen_addr)?;
+        Ok(Self {
+            server,
+            listen_addr,
+        })
+    }
+
+    /// Serves incoming requests until the listening socket is closed
+    pub async fn run(mut self) {
+        info!("Metrics service starting @ {}", self.listen_addr);
+        while let Some(conn) = self.server.next().await {
+            match conn {
+                Ok(conn) => {
+                    tokio::spawn(Self::handle_connection(conn));
+                }
+                Err(e) => info!("Metrics connection error: {}", e),
+            }
+        }
+        info!("Metrics service terminated");
+    }
+
+    async fn handle_connection(mut conn: TcpStream) {
+        let result = Self::read_request(&mut conn)
+            .timeout(Self::REQUEST_TIMEOUT)
+            .await;
+        let response = match result {
+            Ok(Ok(request)) => Self::build_response(&request),
+            Ok(Err(e)) => {
+                info!("Cannot read metrics request: {}", e);
+                return;
+            }
+            Err(e) => {
+                info!("Metrics request timed out: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = conn.write_all(&response).await {
+            info!("Cannot send metrics response: {}", e);
+        }
+    }
+
+    /// Reads the request head, the request body is never expected
+    async fn read_request(conn: &mut TcpStream) -> std::io::Result<Vec<u8>> {
+        let mut request = vec![];
+        let mut buf = [0u8; 512];
+        while !request.windows(4).any(|window| window == b"\r\n\r\n")
+            && request.len() < Self::MAX_REQUEST_SIZE
+        {
+            let len = conn.read(&mut buf).await?;
+            if len == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..len]);
+        }
+        Ok(request)
+    }
+
+    /// Builds HTTP response for `request`, only GET of `METRICS_PATH` is supported
+    fn build_response(request: &[u8]) -> Vec<u8> {
+        let request = String::from_utf8_lossy(request);
+        let mut request_line = request
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .split_whitespace();
+        let (status, content_type, body) = match (request_line.next(), request_line.next()) {
+            (Some("GET"), Some(Self::METRICS_PATH)) => {
+                let mut body = vec![];
+                let encoder = TextEncoder::new();
+                match encoder.encode(&prometheus::gather(), &mut body) {
+                    Ok(()) => ("200 OK", encoder.format_type().to_string(), body),
+                    Err(e) => (
+                        "500 Internal Server Error",
+                        "text/plain".to_string(),
+                        e.to_string().into_bytes(),
+                    ),
+                }
+            }
+            (Some("GET"), _) => ("404 Not Found", "text/plain".to_string(), vec![]),
+            _ => ("405 Method Not Allowed", "text/plain".to_string(), vec![]),
+        };
+
+        let mut response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            content_type,
+            body.len()
+        )
+        .into_bytes();
+        response.extend_from_slice(&body);
+        response
+    }
+}
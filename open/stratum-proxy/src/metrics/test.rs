@@ -0,0 +1,53 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use super::*;
+
+/// Verifies that recorded share results are exported on the metrics path
+#[test]
+fn test_metrics_response() {
+    let submit_time = time::Instant::now();
+    record_share_result("10.0.0.1:3333", "test.worker", submit_time, None);
+    record_share_result(
+        "10.0.0.1:3333",
+        "test.worker",
+        submit_time,
+        Some(SHARE_TIMEOUT_REASON),
+    );
+
+    let response = MetricsServer::build_response(b"GET /metrics HTTP/1.1\r\nHost: x\r\n\r\n");
+    let response = String::from_utf8(response).expect("Invalid response");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains(
+        r#"stratum_proxy_shares_accepted_total{upstream="10.0.0.1:3333",user="test.worker"} 1"#
+    ));
+    assert!(response.contains(
+        r#"stratum_proxy_shares_rejected_total{reason="timeout",upstream="10.0.0.1:3333",user="test.worker"} 1"#
+    ));
+    assert!(response
+        .contains(r#"stratum_proxy_submit_latency_seconds_count{upstream="10.0.0.1:3333"} 2"#));
+
+    let response = MetricsServer::build_response(b"GET / HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with(b"HTTP/1.1 404 Not Found\r\n"));
+    let response = MetricsServer::build_response(b"POST /metrics HTTP/1.1\r\n\r\n");
+    assert!(response.starts_with(b"HTTP/1.1 405 Method Not Allowed\r\n"));
+}
@@ -0,0 +1,833 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Pass-through of Stratum V2 connections onto upstream V2 servers. Each downstream connection
+//! gets a dedicated upstream connection, the proxy terminates noise on both sides and remaps
+//! request and channel IDs so that the downstream never sees the IDs assigned by the upstream.
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::net::SocketAddr;
+use std::time;
+
+use async_trait::async_trait;
+use futures::channel::mpsc;
+
+use ii_async_compat::prelude::*;
+use ii_async_compat::{futures, select, tokio};
+use ii_logging::macros::*;
+use ii_stratum::v2::{self, framing::Header, messages::*};
+use ii_wire::Connection;
+
+use crate::error::{Error, Result};
+use crate::limits::DownstreamMonitor;
+use crate::metrics;
+use crate::server::ConnTranslation;
+use crate::translation::{SeqId, V2ToV1Translation};
+use crate::upstream::{UpstreamConnection, UpstreamConnector};
+use crate::util;
+
+#[cfg(test)]
+mod test;
+
+/// Peer that the relay communicates with
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Peer {
+    Downstream,
+    Upstream,
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Peer::Downstream => write!(f, "downstream"),
+            Peer::Upstream => write!(f, "upstream"),
+        }
+    }
+}
+
+/// Bidirectional mapping of channel IDs assigned by the upstream onto channel IDs presented to
+/// the downstream. Group channels share the ID space with standard and extended channels.
+#[derive(Default, Debug)]
+struct ChannelIdMap {
+    /// Unique channel ID generator for the downstream
+    downstream_channel_id: SeqId,
+    to_downstream: HashMap<u32, u32>,
+    to_upstream: HashMap<u32, u32>,
+}
+
+impl ChannelIdMap {
+    /// Provides downstream ID of upstream channel `upstream_id`, the channel is registered when
+    /// it is not known yet
+    fn register(&mut self, upstream_id: u32) -> u32 {
+        if let Some(downstream_id) = self.to_downstream.get(&upstream_id) {
+            return *downstream_id;
+        }
+        let downstream_id = self.downstream_channel_id.next();
+        self.to_downstream.insert(upstream_id, downstream_id);
+        self.to_upstream.insert(downstream_id, upstream_id);
+        downstream_id
+    }
+
+    fn downstream_id(&self, upstream_id: u32) -> Option<u32> {
+        self.to_downstream.get(&upstream_id).cloned()
+    }
+
+    fn upstream_id(&self, downstream_id: u32) -> Option<u32> {
+        self.to_upstream.get(&downstream_id).cloned()
+    }
+
+    fn remove_downstream_id(&mut self, downstream_id: u32) -> Option<u32> {
+        let upstream_id = self.to_upstream.remove(&downstream_id)?;
+        self.to_downstream.remove(&upstream_id);
+        Some(upstream_id)
+    }
+
+    fn remove_upstream_id(&mut self, upstream_id: u32) -> Option<u32> {
+        let downstream_id = self.to_downstream.remove(&upstream_id)?;
+        self.to_upstream.remove(&downstream_id);
+        Some(downstream_id)
+    }
+
+    fn downstream_ids(&self) -> Vec<u32> {
+        let mut downstream_ids: Vec<u32> = self.to_upstream.keys().cloned().collect();
+        downstream_ids.sort();
+        downstream_ids
+    }
+}
+
+/// Relays V2 messages between a downstream and an upstream connection. Request IDs of the
+/// downstream are replaced with IDs generated by the relay and channel IDs assigned by the
+/// upstream are replaced with IDs generated by the relay, responses are mapped back.
+pub struct V2Relay {
+    /// Peer that has sent the message that is being handled
+    source: Peer,
+    /// Frames to be sent out via the downstream connection
+    downstream_tx: mpsc::Sender<v2::Frame>,
+    /// Frames to be sent out via the upstream connection
+    upstream_tx: mpsc::Sender<v2::Frame>,
+    /// Unique request ID generator for the upstream
+    upstream_req_id: SeqId,
+    /// Translates upstream request ID back to the request ID used by the downstream
+    req_map: HashMap<u32, u32>,
+    channels: ChannelIdMap,
+}
+
+impl V2Relay {
+    const INVALID_CHANNEL_ERR: &'static str = "invalid-channel-id";
+
+    pub fn new(
+        downstream_tx: mpsc::Sender<v2::Frame>,
+        upstream_tx: mpsc::Sender<v2::Frame>,
+    ) -> Self {
+        Self {
+            source: Peer::Downstream,
+            downstream_tx,
+            upstream_tx,
+            upstream_req_id: SeqId::new(),
+            req_map: HashMap::default(),
+            channels: ChannelIdMap::default(),
+        }
+    }
+
+    pub async fn handle_downstream_frame(&mut self, frame: v2::Frame) -> Result<()> {
+        self.handle_frame(Peer::Downstream, frame).await
+    }
+
+    pub async fn handle_upstream_frame(&mut self, frame: v2::Frame) -> Result<()> {
+        self.handle_frame(Peer::Upstream, frame).await
+    }
+
+    async fn handle_frame(&mut self, source: Peer, frame: v2::Frame) -> Result<()> {
+        match frame.header.extension_type {
+            v2::extensions::BASE => {
+                self.source = source;
+                let msg = v2::build_message_from_frame(frame)?;
+                msg.accept(self).await;
+            }
+            // Other extensions are not relayed
+            _ => {
+                warn!("Unsupported extension frame from {}: {:x?}", source, frame);
+            }
+        }
+        Ok(())
+    }
+
+    /// Notifies the downstream that all of its channels have been closed
+    pub fn close_all_channels(&mut self, reason_code: &str) {
+        for channel_id in self.channels.downstream_ids() {
+            let msg = CloseChannel {
+                channel_id,
+                reason_code: reason_code.try_into().expect("BUG: incorrect reason code"),
+            };
+            self.submit(Peer::Downstream, msg);
+        }
+        self.channels = ChannelIdMap::default();
+    }
+
+    fn submit<T>(&mut self, destination: Peer, msg: T)
+    where
+        T: TryInto<v2::Frame, Error = ii_stratum::error::Error>,
+    {
+        let tx = match destination {
+            Peer::Downstream => &mut self.downstream_tx,
+            Peer::Upstream => &mut self.upstream_tx,
+        };
+        if let Err(e) = util::submit_message(tx, msg) {
+            info!("Cannot submit message to {}: {}", destination, e);
+        }
+    }
+
+    /// Checks that `payload` has been received from the `expected` peer, messages sent in the
+    /// wrong direction are dropped
+    fn is_from<T: fmt::Debug>(&self, expected: Peer, payload: &T) -> bool {
+        if self.source != expected {
+            warn!(
+                "Dropping unexpected message from {}: {:?}",
+                self.source, payload
+            );
+            return false;
+        }
+        true
+    }
+
+    /// Allocates upstream request ID for request `req_id` of the downstream
+    fn register_request(&mut self, req_id: u32) -> u32 {
+        let upstream_req_id = self.upstream_req_id.next();
+        self.req_map.insert(upstream_req_id, req_id);
+        upstream_req_id
+    }
+
+    /// Provides downstream request ID of a request that the upstream has responded to
+    fn take_request(&mut self, upstream_req_id: u32) -> Option<u32> {
+        let req_id = self.req_map.remove(&upstream_req_id);
+        if req_id.is_none() {
+            info!("Dropping response to unknown request {}", upstream_req_id);
+        }
+        req_id
+    }
+
+    /// Rejects share submitted by the downstream on a channel that is not open
+    fn reject_submit(&mut self, channel_id: u32, seq_num: u32) {
+        let msg = SubmitSharesError {
+            channel_id,
+            seq_num,
+            code: Self::INVALID_CHANNEL_ERR
+                .try_into()
+                .expect("BUG: incorrect error message"),
+        };
+        self.submit(Peer::Downstream, msg);
+    }
+
+    /// Provides downstream ID of upstream channel `channel_id`
+    fn downstream_channel_id(&self, channel_id: u32) -> Option<u32> {
+        let downstream_id = self.channels.downstream_id(channel_id);
+        if downstream_id.is_none() {
+            trace!(
+                "Dropping message for unknown upstream channel {}",
+                channel_id
+            );
+        }
+        downstream_id
+    }
+}
+
+#[async_trait]
+impl v2::Handler for V2Relay {
+    async fn visit_setup_connection(&mut self, _header: &Header, payload: &SetupConnection) {
+        if self.is_from(Peer::Downstream, payload) {
+            self.submit(Peer::Upstream, payload.clone());
+        }
+    }
+
+    async fn visit_setup_connection_success(
+        &mut self,
+        _header: &Header,
+        payload: &SetupConnectionSuccess,
+    ) {
+        if self.is_from(Peer::Upstream, payload) {
+            self.submit(Peer::Downstream, payload.clone());
+        }
+    }
+
+    async fn visit_setup_connection_error(
+        &mut self,
+        _header: &Header,
+        payload: &SetupConnectionError,
+    ) {
+        if self.is_from(Peer::Upstream, payload) {
+            self.submit(Peer::Downstream, payload.clone());
+        }
+    }
+
+    async fn visit_open_standard_mining_channel(
+        &mut self,
+        _header: &Header,
+        payload: &OpenStandardMiningChannel,
+    ) {
+        if self.is_from(Peer::Downstream, payload) {
+            let msg = OpenStandardMiningChannel {
+                req_id: self.register_request(payload.req_id),
+                ..payload.clone()
+            };
+            self.submit(Peer::Upstream, msg);
+        }
+    }
+
+    async fn visit_open_standard_mining_channel_success(
+        &mut self,
+        _header: &Header,
+        payload: &OpenStandardMiningChannelSuccess,
+    ) {
+        if !self.is_from(Peer::Upstream, payload) {
+            return;
+        }
+        if let Some(req_id) = self.take_request(payload.req_id) {
+            let msg = OpenStandardMiningChannelSuccess {
+                req_id,
+                channel_id: self.channels.register(payload.channel_id),
+                group_channel_id: self.channels.register(payload.group_channel_id),
+                ..payload.clone()
+            };
+            self.submit(Peer::Downstream, msg);
+        }
+    }
+
+    async fn visit_open_standard_mining_channel_error(
+        &mut self,
+        _header: &Header,
+        payload: &OpenStandardMiningChannelError,
+    ) {
+        if !self.is_from(Peer::Upstream, payload) {
+            return;
+        }
+        if let Some(req_id) = self.take_request(payload.req_id) {
+            let msg = OpenStandardMiningChannelError {
+                req_id,
+                ..payload.clone()
+            };
+            self.submit(Peer::Downstream, msg);
+        }
+    }
+
+    async fn visit_open_extended_mining_channel(
+        &mut self,
+        _header: &Header,
+        payload: &OpenExtendedMiningChannel,
+    ) {
+        if self.is_from(Peer::Downstream, payload) {
+            let msg = OpenExtendedMiningChannel {
+                req_id: self.register_request(payload.req_id),
+                ..payload.clone()
+            };
+            self.submit(Peer::Upstream, msg);
+        }
+    }
+
+    async fn visit_open_extended_mining_channel_success(
+        &mut self,
+        _header: &Header,
+        payload: &OpenExtendedMiningChannelSuccess,
+    ) {
+        if !self.is_from(Peer::Upstream, payload) {
+            return;
+        }
+        if let Some(req_id) = self.take_request(payload.req_id) {
+            let msg = OpenExtendedMiningChannelSuccess {
+                req_id,
+                channel_id: self.channels.register(payload.channel_id),
+                ..payload.clone()
+            };
+            self.submit(Peer::Downstream, msg);
+        }
+    }
+
+    async fn visit_open_extended_mining_channel_error(
+        &mut self,
+        _header: &Header,
+        payload: &OpenExtendedMiningChannelError,
+    ) {
+        if !self.is_from(Peer::Upstream, payload) {
+            return;
+        }
+        if let Some(req_id) = self.take_request(payload.req_id) {
+            let msg = OpenExtendedMiningChannelError {
+                req_id,
+                ..payload.clone()
+            };
+            self.submit(Peer::Downstream, msg);
+        }
+    }
+
+    async fn visit_update_channel(&mut self, _header: &Header, payload: &UpdateChannel) {
+        if !self.is_from(Peer::Downstream, payload) {
+            return;
+        }
+        match self.channels.upstream_id(payload.channel_id) {
+            Some(channel_id) => {
+                let msg = UpdateChannel {
+                    channel_id,
+                    ..payload.clone()
+                };
+                self.submit(Peer::Upstream, msg);
+            }
+            None => {
+                let msg = UpdateChannelError {
+                    channel_id: payload.channel_id,
+                    code: Self::INVALID_CHANNEL_ERR
+                        .try_into()
+                        .expect("BUG: incorrect error message"),
+                };
+                self.submit(Peer::Downstream, msg);
+            }
+        }
+    }
+
+    async fn visit_update_channel_error(&mut self, _header: &Header, payload: &UpdateChannelError) {
+        if !self.is_from(Peer::Upstream, payload) {
+            return;
+        }
+        if let Some(channel_id) = self.downstream_channel_id(payload.channel_id) {
+            let msg = UpdateChannelError {
+                channel_id,
+                ..payload.clone()
+            };
+            self.submit(Peer::Downstream, msg);
+        }
+    }
+
+    /// Channels can be closed by both sides, the mapping is dropped in either case
+    async fn visit_close_channel(&mut self, _header: &Header, payload: &CloseChannel) {
+        let (channel_id, destination) = match self.source {
+            Peer::Downstream => (
+                self.channels.remove_downstream_id(payload.channel_id),
+                Peer::Upstream,
+            ),
+            Peer::Upstream => (
+                self.channels.remove_upstream_id(payload.channel_id),
+                Peer::Downstream,
+            ),
+        };
+        match channel_id {
+            Some(channel_id) => {
+                let msg = CloseChannel {
+                    channel_id,
+                    ..payload.clone()
+                };
+                self.submit(destination, msg);
+            }
+            None => info!(
+                "Dropping close of unknown {} channel {}",
+                self.source, payload.channel_id
+            ),
+        }
+    }
+
+    async fn visit_submit_shares_standard(
+        &mut self,
+        _header: &Header,
+        payload: &SubmitSharesStandard,
+    ) {
+        if !self.is_from(Peer::Downstream, payload) {
+            return;
+        }
+        match self.channels.upstream_id(payload.channel_id) {
+            Some(channel_id) => {
+                let msg = SubmitSharesStandard {
+                    channel_id,
+                    ..payload.clone()
+                };
+                self.submit(Peer::Upstream, msg);
+            }
+            None => self.reject_submit(payload.channel_id, payload.seq_num),
+        }
+    }
+
+    async fn visit_submit_shares_extended(
+        &mut self,
+        _header: &Header,
+        payload: &SubmitSharesExtended,
+    ) {
+        if !self.is_from(Peer::Downstream, payload) {
+            return;
+        }
+        match self.channels.upstream_id(payload.channel_id) {
+            Some(channel_id) => {
+                let msg = SubmitSharesExtended {
+                    channel_id,
+                    ..payload.clone()
+                };
+                self.submit(Peer::Upstream, msg);
+            }
+            None => self.reject_submit(payload.channel_id, payload.seq_num),
+        }
+    }
+
+    async fn visit_submit_shares_success(
+        &mut self,
+        _header: &Header,
+        payload: &SubmitSharesSuccess,
+    ) {
+        if !self.is_from(Peer::Upstream, payload) {
+            return;
+        }
+        if let Some(channel_id) = self.downstream_channel_id(payload.channel_id) {
+            let msg = SubmitSharesSuccess {
+                channel_id,
+                ..payload.clone()
+            };
+            self.submit(Peer::Downstream, msg);
+        }
+    }
+
+    async fn visit_submit_shares_error(&mut self, _header: &Header, payload: &SubmitSharesError) {
+        if !self.is_from(Peer::Upstream, payload) {
+            return;
+        }
+        if let Some(channel_id) = self.downstream_channel_id(payload.channel_id) {
+            let msg = SubmitSharesError {
+                channel_id,
+                ..payload.clone()
+            };
+            self.submit(Peer::Downstream, msg);
+        }
+    }
+
+    async fn visit_new_mining_job(&mut self, _header: &Header, payload: &NewMiningJob) {
+        if !self.is_from(Peer::Upstream, payload) {
+            return;
+        }
+        if let Some(channel_id) = self.downstream_channel_id(payload.channel_id) {
+            let msg = NewMiningJob {
+                channel_id,
+                ..payload.clone()
+            };
+            self.submit(Peer::Downstream, msg);
+        }
+    }
+
+    async fn visit_new_extended_mining_job(
+        &mut self,
+        _header: &Header,
+        payload: &NewExtendedMiningJob,
+    ) {
+        if !self.is_from(Peer::Upstream, payload) {
+            return;
+        }
+        if let Some(channel_id) = self.downstream_channel_id(payload.channel_id) {
+            let msg = NewExtendedMiningJob {
+                channel_id,
+                ..payload.clone()
+            };
+            self.submit(Peer::Downstream, msg);
+        }
+    }
+
+    async fn visit_set_extranonce_prefix(
+        &mut self,
+        _header: &Header,
+        payload: &SetExtranoncePrefix,
+    ) {
+        if !self.is_from(Peer::Upstream, payload) {
+            return;
+        }
+        if let Some(channel_id) = self.downstream_channel_id(payload.channel_id) {
+            let msg = SetExtranoncePrefix {
+                channel_id,
+                ..payload.clone()
+            };
+            self.submit(Peer::Downstream, msg);
+        }
+    }
+
+    async fn visit_set_new_prev_hash(&mut self, _header: &Header, payload: &SetNewPrevHash) {
+        if !self.is_from(Peer::Upstream, payload) {
+            return;
+        }
+        if let Some(channel_id) = self.downstream_channel_id(payload.channel_id) {
+            let msg = SetNewPrevHash {
+                channel_id,
+                ..payload.clone()
+            };
+            self.submit(Peer::Downstream, msg);
+        }
+    }
+
+    async fn visit_set_target(&mut self, _header: &Header, payload: &SetTarget) {
+        if !self.is_from(Peer::Upstream, payload) {
+            return;
+        }
+        if let Some(channel_id) = self.downstream_channel_id(payload.channel_id) {
+            let msg = SetTarget {
+                channel_id,
+                ..payload.clone()
+            };
+            self.submit(Peer::Downstream, msg);
+        }
+    }
+
+    /// Member channels that are not known to the downstream are left out of the group
+    async fn visit_set_group_channel(&mut self, _header: &Header, payload: &SetGroupChannel) {
+        if !self.is_from(Peer::Upstream, payload) {
+            return;
+        }
+        let channel_ids: Vec<u32> = payload
+            .channel_ids
+            .iter()
+            .filter_map(|channel_id| self.channels.downstream_id(*channel_id))
+            .collect();
+        let msg = SetGroupChannel {
+            group_channel_id: self.channels.register(payload.group_channel_id),
+            channel_ids: v2::types::Seq0_64k::from_vec(channel_ids),
+        };
+        self.submit(Peer::Downstream, msg);
+    }
+
+    async fn visit_set_custom_mining_job(
+        &mut self,
+        _header: &Header,
+        payload: &SetCustomMiningJob,
+    ) {
+        if !self.is_from(Peer::Downstream, payload) {
+            return;
+        }
+        match self.channels.upstream_id(payload.channel_id) {
+            Some(channel_id) => {
+                let msg = SetCustomMiningJob {
+                    channel_id,
+                    req_id: self.register_request(payload.req_id),
+                    ..payload.clone()
+                };
+                self.submit(Peer::Upstream, msg);
+            }
+            None => {
+                let msg = SetCustomMiningJobError {
+                    channel_id: payload.channel_id,
+                    req_id: payload.req_id,
+                    code: Self::INVALID_CHANNEL_ERR
+                        .try_into()
+                        .expect("BUG: incorrect error message"),
+                };
+                self.submit(Peer::Downstream, msg);
+            }
+        }
+    }
+
+    async fn visit_set_custom_mining_job_success(
+        &mut self,
+        _header: &Header,
+        payload: &SetCustomMiningJobSuccess,
+    ) {
+        if !self.is_from(Peer::Upstream, payload) {
+            return;
+        }
+        if let Some(req_id) = self.take_request(payload.req_id) {
+            if let Some(channel_id) = self.downstream_channel_id(payload.channel_id) {
+                let msg = SetCustomMiningJobSuccess {
+                    channel_id,
+                    req_id,
+                    ..payload.clone()
+                };
+                self.submit(Peer::Downstream, msg);
+            }
+        }
+    }
+
+    async fn visit_set_custom_mining_job_error(
+        &mut self,
+        _header: &Header,
+        payload: &SetCustomMiningJobError,
+    ) {
+        if !self.is_from(Peer::Upstream, payload) {
+            return;
+        }
+        if let Some(req_id) = self.take_request(payload.req_id) {
+            if let Some(channel_id) = self.downstream_channel_id(payload.channel_id) {
+                let msg = SetCustomMiningJobError {
+                    channel_id,
+                    req_id,
+                    ..payload.clone()
+                };
+                self.submit(Peer::Downstream, msg);
+            }
+        }
+    }
+
+    /// The downstream is connected to the proxy and not to the upstream, the request is
+    /// therefore not relayed
+    async fn visit_reconnect(&mut self, _header: &Header, payload: &Reconnect) {
+        if self.is_from(Peer::Upstream, payload) {
+            info!("Ignoring reconnect request from upstream: {:?}", payload);
+        }
+    }
+}
+
+/// Represents a single pass-through session (one V2 client relayed to one V2 server)
+struct ConnRelay {
+    relay: V2Relay,
+    /// Downstream connection
+    v2_conn: v2::Framed,
+    /// Address of the v2 peer that has connected
+    v2_peer_addr: SocketAddr,
+    /// Idle timeout and message rate of the downstream connection
+    v2_monitor: DownstreamMonitor,
+    /// Upstream connection
+    upstream_conn: v2::Framed,
+    upstream_peer_addr: SocketAddr,
+    /// Frames from the relay to be sent out via the downstream connection
+    v2_relay_rx: mpsc::Receiver<v2::Frame>,
+    /// Frames from the relay to be sent out via the upstream connection
+    upstream_relay_rx: mpsc::Receiver<v2::Frame>,
+}
+
+impl ConnRelay {
+    const MAX_RELAY_CHANNEL_SIZE: usize = 10;
+    const V2_UPSTREAM_TIMEOUT: time::Duration = time::Duration::from_secs(60);
+
+    fn new(
+        v2_conn: v2::Framed,
+        v2_peer_addr: SocketAddr,
+        v2_monitor: DownstreamMonitor,
+        upstream_conn: v2::Framed,
+        upstream_peer_addr: SocketAddr,
+    ) -> Self {
+        let (v2_relay_tx, v2_relay_rx) = mpsc::channel(Self::MAX_RELAY_CHANNEL_SIZE);
+        let (upstream_relay_tx, upstream_relay_rx) = mpsc::channel(Self::MAX_RELAY_CHANNEL_SIZE);
+
+        Self {
+            relay: V2Relay::new(v2_relay_tx, upstream_relay_tx),
+            v2_conn,
+            v2_peer_addr,
+            v2_monitor,
+            upstream_conn,
+            upstream_peer_addr,
+            v2_relay_rx,
+            upstream_relay_rx,
+        }
+    }
+
+    async fn run(self) -> Result<()> {
+        let mut relay = self.relay;
+        let mut v2_monitor = self.v2_monitor;
+
+        let (v2_conn_tx, mut v2_conn_rx) = self.v2_conn.split();
+        tokio::spawn(ConnTranslation::v2_send_task(
+            v2_conn_tx,
+            self.v2_relay_rx,
+            self.v2_peer_addr,
+        ));
+        let (upstream_conn_tx, mut upstream_conn_rx) = self.upstream_conn.split();
+        tokio::spawn(ConnTranslation::v2_send_task(
+            upstream_conn_tx,
+            self.upstream_relay_rx,
+            self.upstream_peer_addr,
+        ));
+
+        loop {
+            select! {
+                // Relay upstream frame to the downstream
+                upstream_frame = upstream_conn_rx.next().timeout(Self::V2_UPSTREAM_TIMEOUT).fuse() => {
+                    let upstream_frame: Result<v2::Frame> = match upstream_frame {
+                        Ok(Some(upstream_frame)) => upstream_frame.map_err(Into::into),
+                        Ok(None) => Err(format!(
+                            "Upstream V2 stratum connection dropped ({:?})",
+                            self.upstream_peer_addr
+                        )
+                        .into()),
+                        Err(e) => Err(Error::from(e)),
+                    };
+                    match upstream_frame {
+                        Ok(upstream_frame) => relay.handle_upstream_frame(upstream_frame).await?,
+                        Err(e) => {
+                            relay.close_all_channels(V2ToV1Translation::UPSTREAM_LOST_REASON);
+                            Err(e)?;
+                        }
+                    }
+                },
+                // Relay downstream frame to the upstream
+                v2_frame = v2_monitor.next_frame(&mut v2_conn_rx).fuse() => {
+                    match v2_frame? {
+                        Some(v2_frame) => relay.handle_downstream_frame(v2_frame?).await?,
+                        None => {
+                            Err(format!("V2 client disconnected ({:?})", self.v2_peer_addr))?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Builds V2 framed stream from the upstream connection, noise handshake authenticates the
+/// upstream with any of `authority_public_keys` unless no key is provided
+pub(crate) async fn connect_upstream(
+    upstream_conn: UpstreamConnection,
+    authority_public_keys: Vec<v2::noise::auth::EncodedEd25519PublicKey>,
+) -> Result<v2::Framed> {
+    let upstream_conn = match authority_public_keys.is_empty() {
+        false => {
+            let authority_public_keys = authority_public_keys
+                .into_iter()
+                .map(|key| key.into_inner())
+                .collect();
+            let upstream_conn =
+                v2::noise::Initiator::with_authority_public_keys(authority_public_keys)
+                    .connect(upstream_conn.conn)
+                    .await;
+            if upstream_conn.is_err() {
+                metrics::HANDSHAKE_FAILURES_TOTAL
+                    .with_label_values(&["upstream"])
+                    .inc();
+            }
+            upstream_conn?
+        }
+        true => Connection::<v2::Framing>::new(upstream_conn.conn).into_inner(),
+    };
+    Ok(upstream_conn)
+}
+
+/// Relays connection `v2_conn` onto the upstream V2 connection `upstream_conn`. The upstream is
+/// authenticated with any of `authority_public_keys` during the noise handshake, the connection
+/// is unencrypted when no key is provided. When the upstream is lost, the downstream channels are
+/// closed and the downstream is expected to reconnect, the proxy then connects to the first
+/// available upstream server.
+pub async fn handle_connection(
+    v2_conn: v2::Framed,
+    v2_peer_addr: SocketAddr,
+    upstream_conn: UpstreamConnection,
+    upstream_connector: UpstreamConnector,
+    authority_public_keys: Vec<v2::noise::auth::EncodedEd25519PublicKey>,
+) -> Result<()> {
+    let upstream_peer_addr = upstream_conn.peer_addr;
+    let upstream_conn = connect_upstream(upstream_conn, authority_public_keys).await?;
+    let v2_monitor = DownstreamMonitor::new(&upstream_connector.connection_limits(), v2_peer_addr);
+    let relay = ConnRelay::new(
+        v2_conn,
+        v2_peer_addr,
+        v2_monitor,
+        upstream_conn,
+        upstream_peer_addr,
+    );
+
+    relay.run().await
+}
@@ -0,0 +1,168 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use ii_async_compat::tokio;
+
+use super::*;
+use ii_stratum::test_utils;
+
+/// Converts `message` into a frame and passes it to `relay` as if it has been received via the
+/// downstream connection
+async fn simulate_downstream_message<M>(relay: &mut V2Relay, message: M)
+where
+    M: TryInto<v2::Frame, Error = ii_stratum::error::Error>,
+{
+    let frame: v2::Frame = message.try_into().expect("Could not serialize message");
+    relay
+        .handle_downstream_frame(frame)
+        .await
+        .expect("Relaying failed");
+}
+
+/// Converts `message` into a frame and passes it to `relay` as if it has been received via the
+/// upstream connection
+async fn simulate_upstream_message<M>(relay: &mut V2Relay, message: M)
+where
+    M: TryInto<v2::Frame, Error = ii_stratum::error::Error>,
+{
+    let frame: v2::Frame = message.try_into().expect("Could not serialize message");
+    relay
+        .handle_upstream_frame(frame)
+        .await
+        .expect("Relaying failed");
+}
+
+/// Opens a channel through the relay, the upstream assigns channel 42 in group channel 100
+async fn open_channel(
+    relay: &mut V2Relay,
+    downstream_rx: &mut mpsc::Receiver<v2::Frame>,
+    upstream_rx: &mut mpsc::Receiver<v2::Frame>,
+) -> OpenStandardMiningChannelSuccess {
+    let open_channel = test_utils::v2::build_open_channel();
+    simulate_downstream_message(relay, open_channel.clone()).await;
+    let upstream_open_channel: OpenStandardMiningChannel =
+        test_utils::v2::receive_message(upstream_rx).await;
+    assert_eq!(upstream_open_channel.req_id, 0);
+    assert_eq!(upstream_open_channel.user, open_channel.user);
+
+    let open_channel_success = OpenStandardMiningChannelSuccess {
+        req_id: upstream_open_channel.req_id,
+        channel_id: 42,
+        group_channel_id: 100,
+        ..test_utils::v2::build_open_channel_success()
+    };
+    simulate_upstream_message(relay, open_channel_success).await;
+    let downstream_open_channel_success: OpenStandardMiningChannelSuccess =
+        test_utils::v2::receive_message(downstream_rx).await;
+    assert_eq!(downstream_open_channel_success.req_id, open_channel.req_id);
+    downstream_open_channel_success
+}
+
+/// Verifies that request and channel IDs are remapped in both directions
+#[tokio::test]
+async fn test_relay_remaps_ids() {
+    let (downstream_tx, mut downstream_rx) = mpsc::channel(1);
+    let (upstream_tx, mut upstream_rx) = mpsc::channel(1);
+    let mut relay = V2Relay::new(downstream_tx, upstream_tx);
+
+    let open_channel_success = open_channel(&mut relay, &mut downstream_rx, &mut upstream_rx).await;
+    assert_eq!(open_channel_success.channel_id, 0);
+    assert_eq!(open_channel_success.group_channel_id, 1);
+
+    // Jobs sent to the upstream group channel are delivered to the downstream group channel
+    let new_mining_job = NewMiningJob {
+        channel_id: 100,
+        ..test_utils::v2::build_new_mining_job()
+    };
+    simulate_upstream_message(&mut relay, new_mining_job).await;
+    let new_mining_job: NewMiningJob = test_utils::v2::receive_message(&mut downstream_rx).await;
+    assert_eq!(new_mining_job.channel_id, 1);
+
+    // Shares are submitted on the upstream channel
+    let submit_shares = SubmitSharesStandard {
+        channel_id: open_channel_success.channel_id,
+        ..test_utils::v2::build_submit_shares()
+    };
+    simulate_downstream_message(&mut relay, submit_shares.clone()).await;
+    let upstream_submit_shares: SubmitSharesStandard =
+        test_utils::v2::receive_message(&mut upstream_rx).await;
+    assert_eq!(upstream_submit_shares.channel_id, 42);
+    assert_eq!(upstream_submit_shares.seq_num, submit_shares.seq_num);
+
+    let submit_shares_success = SubmitSharesSuccess {
+        channel_id: 42,
+        last_seq_num: submit_shares.seq_num,
+        new_submits_accepted_count: 1,
+        new_shares_sum: 1,
+    };
+    simulate_upstream_message(&mut relay, submit_shares_success).await;
+    let submit_shares_success: SubmitSharesSuccess =
+        test_utils::v2::receive_message(&mut downstream_rx).await;
+    assert_eq!(
+        submit_shares_success.channel_id,
+        open_channel_success.channel_id
+    );
+
+    // Closing the channel releases the mapping
+    let close_channel = CloseChannel {
+        channel_id: open_channel_success.channel_id,
+        ..test_utils::v2::build_close_channel()
+    };
+    simulate_downstream_message(&mut relay, close_channel).await;
+    let close_channel: CloseChannel = test_utils::v2::receive_message(&mut upstream_rx).await;
+    assert_eq!(close_channel.channel_id, 42);
+    assert_eq!(
+        relay.channels.upstream_id(open_channel_success.channel_id),
+        None
+    );
+}
+
+/// Verifies that shares for unknown channels are rejected by the relay and that messages sent in
+/// the wrong direction are dropped
+#[tokio::test]
+async fn test_relay_rejects_invalid_messages() {
+    let (downstream_tx, mut downstream_rx) = mpsc::channel(1);
+    let (upstream_tx, mut upstream_rx) = mpsc::channel(1);
+    let mut relay = V2Relay::new(downstream_tx, upstream_tx);
+
+    let submit_shares = test_utils::v2::build_submit_shares();
+    simulate_downstream_message(&mut relay, submit_shares.clone()).await;
+    let submit_shares_error: SubmitSharesError =
+        test_utils::v2::receive_message(&mut downstream_rx).await;
+    assert_eq!(submit_shares_error.channel_id, submit_shares.channel_id);
+    assert_eq!(submit_shares_error.seq_num, submit_shares.seq_num);
+    assert_eq!(
+        submit_shares_error.code.to_string(),
+        V2Relay::INVALID_CHANNEL_ERR
+    );
+
+    // Jobs can only be sent by the upstream
+    simulate_downstream_message(&mut relay, test_utils::v2::build_new_mining_job()).await;
+    assert!(upstream_rx.try_next().is_err());
+    assert!(downstream_rx.try_next().is_err());
+
+    // Upstream loss closes the downstream channels
+    let open_channel_success = open_channel(&mut relay, &mut downstream_rx, &mut upstream_rx).await;
+    relay.close_all_channels(V2ToV1Translation::UPSTREAM_LOST_REASON);
+    let close_channel: CloseChannel = test_utils::v2::receive_message(&mut downstream_rx).await;
+    assert_eq!(close_channel.channel_id, open_channel_success.channel_id);
+}
@@ -0,0 +1,154 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! PROXY protocol header (versions 1 and 2) is sent by TCP load balancers at the start of each
+//! connection, it carries the address of the original client. See
+//! https://www.haproxy.org/download/2.0/doc/proxy-protocol.txt
+//!
+//! The header is read byte-exactly so that the rest of the stream (e.g. the noise handshake) is
+//! left intact.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use ii_async_compat::tokio;
+
+use crate::error::Result;
+
+#[cfg(test)]
+mod test;
+
+/// Signature of the binary header (version 2)
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Prefix of the text header (version 1)
+const V1_PREFIX: &[u8] = b"PROXY ";
+/// Maximum length of the text header including CRLF
+const V1_MAX_LENGTH: usize = 107;
+/// Length of the fixed part of the binary header
+const V2_HEADER_LENGTH: usize = 16;
+
+/// Reads PROXY protocol header of either version from `reader`
+/// Return:
+///  - address of the original client
+///  - None when the balancer doesn't provide the address (health checks, unknown protocols)
+pub async fn read_header<R>(reader: &mut R) -> Result<Option<SocketAddr>>
+where
+    R: AsyncRead + Unpin,
+{
+    // Both versions are longer than the signature of the binary header
+    let mut header = vec![0u8; V2_SIGNATURE.len()];
+    reader.read_exact(&mut header).await?;
+    if header[..] == V2_SIGNATURE[..] {
+        header.resize(V2_HEADER_LENGTH, 0);
+        reader.read_exact(&mut header[V2_SIGNATURE.len()..]).await?;
+        let length = u16::from_be_bytes([header[14], header[15]]) as usize;
+        let mut addresses = vec![0u8; length];
+        reader.read_exact(&mut addresses).await?;
+        parse_v2(&header, &addresses)
+    } else if header.starts_with(V1_PREFIX) {
+        // The text header is read byte by byte as its length is not known in advance
+        while !header.ends_with(b"\r\n") {
+            if header.len() >= V1_MAX_LENGTH {
+                Err("PROXY protocol header too long")?;
+            }
+            header.push(reader.read_u8().await?);
+        }
+        let line = std::str::from_utf8(&header).map_err(|_| "Invalid PROXY protocol header")?;
+        parse_v1(line)
+    } else {
+        Err("Missing PROXY protocol header")?
+    }
+}
+
+/// Parses text header, e.g. `PROXY TCP4 192.0.2.1 198.51.100.1 56324 3336\r\n`
+fn parse_v1(line: &str) -> Result<Option<SocketAddr>> {
+    let fields: Vec<&str> = line.trim_end_matches("\r\n").split(' ').collect();
+    match fields.as_slice() {
+        fields if fields.starts_with(&["PROXY", "UNKNOWN"]) => Ok(None),
+        ["PROXY", protocol @ "TCP4", source, _, source_port, _]
+        | ["PROXY", protocol @ "TCP6", source, _, source_port, _] => {
+            let source = IpAddr::from_str(source)
+                .map_err(|_| format!("Invalid PROXY protocol source address: {}", source))?;
+            if source.is_ipv4() != (*protocol == "TCP4") {
+                Err(format!(
+                    "PROXY protocol source address {} doesn't match {}",
+                    source, protocol
+                ))?;
+            }
+            let source_port = u16::from_str(source_port)
+                .map_err(|_| format!("Invalid PROXY protocol source port: {}", source_port))?;
+            Ok(Some(SocketAddr::new(source, source_port)))
+        }
+        _ => Err(format!("Invalid PROXY protocol header: {:?}", line))?,
+    }
+}
+
+/// Parses binary header, `header` is the fixed part of the header and `addresses` is the rest
+/// of the header (the addresses followed by optional TLVs)
+fn parse_v2(header: &[u8], addresses: &[u8]) -> Result<Option<SocketAddr>> {
+    let version_command = header[12];
+    if version_command >> 4 != 2 {
+        Err(format!(
+            "Unsupported PROXY protocol version: {}",
+            version_command >> 4
+        ))?;
+    }
+    match version_command & 0x0f {
+        // LOCAL connection established by the balancer itself
+        0x0 => return Ok(None),
+        // PROXY
+        0x1 => (),
+        command => Err(format!("Unsupported PROXY protocol command: {}", command))?,
+    }
+    match header[13] {
+        // TCP over IPv4
+        0x11 => {
+            if addresses.len() < 12 {
+                Err("PROXY protocol IPv4 addresses truncated")?;
+            }
+            let mut source = [0u8; 4];
+            source.copy_from_slice(&addresses[..4]);
+            let source_port = u16::from_be_bytes([addresses[8], addresses[9]]);
+            Ok(Some(SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::from(source)),
+                source_port,
+            )))
+        }
+        // TCP over IPv6
+        0x21 => {
+            if addresses.len() < 36 {
+                Err("PROXY protocol IPv6 addresses truncated")?;
+            }
+            let mut source = [0u8; 16];
+            source.copy_from_slice(&addresses[..16]);
+            let source_port = u16::from_be_bytes([addresses[32], addresses[33]]);
+            Ok(Some(SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::from(source)),
+                source_port,
+            )))
+        }
+        // Unspecified, UDP or UNIX sockets don't carry a usable TCP source address
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,93 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use ii_async_compat::tokio;
+
+use super::*;
+
+/// Reads header from `stream` and verifies that the rest of the stream is left intact
+async fn read_header_with_payload(stream: &[u8]) -> Result<Option<SocketAddr>> {
+    let mut reader = stream;
+    let peer_addr = read_header(&mut reader).await?;
+    assert_eq!(reader, b"payload", "Header not consumed exactly");
+    Ok(peer_addr)
+}
+
+#[tokio::test]
+async fn test_v1_header() {
+    let peer_addr =
+        read_header_with_payload(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 3336\r\npayload")
+            .await
+            .expect("Cannot read header");
+    assert_eq!(peer_addr, Some("192.0.2.1:56324".parse().unwrap()));
+
+    let peer_addr =
+        read_header_with_payload(b"PROXY TCP6 2001:db8::1 2001:db8::2 4000 3336\r\npayload")
+            .await
+            .expect("Cannot read header");
+    assert_eq!(peer_addr, Some("[2001:db8::1]:4000".parse().unwrap()));
+
+    let peer_addr = read_header_with_payload(b"PROXY UNKNOWN\r\npayload")
+        .await
+        .expect("Cannot read header");
+    assert_eq!(peer_addr, None);
+}
+
+#[tokio::test]
+async fn test_v2_header() {
+    let mut stream = V2_SIGNATURE.to_vec();
+    // PROXY command, TCP over IPv4, 12 bytes of addresses and 3 bytes of a TLV
+    stream.extend_from_slice(&[0x21, 0x11, 0x00, 15]);
+    stream.extend_from_slice(&[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x0d, 0x08]);
+    stream.extend_from_slice(&[0x04, 0x00, 0x00]);
+    stream.extend_from_slice(b"payload");
+    let peer_addr = read_header_with_payload(&stream)
+        .await
+        .expect("Cannot read header");
+    assert_eq!(peer_addr, Some("192.0.2.1:56324".parse().unwrap()));
+
+    // LOCAL command carries no address
+    let mut stream = V2_SIGNATURE.to_vec();
+    stream.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+    stream.extend_from_slice(b"payload");
+    let peer_addr = read_header_with_payload(&stream)
+        .await
+        .expect("Cannot read header");
+    assert_eq!(peer_addr, None);
+}
+
+#[tokio::test]
+async fn test_invalid_header() {
+    for stream in &[
+        &b"GET / HTTP/1.1\r\n\r\n"[..],
+        &b"PROXY TCP4 2001:db8::1 198.51.100.1 56324 3336\r\n"[..],
+        &b"PROXY TCP4 192.0.2.1 198.51.100.1 port 3336\r\n"[..],
+        &b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 3336 3336 3336 3336 3336 3336 3336 3336 3336 \
+           3336 3336 3336\r\n"[..],
+    ] {
+        assert!(
+            read_header(&mut &stream[..]).await.is_err(),
+            "Invalid header accepted: {:?}",
+            stream
+        );
+    }
+}
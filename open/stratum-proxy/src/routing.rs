@@ -0,0 +1,280 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Routing rules rewrite credentials of downstream channels before the channels are authorized
+//! with the upstream V1 server. The rules are loaded from a TOML file, e.g.:
+//!
+//! ```toml
+//! [[rule]]
+//! user_regex = '^farm1-(?P<rig>\w+)$'
+//! account = "farm1"
+//! worker = "${rig}"
+//! upstream = ["pool.example.com:3333", "backup.example.com:3333"]
+//!
+//! [[rule]]
+//! user_prefix = "braiins."
+//! source = "10.0.0.0/8"
+//! account = "braiins"
+//! ```
+//!
+//! A rule matches the downstream user exactly (`user`), by its prefix (`user_prefix`) or by a
+//! regular expression (`user_regex`). Optionally the rule also restricts the source address of
+//! the downstream connection (`source`, an address or a network). The first matching rule
+//! determines the upstream account and the worker name, the downstream worker name (the part of
+//! the user after the first dot) is kept unless the rule specifies its own one. Worker and
+//! account of a regular expression rule may refer to the captured groups. The rule may route the
+//! channel to other upstream servers than the configured ones. Channels of users that don't
+//! match any rule are refused.
+
+use std::convert::TryFrom;
+use std::net::IpAddr;
+
+use regex::Regex;
+use serde::Deserialize;
+
+use ii_wire::Address;
+
+use crate::error::{Error, Result, ResultExt};
+
+#[cfg(test)]
+mod test;
+
+/// Rule as it is written in the rules file
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct RuleConfig {
+    user: Option<String>,
+    user_prefix: Option<String>,
+    user_regex: Option<String>,
+    source: Option<String>,
+    account: String,
+    worker: Option<String>,
+    upstream: Option<Vec<String>>,
+}
+
+/// Contents of the rules file
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct RulesConfig {
+    #[serde(default)]
+    rule: Vec<RuleConfig>,
+}
+
+/// Describes which downstream users the rule applies to
+#[derive(Debug)]
+enum UserMatcher {
+    Any,
+    Exact(String),
+    Prefix(String),
+    Regex(Regex),
+}
+
+/// Network of the source address, single address is a network with full prefix length
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SourceNetwork {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl SourceNetwork {
+    fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let mask = std::u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(addr) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let mask = std::u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(addr) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl std::str::FromStr for SourceNetwork {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(2, '/');
+        let addr: IpAddr = parts
+            .next()
+            .unwrap_or_default()
+            .parse::<IpAddr>()
+            .context(format!("Invalid source address: {}", s))?;
+        let max_prefix_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix_len = match parts.next() {
+            Some(prefix_len) => prefix_len
+                .parse()
+                .ok()
+                .filter(|prefix_len| *prefix_len <= max_prefix_len)
+                .ok_or_else(|| format!("Invalid source network prefix: {}", s))?,
+            None => max_prefix_len,
+        };
+        Ok(Self { addr, prefix_len })
+    }
+}
+
+#[derive(Debug)]
+struct Rule {
+    user: UserMatcher,
+    source: Option<SourceNetwork>,
+    account: String,
+    worker: Option<String>,
+    upstream: Option<Vec<Address>>,
+}
+
+impl Rule {
+    /// Provides the upstream user when the rule matches downstream `user` connected from
+    /// `peer_addr`
+    fn apply(&self, user: &str, peer_addr: Option<IpAddr>) -> Option<String> {
+        if let Some(source) = self.source {
+            if !peer_addr.map_or(false, |peer_addr| source.contains(peer_addr)) {
+                return None;
+            }
+        }
+        let captures = match &self.user {
+            UserMatcher::Any => None,
+            UserMatcher::Exact(exact) if exact == user => None,
+            UserMatcher::Prefix(prefix) if user.starts_with(prefix.as_str()) => None,
+            UserMatcher::Regex(regex) => Some(regex.captures(user)?),
+            _ => return None,
+        };
+        // Only templates of regular expression rules refer to the captured groups
+        let expand = |template: &String| match &captures {
+            Some(captures) => {
+                let mut expanded = String::new();
+                captures.expand(template, &mut expanded);
+                expanded
+            }
+            None => template.clone(),
+        };
+
+        let account = expand(&self.account);
+        let worker = match &self.worker {
+            Some(worker) => expand(worker),
+            None => user.splitn(2, '.').nth(1).unwrap_or_default().to_string(),
+        };
+        Some(match worker.is_empty() {
+            true => account,
+            false => format!("{}.{}", account, worker),
+        })
+    }
+}
+
+impl TryFrom<RuleConfig> for Rule {
+    type Error = Error;
+
+    fn try_from(config: RuleConfig) -> Result<Self> {
+        let user = match (config.user, config.user_prefix, config.user_regex) {
+            (None, None, None) => UserMatcher::Any,
+            (Some(user), None, None) => UserMatcher::Exact(user),
+            (None, Some(prefix), None) => UserMatcher::Prefix(prefix),
+            (None, None, Some(regex)) => UserMatcher::Regex(
+                Regex::new(&regex).context(format!("Invalid user regex: {}", regex))?,
+            ),
+            _ => Err("Only one of 'user', 'user_prefix' and 'user_regex' can be specified")?,
+        };
+        let source = match config.source {
+            Some(source) => Some(source.parse()?),
+            None => None,
+        };
+        let upstream = match config.upstream {
+            Some(addrs) if addrs.is_empty() => Err("Empty list of upstream servers")?,
+            Some(addrs) => Some(
+                addrs
+                    .iter()
+                    .map(|addr| {
+                        addr.parse::<Address>()
+                            .map_err(|_| format!("Invalid upstream address: {}", addr).into())
+                    })
+                    .collect::<Result<Vec<_>>>()?,
+            ),
+            None => None,
+        };
+        if config.account.is_empty() {
+            Err("Empty upstream account")?;
+        }
+        Ok(Self {
+            user,
+            source,
+            account: config.account,
+            worker: config.worker,
+            upstream,
+        })
+    }
+}
+
+/// Outcome of routing a downstream channel
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route {
+    /// User that the channel is authorized with in the upstream
+    pub user: String,
+    /// Upstream servers in the order of preference, the configured servers are used when there
+    /// is none
+    pub upstream: Option<Vec<Address>>,
+}
+
+/// Ordered list of routing rules
+#[derive(Debug)]
+pub struct RoutingRules {
+    rules: Vec<Rule>,
+}
+
+impl RoutingRules {
+    /// Routes channel of downstream `user` connected from `peer_addr` according to the first
+    /// matching rule, there is no route for unknown users
+    pub fn route(&self, user: &str, peer_addr: Option<IpAddr>) -> Option<Route> {
+        self.rules.iter().find_map(|rule| {
+            rule.apply(user, peer_addr).map(|user| Route {
+                user,
+                upstream: rule.upstream.clone(),
+            })
+        })
+    }
+}
+
+impl TryFrom<String> for RoutingRules {
+    type Error = Error;
+
+    fn try_from(rules: String) -> Result<Self> {
+        let config: RulesConfig = toml::from_str(&rules).context("Invalid routing rules")?;
+        let rules = config
+            .rule
+            .into_iter()
+            .enumerate()
+            .map(|(index, rule)| {
+                Rule::try_from(rule)
+                    .context(format!("Routing rule #{}", index + 1))
+                    .map_err(Into::into)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { rules })
+    }
+}
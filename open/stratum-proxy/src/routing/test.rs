@@ -0,0 +1,108 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use super::*;
+
+const RULES: &str = r#"
+[[rule]]
+user = "alice"
+account = "alice-account"
+worker = "rig"
+
+[[rule]]
+user_regex = '^farm1-(?P<rig>\w+)$'
+account = "farm1"
+worker = "${rig}"
+upstream = ["pool.example.com:3333", "backup.example.com:3333"]
+
+[[rule]]
+user_prefix = "braiins."
+source = "10.0.0.0/8"
+account = "braiins-lan"
+
+[[rule]]
+source = "192.168.1.1"
+account = "guest"
+"#;
+
+fn route(rules: &RoutingRules, user: &str, peer_addr: &str) -> Option<Route> {
+    rules.route(user, Some(peer_addr.parse().expect("BUG: invalid address")))
+}
+
+/// Verifies that the first matching rule rewrites the user and that unknown users have no route
+#[test]
+fn test_routing_rules() {
+    let rules = RoutingRules::try_from(RULES.to_string()).expect("BUG: cannot parse rules");
+
+    assert_eq!(
+        route(&rules, "alice", "127.0.0.1"),
+        Some(Route {
+            user: "alice-account.rig".to_string(),
+            upstream: None,
+        })
+    );
+    assert_eq!(
+        route(&rules, "farm1-s9x", "127.0.0.1"),
+        Some(Route {
+            user: "farm1.s9x".to_string(),
+            upstream: Some(vec![
+                Address("pool.example.com".to_string(), 3333),
+                Address("backup.example.com".to_string(), 3333),
+            ]),
+        })
+    );
+    // Downstream worker name is kept
+    assert_eq!(
+        route(&rules, "braiins.worker0", "10.1.2.3").map(|route| route.user),
+        Some("braiins-lan.worker0".to_string())
+    );
+    // Source address doesn't match the network of the rule
+    assert_eq!(route(&rules, "braiins.worker0", "11.1.2.3"), None);
+    assert_eq!(
+        route(&rules, "anybody.worker1", "192.168.1.1").map(|route| route.user),
+        Some("guest.worker1".to_string())
+    );
+    assert_eq!(rules.route("anybody.worker1", None), None);
+    assert_eq!(route(&rules, "farm2-s9x", "127.0.0.1"), None);
+}
+
+/// Verifies that invalid rules are refused
+#[test]
+fn test_invalid_routing_rules() {
+    for rules in [
+        "[[rule]]\nuser = 'a'\nuser_prefix = 'b'\naccount = 'c'\n",
+        "[[rule]]\nuser_regex = '('\naccount = 'c'\n",
+        "[[rule]]\nsource = '10.0.0.0/33'\naccount = 'c'\n",
+        "[[rule]]\nupstream = []\naccount = 'c'\n",
+        "[[rule]]\nupstream = ['pool']\naccount = 'c'\n",
+        "[[rule]]\nuser = 'a'\n",
+        "[[rule]]\naccount = 'c'\nunknown = 1\n",
+    ]
+    .iter()
+    {
+        assert!(
+            RoutingRules::try_from(rules.to_string()).is_err(),
+            "Rules accepted: {}",
+            rules
+        );
+    }
+}
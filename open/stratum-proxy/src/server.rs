@@ -22,73 +22,90 @@
 
 use bytes::Bytes;
 use std::net::SocketAddr;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 use std::time;
 
 use futures::channel::mpsc;
 use futures::future::{self, Either};
 use tokio::net::TcpStream;
+use tokio::signal::unix::{signal, Signal, SignalKind};
 
 use ii_async_compat::prelude::*;
 use ii_async_compat::{bytes, select};
 use ii_logging::macros::*;
 use ii_stratum::v1;
 use ii_stratum::v2;
-use ii_wire::{Address, Client, Connection, Server};
+use ii_wire::{Address, Connection, Server};
 
-use crate::error::{ErrorKind, Result, ResultExt};
+use crate::aggregation;
+use crate::error::{ErrorKind, Result};
+use crate::frontend;
+use crate::limits::{ConnectionLimits, ConnectionPermit, ConnectionTracker, DownstreamMonitor};
+use crate::metrics;
+use crate::proxy_protocol;
+use crate::routing::RoutingRules;
 use crate::translation::V2ToV1Translation;
+use crate::upstream::{UpstreamConnection, UpstreamConnector, V1Upstream};
+use crate::v1_downstream;
+use crate::vardiff::VarDiffOptions;
+
+#[cfg(test)]
+mod test;
 
 /// Represents a single protocol translation session (one V2 client talking to one V1 server)
 pub struct ConnTranslation {
     /// Actual protocol translator
     translation: V2ToV1Translation,
-    /// Upstream connection
-    v1_conn: v1::Framed,
-    /// Address of the v1 upstream peer
-    v1_peer_addr: SocketAddr,
-    // TODO to be removed as the translator may send out items directly via a particular connection
-    // (when treated as a sink)
-    /// Frames from the translator to be sent out via V1 connection
-    v1_translation_rx: mpsc::Receiver<v1::Frame>,
+    /// Upstream connection that fails over to other upstream servers
+    v1: V1Upstream,
     /// Downstream connection
     v2_conn: v2::Framed,
     /// Address of the v2 peer that has connected
     v2_peer_addr: SocketAddr,
+    /// Idle timeout and message rate of the V2 connection
+    v2_monitor: DownstreamMonitor,
     /// Frames from the translator to be sent out via V2 connection
     v2_translation_rx: mpsc::Receiver<v2::Frame>,
 }
 
 impl ConnTranslation {
     const MAX_TRANSLATION_CHANNEL_SIZE: usize = 10;
-    const V1_UPSTREAM_TIMEOUT: time::Duration = time::Duration::from_secs(60);
-    const V2_DOWNSTREAM_TIMEOUT: time::Duration = time::Duration::from_secs(60);
+    pub(crate) const V1_UPSTREAM_TIMEOUT: time::Duration = time::Duration::from_secs(60);
 
     fn new(
         v2_conn: v2::Framed,
         v2_peer_addr: SocketAddr,
-        v1_conn: v1::Framed,
-        v1_peer_addr: SocketAddr,
+        v1_conn: UpstreamConnection,
+        upstream_connector: UpstreamConnector,
     ) -> Self {
-        let (v1_translation_tx, v1_translation_rx) =
-            mpsc::channel(Self::MAX_TRANSLATION_CHANNEL_SIZE);
+        let translation_options = upstream_connector.translation_options();
+        let v2_monitor =
+            DownstreamMonitor::new(&upstream_connector.connection_limits(), v2_peer_addr);
+        let (v1, v1_translation_tx) = V1Upstream::new(
+            upstream_connector,
+            v1_conn,
+            Self::MAX_TRANSLATION_CHANNEL_SIZE,
+        );
         let (v2_translation_tx, v2_translation_rx) =
             mpsc::channel(Self::MAX_TRANSLATION_CHANNEL_SIZE);
-        let translation =
-            V2ToV1Translation::new(v1_translation_tx, v2_translation_tx, Default::default());
+        let mut translation =
+            V2ToV1Translation::new(v1_translation_tx, v2_translation_tx, translation_options);
+        translation.set_upstream_addr(v1.peer_addr);
+        translation
+            .set_downstream_peer_addr(V2ToV1Translation::DEFAULT_DOWNSTREAM_ID, v2_peer_addr);
 
         Self {
             translation,
-            v1_conn,
-            v1_peer_addr,
-            v1_translation_rx,
+            v1,
             v2_conn,
             v2_peer_addr,
+            v2_monitor,
             v2_translation_rx,
         }
     }
 
-    async fn v1_handle_frame(
+    pub(crate) async fn v1_handle_frame(
         translation: &mut V2ToV1Translation,
         frame: v1::framing::Frame,
     ) -> Result<()> {
@@ -98,7 +115,7 @@ impl ConnTranslation {
     }
 
     //    async fn handle_frame(&mut self, frame: v2::framing::Frame) -> Result<()> {
-    async fn v2_handle_frame(
+    pub(crate) async fn v2_handle_frame(
         translation: &mut V2ToV1Translation,
         frame: v2::framing::Frame,
     ) -> Result<()> {
@@ -139,7 +156,7 @@ impl ConnTranslation {
     /// Send all V2 frames via the specified V2 connection
     /// TODO consolidate this method into V2Handler, turn the parameters into fields and
     /// implement ConnTranslation::split()
-    async fn v2_send_task<S>(
+    pub(crate) async fn v2_send_task<S>(
         mut conn_sender: S,
         mut translation_receiver: mpsc::Receiver<v2::Frame>,
         peer_addr: SocketAddr,
@@ -161,56 +178,61 @@ impl ConnTranslation {
     }
 
     async fn run(self) -> Result<()> {
-        let mut v1_translation_rx = self.v1_translation_rx;
         let mut translation = self.translation;
+        let mut v1 = self.v1;
+        let mut v2_monitor = self.v2_monitor;
 
         // TODO make connections 'optional' so that we can remove them from the instance and use
         //  the rest of the instance in as 'borrowed mutable reference'.
-        let (mut v1_conn_tx, mut v1_conn_rx) = self.v1_conn.split();
         let (v2_conn_tx, mut v2_conn_rx) = self.v2_conn.split();
 
-        // TODO factor out the frame pumping functionality and append the JoinHandle of this task
-        //  to the select statement to detect any problems and to terminate the translation, too
-        // V1 message send out loop
-        let v1_send_task = async move {
-            while let Some(frame) = v1_translation_rx.next().await {
-                if let Err(err) = v1_conn_tx.send(frame).await {
-                    error!("V1 connection failed: {}", err);
-                    break;
-                }
-            }
-        };
-        tokio::spawn(v1_send_task);
-
         tokio::spawn(Self::v2_send_task(
             v2_conn_tx,
             self.v2_translation_rx,
             self.v2_peer_addr,
         ));
 
+        // Submits that are not confirmed by the upstream in time are periodically reported,
+        // the difficulty of idle channels is adjusted at the same time
+        let mut submit_timeout_check =
+            tokio::time::interval(V2ToV1Translation::V1_SUBMIT_TIMEOUT / 2);
+
         // TODO: add cancel handler into the select statement
         loop {
             select! {
+                _ = submit_timeout_check.tick().fuse() => {
+                    translation.report_timed_out_submits(time::Instant::now());
+                    translation.retarget_channels(time::Instant::now());
+                },
                 // Receive V1 frame and translate it to V2 message
-                v1_frame = v1_conn_rx.next().timeout(Self::V1_UPSTREAM_TIMEOUT).fuse()=> {
-                    // Unwrap the potentially elapsed timeout
-                    match v1_frame? {
-                        Some(v1_frame) => {
-                            Self::v1_handle_frame(&mut translation, v1_frame?).await?;
-                        }
-                        None => {
-                            Err(format!(
-                                "Upstream V1 stratum connection dropped ({:?})",
-                                self.v1_peer_addr
-                            ))?;
-                        }
+                v1_frame = v1.conn_rx.next().timeout(Self::V1_UPSTREAM_TIMEOUT).fuse()=> {
+                    v1.handle_frame(&mut translation, v1_frame).await?;
+                    if translation.is_closed() {
+                        Err(format!("Channels refused by upstream V1 ({:?})", v1.peer_addr))?;
                     }
                 },
+                // Send out frames translated into V1
+                v1_frame = v1.translation_rx.next().fuse() => {
+                    v1.send_frame(&mut translation, v1_frame).await?;
+                },
+                // More preferred upstream server has recovered
+                v1_conn = v1.recovery_rx.select_next_some() => {
+                    v1.switch(&mut translation, v1_conn);
+                },
                 // Receive V2 frame and translate it to V1 message
-                v2_frame = v2_conn_rx.next().timeout(Self::V2_DOWNSTREAM_TIMEOUT).fuse() => {
+                v2_frame = v2_monitor.next_frame(&mut v2_conn_rx).fuse() => {
                     match v2_frame? {
                         Some(v2_frame) => {
                             Self::v2_handle_frame(&mut translation, v2_frame?).await?;
+                            v1.follow_route(&mut translation).await?;
+                            if translation.is_closed() {
+                                info!(
+                                    "All channels closed by V2 peer ({:?}), releasing upstream \
+                                     ({:?})",
+                                    self.v2_peer_addr, v1.peer_addr
+                                );
+                                return Ok(());
+                            }
                         }
                         None => {
                             Err(format!("V2 client disconnected ({:?})", self.v2_peer_addr))?;
@@ -225,10 +247,10 @@ impl ConnTranslation {
 pub async fn handle_connection(
     v2_conn: v2::Framed,
     v2_peer_addr: SocketAddr,
-    v1_conn: v1::Framed,
-    v1_peer_addr: SocketAddr,
+    v1_conn: UpstreamConnection,
+    upstream_connector: UpstreamConnector,
 ) -> Result<()> {
-    let translation = ConnTranslation::new(v2_conn, v2_peer_addr, v1_conn, v1_peer_addr);
+    let translation = ConnTranslation::new(v2_conn, v2_peer_addr, v1_conn, upstream_connector);
 
     translation.run().await
 }
@@ -244,6 +266,9 @@ struct SecurityContext {
     signature_noise_message: Bytes,
     /// Static key pair that the server will use within the noise handshake
     static_key_pair: v2::noise::StaticKeypair,
+    /// Validity of the certificate
+    valid_from: time::SystemTime,
+    not_valid_after: time::SystemTime,
 }
 
 impl SecurityContext {
@@ -257,6 +282,8 @@ impl SecurityContext {
             .freeze();
         // TODO secret key validation is currently not possible
         //let public_key = certificate.validate_secret_key(&secret_key)?;
+        let valid_from = certificate.signed_part_header().valid_from();
+        let not_valid_after = certificate.signed_part_header().not_valid_after();
         let static_key_pair = v2::noise::StaticKeypair {
             private: secret_key.into_inner(),
             public: certificate.public_key.into_inner(),
@@ -265,62 +292,370 @@ impl SecurityContext {
         Ok(Self {
             signature_noise_message,
             static_key_pair,
+            valid_from,
+            not_valid_after,
         })
     }
 }
 
+/// Security contexts of the server. A certificate that is reloaded before its validity starts
+/// is kept aside and the current certificate is served until then so that the certificates
+/// overlap during their rotation.
+struct SecurityContextStore {
+    current: Arc<SecurityContext>,
+    /// Context with a reloaded certificate that is not valid yet
+    next: Option<Arc<SecurityContext>>,
+}
+
+impl SecurityContextStore {
+    fn new(context: SecurityContext) -> Self {
+        Self {
+            current: Arc::new(context),
+            next: None,
+        }
+    }
+
+    /// Provides context for a handshake at `now`, the reloaded certificate takes over as soon
+    /// as it is valid
+    fn select(&mut self, now: time::SystemTime) -> Arc<SecurityContext> {
+        if let Some(next) = self.next.take() {
+            if next.valid_from <= now {
+                info!("Switching to the reloaded certificate");
+                self.current = next;
+            } else {
+                self.next = Some(next);
+            }
+        }
+        self.current.clone()
+    }
+
+    /// Adds reloaded `context`, it replaces the current context right away when its certificate
+    /// is already valid at `now`
+    fn update(&mut self, context: SecurityContext, now: time::SystemTime) -> Result<()> {
+        if context.not_valid_after < now {
+            Err("Reloaded certificate has already expired")?;
+        }
+        if context.valid_from <= now {
+            self.current = Arc::new(context);
+            self.next = None;
+        } else {
+            self.next = Some(Arc::new(context));
+        }
+        Ok(())
+    }
+
+    /// Expiration of the certificate that is going to be served last
+    fn not_valid_after(&self) -> time::SystemTime {
+        self.next.as_ref().unwrap_or(&self.current).not_valid_after
+    }
+}
+
+/// Reloads the security context when the certificate or the secret key file is modified or
+/// when the process receives SIGHUP
+struct CertificateWatcher {
+    store: Arc<Mutex<SecurityContextStore>>,
+    certificate_file: PathBuf,
+    secret_key_file: PathBuf,
+    /// Modification times of the files when they have been loaded
+    modified: Option<(time::SystemTime, time::SystemTime)>,
+    hangup: Signal,
+    last_expiry_warning: Option<time::Instant>,
+}
+
+impl CertificateWatcher {
+    const CHECK_INTERVAL: time::Duration = time::Duration::from_secs(10);
+    /// Approaching expiration of the certificate is reported within this period
+    const EXPIRY_WARNING_PERIOD: time::Duration = time::Duration::from_secs(7 * 24 * 60 * 60);
+    const EXPIRY_WARNING_INTERVAL: time::Duration = time::Duration::from_secs(60 * 60);
+
+    fn new(
+        store: Arc<Mutex<SecurityContextStore>>,
+        certificate_file: PathBuf,
+        secret_key_file: PathBuf,
+    ) -> Result<Self> {
+        Ok(Self {
+            store,
+            certificate_file,
+            secret_key_file,
+            modified: None,
+            hangup: signal(SignalKind::hangup())?,
+            last_expiry_warning: None,
+        })
+    }
+
+    async fn modification_times(&self) -> Result<(time::SystemTime, time::SystemTime)> {
+        let certificate_modified = tokio::fs::metadata(&self.certificate_file)
+            .await?
+            .modified()?;
+        let secret_key_modified = tokio::fs::metadata(&self.secret_key_file)
+            .await?
+            .modified()?;
+        Ok((certificate_modified, secret_key_modified))
+    }
+
+    /// Loads the files and updates the security context
+    async fn reload(&mut self) -> Result<()> {
+        let modified = self.modification_times().await?;
+        let certificate = frontend::read_from_file::<v2::noise::auth::Certificate>(
+            Some(&self.certificate_file),
+            "Certificate",
+        )
+        .await?;
+        let secret_key = frontend::read_from_file::<v2::noise::auth::StaticSecretKeyFormat>(
+            Some(&self.secret_key_file),
+            "Secret key",
+        )
+        .await?;
+        let context = SecurityContext::from_certificate_and_secret_key(certificate, secret_key)?;
+        self.store
+            .lock()
+            .expect("BUG: security context lock poisoned")
+            .update(context, time::SystemTime::now())?;
+        // The files are attempted again only once they are modified again
+        self.modified = Some(modified);
+        info!(
+            "Reloaded certificate {}",
+            self.certificate_file.to_string_lossy()
+        );
+        Ok(())
+    }
+
+    /// Reloads the files when they have been modified, the files are attempted again after
+    /// each failure as they may have been only partially written. Files with unknown
+    /// modification times (e.g. they have been missing at startup) are considered modified.
+    async fn check_files(&mut self) -> Result<()> {
+        let modified = self.modification_times().await?;
+        if self.modified.map_or(true, |loaded| loaded != modified) {
+            self.reload().await?;
+        }
+        Ok(())
+    }
+
+    fn check_expiration(&mut self) {
+        let not_valid_after = self
+            .store
+            .lock()
+            .expect("BUG: security context lock poisoned")
+            .not_valid_after();
+        let remaining = not_valid_after
+            .duration_since(time::SystemTime::now())
+            .unwrap_or_default();
+        let warned_recently = self.last_expiry_warning.map_or(false, |warning| {
+            warning.elapsed() < Self::EXPIRY_WARNING_INTERVAL
+        });
+        if remaining < Self::EXPIRY_WARNING_PERIOD && !warned_recently {
+            warn!(
+                "Certificate {} expires in {} hours, rotate it",
+                self.certificate_file.to_string_lossy(),
+                remaining.as_secs() / 3600
+            );
+            self.last_expiry_warning = Some(time::Instant::now());
+        }
+    }
+
+    async fn run(mut self) {
+        match self.modification_times().await {
+            Ok(modified) => self.modified = Some(modified),
+            Err(e) => warn!("Cannot check certificate files: {}", e),
+        }
+        let mut check = tokio::time::interval(Self::CHECK_INTERVAL);
+        loop {
+            let result = select! {
+                _ = check.tick().fuse() => {
+                    self.check_expiration();
+                    self.check_files().await
+                },
+                _ = self.hangup.recv().fuse() => {
+                    info!("Received SIGHUP, reloading certificate");
+                    self.reload().await
+                },
+            };
+            if let Err(e) = result {
+                warn!("Cannot reload certificate: {}", e);
+            }
+        }
+    }
+}
+
+/// Admits downstream connections that are within the connection limits. The address of the
+/// downstream peer is read from the PROXY protocol header when the proxy runs behind a load
+/// balancer.
+#[derive(Clone)]
+struct Admission {
+    /// Open downstream connections of both protocols
+    connection_tracker: Arc<ConnectionTracker>,
+    /// Connections start with the PROXY protocol header
+    proxy_protocol: bool,
+    /// The PROXY protocol header has to arrive within this time
+    header_timeout: time::Duration,
+}
+
+impl Admission {
+    fn new(connection_limits: ConnectionLimits, proxy_protocol: bool) -> Self {
+        Self {
+            connection_tracker: Arc::new(ConnectionTracker::new(connection_limits)),
+            proxy_protocol,
+            header_timeout: connection_limits.handshake_timeout,
+        }
+    }
+
+    /// Determines the address of the peer of `conn` and registers the connection with the
+    /// connection tracker
+    /// Return:
+    ///  - address of the peer along with the permit that keeps the connection registered
+    async fn admit(
+        &self,
+        protocol: &str,
+        conn: &mut TcpStream,
+    ) -> Result<(SocketAddr, ConnectionPermit)> {
+        let socket_peer_addr = conn.peer_addr()?;
+        let peer_addr = match self.proxy_protocol {
+            true => match proxy_protocol::read_header(conn)
+                .timeout(self.header_timeout)
+                .await
+            {
+                Ok(Ok(Some(peer_addr))) => {
+                    info!(
+                        "{} connection from {} via {}",
+                        protocol, peer_addr, socket_peer_addr
+                    );
+                    peer_addr
+                }
+                // The balancer provides no address e.g. for its own health checks
+                Ok(Ok(None)) => socket_peer_addr,
+                Ok(Err(e)) => Err(format!("{} (peer: {})", e, socket_peer_addr))?,
+                Err(_) => {
+                    metrics::record_downstream_rejection(
+                        protocol,
+                        metrics::HANDSHAKE_TIMEOUT_REASON,
+                    );
+                    Err(format!(
+                        "PROXY protocol header not received within {:?} (peer: {})",
+                        self.header_timeout, socket_peer_addr
+                    ))?
+                }
+            },
+            false => socket_peer_addr,
+        };
+        let permit = self
+            .connection_tracker
+            .clone()
+            .try_acquire(peer_addr.ip())
+            .map_err(|reason| {
+                metrics::record_downstream_rejection(protocol, reason);
+                format!(
+                    "{} connection from {} refused: {}",
+                    protocol, peer_addr, reason
+                )
+            })?;
+        metrics::DOWNSTREAM_CONNECTIONS_TOTAL
+            .with_label_values(&[protocol])
+            .inc();
+        Ok((peer_addr, permit))
+    }
+}
+
+/// Describes how downstream connections are mapped onto upstream V1 sessions
+#[derive(Clone)]
+enum Upstream {
+    /// Each downstream connection gets its own session with the upstream server
+    Dedicated(UpstreamConnector),
+    /// Downstream connections share sessions of the pool
+    Aggregated(Arc<aggregation::SessionPool>),
+}
+
 struct ProxyConnection<FN> {
     /// Downstream connection that is to be handled
     v2_downstream_conn: TcpStream,
-    /// Upstream server that we should try to connect to
-    v1_upstream_addr: Address,
+    /// Checks the connection limits before the connection is handled
+    admission: Admission,
+    /// Upstream that the downstream connection is to be mapped onto
+    upstream: Upstream,
     /// See ProxyServer
     get_connection_handler: Arc<FN>,
     /// Security context for noise handshake
     security_context: Option<Arc<SecurityContext>>,
+    /// Noise handshake has to complete within this time
+    handshake_timeout: time::Duration,
 }
 
 impl<FN, FT> ProxyConnection<FN>
 where
     FT: Future<Output = Result<()>>,
-    FN: Fn(v2::Framed, SocketAddr, v1::Framed, SocketAddr) -> FT,
+    FN: Fn(v2::Framed, SocketAddr, UpstreamConnection, UpstreamConnector) -> FT,
 {
     fn new(
         v2_downstream_conn: TcpStream,
-        v1_upstream_addr: Address,
+        admission: Admission,
+        upstream: Upstream,
         security_context: Option<Arc<SecurityContext>>,
+        handshake_timeout: time::Duration,
         get_connection_handler: Arc<FN>,
     ) -> Self {
         Self {
             v2_downstream_conn,
-            v1_upstream_addr,
+            admission,
+            upstream,
             get_connection_handler,
             security_context,
+            handshake_timeout,
         }
     }
 
     /// Handle incoming connection:
-    ///  - establish upstream V1 connection
+    ///  - establish upstream connection (unless the connection joins a shared session)
     ///  - establish noise handshake (if configured)
-    ///  - run the custom connection handler that
-    async fn do_handle(self) -> Result<()> {
-        let v2_peer_addr = self.v2_downstream_conn.peer_addr()?;
-
-        // Connect to upstream V1 server
-        let mut v1_client = Client::new(self.v1_upstream_addr);
-        // TODO Attempt only once to connect -> consider using the backoff for a few rounds before
-        // failing. Also
-        // Use the connection only to build the Framed object with V1 framing and to extract the
-        // peer address
-        let v1_conn = v1_client.next().await.context("V1 upstream connection")?;
-        let v1_peer_addr = v1_conn.peer_addr().context("V1 upstream peer address")?;
-        let v1_framed_stream = Connection::<v1::Framing>::new(v1_conn).into_inner();
+    ///  - run the custom connection handler or attach the connection to a shared session
+    async fn do_handle(self, v2_peer_addr: SocketAddr) -> Result<()> {
+        let upstream_connector = match self.upstream {
+            Upstream::Dedicated(upstream_connector) => upstream_connector,
+            Upstream::Aggregated(session_pool) => {
+                let v2_framed_stream = Self::accept_v2(
+                    self.v2_downstream_conn,
+                    self.security_context,
+                    self.handshake_timeout,
+                )
+                .await?;
+                return session_pool
+                    .handle_downstream(v2_framed_stream, v2_peer_addr)
+                    .await;
+            }
+        };
+
+        // Connect to the first available upstream server
+        let upstream_conn = upstream_connector.connect().await?;
         info!(
-            "Established translation connection with upstream V1 {} for V2 peer: {}",
-            v1_peer_addr, v2_peer_addr
+            "Established connection with upstream {} for V2 peer: {}",
+            upstream_conn.peer_addr, v2_peer_addr
         );
 
-        let v2_framed_stream = match self.security_context {
+        let v2_framed_stream = Self::accept_v2(
+            self.v2_downstream_conn,
+            self.security_context,
+            self.handshake_timeout,
+        )
+        .await?;
+
+        // Start processing of both ends
+        // TODO adjust connection handler to return a Result
+        (self.get_connection_handler)(
+            v2_framed_stream,
+            v2_peer_addr,
+            upstream_conn,
+            upstream_connector,
+        )
+        .await
+    }
+
+    /// Builds V2 framed stream from the downstream connection, noise handshake is performed
+    /// when `security_context` is provided. The handshake fails unless it completes within
+    /// `handshake_timeout`.
+    async fn accept_v2(
+        v2_downstream_conn: TcpStream,
+        security_context: Option<Arc<SecurityContext>>,
+        handshake_timeout: time::Duration,
+    ) -> Result<v2::Framed> {
+        let v2_framed_stream = match security_context {
             // Establish noise responder and run the handshake
             Some(security_context) => {
                 // TODO pass the signature message once the Responder API is adjusted
@@ -328,37 +663,74 @@ where
                     &security_context.static_key_pair,
                     security_context.signature_noise_message.clone(),
                 );
-                responder.accept(self.v2_downstream_conn).await?
+                let v2_framed_stream = match responder
+                    .accept(v2_downstream_conn)
+                    .timeout(handshake_timeout)
+                    .await
+                {
+                    Ok(v2_framed_stream) => v2_framed_stream,
+                    Err(_) => {
+                        metrics::record_downstream_rejection(
+                            "v2",
+                            metrics::HANDSHAKE_TIMEOUT_REASON,
+                        );
+                        Err(format!(
+                            "Noise handshake not completed within {:?}",
+                            handshake_timeout
+                        ))?
+                    }
+                };
+                if v2_framed_stream.is_err() {
+                    metrics::HANDSHAKE_FAILURES_TOTAL
+                        .with_label_values(&["downstream"])
+                        .inc();
+                }
+                v2_framed_stream?
             }
             // Insecure operation has been configured
-            None => Connection::<v2::Framing>::new(self.v2_downstream_conn).into_inner(),
+            None => Connection::<v2::Framing>::new(v2_downstream_conn).into_inner(),
         };
-
-        // Start processing of both ends
-        // TODO adjust connection handler to return a Result
-        (self.get_connection_handler)(
-            v2_framed_stream,
-            v2_peer_addr,
-            v1_framed_stream,
-            v1_peer_addr,
-        )
-        .await
+        Ok(v2_framed_stream)
     }
 
     /// Handle connection by delegating it to a method that is able to handle a Result so that we
     /// have info/error reporting in a single place
-    async fn handle(self) {
-        let v2_peer_addr = self
-            .v2_downstream_conn
-            .peer_addr()
-            .expect("BUG: cannot read V2 peer address");
-        match self.do_handle().await {
+    async fn handle(mut self) {
+        let (v2_peer_addr, _permit) = match self
+            .admission
+            .admit("v2", &mut self.v2_downstream_conn)
+            .await
+        {
+            Ok(admitted) => admitted,
+            Err(err) => {
+                error!("Connection error: {}", err);
+                return;
+            }
+        };
+        let connections = metrics::DOWNSTREAM_CONNECTIONS.with_label_values(&["v2"]);
+        connections.inc();
+        match self.do_handle(v2_peer_addr).await {
             Ok(()) => info!("Closing connection from {} ...", v2_peer_addr),
             Err(err) => error!("Connection error: {}, peer: {}", err, v2_peer_addr),
         }
+        connections.dec();
     }
 }
 
+/// Listening socket for V1 downstream connections
+struct V1Listener {
+    server: Server,
+    listen_addr: Address,
+    /// Describes how the V1 connections reach the upstream
+    upstream_mode: v1_downstream::UpstreamMode,
+}
+
+/// Incoming connection tagged with the protocol of the socket that has accepted it
+enum Incoming {
+    V2(std::io::Result<TcpStream>),
+    V1(std::io::Result<TcpStream>),
+}
+
 /// Structure representing the main server task.
 ///
 /// Created by binding a listening socket.
@@ -369,53 +741,158 @@ where
 pub struct ProxyServer<FN> {
     server: Server,
     listen_addr: Address,
-    v1_upstream_addr: Address,
+    /// Optional socket for V1 miners
+    v1_listener: Option<V1Listener>,
+    /// Upstream V1 servers in the order of preference
+    upstream_connector: UpstreamConnector,
+    /// Shared upstream sessions when downstream connections are aggregated
+    session_pool: Option<Arc<aggregation::SessionPool>>,
     quit_tx: mpsc::Sender<()>,
     quit_rx: Option<mpsc::Receiver<()>>,
     /// Closure that generates a handler in the form of a Future that will be passed to the
     get_connection_handler: Arc<FN>,
-    /// Security context for noise handshake
-    security_context: Option<Arc<SecurityContext>>,
+    /// Security contexts for noise handshake
+    security_context: Option<Arc<Mutex<SecurityContextStore>>>,
+    /// Limits of the downstream connections
+    connection_limits: ConnectionLimits,
+    /// Checks the connection limits of the downstream connections
+    admission: Admission,
 }
 
 impl<FN, FT> ProxyServer<FN>
 where
     FT: Future<Output = Result<()>> + Send + 'static,
-    FN: Fn(v2::Framed, SocketAddr, v1::Framed, SocketAddr) -> FT + Send + Sync + 'static,
+    FN: Fn(v2::Framed, SocketAddr, UpstreamConnection, UpstreamConnector) -> FT
+        + Send
+        + Sync
+        + 'static,
 {
     /// Constructor, binds the listening socket and builds the `ProxyServer` instance with a
-    /// specified `get_connection_handler` that builds the connection handler `Future` on demand
+    /// specified `get_connection_handler` that builds the connection handler `Future` on demand.
+    /// Connections are translated to the first available server from `upstream_addrs`, the
+    /// servers are listed in the order of preference.
     pub fn listen(
         listen_addr: Address,
-        stratum_addr: Address,
+        upstream_addrs: Vec<Address>,
         get_connection_handler: FN,
         certificate_secret_key_pair: Option<(
             v2::noise::auth::Certificate,
             v2::noise::auth::StaticSecretKeyFormat,
         )>,
     ) -> Result<ProxyServer<FN>> {
+        let upstream_connector = UpstreamConnector::new(upstream_addrs)?;
         let server = Server::bind(&listen_addr)?;
 
         let (quit_tx, quit_rx) = mpsc::channel(1);
 
         let security_context = match certificate_secret_key_pair {
-            Some((certificate, secret_key)) => Some(Arc::new(
-                SecurityContext::from_certificate_and_secret_key(certificate, secret_key)?,
-            )),
+            Some((certificate, secret_key)) => {
+                Some(Arc::new(Mutex::new(SecurityContextStore::new(
+                    SecurityContext::from_certificate_and_secret_key(certificate, secret_key)?,
+                ))))
+            }
             None => None,
         };
 
         Ok(ProxyServer {
             server,
             listen_addr,
-            v1_upstream_addr: stratum_addr,
+            v1_listener: None,
+            upstream_connector,
+            session_pool: None,
             quit_rx: Some(quit_rx),
             quit_tx,
             get_connection_handler: Arc::new(get_connection_handler),
             security_context,
+            connection_limits: ConnectionLimits::default(),
+            admission: Admission::new(ConnectionLimits::default(), false),
         })
     }
 
+    /// Rewrite users of the translated channels according to `routing_rules`. The rules are to
+    /// be configured before aggregating the connections via `aggregate()`.
+    pub fn route(mut self, routing_rules: RoutingRules) -> Self {
+        self.upstream_connector = self.upstream_connector.with_routing_rules(routing_rules);
+        self
+    }
+
+    /// Adjust difficulty of each translated channel to keep its share rate as configured by
+    /// `vardiff`. Variable difficulty is to be configured before aggregating the connections via
+    /// `aggregate()`.
+    pub fn vardiff(mut self, vardiff: VarDiffOptions) -> Self {
+        self.upstream_connector = self.upstream_connector.with_vardiff(vardiff);
+        self
+    }
+
+    /// Try to subscribe for extranonce changes in the upstream V1 sessions. The subscription is
+    /// to be configured before aggregating the connections via `aggregate()`.
+    pub fn extranonce_subscribe(mut self) -> Self {
+        self.upstream_connector = self.upstream_connector.with_extranonce_subscribe();
+        self
+    }
+
+    /// Refuse or drop downstream connections that exceed `connection_limits`. The limits are to
+    /// be configured before aggregating the connections via `aggregate()`.
+    pub fn limit_connections(mut self, connection_limits: ConnectionLimits) -> Self {
+        self.connection_limits = connection_limits;
+        self.admission = Admission::new(connection_limits, self.admission.proxy_protocol);
+        self.upstream_connector = self
+            .upstream_connector
+            .with_connection_limits(connection_limits);
+        self
+    }
+
+    /// Expect the PROXY protocol header at the start of each downstream connection on both
+    /// listeners, the connections are then attributed to the client addresses from the headers
+    pub fn accept_proxy_protocol(mut self) -> Self {
+        self.admission.proxy_protocol = true;
+        self
+    }
+
+    /// Multiplex all downstream connections onto `session_count` upstream sessions instead of
+    /// establishing a dedicated upstream session for each downstream connection
+    pub fn aggregate(mut self, session_count: usize) -> Self {
+        self.session_pool = Some(Arc::new(aggregation::SessionPool::new(
+            self.upstream_connector.clone(),
+            session_count,
+        )));
+        self
+    }
+
+    /// Accept also V1 connections on `listen_addr`. Each V1 connection is translated onto its own
+    /// upstream session as described by `upstream_mode`, the connections are never aggregated.
+    pub fn listen_v1(
+        mut self,
+        listen_addr: Address,
+        upstream_mode: v1_downstream::UpstreamMode,
+    ) -> Result<Self> {
+        let server = Server::bind(&listen_addr)?;
+        self.v1_listener = Some(V1Listener {
+            server,
+            listen_addr,
+            upstream_mode,
+        });
+        Ok(self)
+    }
+
+    /// Reload the certificate and the secret key from `certificate_file` and `secret_key_file`
+    /// when the files are modified or when the process receives SIGHUP. New handshakes use
+    /// the reloaded certificate as soon as its validity starts, established connections are
+    /// not affected.
+    pub fn reload_certificate(
+        self,
+        certificate_file: PathBuf,
+        secret_key_file: PathBuf,
+    ) -> Result<Self> {
+        let store = self
+            .security_context
+            .clone()
+            .ok_or("Certificate reloading requires noise to be enabled")?;
+        let watcher = CertificateWatcher::new(store, certificate_file, secret_key_file)?;
+        tokio::spawn(watcher.run());
+        Ok(self)
+    }
+
     /// Obtain the quit channel transmit end,
     /// which can be used to terminate the server task.
     pub fn quit_channel(&self) -> mpsc::Sender<()> {
@@ -423,19 +900,33 @@ where
     }
 
     /// Helper method for accepting incoming connections
-    async fn accept(&self, connection_result: std::io::Result<TcpStream>) -> Result<SocketAddr> {
+    async fn accept(&self, incoming: Incoming) -> Result<SocketAddr> {
+        let connection_result = match incoming {
+            Incoming::V2(connection_result) => connection_result,
+            Incoming::V1(connection_result) => return self.accept_v1(connection_result),
+        };
         let connection = connection_result?;
 
         let peer_addr = connection.peer_addr()?;
 
+        let upstream = match self.session_pool.as_ref() {
+            Some(session_pool) => Upstream::Aggregated(session_pool.clone()),
+            None => Upstream::Dedicated(self.upstream_connector.clone()),
+        };
+
         // Fully secured connection has been established
         tokio::spawn(
             ProxyConnection::new(
                 connection,
-                self.v1_upstream_addr.clone(),
-                self.security_context
-                    .as_ref()
-                    .map(|context| context.clone()),
+                self.admission.clone(),
+                upstream,
+                self.security_context.as_ref().map(|store| {
+                    store
+                        .lock()
+                        .expect("BUG: security context lock poisoned")
+                        .select(time::SystemTime::now())
+                }),
+                self.connection_limits.handshake_timeout,
                 self.get_connection_handler.clone(),
             )
             .handle(),
@@ -444,6 +935,63 @@ where
         Ok(peer_addr)
     }
 
+    /// Spawns translation of V1 connection onto a dedicated upstream session
+    fn accept_v1(&self, connection_result: std::io::Result<TcpStream>) -> Result<SocketAddr> {
+        let mut connection = connection_result?;
+        let peer_addr = connection.peer_addr()?;
+        let admission = self.admission.clone();
+        let upstream_mode = self
+            .v1_listener
+            .as_ref()
+            .expect("BUG: V1 connection without V1 listener")
+            .upstream_mode
+            .clone();
+        let upstream_connector = self.upstream_connector.clone();
+
+        tokio::spawn(async move {
+            let (peer_addr, _permit) = match admission.admit("v1", &mut connection).await {
+                Ok(admitted) => admitted,
+                Err(err) => {
+                    error!("Connection error: {}", err);
+                    return;
+                }
+            };
+            let connections = metrics::DOWNSTREAM_CONNECTIONS.with_label_values(&["v1"]);
+            connections.inc();
+            match v1_downstream::handle_connection(
+                connection,
+                peer_addr,
+                upstream_connector,
+                upstream_mode,
+            )
+            .await
+            {
+                Ok(()) => info!("Closing connection from {} ...", peer_addr),
+                Err(err) => error!("Connection error: {}, peer: {}", err, peer_addr),
+            }
+            connections.dec();
+        });
+
+        Ok(peer_addr)
+    }
+
+    /// Awaits the next connection on any of the listening sockets
+    async fn next_incoming(
+        server: &mut Server,
+        v1_listener: Option<&mut V1Listener>,
+    ) -> Option<Incoming> {
+        match v1_listener {
+            Some(v1_listener) => {
+                match future::select(server.next(), v1_listener.server.next()).await {
+                    Either::Left((Some(conn), _)) => Some(Incoming::V2(conn)),
+                    Either::Right((Some(conn), _)) => Some(Incoming::V1(conn)),
+                    _ => None,
+                }
+            }
+            None => server.next().await.map(Incoming::V2),
+        }
+    }
+
     /// Handle a connection. Call this in a loop to make the `ProxyServer`
     /// perform its job while being able to handle individual connection errors.
     ///
@@ -457,7 +1005,8 @@ where
         // unfortunately you can't await in map() et al.
         let conn = match self.quit_rx {
             Some(ref mut quit_rx) => {
-                match future::select(self.server.next(), quit_rx.next()).await {
+                let incoming = Self::next_incoming(&mut self.server, self.v1_listener.as_mut());
+                match future::select(Box::pin(incoming), quit_rx.next()).await {
                     Either::Left((Some(conn), _)) => Some(conn),
                     Either::Right((None, _)) => {
                         // The quit_rx channel has been closed / quit_tx dropped,
@@ -475,7 +1024,7 @@ where
         // and we can just await the socket
         let conn = match conn {
             Some(conn) => conn,
-            None => match self.server.next().await {
+            None => match Self::next_incoming(&mut self.server, self.v1_listener.as_mut()).await {
                 Some(conn) => conn,
                 None => return None, // Socket closed
             },
@@ -492,8 +1041,14 @@ where
     pub async fn run(mut self) {
         info!(
             "Stratum proxy service starting @ {} -> {}",
-            self.listen_addr, self.v1_upstream_addr
+            self.listen_addr, self.upstream_connector
         );
+        if let Some(v1_listener) = self.v1_listener.as_ref() {
+            info!(
+                "Stratum V1 service starting @ {} -> {}",
+                v1_listener.listen_addr, self.upstream_connector
+            );
+        }
 
         while let Some(result) = self.next().await {
             match result {
@@ -0,0 +1,104 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use super::*;
+
+/// Builds security context valid between `valid_from` and `not_valid_after` seconds since
+/// `base`, `tag` identifies the context
+fn build_context(
+    base: time::SystemTime,
+    valid_from: u64,
+    not_valid_after: u64,
+    tag: u8,
+) -> SecurityContext {
+    SecurityContext {
+        signature_noise_message: Bytes::new(),
+        static_key_pair: v2::noise::StaticKeypair {
+            private: vec![tag],
+            public: vec![tag],
+        },
+        valid_from: base + time::Duration::from_secs(valid_from),
+        not_valid_after: base + time::Duration::from_secs(not_valid_after),
+    }
+}
+
+fn selected_tag(store: &mut SecurityContextStore, now: time::SystemTime) -> u8 {
+    store.select(now).static_key_pair.public[0]
+}
+
+/// Verifies that a reloaded certificate is served only once its validity starts and that the
+/// current certificate is served until then
+#[test]
+fn test_security_context_rotation() {
+    let base = time::UNIX_EPOCH + time::Duration::from_secs(1_000_000);
+    let mut store = SecurityContextStore::new(build_context(base, 0, 100, 1));
+    assert_eq!(selected_tag(&mut store, base), 1);
+
+    // Overlapping certificate that becomes valid in the future
+    store
+        .update(
+            build_context(base, 50, 200, 2),
+            base + time::Duration::from_secs(10),
+        )
+        .expect("BUG: cannot update security context");
+    assert_eq!(
+        store.not_valid_after(),
+        base + time::Duration::from_secs(200)
+    );
+    assert_eq!(
+        selected_tag(&mut store, base + time::Duration::from_secs(49)),
+        1
+    );
+    assert_eq!(
+        selected_tag(&mut store, base + time::Duration::from_secs(50)),
+        2
+    );
+    assert_eq!(
+        selected_tag(&mut store, base + time::Duration::from_secs(10)),
+        2,
+        "Rotated certificate must not be switched back"
+    );
+
+    // Certificate that is valid already replaces the current one right away
+    store
+        .update(
+            build_context(base, 0, 300, 3),
+            base + time::Duration::from_secs(60),
+        )
+        .expect("BUG: cannot update security context");
+    assert_eq!(
+        selected_tag(&mut store, base + time::Duration::from_secs(60)),
+        3
+    );
+
+    // Expired certificate is refused
+    assert!(store
+        .update(
+            build_context(base, 0, 100, 4),
+            base + time::Duration::from_secs(150)
+        )
+        .is_err());
+    assert_eq!(
+        selected_tag(&mut store, base + time::Duration::from_secs(150)),
+        3
+    );
+}
@@ -20,12 +20,15 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::From;
 use std::convert::TryFrom;
 use std::convert::TryInto;
 use std::fmt;
 use std::mem::size_of;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time;
 
 use ii_async_compat::{bytes, futures};
 
@@ -39,13 +42,18 @@ use serde_json;
 use ii_stratum::v1;
 use ii_stratum::v2::{
     self,
-    types::{Bytes0_32, Uint256Bytes},
+    types::{Bytes0_32, Bytes0_64k, Seq0_255, Uint256Bytes},
 };
 
+use ii_bitcoin::MeetsTarget as _;
 use ii_logging::macros::*;
+use ii_wire::Address;
 
 use crate::error::{Error, Result, ResultExt};
+use crate::metrics;
+use crate::routing::RoutingRules;
 use crate::util;
+use crate::vardiff::{VarDiff, VarDiffOptions};
 
 #[cfg(test)]
 mod test;
@@ -70,12 +78,24 @@ impl SeqId {
 pub struct V2ToV1TranslationOptions {
     /// Try to send `extranonce.subscribe` during handshake
     pub try_enable_xnsub: bool,
+    /// Rules for rewriting users of the channels, users are passed to the upstream unchanged
+    /// when there are no rules
+    pub routing_rules: Option<Arc<RoutingRules>>,
+    /// Check that shares meet the target and that they haven't been submitted before, invalid
+    /// shares are not submitted upstream
+    pub validate_shares: bool,
+    /// Adjust difficulty of each channel to its share rate instead of passing the upstream
+    /// difficulty to all channels
+    pub vardiff: Option<VarDiffOptions>,
 }
 
 impl Default for V2ToV1TranslationOptions {
     fn default() -> Self {
         Self {
             try_enable_xnsub: false,
+            routing_rules: None,
+            validate_shares: false,
+            vardiff: None,
         }
     }
 }
@@ -96,6 +116,11 @@ enum V2ToV1TranslationState {
     V1SubscribeOrAuthorizeFail,
     /// Channel is operational
     Operational,
+    /// The upstream session is being re-established with another upstream server, the
+    /// operational channels resume mining once the new session has been authorized
+    V1Reconnect,
+    /// All channels have been closed by the downstream, the upstream session can be released
+    Closed,
 }
 
 /// Represents a handler method that can process a particular ii_stratum result.
@@ -117,6 +142,19 @@ struct V1SubmitTemplate {
     job_id: v1::messages::JobId,
     time: u32,
     version: u32,
+    /// Original job, the block header of submitted shares is reconstructed from it
+    notify: v1::messages::Notify,
+    /// Shares submitted for the job so far, duplicate shares are rejected
+    submitted_shares: HashSet<V1ShareKey>,
+}
+
+/// Identifies share submitted for a particular job
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+struct V1ShareKey {
+    extra_nonce2: Vec<u8>,
+    ntime: u32,
+    nonce: u32,
+    version: u32,
 }
 
 enum V1ResultOrError<'a> {
@@ -127,16 +165,98 @@ enum V1ResultOrError<'a> {
 /// Maps V2 job ID to V1 job ID so that we can submit mining results upstream to V1 server
 type JobMap = HashMap<u32, V1SubmitTemplate>;
 
+/// Identifies a downstream V2 connection served by the translation
+pub type DownstreamId = u32;
+
+/// Downstream V2 connection that shares the upstream V1 session
+struct V2Downstream {
+    /// Channel for sending out V2 responses
+    tx: mpsc::Sender<v2::Frame>,
+    /// Connection details provided by SetupConnection
+    conn_details: Option<v2::messages::SetupConnection>,
+    /// Address of the downstream peer, routing rules may restrict the source address
+    peer_addr: Option<SocketAddr>,
+}
+
+/// Maps downstream ID to its connection
+type DownstreamMap = HashMap<DownstreamId, V2Downstream>;
+
+/// Channel open on the V2 connection. All channels share the upstream V1 session, each
+/// standard channel has its own extra nonce 2 derived from its ID.
+#[derive(Clone, Debug)]
+struct V2Channel {
+    /// Downstream connection that has opened the channel
+    downstream_id: DownstreamId,
+    /// User that has been authorized for this channel with the V1 upstream
+    user: String,
+    /// Extra nonce 2 layout of an extended channel, standard channels have none
+    extended: Option<V2ExtendedChannel>,
+    /// Difficulty of the channel when variable difficulty is enabled, the channel uses the
+    /// upstream difficulty otherwise
+    vardiff: Option<VarDiff>,
+}
+
+/// Extended channel rolls the leading part of extra nonce 2 on its own. The trailing byte of
+/// extra nonce 2 is a tag that tells the extended channels apart, it is passed downstream as part
+/// of the coinbase suffix. Extra nonce 2 of standard channels ends with a zero byte as long as
+/// the channel ID fits into the lower bytes, hence the tag is never zero.
+#[derive(Clone, Copy, Debug)]
+struct V2ExtendedChannel {
+    /// Size of the extranonce rolled by the downstream
+    extranonce_size: usize,
+    tag: u8,
+}
+
+/// Channel that is being opened by a downstream connection
+#[derive(Clone, Debug)]
+struct V2PendingChannel {
+    downstream_id: DownstreamId,
+    /// Extended channel requests are kept in the form of OpenStandardMiningChannel as both
+    /// requests carry the same channel parameters
+    open_msg: v2::messages::OpenStandardMiningChannel,
+    /// Extranonce size requested for an extended channel, standard channels have none
+    min_extranonce_size: Option<u16>,
+    /// User to be authorized with the upstream, it is the downstream user unless it has been
+    /// rewritten by the routing rules
+    v1_user: String,
+}
+
+/// Maps V2 channel ID to its details
+type ChannelMap = HashMap<u32, V2Channel>;
+
+/// Share submitted upstream via V1 mining.submit that waits for the result
+#[derive(Clone, Debug)]
+struct V1PendingSubmit {
+    /// Channel that has submitted the share
+    channel_id: u32,
+    /// Sequence number of the original SubmitSharesStandard
+    seq_num: u32,
+    /// Difficulty of the share at the time of submission
+    difficulty: u32,
+    /// When the V1 submit has been sent out, used for detecting timed out submits
+    timestamp: time::Instant,
+}
+
+/// Maps V1 request ID of mining.submit to the share that it carries
+type V1PendingSubmitMap = HashMap<u32, V1PendingSubmit>;
+
 //type V2ReqMap = HashMap<u32, FnMut(&mut V2ToV1Translation, &ii_stratum::Message<Protocol>, &v1::rpc::StratumResult)>;
 
-/// Object capable of translating stratm V2 header-only mining protocol that uses standard mining
-/// channels into stratum V1 including extranonce 1 subscription
+/// Object capable of translating stratm V2 mining protocol that uses standard (header-only) or
+/// extended mining channels into stratum V1 including extranonce 1 subscription
 pub struct V2ToV1Translation {
     /// Statemachine tracking the translation setup
     state: V2ToV1TranslationState,
 
     /// Channel for sending out V1 responses
     v1_tx: mpsc::Sender<v1::Frame>,
+    /// Address of the upstream server, share metrics are labeled with it
+    v1_upstream_addr: Option<SocketAddr>,
+    /// Upstream servers that the routing rules have assigned to the session, the session uses
+    /// the configured servers when there are none
+    v1_routed_upstream: Option<Vec<Address>>,
+    /// Upstream servers that the session is to be moved to before opening its first channel
+    v1_upstream_request: Option<Vec<Address>>,
     /// Unique request ID generator
     v1_req_id: SeqId,
     /// Mapping for pairing of incoming V1 message with original requests
@@ -155,17 +275,43 @@ pub struct V2ToV1Translation {
     /// This allows immediate completion of channel open on V2.
     v1_deferred_notify: Option<v1::messages::Notify>,
 
-    /// Channel for sending out V2 responses
-    v2_tx: mpsc::Sender<v2::Frame>,
+    /// All downstream connections that share the upstream session
+    v2_downstreams: DownstreamMap,
+    /// Downstream connection whose message is being processed
+    v2_current_downstream: DownstreamId,
+    /// Downstream connections waiting for the upstream mining.configure result
+    v2_pending_setups: Vec<DownstreamId>,
+    /// The upstream session is shared by multiple downstream connections and it is kept open
+    /// even when all channels have been closed
+    v2_shared: bool,
     #[allow(dead_code)] // TODO: unused as of now
     v2_req_id: SeqId,
-    /// All connection details
-    v2_conn_details: Option<v2::messages::SetupConnection>,
     /// Additional information about the pending channel being open
-    v2_channel_details: Option<v2::messages::OpenStandardMiningChannel>,
+    v2_channel_details: Option<V2PendingChannel>,
+    /// Channels that have been requested while the first channel of the upstream session is
+    /// being opened, they join the group once the session is operational
+    v2_deferred_channels: Vec<V2PendingChannel>,
+    /// Unique channel ID generator
+    v2_channel_id: SeqId,
+    /// All operational standard channels, they are all members of the same group channel
+    v2_channels: ChannelMap,
+    /// Channels opened on top of the operational V1 session that wait for V1 authorize, keyed
+    /// by the V1 request ID of the authorize request
+    v2_pending_channels: HashMap<u32, V2PendingChannel>,
+    /// Operational channels that are being authorized with a new upstream, keyed by the V1
+    /// request ID of the authorize request
+    v2_reauthorized_channels: HashMap<u32, u32>,
+    /// Shares that have been submitted upstream and wait for the V1 result
+    v1_pending_submits: V1PendingSubmitMap,
+    /// Latest mining.notify along with the V2 job ID that it has been translated into. It is
+    /// used for sending the current job to newly opened channels.
+    v1_last_notify: Option<(u32, v1::messages::Notify)>,
     /// Target difficulty derived from mining.set_difficulty message
     /// The channel opening is not complete until the target is determined
     v2_target: Option<uint::U256>,
+    /// Difficulty from the latest mining.set_difficulty message, each accepted share contributes
+    /// with it to the shares sum reported downstream
+    v1_difficulty: u32,
     /// Unique job ID generator
     v2_job_id: SeqId,
     /// Translates V2 job ID to V1 job ID
@@ -176,30 +322,88 @@ pub struct V2ToV1Translation {
 
 impl V2ToV1Translation {
     const PROTOCOL_VERSION: usize = 0;
-    /// No support for the extended protocol yet, therefore, no extranonce advertised
-    #[allow(dead_code)]
-    const MAX_EXTRANONCE_SIZE: usize = 0;
-    /// Currently, no support for multiple channels in the proxy
-    const CHANNEL_ID: u32 = 0;
-    /// Default group channel
-    const DEFAULT_GROUP_CHANNEL_ID: u32 = 0;
+    /// Extranonce of extended channels is limited by the extranonce field of SubmitSharesExtended
+    const MAX_EXTRANONCE_SIZE: usize = 32;
+    /// Trailing part of extra nonce 2 that identifies an extended channel
+    const EXTENDED_CHANNEL_TAG_SIZE: usize = 1;
+    /// All standard channels are members of this group channel. Standard channel IDs are
+    /// allocated sequentially from 0, the group takes the top of the ID space to prevent any
+    /// collision.
+    const GROUP_CHANNEL_ID: u32 = std::u32::MAX;
+    /// Downstream connection of a translation that is dedicated to a single V2 connection
+    pub const DEFAULT_DOWNSTREAM_ID: DownstreamId = 0;
+    /// Reason for closing downstream channels when the V1 upstream session has been lost
+    pub const UPSTREAM_LOST_REASON: &'static str = "upstream-disconnected";
+    /// Maximum time the upstream has for responding to mining.submit
+    pub const V1_SUBMIT_TIMEOUT: time::Duration = time::Duration::from_secs(30);
+    /// Error code of shares that haven't been confirmed by the upstream within
+    /// `V1_SUBMIT_TIMEOUT`
+    const SUBMIT_TIMEOUT_ERROR_CODE: &'static str = "upstream-submit-timeout";
+    /// Error code of shares that reference a job the proxy doesn't know (anymore)
+    const INVALID_JOB_ERROR_CODE: &'static str = "invalid-job-id";
+    /// Error code of shares whose hash doesn't meet the channel target
+    const LOW_DIFFICULTY_ERROR_CODE: &'static str = "difficulty-too-low";
+    /// Error code of shares that have already been submitted for the same job
+    const DUPLICATE_SHARE_ERROR_CODE: &'static str = "duplicate-share";
+    /// Error code of shares that the upstream has rejected as they belong to an old job
+    const STALE_SHARE_ERROR_CODE: &'static str = "stale-share";
+    /// Error code of shares that the upstream has rejected for any other reason
+    const SHARE_REJECTED_ERROR_CODE: &'static str = "share-rejected";
+    /// Error codes of rejected shares as used by V1 pools
+    const V1_STALE_SHARE_ERROR_CODE: i32 = 21;
+    const V1_DUPLICATE_SHARE_ERROR_CODE: i32 = 22;
+    const V1_LOW_DIFFICULTY_ERROR_CODE: i32 = 23;
+    /// Error code of channels whose user doesn't match any routing rule
+    const UNKNOWN_USER_ERROR_CODE: &'static str = "unknown-user";
+    /// Error code of channels routed to other upstream servers than their session uses
+    const UPSTREAM_MISMATCH_ERROR_CODE: &'static str = "upstream-mismatch";
 
     /// U256 in little endian
     /// TODO: consolidate into common part/generalize
     /// TODO: DIFF1 const target is broken, the last U64 word gets actually initialized to 0xffffffff, not sure why
-    const DIFF1_TARGET: uint::U256 = uint::U256([0, 0, 0, 0xffff0000u64]);
+    pub(crate) const DIFF1_TARGET: uint::U256 = uint::U256([0, 0, 0, 0xffff0000u64]);
 
+    /// Builds a translation dedicated to a single downstream connection, the upstream session
+    /// is released along with the last channel
     pub fn new(
         v1_tx: mpsc::Sender<v1::Frame>,
         v2_tx: mpsc::Sender<v2::Frame>,
         options: V2ToV1TranslationOptions,
     ) -> Self {
+        let mut translation = Self::new_base(v1_tx, options);
+        translation.add_downstream(Self::DEFAULT_DOWNSTREAM_ID, v2_tx);
+        translation
+    }
+
+    /// Builds a translation that multiplexes channels of all downstream connections added via
+    /// `add_downstream()` onto a single upstream session
+    pub fn new_shared(v1_tx: mpsc::Sender<v1::Frame>, options: V2ToV1TranslationOptions) -> Self {
+        let mut translation = Self::new_base(v1_tx, options);
+        translation.v2_shared = true;
+        translation
+    }
+
+    fn new_base(v1_tx: mpsc::Sender<v1::Frame>, options: V2ToV1TranslationOptions) -> Self {
         Self {
-            v2_conn_details: None,
+            v2_downstreams: DownstreamMap::default(),
+            v2_current_downstream: Self::DEFAULT_DOWNSTREAM_ID,
+            v2_pending_setups: vec![],
+            v2_shared: false,
             v2_channel_details: None,
+            v2_deferred_channels: vec![],
+            v2_channel_id: SeqId::new(),
+            v2_channels: ChannelMap::default(),
+            v2_pending_channels: HashMap::default(),
+            v2_reauthorized_channels: HashMap::default(),
+            v1_pending_submits: V1PendingSubmitMap::default(),
+            v1_last_notify: None,
             v2_target: None,
+            v1_difficulty: 0,
             state: V2ToV1TranslationState::Init,
             v1_tx,
+            v1_upstream_addr: None,
+            v1_routed_upstream: None,
+            v1_upstream_request: None,
             v1_req_id: SeqId::new(),
             v1_req_map: V1ReqMap::default(),
             v1_extra_nonce1: None,
@@ -208,7 +412,6 @@ impl V2ToV1Translation {
             v1_force_future_jobs: true,
             v1_xnsub_enabled: false,
             v1_deferred_notify: None,
-            v2_tx,
             v2_req_id: SeqId::new(),
             v2_job_id: SeqId::new(),
             v2_to_v1_job_map: JobMap::default(),
@@ -251,31 +454,330 @@ impl V2ToV1Translation {
         .into()
     }
 
+    /// Extracts ID of a V1 request built by `v1_method_into_message()`
+    fn v1_request_id(request: &v1::rpc::Rpc) -> u32 {
+        match request {
+            v1::rpc::Rpc::Request(request) => request.id,
+            _ => None,
+        }
+        .expect("BUG: V1 request has no ID")
+    }
+
+    /// Registers downstream connection `downstream_id`, all V2 messages for the connection are
+    /// sent out via `v2_tx`
+    pub fn add_downstream(&mut self, downstream_id: DownstreamId, v2_tx: mpsc::Sender<v2::Frame>) {
+        let downstream = V2Downstream {
+            tx: v2_tx,
+            conn_details: None,
+            peer_addr: None,
+        };
+        if self
+            .v2_downstreams
+            .insert(downstream_id, downstream)
+            .is_some()
+        {
+            panic!("BUG: downstream {} already exists", downstream_id);
+        }
+    }
+
+    /// Releases downstream connection `downstream_id` along with all its channels
+    pub fn remove_downstream(&mut self, downstream_id: DownstreamId) {
+        if self.v2_downstreams.remove(&downstream_id).is_none() {
+            return;
+        }
+        self.v2_channels
+            .retain(|_, v2_channel| v2_channel.downstream_id != downstream_id);
+        self.v2_deferred_channels
+            .retain(|pending_channel| pending_channel.downstream_id != downstream_id);
+        self.v2_pending_setups.retain(|id| *id != downstream_id);
+    }
+
+    /// Sets address of the peer of downstream connection `downstream_id`
+    pub fn set_downstream_peer_addr(&mut self, downstream_id: DownstreamId, peer_addr: SocketAddr) {
+        if let Some(downstream) = self.v2_downstreams.get_mut(&downstream_id) {
+            downstream.peer_addr = Some(peer_addr);
+        }
+    }
+
+    /// Provides upstream servers that the routing rules have assigned to the first channel of
+    /// the session. The session is expected to be moved onto the servers via
+    /// `switch_upstream()`, the channel is opened with the new upstream.
+    pub fn take_upstream_request(&mut self) -> Option<Vec<Address>> {
+        self.v1_upstream_request.take()
+    }
+
+    /// Sets address of the upstream server that the translation is connected to
+    pub fn set_upstream_addr(&mut self, upstream_addr: SocketAddr) {
+        self.v1_upstream_addr = Some(upstream_addr);
+    }
+
+    /// Moves the translation onto a new upstream session with `upstream_addr` whose frames are
+    /// sent out via `v1_tx`. Downstream connections and their operational channels are
+    /// preserved, the channels are authorized with the new upstream and receive its target and
+    /// jobs without being reopened. Shares that wait for the result from the previous upstream
+    /// are reported as rejected.
+    pub fn switch_upstream(&mut self, v1_tx: mpsc::Sender<v1::Frame>, upstream_addr: SocketAddr) {
+        if self.state == V2ToV1TranslationState::Closed {
+            return;
+        }
+        self.v1_tx = v1_tx;
+        // No response is going to arrive from the previous upstream
+        self.v1_req_map.clear();
+        self.v2_reauthorized_channels.clear();
+        self.report_lost_submits();
+        self.v1_upstream_addr = Some(upstream_addr);
+        self.v1_extra_nonce1 = None;
+        self.v1_extra_nonce2_size = 0;
+        self.v1_authorized = false;
+        self.v1_xnsub_enabled = false;
+        self.v1_deferred_notify = None;
+        self.v1_last_notify = None;
+        self.v2_target = None;
+        // Jobs of the previous upstream cannot be submitted anymore
+        self.v2_to_v1_job_map.clear();
+
+        // Channels whose opening hasn't completed yet are opened with the new upstream
+        let mut group_members: Vec<(u32, V2PendingChannel)> =
+            self.v2_pending_channels.drain().collect();
+        group_members.sort_by_key(|(id, _)| *id);
+        let mut pending_channels: Vec<V2PendingChannel> =
+            self.v2_channel_details.take().into_iter().collect();
+        pending_channels.extend(
+            group_members
+                .into_iter()
+                .map(|(_, pending_channel)| pending_channel),
+        );
+        pending_channels.append(&mut self.v2_deferred_channels);
+        self.v2_deferred_channels = pending_channels;
+
+        // Nothing is to be negotiated until some downstream sets up its connection
+        if self
+            .v2_downstreams
+            .values()
+            .all(|downstream| downstream.conn_details.is_none())
+        {
+            self.state = V2ToV1TranslationState::Init;
+            return;
+        }
+        if let Err(submit_err) = self.send_configure() {
+            info!("Cannot submit mining.configure: {:?}", submit_err);
+        }
+    }
+
+    /// Processes `msg` that has arrived via downstream connection `downstream_id`
+    pub async fn accept_downstream_message(
+        &mut self,
+        downstream_id: DownstreamId,
+        msg: ii_stratum::Message<v2::Protocol>,
+    ) {
+        if !self.v2_downstreams.contains_key(&downstream_id) {
+            info!("Ignoring message from unknown downstream {}", downstream_id);
+            return;
+        }
+        self.v2_current_downstream = downstream_id;
+        msg.accept(self).await;
+    }
+
+    /// Submits `msg` to downstream connection `downstream_id`. Messages for connections that
+    /// have been removed in the meantime are dropped.
+    fn v2_submit_message<T>(&mut self, downstream_id: DownstreamId, msg: T) -> Result<()>
+    where
+        T: TryInto<v2::Frame, Error = ii_stratum::error::Error>,
+    {
+        match self.v2_downstreams.get_mut(&downstream_id) {
+            Some(downstream) => util::submit_message(&mut downstream.tx, msg),
+            None => {
+                trace!("Dropping message for removed downstream {}", downstream_id);
+                Ok(())
+            }
+        }
+    }
+
+    /// Submits `msg` to the downstream connection that owns channel `channel_id`
+    fn v2_submit_channel_message<T>(&mut self, channel_id: u32, msg: T) -> Result<()>
+    where
+        T: TryInto<v2::Frame, Error = ii_stratum::error::Error>,
+    {
+        match self.v2_channels.get(&channel_id) {
+            Some(v2_channel) => self.v2_submit_message(v2_channel.downstream_id, msg),
+            None => {
+                trace!("Dropping message for closed channel {}", channel_id);
+                Ok(())
+            }
+        }
+    }
+
+    /// Submits group channel `msg` to all downstream connections that have opened a standard
+    /// channel
+    fn v2_submit_group_message<T>(&mut self, msg: T) -> Result<()>
+    where
+        T: TryInto<v2::Frame, Error = ii_stratum::error::Error> + Clone,
+    {
+        let mut downstream_ids: Vec<DownstreamId> = self
+            .v2_channels
+            .values()
+            .filter(|v2_channel| v2_channel.extended.is_none())
+            .map(|v2_channel| v2_channel.downstream_id)
+            .collect();
+        downstream_ids.sort();
+        downstream_ids.dedup();
+        for downstream_id in downstream_ids {
+            self.v2_submit_message(downstream_id, msg.clone())?;
+        }
+        Ok(())
+    }
+
+    /// Finds channel `channel_id` provided that it has been opened by the downstream connection
+    /// whose message is being processed
+    fn current_downstream_channel(&self, channel_id: u32) -> Option<V2Channel> {
+        self.v2_channels
+            .get(&channel_id)
+            .filter(|v2_channel| v2_channel.downstream_id == self.v2_current_downstream)
+            .cloned()
+    }
+
+    /// Assigns extra nonce 2 layout to a new extended channel that needs at least
+    /// `min_extranonce_size` bytes for rolling. The channel gets all the space that the upstream
+    /// session provides.
+    fn allocate_extended_channel(
+        &self,
+        min_extranonce_size: u16,
+    ) -> std::result::Result<V2ExtendedChannel, &'static str> {
+        let extranonce_size = self
+            .v1_extra_nonce2_size
+            .saturating_sub(Self::EXTENDED_CHANNEL_TAG_SIZE)
+            .min(Self::MAX_EXTRANONCE_SIZE);
+        if extranonce_size < min_extranonce_size as usize {
+            return Err("Extranonce size not available");
+        }
+        if self.v2_extranonce_prefix().is_none() {
+            return Err("Upstream extranonce too long");
+        }
+        let tag = (1..=std::u8::MAX)
+            .find(|tag| {
+                self.v2_channels.values().all(|v2_channel| {
+                    v2_channel.extended.map(|extended| extended.tag) != Some(*tag)
+                })
+            })
+            .ok_or("Too many extended channels")?;
+        Ok(V2ExtendedChannel {
+            extranonce_size,
+            tag,
+        })
+    }
+
+    /// Extranonce prefix of extended channels is the extra nonce 1 of the upstream session
+    fn v2_extranonce_prefix(&self) -> Option<Bytes0_32> {
+        self.v1_extra_nonce1.as_ref().and_then(|v1_extra_nonce1| {
+            Bytes0_32::try_from(v1_extra_nonce1.0.as_ref().as_slice()).ok()
+        })
+    }
+
+    /// Builds the part of extra nonce 2 that follows the extranonce rolled by extended channel.
+    /// Nothing is provided when the channel doesn't fit into extra nonce 2 of the upstream
+    /// session anymore.
+    fn extended_extra_nonce2_suffix(&self, extended: V2ExtendedChannel) -> Option<Vec<u8>> {
+        let padding = self
+            .v1_extra_nonce2_size
+            .checked_sub(extended.extranonce_size + Self::EXTENDED_CHANNEL_TAG_SIZE)?;
+        let mut suffix = vec![0; padding];
+        suffix.push(extended.tag);
+        Some(suffix)
+    }
+
+    /// Registers a new channel and reports success of its opening, standard channels become
+    /// members of the group. No channel is registered when the downstream connection has been
+    /// removed in the meantime or when extranonce for an extended channel is not available.
+    fn add_channel(&mut self, pending_channel: &V2PendingChannel) -> Result<Option<u32>> {
+        if !self
+            .v2_downstreams
+            .contains_key(&pending_channel.downstream_id)
+        {
+            trace!(
+                "Dropping channel of removed downstream {}",
+                pending_channel.downstream_id
+            );
+            return Ok(None);
+        }
+        let vardiff = self
+            .options
+            .vardiff
+            .map(|options| VarDiff::new(options, self.v1_difficulty, time::Instant::now()));
+
+        let extended = match pending_channel.min_extranonce_size {
+            Some(min_extranonce_size) => {
+                match self.allocate_extended_channel(min_extranonce_size) {
+                    Ok(extended) => Some(extended),
+                    Err(err_msg) => {
+                        info!(
+                            "Cannot open extended channel for {}: {}",
+                            pending_channel.open_msg.user.to_string(),
+                            err_msg
+                        );
+                        self.reject_pending_channel(pending_channel.clone(), err_msg);
+                        return Ok(None);
+                    }
+                }
+            }
+            None => None,
+        };
+
+        let channel_id = self.v2_channel_id.next();
+        self.v2_channels.insert(
+            channel_id,
+            V2Channel {
+                downstream_id: pending_channel.downstream_id,
+                user: pending_channel.v1_user.clone(),
+                extended,
+                vardiff,
+            },
+        );
+        let init_target = Uint256Bytes::from(self.channel_target(channel_id).expect(
+            "Bug: initial target still not defined when attempting to finalize \
+             OpenStandardMiningChannel",
+        ));
+        match extended {
+            Some(extended) => {
+                let msg = v2::messages::OpenExtendedMiningChannelSuccess {
+                    req_id: pending_channel.open_msg.req_id,
+                    channel_id,
+                    target: init_target,
+                    extranonce_size: extended.extranonce_size as u16,
+                    extranonce_prefix: self
+                        .v2_extranonce_prefix()
+                        .expect("BUG: extranonce prefix checked on allocation"),
+                };
+                self.v2_submit_message(pending_channel.downstream_id, msg)?;
+            }
+            None => {
+                let msg = v2::messages::OpenStandardMiningChannelSuccess {
+                    req_id: pending_channel.open_msg.req_id,
+                    channel_id,
+                    target: init_target,
+                    extranonce_prefix: Bytes0_32::new(),
+                    group_channel_id: Self::GROUP_CHANNEL_ID,
+                };
+                self.v2_submit_message(pending_channel.downstream_id, msg)?;
+            }
+        }
+        Ok(Some(channel_id))
+    }
+
     /// Sets the current pending channel to operational state and submits success message
     fn finalize_open_channel(&mut self) -> Result<()> {
         trace!("finalize_open_channel()");
-        let mut init_target: Uint256Bytes = Uint256Bytes([0; 32]);
-        self.v2_target
-            .expect("Bug: initial target still not defined when attempting to finalize OpenStandardMiningChannel")
-            .to_little_endian(init_target.as_mut());
-
+        // Channels that have been moved onto a new upstream session only need its target
+        if self.state == V2ToV1TranslationState::V1Reconnect {
+            self.state = V2ToV1TranslationState::Operational;
+            self.update_extended_channels()?;
+            self.send_set_target()?;
+            return self.complete_session_setup();
+        }
         // when V1 authorization has already taken place, report channel opening success
-        if let Some(v2_channel_details) = self.v2_channel_details.as_ref() {
+        if let Some(v2_channel_details) = self.v2_channel_details.clone() {
             self.state = V2ToV1TranslationState::Operational;
-            let msg = v2::messages::OpenStandardMiningChannelSuccess {
-                req_id: v2_channel_details.req_id,
-                channel_id: Self::CHANNEL_ID,
-                target: init_target.clone(),
-                extranonce_prefix: Bytes0_32::new(),
-                group_channel_id: Self::DEFAULT_GROUP_CHANNEL_ID,
-            };
-            util::submit_message(&mut self.v2_tx, msg)?;
-
-            // If mining.notify is pending, process it now as part of open channel finalization
-            if let Some(notify_payload) = self.v1_deferred_notify.take() {
-                self.perform_notify(&notify_payload)?;
-            }
-            return Ok(());
+            self.add_channel(&v2_channel_details)?;
+            self.complete_session_setup()
         } else {
             Err(
                 ii_stratum::error::Error::from(v2::error::ErrorKind::ChannelNotOperational(
@@ -286,21 +788,144 @@ impl V2ToV1Translation {
         }
     }
 
+    /// Processes everything that has been waiting for the upstream session to become operational
+    fn complete_session_setup(&mut self) -> Result<()> {
+        // If mining.notify is pending, process it now as part of open channel finalization
+        if let Some(notify_payload) = self.v1_deferred_notify.take() {
+            self.perform_notify(&notify_payload)?;
+        }
+        // Channels requested in the meantime only join the group now
+        for pending_channel in std::mem::take(&mut self.v2_deferred_channels) {
+            self.open_group_member_channel(pending_channel);
+        }
+        Ok(())
+    }
+
+    /// Indicates that the upstream session is being negotiated and the channel opening
+    /// completes once subscription, authorization and target are known
+    fn is_session_pending(&self) -> bool {
+        match self.state {
+            V2ToV1TranslationState::OpenStandardMiningChannelPending
+            | V2ToV1TranslationState::V1Reconnect => true,
+            _ => false,
+        }
+    }
+
+    /// Indicates that the downstream has closed all its channels and there is no use for the
+    /// upstream session anymore
+    pub fn is_closed(&self) -> bool {
+        self.state == V2ToV1TranslationState::Closed
+    }
+
+    /// Closes all operational channels with the specified `reason_code`, the translation cannot
+    /// be used for mining anymore
+    pub fn close_all_channels(&mut self, reason_code: &str) {
+        self.close_channels(reason_code);
+        self.state = V2ToV1TranslationState::Closed;
+    }
+
+    /// Closes all operational channels with the specified `reason_code`
+    fn close_channels(&mut self, reason_code: &str) {
+        let mut channel_ids: Vec<u32> = self.v2_channels.keys().cloned().collect();
+        channel_ids.sort();
+        for channel_id in channel_ids {
+            let msg = v2::messages::CloseChannel {
+                channel_id,
+                reason_code: reason_code.try_into().expect("BUG: incorrect reason code"),
+            };
+            if let Err(submit_err) = self.v2_submit_channel_message(channel_id, msg) {
+                info!("Cannot close channel {}: {:?}", channel_id, submit_err);
+            }
+        }
+        self.v2_channels.clear();
+    }
+
     /// Send new target
     /// TODO extend the translation unit test accordingly
     fn send_set_target(&mut self) -> Result<()> {
         trace!("send_set_target()");
-        let max_target = Uint256Bytes::from(self.v2_target.expect(
+        // Channels with variable difficulty keep their own difficulty, other channels share the
+        // upstream difficulty
+        let mut channel_ids: Vec<u32> = self
+            .v2_channels
+            .iter()
+            .filter(|(_, v2_channel)| v2_channel.vardiff.is_none())
+            .map(|(channel_id, _)| *channel_id)
+            .collect();
+        channel_ids.sort();
+        for channel_id in channel_ids {
+            self.send_channel_target(channel_id)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the current target of `channel_id` downstream
+    fn send_channel_target(&mut self, channel_id: u32) -> Result<()> {
+        let max_target = Uint256Bytes::from(self.channel_target(channel_id).expect(
             "Bug: initial target still not defined when attempting to finalize \
              OpenStandardMiningChannel",
         ));
-
         let msg = v2::messages::SetTarget {
-            channel_id: Self::CHANNEL_ID,
+            channel_id,
             max_target,
         };
+        self.v2_submit_channel_message(channel_id, msg)
+    }
+
+    /// Provides target of `channel_id` that is derived either from its variable difficulty or
+    /// from the upstream difficulty
+    fn channel_target(&self, channel_id: u32) -> Option<uint::U256> {
+        match self
+            .v2_channels
+            .get(&channel_id)
+            .and_then(|v2_channel| v2_channel.vardiff.as_ref())
+        {
+            Some(vardiff) => Some(Self::DIFF1_TARGET / vardiff.difficulty()),
+            None => self.v2_target,
+        }
+    }
 
-        util::submit_message(&mut self.v2_tx, msg)
+    /// Provides difficulty that shares of `v2_channel` are credited with
+    fn channel_difficulty(&self, v2_channel: &V2Channel) -> u32 {
+        v2_channel
+            .vardiff
+            .as_ref()
+            .map_or(self.v1_difficulty, VarDiff::difficulty)
+    }
+
+    /// Adjusts difficulty of `channel_id` to its share rate and sends the new target when the
+    /// difficulty has changed
+    fn retarget_channel(&mut self, channel_id: u32, now: time::Instant) {
+        let difficulty = match self
+            .v2_channels
+            .get_mut(&channel_id)
+            .and_then(|v2_channel| v2_channel.vardiff.as_mut())
+            .and_then(|vardiff| vardiff.retarget(now))
+        {
+            Some(difficulty) => difficulty,
+            None => return,
+        };
+        trace!(
+            "Channel {} retargeted to difficulty {}",
+            channel_id,
+            difficulty
+        );
+        if let Err(e) = self.send_channel_target(channel_id) {
+            info!("Cannot send SetTarget to channel {}: {}", channel_id, e);
+        }
+    }
+
+    /// Adjusts difficulty of all channels that have variable difficulty, it is to be called
+    /// periodically so that channels that stopped submitting shares get lower difficulty
+    pub fn retarget_channels(&mut self, now: time::Instant) {
+        if self.state != V2ToV1TranslationState::Operational {
+            return;
+        }
+        let mut channel_ids: Vec<u32> = self.v2_channels.keys().cloned().collect();
+        channel_ids.sort();
+        for channel_id in channel_ids {
+            self.retarget_channel(channel_id, now);
+        }
     }
 
     /// Reports failure to open the channel and changes the translation state
@@ -311,6 +936,10 @@ impl V2ToV1Translation {
             self.v2_channel_details,
             err_msg
         );
+        if self.state == V2ToV1TranslationState::Closed {
+            return;
+        }
+        let reconnecting = self.state == V2ToV1TranslationState::V1Reconnect;
         self.state = V2ToV1TranslationState::V1SubscribeOrAuthorizeFail;
 
         // Cleanup all parts associated with opening the channel
@@ -318,18 +947,16 @@ impl V2ToV1Translation {
         self.v1_extra_nonce1 = None;
         self.v1_extra_nonce2_size = 0;
 
-        if let Some(v2_channel_details) = self.v2_channel_details.as_ref() {
-            let msg = v2::messages::OpenStandardMiningChannelError {
-                req_id: v2_channel_details.req_id,
-                code: err_msg.try_into().expect("BUG: incorrect error message"),
-            };
-            self.v2_channel_details = None;
+        if reconnecting {
+            self.abort_reconnect(err_msg);
+            return;
+        }
 
-            if let Err(submit_err) = util::submit_message(&mut self.v2_tx, msg) {
-                info!(
-                    "abort_open_channel() failed: {:?}, abort message: {}",
-                    submit_err, err_msg
-                );
+        if let Some(v2_channel_details) = self.v2_channel_details.take() {
+            // Deferred channels depend on the same upstream session, they are rejected, too
+            let deferred_channels = std::mem::take(&mut self.v2_deferred_channels);
+            for pending_channel in std::iter::once(v2_channel_details).chain(deferred_channels) {
+                self.reject_pending_channel(pending_channel, err_msg);
             }
         } else {
             error!(
@@ -340,6 +967,45 @@ impl V2ToV1Translation {
         }
     }
 
+    /// Closes channels that have been moved onto a new upstream session when the session cannot
+    /// be established, channels waiting for the session are rejected. A dedicated translation
+    /// cannot be used anymore.
+    fn abort_reconnect(&mut self, err_msg: &str) {
+        self.close_channels(err_msg);
+        for pending_channel in std::mem::take(&mut self.v2_deferred_channels) {
+            self.reject_pending_channel(pending_channel, err_msg);
+        }
+        if !self.v2_shared {
+            self.state = V2ToV1TranslationState::Closed;
+        }
+    }
+
+    fn reject_pending_channel(&mut self, pending_channel: V2PendingChannel, err_msg: &str) {
+        if let Err(submit_err) = self.send_open_channel_error(&pending_channel, err_msg) {
+            info!(
+                "abort_open_channel() failed: {:?}, abort message: {}",
+                submit_err, err_msg
+            );
+        }
+    }
+
+    /// Reports failure to open a channel using the error message that matches the request
+    fn send_open_channel_error(
+        &mut self,
+        pending_channel: &V2PendingChannel,
+        err_msg: &str,
+    ) -> Result<()> {
+        let req_id = pending_channel.open_msg.req_id;
+        let code = err_msg.try_into().expect("BUG: incorrect error message");
+        if pending_channel.min_extranonce_size.is_some() {
+            let msg = v2::messages::OpenExtendedMiningChannelError { req_id, code };
+            self.v2_submit_message(pending_channel.downstream_id, msg)
+        } else {
+            let msg = v2::messages::OpenStandardMiningChannelError { req_id, code };
+            self.v2_submit_message(pending_channel.downstream_id, msg)
+        }
+    }
+
     /// Finalizes a pending SetupConnection upon successful negotiation of
     /// mining configuration of version rolling bits
     fn handle_configure_result(
@@ -370,23 +1036,231 @@ impl V2ToV1Translation {
             && (proposed_version_mask.0).0 == ii_stratum::BIP320_N_VERSION_MASK
         {
             self.state = V2ToV1TranslationState::ConnectionSetup;
-
-            let success = v2::messages::SetupConnectionSuccess {
-                used_version: Self::PROTOCOL_VERSION as u16,
-                flags: 0,
-            };
-            util::submit_message(&mut self.v2_tx, success)
+            for downstream_id in std::mem::take(&mut self.v2_pending_setups) {
+                self.send_setup_connection_success(downstream_id)?;
+            }
+            self.resume_channels()
         } else {
+            self.abort_pending_setups()
+        }
+    }
+
+    fn send_setup_connection_success(&mut self, downstream_id: DownstreamId) -> Result<()> {
+        let success = v2::messages::SetupConnectionSuccess {
+            used_version: Self::PROTOCOL_VERSION as u16,
+            flags: 0,
+        };
+        self.v2_submit_message(downstream_id, success)
+    }
+
+    /// Starts mining.configure with the upstream
+    fn send_configure(&mut self) -> Result<()> {
+        let mut configure = v1::messages::Configure::new();
+        configure
+            .add_feature(v1::messages::VersionRolling::new(
+                ii_stratum::BIP320_N_VERSION_MASK,
+                ii_stratum::BIP320_N_VERSION_MAX_BITS,
+            ))
+            .expect("addfeature failed"); // FIXME: how to handle errors from configure.add_feature() ?
+
+        let v1_configure_message = self.v1_method_into_message(
+            configure,
+            Self::handle_configure_result,
+            Self::handle_configure_error,
+        );
+        util::submit_message(&mut self.v1_tx, v1_configure_message)?;
+        self.state = V2ToV1TranslationState::V1Configure;
+        Ok(())
+    }
+
+    /// Reopens channels on a new upstream session that has just been configured. The session
+    /// is subscribed for the lowest operational channel and the remaining channels are
+    /// authorized along with it. Without any operational channel the first of the channels
+    /// waiting for the session is opened as usual.
+    fn resume_channels(&mut self) -> Result<()> {
+        let mut channel_ids: Vec<u32> = self.v2_channels.keys().cloned().collect();
+        channel_ids.sort();
+        let session_channel = match channel_ids.first() {
+            Some(channel_id) => self.v2_channels[channel_id].clone(),
+            None => {
+                if !self.v2_deferred_channels.is_empty() {
+                    let pending_channel = self.v2_deferred_channels.remove(0);
+                    let conn_details = self
+                        .v2_downstreams
+                        .get(&pending_channel.downstream_id)
+                        .and_then(|downstream| downstream.conn_details.clone())
+                        .expect("BUG: channel without connection details");
+                    self.open_first_channel(pending_channel, &conn_details);
+                }
+                return Ok(());
+            }
+        };
+        let conn_details = self
+            .v2_downstreams
+            .get(&session_channel.downstream_id)
+            .and_then(|downstream| downstream.conn_details.clone())
+            .expect("BUG: channel without connection details");
+
+        self.state = V2ToV1TranslationState::V1Reconnect;
+        self.v1_subscribe_and_authorize(&conn_details, session_channel.user.clone())?;
+        for channel_id in channel_ids.into_iter().skip(1) {
+            let user = self.v2_channels[&channel_id].user.clone();
+            let authorize = v1::messages::Authorize(user, "".to_string());
+            let v1_authorize_message = self.v1_method_into_message(
+                authorize,
+                Self::handle_reauthorize_result,
+                Self::handle_reauthorize_error,
+            );
+            self.v2_reauthorized_channels
+                .insert(Self::v1_request_id(&v1_authorize_message), channel_id);
+            util::submit_message(&mut self.v1_tx, v1_authorize_message)?;
+        }
+        Ok(())
+    }
+
+    /// Opens the first channel of the upstream session, the session is subscribed on behalf of
+    /// the downstream connection described by `conn_details`
+    fn open_first_channel(
+        &mut self,
+        pending_channel: V2PendingChannel,
+        conn_details: &v2::messages::SetupConnection,
+    ) {
+        let user = pending_channel.v1_user.clone();
+        self.v2_channel_details = Some(pending_channel);
+        self.state = V2ToV1TranslationState::OpenStandardMiningChannelPending;
+        if let Err(submit_err) = self.v1_subscribe_and_authorize(conn_details, user) {
+            info!("Cannot open upstream V1 session: {:?}", submit_err);
+        }
+    }
+
+    /// Sends out mining.subscribe (along with mining.extranonce_subscribe if enabled) and
+    /// mining.authorize for `user`. The results finalize opening of the upstream session.
+    fn v1_subscribe_and_authorize(
+        &mut self,
+        conn_details: &v2::messages::SetupConnection,
+        user: String,
+    ) -> Result<()> {
+        let hostname: String = conn_details
+            .endpoint_host
+            .clone()
+            .try_into()
+            .expect("BUG: Cannot convert to string from connection details");
+
+        let hostname_port = format!("{}:{}", hostname, conn_details.endpoint_port);
+        let subscribe = v1::messages::Subscribe(
+            Some(conn_details.device.fw_ver.to_string()),
+            None,
+            Some(hostname_port),
+            None,
+        );
+
+        let v1_subscribe_message = self.v1_method_into_message(
+            subscribe,
+            Self::handle_subscribe_result,
+            Self::handle_authorize_or_subscribe_error,
+        );
+        util::submit_message(&mut self.v1_tx, v1_subscribe_message)
+            .context("V1 mining.subscribe")?;
+
+        if self.options.try_enable_xnsub {
+            let extranonce_subscribe = v1::messages::ExtranonceSubscribe();
+            let v1_extranonce_subscribe = self.v1_method_into_message(
+                extranonce_subscribe,
+                Self::handle_extranonce_subscribe_result,
+                Self::handle_extranonce_subscribe_error,
+            );
+            util::submit_message(&mut self.v1_tx, v1_extranonce_subscribe)
+                .context("V1 mining.extranonce_subscribe")?;
+        }
+
+        let authorize = v1::messages::Authorize(user, "".to_string());
+        let v1_authorize_message = self.v1_method_into_message(
+            authorize,
+            Self::handle_authorize_result,
+            Self::handle_authorize_or_subscribe_error,
+        );
+        util::submit_message(&mut self.v1_tx, v1_authorize_message)
+            .context("V1 mining.authorize")?;
+        Ok(())
+    }
+
+    /// Closes a channel that the new upstream has refused to authorize, the rest of the group
+    /// is not affected
+    fn abort_reauthorized_channel(&mut self, id: &v1::MessageId, err_msg: &str) -> Result<()> {
+        let channel_id = match id.and_then(|id| self.v2_reauthorized_channels.remove(&id)) {
+            Some(channel_id) => channel_id,
+            None => return Ok(()),
+        };
+        let msg = v2::messages::CloseChannel {
+            channel_id,
+            reason_code: err_msg.try_into().expect("BUG: incorrect reason code"),
+        };
+        self.v2_submit_channel_message(channel_id, msg)?;
+        if let Some(v2_channel) = self.v2_channels.remove(&channel_id) {
+            info!(
+                "Channel {} of {} cannot be moved to the new upstream: {}",
+                channel_id, v2_channel.user, err_msg
+            );
+        }
+        Ok(())
+    }
+
+    fn handle_reauthorize_result(
+        &mut self,
+        id: &v1::MessageId,
+        payload: &v1::rpc::StratumResult,
+    ) -> Result<()> {
+        trace!(
+            "handle_reauthorize_result() id={:?} state={:?} payload:{:?}",
+            id,
+            self.state,
+            payload,
+        );
+        match v1::messages::BooleanResult::try_from(payload) {
+            Ok(bool_result) if bool_result.0 => {
+                id.and_then(|id| self.v2_reauthorized_channels.remove(&id));
+                Ok(())
+            }
+            _ => self.abort_reauthorized_channel(id, "Not authorized"),
+        }
+    }
+
+    fn handle_reauthorize_error(
+        &mut self,
+        id: &v1::MessageId,
+        payload: &v1::rpc::StratumError,
+    ) -> Result<()> {
+        trace!(
+            "handle_reauthorize_error() id={:?} state={:?} payload:{:?}",
+            id,
+            self.state,
+            payload,
+        );
+        self.abort_reauthorized_channel(id, "Service not ready")
+    }
+
+    /// Reports failed upstream configuration to all downstream connections that wait for it.
+    /// The configuration is attempted again with the next SetupConnection.
+    fn abort_pending_setups(&mut self) -> Result<()> {
+        const ERR_MSG: &str = "Cannot negotiate upstream V1 version mask";
+        self.state = V2ToV1TranslationState::Init;
+        // The configuration has been attempted with a new upstream for already open channels
+        if !self.v2_channels.is_empty() || !self.v2_deferred_channels.is_empty() {
+            self.abort_reconnect(ERR_MSG);
+        }
+        for downstream_id in std::mem::take(&mut self.v2_pending_setups) {
+            if let Some(downstream) = self.v2_downstreams.get_mut(&downstream_id) {
+                downstream.conn_details = None;
+            }
             // TODO consolidate into abort_connection() + communicate shutdown of this
             // connection similarly everywhere in the code
             let response = v2::messages::SetupConnectionError {
                 flags: 0, // TODO handle flags
-                code: "Cannot negotiate upstream V1 version mask"
-                    .try_into()
-                    .expect("BUG: incorrect error message"),
+                code: ERR_MSG.try_into().expect("BUG: incorrect error message"),
             };
-            util::submit_message(&mut self.v2_tx, response)
+            self.v2_submit_message(downstream_id, response)?;
         }
+        Ok(())
     }
 
     fn handle_configure_error(
@@ -400,15 +1274,7 @@ impl V2ToV1Translation {
             self.state,
             payload,
         );
-        // TODO consolidate into abort_connection() + communicate shutdown of this
-        // connection similarly everywhere in the code
-        let response = v2::messages::SetupConnectionError {
-            flags: 0, // TODO handle flags
-            code: "Cannot negotiate upstream V1 version mask"
-                .try_into()
-                .expect("BUG: incorrect error message"),
-        };
-        util::submit_message(&mut self.v2_tx, response)
+        self.abort_pending_setups()
     }
 
     fn handle_extranonce_subscribe_result(
@@ -530,7 +1396,7 @@ impl V2ToV1Translation {
             payload,
         );
         // Only the first of authorize or subscribe error issues the OpenStandardMiningChannelError message
-        if self.state != V2ToV1TranslationState::V1SubscribeOrAuthorizeFail {
+        if self.is_session_pending() {
             trace!(
                 "Upstream connection init failed, dropping channel: {:?}",
                 payload
@@ -557,49 +1423,40 @@ impl V2ToV1Translation {
             payload,
         );
         // mining.submit response is expected as a plain boolean answer
-        v1::messages::BooleanResult::try_from(payload)
-            // Convert potential stratum error to proxy error first
-            .map_err(Into::into)
-            // Handle the actual submission result
-            .and_then(|bool_result| {
-                let v2_channel_details = self
-                    .v2_channel_details
-                    .as_ref()
-                    .expect("BUG: V2 channel details missing");
-                trace!(
-                    "Submit result: {:?}, V2 channel: {:?}",
-                    bool_result,
-                    v2_channel_details
-                );
-                if bool_result.0 {
-                    // TODO this is currently incomplete, we have to track all pending mining
-                    // results so that we can correlate the success message and ack
-                    let success_msg = v2::messages::SubmitSharesSuccess {
-                        channel_id: Self::CHANNEL_ID,
-                        last_seq_num: 0,
-                        new_submits_accepted_count: 1,
-                        new_shares_sum: 1, // TODO is this really 1?
-                    };
-                    self.log_session_details("Share accepted");
-                    util::submit_message(&mut self.v2_tx, success_msg)
-                } else {
-                    // TODO use reject_shares() method once we can track the original payload message
-                    let err_msg = v2::messages::SubmitSharesError {
-                        channel_id: Self::CHANNEL_ID,
-                        // TODO the sequence number needs to be determined from the failed submit, currently,
-                        // there is no infrastructure to get this
-                        seq_num: 0,
-                        code: format!("ShareRjct:{:?}", payload)[..32]
-                            .try_into()
-                            .expect("BUG: incorrect error message"),
-                    };
-                    info!("Share rejected for {}", v2_channel_details.user.to_string());
-                    util::submit_message(&mut self.v2_tx, err_msg)
-                }
-            })
-            // TODO what should be the behavior when the result is incorrectly passed, shall we
-            // report it as a SubmitSharesError?
-            .map_err(Into::into)
+        let bool_result = v1::messages::BooleanResult::try_from(payload)?;
+        let (pending_submit, v2_channel) = match self.take_pending_submit(id) {
+            Some(pending_submit) => pending_submit,
+            None => return Ok(()),
+        };
+        trace!(
+            "Submit result: {:?}, V2 channel: {} {:?}",
+            bool_result,
+            pending_submit.channel_id,
+            v2_channel
+        );
+        let rejected_reason = match bool_result.0 {
+            true => None,
+            false => Some(metrics::SHARE_REJECTED_REASON),
+        };
+        self.record_share_result(&pending_submit, &v2_channel, rejected_reason);
+        if bool_result.0 {
+            let success_msg = v2::messages::SubmitSharesSuccess {
+                channel_id: pending_submit.channel_id,
+                last_seq_num: pending_submit.seq_num,
+                new_submits_accepted_count: 1,
+                new_shares_sum: pending_submit.difficulty,
+            };
+            self.log_session_details("Share accepted", &v2_channel);
+            self.v2_submit_message(v2_channel.downstream_id, success_msg)
+        } else {
+            info!("Share rejected for {}", v2_channel.user);
+            self.send_submit_shares_error(
+                v2_channel.downstream_id,
+                pending_submit.channel_id,
+                pending_submit.seq_num,
+                Self::SHARE_REJECTED_ERROR_CODE,
+            )
+        }
     }
 
     fn handle_submit_error(
@@ -613,18 +1470,146 @@ impl V2ToV1Translation {
             self.state,
             payload,
         );
-        // TODO use reject_shares() method once we can track the original payload message
-        let err_msg = v2::messages::SubmitSharesError {
-            channel_id: Self::CHANNEL_ID,
-            // TODO the sequence number needs to be determined from the failed submit, currently,
-            // there is no infrastructure to get this
-            seq_num: 0,
-            code: format!("ShareRjct:{:?}", payload)[..32]
-                .try_into()
-                .expect("BUG: wrong error code string"),
+        let (pending_submit, v2_channel) = match self.take_pending_submit(id) {
+            Some(pending_submit) => pending_submit,
+            None => return Ok(()),
+        };
+        info!("Share rejected for {}: {:?}", v2_channel.user, payload);
+        self.record_share_result(
+            &pending_submit,
+            &v2_channel,
+            Some(metrics::SHARE_REJECTED_REASON),
+        );
+        self.send_submit_shares_error(
+            v2_channel.downstream_id,
+            pending_submit.channel_id,
+            pending_submit.seq_num,
+            Self::submit_error_code(payload.0),
+        )
+    }
+
+    /// Translates error code of V1 share rejection into SubmitSharesError code
+    fn submit_error_code(v1_error_code: i32) -> &'static str {
+        match v1_error_code {
+            Self::V1_STALE_SHARE_ERROR_CODE => Self::STALE_SHARE_ERROR_CODE,
+            Self::V1_DUPLICATE_SHARE_ERROR_CODE => Self::DUPLICATE_SHARE_ERROR_CODE,
+            Self::V1_LOW_DIFFICULTY_ERROR_CODE => Self::LOW_DIFFICULTY_ERROR_CODE,
+            _ => Self::SHARE_REJECTED_ERROR_CODE,
+        }
+    }
+
+    /// Finds the share that has been submitted with V1 request `id`. Nothing is provided when
+    /// the share has already timed out or when its channel has been closed in the meantime.
+    fn take_pending_submit(&mut self, id: &v1::MessageId) -> Option<(V1PendingSubmit, V2Channel)> {
+        let pending_submit = match id.and_then(|id| self.v1_pending_submits.remove(&id)) {
+            Some(pending_submit) => pending_submit,
+            None => {
+                trace!("Ignoring result for unknown or timed out submit {:?}", id);
+                return None;
+            }
         };
+        match self.v2_channels.get(&pending_submit.channel_id) {
+            Some(v2_channel) => Some((pending_submit, v2_channel.clone())),
+            None => {
+                // The result arrived after the channel has been closed
+                trace!(
+                    "Ignoring submit result for closed channel {}",
+                    pending_submit.channel_id
+                );
+                None
+            }
+        }
+    }
+
+    /// Updates share metrics with the result of `pending_submit`, the share has been accepted
+    /// unless `rejected_reason` is provided
+    fn record_share_result(
+        &self,
+        pending_submit: &V1PendingSubmit,
+        v2_channel: &V2Channel,
+        rejected_reason: Option<&str>,
+    ) {
+        metrics::record_share_result(
+            &metrics::upstream_label(self.v1_upstream_addr),
+            &v2_channel.user,
+            pending_submit.timestamp,
+            rejected_reason,
+        );
+    }
 
-        util::submit_message(&mut self.v2_tx, err_msg)
+    /// Updates share metrics with share of `v2_channel` that has been rejected by the proxy
+    fn record_share_rejected_locally(&self, v2_channel: &V2Channel, code: &str) {
+        metrics::record_share_rejected_locally(
+            &metrics::upstream_label(self.v1_upstream_addr),
+            &v2_channel.user,
+            code,
+        );
+    }
+
+    /// Reports all shares that haven't been confirmed by the upstream within
+    /// `V1_SUBMIT_TIMEOUT` as rejected. Late results of such submits are ignored.
+    pub fn report_timed_out_submits(&mut self, now: time::Instant) {
+        let mut timed_out_ids: Vec<u32> = self
+            .v1_pending_submits
+            .iter()
+            .filter(|(_, pending_submit)| {
+                now.saturating_duration_since(pending_submit.timestamp) >= Self::V1_SUBMIT_TIMEOUT
+            })
+            .map(|(id, _)| *id)
+            .collect();
+        // Report the shares in the order of submission
+        timed_out_ids.sort();
+
+        for id in timed_out_ids {
+            self.v1_req_map.remove(&id);
+            if let Some((pending_submit, v2_channel)) = self.take_pending_submit(&Some(id)) {
+                info!(
+                    "Share submit timed out for {}, seq_num={}",
+                    v2_channel.user, pending_submit.seq_num
+                );
+                self.record_share_result(
+                    &pending_submit,
+                    &v2_channel,
+                    Some(metrics::SHARE_TIMEOUT_REASON),
+                );
+                if let Err(e) = self.send_submit_shares_error(
+                    v2_channel.downstream_id,
+                    pending_submit.channel_id,
+                    pending_submit.seq_num,
+                    Self::SUBMIT_TIMEOUT_ERROR_CODE,
+                ) {
+                    info!("Cannot send 'SubmitSharesError': {:?}", e);
+                }
+            }
+        }
+    }
+
+    /// Reports all shares that wait for the result from the upstream as rejected, the upstream
+    /// session has been lost
+    fn report_lost_submits(&mut self) {
+        let mut pending_submits: Vec<(u32, V1PendingSubmit)> =
+            self.v1_pending_submits.drain().collect();
+        pending_submits.sort_by_key(|(id, _)| *id);
+
+        for (_, pending_submit) in pending_submits {
+            let v2_channel = match self.v2_channels.get(&pending_submit.channel_id) {
+                Some(v2_channel) => v2_channel.clone(),
+                None => continue,
+            };
+            self.record_share_result(
+                &pending_submit,
+                &v2_channel,
+                Some(metrics::SHARE_UPSTREAM_LOST_REASON),
+            );
+            if let Err(e) = self.send_submit_shares_error(
+                v2_channel.downstream_id,
+                pending_submit.channel_id,
+                pending_submit.seq_num,
+                Self::UPSTREAM_LOST_REASON,
+            ) {
+                info!("Cannot send 'SubmitSharesError': {:?}", e);
+            }
+        }
     }
 
     /// Iterates the merkle branches and calculates block merkle root using the extra nonce 1.
@@ -632,43 +1617,15 @@ impl V2ToV1Translation {
     /// TODO review, whether a Result has to be returned as missing enonce1 would be considered a bug
     fn calculate_merkle_root(
         &mut self,
+        channel_id: u32,
         payload: &v1::messages::Notify,
     ) -> crate::error::Result<sha256d::Hash> {
-        // TODO get rid of extra nonce 1 cloning
-        if let Some(v1_extra_nonce1) = self.v1_extra_nonce1.clone() {
-            // Build coin base transaction,
-            let mut coin_base: BytesMut = BytesMut::with_capacity(
-                payload.coin_base_1().len()
-                    + (v1_extra_nonce1.0).len()
-                    + self.v1_extra_nonce2_size
-                    + payload.coin_base_2().len(),
-            );
-            coin_base.extend_from_slice(payload.coin_base_1());
-            coin_base.extend_from_slice(v1_extra_nonce1.0.as_ref());
-            coin_base.extend_from_slice(
-                Self::channel_to_extra_nonce2_bytes(Self::CHANNEL_ID, self.v1_extra_nonce2_size)
-                    .as_ref(),
-            );
-            coin_base.extend_from_slice(payload.coin_base_2());
-
-            let mut engine = sha256d::Hash::engine();
-            engine.input(&coin_base);
-
-            let cb_tx_hash = sha256d::Hash::from_engine(engine);
-            trace!("Coinbase TX hash: {:x?} {:x?}", cb_tx_hash, coin_base);
-
-            let merkle_root =
-                payload
-                    .merkle_branch()
-                    .iter()
-                    .fold(cb_tx_hash, |curr_merkle_root, tx_hash| {
-                        let mut engine = sha256d::Hash::engine();
-                        engine.input(&curr_merkle_root.into_inner());
-                        engine.input(tx_hash.as_ref().as_slice());
-                        sha256d::Hash::from_engine(engine)
-                    });
-            trace!("Merkle root calculated: {:x?}", merkle_root);
-            Ok(merkle_root)
+        if let Some(v1_extra_nonce1) = self.v1_extra_nonce1.as_ref() {
+            Ok(Self::build_merkle_root(
+                v1_extra_nonce1.0.as_ref(),
+                Self::channel_to_extra_nonce2_bytes(channel_id, self.v1_extra_nonce2_size).as_ref(),
+                payload,
+            ))
         } else {
             Err(super::error::ErrorKind::General(
                 "Extra nonce 1 missing, cannot calculate merkle root".into(),
@@ -677,6 +1634,104 @@ impl V2ToV1Translation {
         }
     }
 
+    /// Builds the coinbase transaction of job `payload` with the specified extra nonces and
+    /// folds it with the merkle branches into the merkle root
+    fn build_merkle_root(
+        extra_nonce1: &[u8],
+        extra_nonce2: &[u8],
+        payload: &v1::messages::Notify,
+    ) -> sha256d::Hash {
+        // Build coin base transaction,
+        let mut coin_base: BytesMut = BytesMut::with_capacity(
+            payload.coin_base_1().len()
+                + extra_nonce1.len()
+                + extra_nonce2.len()
+                + payload.coin_base_2().len(),
+        );
+        coin_base.extend_from_slice(payload.coin_base_1());
+        coin_base.extend_from_slice(extra_nonce1);
+        coin_base.extend_from_slice(extra_nonce2);
+        coin_base.extend_from_slice(payload.coin_base_2());
+
+        let mut engine = sha256d::Hash::engine();
+        engine.input(&coin_base);
+
+        let cb_tx_hash = sha256d::Hash::from_engine(engine);
+        trace!("Coinbase TX hash: {:x?} {:x?}", cb_tx_hash, coin_base);
+
+        let merkle_root =
+            payload
+                .merkle_branch()
+                .iter()
+                .fold(cb_tx_hash, |curr_merkle_root, tx_hash| {
+                    let mut engine = sha256d::Hash::engine();
+                    engine.input(&curr_merkle_root.into_inner());
+                    engine.input(tx_hash.as_ref().as_slice());
+                    sha256d::Hash::from_engine(engine)
+                });
+        trace!("Merkle root calculated: {:x?}", merkle_root);
+        merkle_root
+    }
+
+    /// Checks that share `payload` hasn't been submitted for its job before and that its
+    /// block header hash meets the channel target. The share is remembered even if it turns out
+    /// to be invalid.
+    /// Return:
+    ///  - true when the share meets the upstream target too and is to be submitted upstream
+    ///  - error code for `SubmitSharesError` when the share is to be rejected
+    fn validate_share(
+        &mut self,
+        payload: &v2::messages::SubmitSharesStandard,
+        extra_nonce2: &[u8],
+    ) -> std::result::Result<bool, &'static str> {
+        let v1_extra_nonce1 = match self.v1_extra_nonce1.as_ref() {
+            Some(v1_extra_nonce1) => v1_extra_nonce1,
+            None => return Err(Self::INVALID_JOB_ERROR_CODE),
+        };
+        let (channel_target, upstream_target) =
+            match (self.channel_target(payload.channel_id), self.v2_target) {
+                (Some(channel_target), Some(upstream_target)) => (
+                    ii_bitcoin::Target::from(channel_target),
+                    ii_bitcoin::Target::from(upstream_target),
+                ),
+                _ => return Err(Self::INVALID_JOB_ERROR_CODE),
+            };
+        let v1_submit_template = match self.v2_to_v1_job_map.get_mut(&payload.job_id) {
+            Some(v1_submit_template) => v1_submit_template,
+            None => return Err(Self::INVALID_JOB_ERROR_CODE),
+        };
+
+        let version_mask = ii_stratum::BIP320_N_VERSION_MASK;
+        let version = payload.version & version_mask;
+        if !v1_submit_template.submitted_shares.insert(V1ShareKey {
+            extra_nonce2: extra_nonce2.to_vec(),
+            ntime: payload.ntime,
+            nonce: payload.nonce,
+            version,
+        }) {
+            return Err(Self::DUPLICATE_SHARE_ERROR_CODE);
+        }
+
+        let notify = &v1_submit_template.notify;
+        let previous_hash = sha256d::Hash::from_slice(notify.prev_hash())
+            .map_err(|_| Self::INVALID_JOB_ERROR_CODE)?;
+        let block_header = ii_bitcoin::BlockHeader {
+            version: (notify.version() & !version_mask) | version,
+            previous_hash: previous_hash.into_inner(),
+            merkle_root: Self::build_merkle_root(v1_extra_nonce1.0.as_ref(), extra_nonce2, notify)
+                .into_inner(),
+            time: payload.ntime,
+            bits: notify.bits(),
+            nonce: payload.nonce,
+        };
+        let hash = block_header.hash();
+        if hash.meets(&channel_target) {
+            Ok(hash.meets(&upstream_target))
+        } else {
+            Err(Self::LOW_DIFFICULTY_ERROR_CODE)
+        }
+    }
+
     /// Builds SetNewPrevHash for the specified v1 Notify `payload`
     ///
     /// The SetNewPrevHash has to reference the future job that the V2 downstream has
@@ -692,6 +1747,7 @@ impl V2ToV1Translation {
     /// interval
     fn build_set_new_prev_hash(
         &self,
+        channel_id: u32,
         job_id: u32,
         payload: &v1::messages::Notify,
     ) -> crate::error::Result<v2::messages::SetNewPrevHash> {
@@ -702,7 +1758,7 @@ impl V2ToV1Translation {
         let prev_hash = Uint256Bytes(prev_hash.into_inner());
 
         Ok(v2::messages::SetNewPrevHash {
-            channel_id: Self::CHANNEL_ID,
+            channel_id,
             prev_hash,
             min_ntime: payload.time(),
             nbits: payload.bits(),
@@ -737,50 +1793,162 @@ impl V2ToV1Translation {
     /// Generates log trace entry and reject shares error reply to the client
     fn reject_shares(&mut self, payload: &v2::messages::SubmitSharesStandard, err_msg: String) {
         trace!("Unrecognized channel ID: {}", payload.channel_id);
+        if let Err(submit_err) = self.send_submit_shares_error(
+            self.v2_current_downstream,
+            payload.channel_id,
+            payload.seq_num,
+            &err_msg,
+        ) {
+            info!("Cannot send 'SubmitSharesError': {:?}", submit_err);
+        }
+    }
+
+    /// Reports share with `seq_num` as rejected, the error message is truncated to fit the V2
+    /// error code
+    fn send_submit_shares_error(
+        &mut self,
+        downstream_id: DownstreamId,
+        channel_id: u32,
+        seq_num: u32,
+        err_msg: &str,
+    ) -> Result<()> {
+        let mut code_len = err_msg.len().min(32);
+        while !err_msg.is_char_boundary(code_len) {
+            code_len -= 1;
+        }
         let submit_shares_error_msg = v2::messages::SubmitSharesError {
-            channel_id: payload.channel_id,
-            seq_num: payload.seq_num,
-            code: err_msg[..32].try_into().expect(
-                format!(
-                    "BUG: cannot convert error message to V2 format: {}",
-                    err_msg
-                )
-                .as_str(),
-            ),
+            channel_id,
+            seq_num,
+            code: err_msg[..code_len]
+                .try_into()
+                .expect("BUG: cannot convert error message to V2 format"),
         };
+        self.v2_submit_message(downstream_id, submit_shares_error_msg)
+    }
 
-        if let Err(submit_err) = util::submit_message(&mut self.v2_tx, submit_shares_error_msg) {
-            info!("Cannot send 'SubmitSharesError': {:?}", submit_err);
+    /// Builds mining job for a particular channel, the merkle root is unique for each channel
+    fn build_new_mining_job(
+        &mut self,
+        channel_id: u32,
+        job_id: u32,
+        future_job: bool,
+        payload: &v1::messages::Notify,
+    ) -> Result<v2::messages::NewMiningJob> {
+        let merkle_root = self.calculate_merkle_root(channel_id, payload)?;
+
+        Ok(v2::messages::NewMiningJob {
+            channel_id,
+            job_id,
+            future_job,
+            merkle_root: Uint256Bytes(merkle_root.into_inner()),
+            version: payload.version(),
+        })
+    }
+
+    /// Builds mining job for an extended channel. The channel builds the merkle root on its own,
+    /// the coinbase suffix carries the trailing part of extra nonce 2 that identifies the channel.
+    fn build_new_extended_mining_job(
+        &self,
+        channel_id: u32,
+        extended: V2ExtendedChannel,
+        job_id: u32,
+        future_job: bool,
+        payload: &v1::messages::Notify,
+    ) -> Result<v2::messages::NewExtendedMiningJob> {
+        let mut coinbase_tx_suffix =
+            self.extended_extra_nonce2_suffix(extended).ok_or_else(|| {
+                super::error::ErrorKind::General(format!(
+                    "Extranonce of channel {} doesn't fit into extra nonce 2",
+                    channel_id
+                ))
+            })?;
+        coinbase_tx_suffix.extend_from_slice(payload.coin_base_2());
+        let merkle_path = payload
+            .merkle_branch()
+            .iter()
+            .map(|tx_hash| {
+                <[u8; 32]>::try_from(tx_hash.as_ref().as_slice())
+                    .map(Uint256Bytes)
+                    .map_err(|_| {
+                        super::error::ErrorKind::General("Invalid merkle branch".into()).into()
+                    })
+            })
+            .collect::<Result<Vec<Uint256Bytes>>>()?;
+
+        Ok(v2::messages::NewExtendedMiningJob {
+            channel_id,
+            job_id,
+            future_job,
+            version: payload.version(),
+            version_rolling_allowed: true,
+            merkle_path: Seq0_255::from_vec(merkle_path),
+            coinbase_tx_prefix: Bytes0_64k::from_slice(payload.coin_base_1()),
+            coinbase_tx_suffix: Bytes0_64k::from_vec(coinbase_tx_suffix),
+        })
+    }
+
+    /// Sends the current job to a channel that has just been opened so that it doesn't have to
+    /// wait for the next mining.notify
+    fn send_current_job(&mut self, channel_id: u32) -> Result<()> {
+        if let Some((job_id, payload)) = self.v1_last_notify.clone() {
+            let extended = self
+                .v2_channels
+                .get(&channel_id)
+                .and_then(|v2_channel| v2_channel.extended);
+            match extended {
+                Some(extended) => {
+                    let v2_job = self.build_new_extended_mining_job(
+                        channel_id, extended, job_id, true, &payload,
+                    )?;
+                    self.v2_submit_channel_message(channel_id, v2_job)?;
+                }
+                None => {
+                    let v2_job = self.build_new_mining_job(channel_id, job_id, true, &payload)?;
+                    self.v2_submit_channel_message(channel_id, v2_job)?;
+                }
+            }
+            let set_new_prev_hash = self.build_set_new_prev_hash(channel_id, job_id, &payload)?;
+            self.v2_submit_channel_message(channel_id, set_new_prev_hash)?;
         }
+        Ok(())
     }
 
+    /// Fans out the V1 job to all channels. Each standard channel receives its own NewMiningJob,
+    /// the new prevhash is broadcast once to the whole group. Extended channels receive
+    /// NewExtendedMiningJob along with their own new prevhash.
     fn perform_notify(&mut self, payload: &v1::messages::Notify) -> Result<()> {
-        let merkle_root = self.calculate_merkle_root(payload)?;
-
-        let v2_job = v2::messages::NewMiningJob {
-            channel_id: Self::CHANNEL_ID,
-            job_id: self.v2_job_id.next(),
-            future_job: self.v2_to_v1_job_map.is_empty()
-                || payload.clean_jobs()
-                || self.v1_force_future_jobs,
-            merkle_root: Uint256Bytes(merkle_root.into_inner()),
-            version: payload.version(),
-        };
+        let job_id = self.v2_job_id.next();
+        let future_job =
+            self.v2_to_v1_job_map.is_empty() || payload.clean_jobs() || self.v1_force_future_jobs;
+
+        let mut channel_ids: Vec<u32> = self.v2_channels.keys().cloned().collect();
+        channel_ids.sort();
+        let mut v2_jobs = Vec::with_capacity(channel_ids.len());
+        let mut v2_extended_jobs = vec![];
+        for channel_id in channel_ids {
+            match self.v2_channels[&channel_id].extended {
+                Some(extended) => v2_extended_jobs.push(self.build_new_extended_mining_job(
+                    channel_id, extended, job_id, future_job, payload,
+                )?),
+                None => v2_jobs
+                    .push(self.build_new_mining_job(channel_id, job_id, future_job, payload)?),
+            }
+        }
 
         // Make sure we generate new prev hash. Empty JobMap means this is the first mining.notify
         // message and we also have to issue NewPrevHash. In addition to that, we also check the
         // clean jobs flag that indicates a must for new prev hash, too.
-        let maybe_set_new_prev_hash = if v2_job.future_job {
+        let maybe_set_new_prev_hash = if future_job {
             self.v2_to_v1_job_map.clear();
             // Any error means immediate termination
             // TODO write a unit test for such scenario, too
-            Some(self.build_set_new_prev_hash(v2_job.job_id, payload)?)
+            Some(self.build_set_new_prev_hash(Self::GROUP_CHANNEL_ID, job_id, payload)?)
         } else {
             None
         };
         trace!(
             "Registering V2 job ID {:x?} -> V1 job ID {:x?}",
-            v2_job.job_id,
+            job_id,
             payload.job_id(),
         );
         // TODO extract this duplicate code, turn the map into a new type with this
@@ -788,26 +1956,150 @@ impl V2ToV1Translation {
         if self
             .v2_to_v1_job_map
             .insert(
-                v2_job.job_id,
+                job_id,
                 V1SubmitTemplate {
                     job_id: v1::messages::JobId::from_str(payload.job_id()),
                     time: payload.time(),
                     version: payload.version(),
+                    notify: payload.clone(),
+                    submitted_shares: HashSet::new(),
                 },
             )
             .is_some()
         {
-            error!("BUG: V2 id {} already exists...", v2_job.job_id);
+            error!("BUG: V2 id {} already exists...", job_id);
             // TODO add graceful handling of this bug (shutdown?)
             panic!("V2 id already exists");
         }
+        self.v1_last_notify = Some((job_id, payload.clone()));
+
+        for v2_job in v2_jobs {
+            self.v2_submit_channel_message(v2_job.channel_id, v2_job)?;
+        }
+        for v2_job in v2_extended_jobs.iter() {
+            self.v2_submit_channel_message(v2_job.channel_id, v2_job.clone())?;
+        }
+
+        if let Some(set_new_prev_hash) = maybe_set_new_prev_hash {
+            for v2_job in v2_extended_jobs {
+                let msg = v2::messages::SetNewPrevHash {
+                    channel_id: v2_job.channel_id,
+                    ..set_new_prev_hash.clone()
+                };
+                self.v2_submit_channel_message(v2_job.channel_id, msg)?;
+            }
+            self.v2_submit_group_message(set_new_prev_hash)?
+        }
+        Ok(())
+    }
+
+    /// Provides extended channels with the extranonce prefix of the current upstream session.
+    /// Channels whose extranonce doesn't fit into the extra nonce 2 of the session anymore are
+    /// closed.
+    fn update_extended_channels(&mut self) -> Result<()> {
+        let mut channel_ids: Vec<u32> = self
+            .v2_channels
+            .iter()
+            .filter(|(_, v2_channel)| v2_channel.extended.is_some())
+            .map(|(channel_id, _)| *channel_id)
+            .collect();
+        channel_ids.sort();
+        for channel_id in channel_ids {
+            let extended = self.v2_channels[&channel_id]
+                .extended
+                .expect("BUG: missing extended channel");
+            let extranonce_prefix = self.v2_extranonce_prefix();
+            match extranonce_prefix {
+                Some(extranonce_prefix)
+                    if self.extended_extra_nonce2_suffix(extended).is_some() =>
+                {
+                    let msg = v2::messages::SetExtranoncePrefix {
+                        channel_id,
+                        extranonce_prefix,
+                    };
+                    self.v2_submit_channel_message(channel_id, msg)?;
+                }
+                _ => {
+                    let msg = v2::messages::CloseChannel {
+                        channel_id,
+                        reason_code: "extranonce-size-changed"
+                            .try_into()
+                            .expect("BUG: incorrect reason code"),
+                    };
+                    self.v2_submit_channel_message(channel_id, msg)?;
+                    self.v2_channels.remove(&channel_id);
+                }
+            }
+        }
+        Ok(())
+    }
 
-        util::submit_message(&mut self.v2_tx, v2_job)?;
+    /// Opens an additional channel on top of the operational V1 session. Only the user of the
+    /// new channel needs to be authorized with the upstream.
+    fn open_group_member_channel(&mut self, pending_channel: V2PendingChannel) {
+        let authorize = v1::messages::Authorize(pending_channel.v1_user.clone(), "".to_string());
+        let v1_authorize_message = self.v1_method_into_message(
+            authorize,
+            Self::handle_group_member_authorize_result,
+            Self::handle_group_member_authorize_error,
+        );
+        self.v2_pending_channels
+            .insert(Self::v1_request_id(&v1_authorize_message), pending_channel);
+        if let Err(submit_err) = util::submit_message(&mut self.v1_tx, v1_authorize_message) {
+            info!("Cannot send V1 mining.authorized: {:?}", submit_err);
+        }
+    }
 
-        if let Some(set_new_prev_hash) = maybe_set_new_prev_hash {
-            util::submit_message(&mut self.v2_tx, set_new_prev_hash)?
+    /// Reports failure to open an additional channel, the rest of the group is not affected
+    fn abort_group_member_channel(&mut self, id: &v1::MessageId, err_msg: &str) -> Result<()> {
+        let pending_channel = id
+            .and_then(|id| self.v2_pending_channels.remove(&id))
+            .expect("BUG: missing pending channel for V1 authorize");
+        info!(
+            "Cannot open channel for {}: {}",
+            pending_channel.open_msg.user.to_string(),
+            err_msg
+        );
+        self.send_open_channel_error(&pending_channel, err_msg)
+    }
+
+    fn handle_group_member_authorize_result(
+        &mut self,
+        id: &v1::MessageId,
+        payload: &v1::rpc::StratumResult,
+    ) -> Result<()> {
+        trace!(
+            "handle_group_member_authorize_result() id={:?} state={:?} payload:{:?}",
+            id,
+            self.state,
+            payload,
+        );
+        match v1::messages::BooleanResult::try_from(payload) {
+            Ok(bool_result) if bool_result.0 => {
+                let pending_channel = id
+                    .and_then(|id| self.v2_pending_channels.remove(&id))
+                    .expect("BUG: missing pending channel for V1 authorize");
+                match self.add_channel(&pending_channel)? {
+                    Some(channel_id) => self.send_current_job(channel_id),
+                    None => Ok(()),
+                }
+            }
+            _ => self.abort_group_member_channel(id, "Not authorized"),
         }
-        Ok(())
+    }
+
+    fn handle_group_member_authorize_error(
+        &mut self,
+        id: &v1::MessageId,
+        payload: &v1::rpc::StratumError,
+    ) -> Result<()> {
+        trace!(
+            "handle_group_member_authorize_error() id={:?} state={:?} payload:{:?}",
+            id,
+            self.state,
+            payload,
+        );
+        self.abort_group_member_channel(id, "Service not ready")
     }
 
     /// The result visitor takes care of detecting a spurious response without matching request
@@ -840,19 +2132,250 @@ impl V2ToV1Translation {
         .ok();
     }
 
-    fn log_session_details(&self, msg: &str) {
-        let v2_channel_details = self
-            .v2_channel_details
-            .as_ref()
-            .expect("BUG: V2 channel details missing");
-        let v2_connection_details = self
-            .v2_conn_details
-            .as_ref()
-            .expect("BUG: V2 channel details present but connection details missing?");
+    /// Submits share of a channel that has been validated by the caller upstream via V1
+    /// mining.submit with the specified `extra_nonce2`. The share is remembered until the V1 result
+    /// arrives.
+    fn submit_share(&mut self, payload: &v2::messages::SubmitSharesStandard, extra_nonce2: &[u8]) {
+        let v2_channel = self
+            .v2_channels
+            .get(&payload.channel_id)
+            .cloned()
+            .expect("BUG: share of unknown channel");
+        // Check job ID validity
+        let v1_submit_template = self
+            .v2_to_v1_job_map
+            .get(&payload.job_id)
+            // convert missing job ID (None) into an error
+            .ok_or(crate::error::ErrorKind::General(format!(
+                "V2 Job ID not present {} in registry",
+                payload.job_id
+            )))
+            .map(|tmpl| tmpl.job_id.clone());
+        // Submit upstream V1 job based on the found job ID in the map
+        match v1_submit_template {
+            Ok(v1_job_id) => {
+                let difficulty = self.channel_difficulty(&v2_channel);
+                // Variable difficulty relies on the validation as the channel may submit shares
+                // that don't meet the upstream target
+                if self.options.validate_shares || v2_channel.vardiff.is_some() {
+                    match self.validate_share(payload, extra_nonce2) {
+                        Ok(meets_upstream_target) => {
+                            self.record_vardiff_share(payload.channel_id);
+                            if !meets_upstream_target {
+                                self.accept_share_locally(&v2_channel, payload, difficulty);
+                                return;
+                            }
+                        }
+                        Err(code) => {
+                            info!(
+                                "SubmitShares: rejecting share of channel {}: {}",
+                                payload.channel_id, code
+                            );
+                            self.record_share_rejected_locally(&v2_channel, code);
+                            self.reject_shares(payload, code.to_string());
+                            return;
+                        }
+                    }
+                }
+                let submit = v1::messages::Submit::new(
+                    v2_channel.user.clone(),
+                    v1_job_id,
+                    extra_nonce2,
+                    payload.ntime,
+                    payload.nonce,
+                    // ensure the version bits in the template follow BIP320
+                    payload.version & ii_stratum::BIP320_N_VERSION_MASK,
+                );
+                // Convert the method into a message + provide handling methods
+                let v1_submit_message = self.v1_method_into_message(
+                    submit,
+                    Self::handle_submit_result,
+                    Self::handle_submit_error,
+                );
+                self.v1_pending_submits.insert(
+                    Self::v1_request_id(&v1_submit_message),
+                    V1PendingSubmit {
+                        channel_id: payload.channel_id,
+                        seq_num: payload.seq_num,
+                        difficulty,
+                        timestamp: time::Instant::now(),
+                    },
+                );
+                if let Err(submit_err) = util::submit_message(&mut self.v1_tx, v1_submit_message) {
+                    info!(
+                        "SubmitShares: cannot send translated V1 message: {:?}",
+                        submit_err
+                    );
+                }
+            }
+            Err(e) => self.reject_shares(payload, format!("{}", e)),
+        }
+    }
+
+    /// Accounts share of channel with variable difficulty and adjusts the difficulty if needed
+    fn record_vardiff_share(&mut self, channel_id: u32) {
+        let vardiff = self
+            .v2_channels
+            .get_mut(&channel_id)
+            .and_then(|v2_channel| v2_channel.vardiff.as_mut());
+        if let Some(vardiff) = vardiff {
+            vardiff.record_share();
+            self.retarget_channel(channel_id, time::Instant::now());
+        }
+    }
+
+    /// Confirms share that meets the channel target but not the upstream one, such share is
+    /// not submitted upstream
+    fn accept_share_locally(
+        &mut self,
+        v2_channel: &V2Channel,
+        payload: &v2::messages::SubmitSharesStandard,
+        difficulty: u32,
+    ) {
+        trace!(
+            "Share of channel {} below upstream difficulty accepted",
+            payload.channel_id
+        );
+        let success_msg = v2::messages::SubmitSharesSuccess {
+            channel_id: payload.channel_id,
+            last_seq_num: payload.seq_num,
+            new_submits_accepted_count: 1,
+            new_shares_sum: difficulty,
+        };
+        if let Err(e) = self.v2_submit_message(v2_channel.downstream_id, success_msg) {
+            info!("Cannot send 'SubmitSharesSuccess': {:?}", e);
+        }
+    }
+
+    /// Rewrites the user of `pending_channel` according to the routing rules. Channels of unknown
+    /// users are refused. A channel routed to other upstream servers than the session uses is
+    /// refused too unless it is the first channel of a dedicated session, the session is moved
+    /// to the servers of the channel first.
+    /// Returns the channel when it can be opened right away.
+    fn route_channel(&mut self, mut pending_channel: V2PendingChannel) -> Option<V2PendingChannel> {
+        let routing_rules = match self.options.routing_rules.clone() {
+            Some(routing_rules) => routing_rules,
+            None => return Some(pending_channel),
+        };
+        let peer_addr = self
+            .v2_downstreams
+            .get(&pending_channel.downstream_id)
+            .and_then(|downstream| downstream.peer_addr);
+        let route = match routing_rules.route(
+            &pending_channel.v1_user,
+            peer_addr.map(|peer_addr| peer_addr.ip()),
+        ) {
+            Some(route) => route,
+            None => {
+                info!("No route for user {}", pending_channel.v1_user);
+                self.reject_pending_channel(pending_channel, Self::UNKNOWN_USER_ERROR_CODE);
+                return None;
+            }
+        };
+        pending_channel.v1_user = route.user;
+        if route.upstream == self.v1_routed_upstream {
+            return Some(pending_channel);
+        }
+
+        let first_channel = match self.state {
+            V2ToV1TranslationState::ConnectionSetup
+            | V2ToV1TranslationState::V1SubscribeOrAuthorizeFail => {
+                self.v2_channels.is_empty() && !self.v2_shared
+            }
+            _ => false,
+        };
+        match (first_channel, route.upstream, &self.v1_routed_upstream) {
+            (true, Some(upstream), None) => {
+                info!(
+                    "Routing user {} to upstream {:?}",
+                    pending_channel.v1_user, upstream
+                );
+                // The channel is opened once the session has been moved
+                self.v1_routed_upstream = Some(upstream.clone());
+                self.v1_upstream_request = Some(upstream);
+                self.state = V2ToV1TranslationState::V1Reconnect;
+                self.v2_deferred_channels.push(pending_channel);
+            }
+            _ => {
+                info!(
+                    "User {} is routed to other upstream than the session uses",
+                    pending_channel.v1_user
+                );
+                self.reject_pending_channel(pending_channel, Self::UPSTREAM_MISMATCH_ERROR_CODE);
+            }
+        }
+        None
+    }
+
+    /// Opens `pending_channel` according to the state of the upstream session
+    fn open_channel(&mut self, pending_channel: V2PendingChannel) {
+        let pending_channel = match self.route_channel(pending_channel) {
+            Some(pending_channel) => pending_channel,
+            None => return,
+        };
+        // Connection details are present when the downstream has setup the connection
+        let conn_details = self
+            .v2_downstreams
+            .get(&pending_channel.downstream_id)
+            .and_then(|downstream| downstream.conn_details.clone());
+        let conn_details = match (&self.state, conn_details) {
+            // The upstream session is already established, only the user of the new channel
+            // needs to be authorized
+            (V2ToV1TranslationState::Operational, Some(_)) => {
+                self.open_group_member_channel(pending_channel);
+                return;
+            }
+            // The first channel of the upstream session is being opened or the session is
+            // being moved to another upstream
+            (V2ToV1TranslationState::OpenStandardMiningChannelPending, Some(_))
+            | (V2ToV1TranslationState::V1Reconnect, Some(_)) => {
+                self.v2_deferred_channels.push(pending_channel);
+                return;
+            }
+            // The connection has been setup with the previous upstream, the new one is being
+            // configured
+            (V2ToV1TranslationState::V1Configure, Some(_))
+                if !self
+                    .v2_pending_setups
+                    .contains(&pending_channel.downstream_id) =>
+            {
+                self.v2_deferred_channels.push(pending_channel);
+                return;
+            }
+            (V2ToV1TranslationState::ConnectionSetup, Some(conn_details))
+            | (V2ToV1TranslationState::V1SubscribeOrAuthorizeFail, Some(conn_details)) => {
+                conn_details
+            }
+            _ => {
+                trace!(
+                    "Out of sequence open channel message, received: {:?}",
+                    pending_channel.open_msg
+                );
+                if let Err(submit_err) =
+                    self.send_open_channel_error(&pending_channel, "Out of sequence open channel")
+                {
+                    info!("Cannot send open channel error message: {:?}", submit_err);
+                }
+                return;
+            }
+        };
+
+        self.open_first_channel(pending_channel, &conn_details);
+    }
+
+    fn log_session_details(&self, msg: &str, v2_channel: &V2Channel) {
+        let v2_connection_details = match self
+            .v2_downstreams
+            .get(&v2_channel.downstream_id)
+            .and_then(|downstream| downstream.conn_details.as_ref())
+        {
+            Some(v2_connection_details) => v2_connection_details,
+            None => return,
+        };
         info!(
             "{} SESSION;{};{};{};{};{:x};{};{};{};{};{};{};",
             msg,
-            v2_channel_details.user.to_string(),
+            v2_channel.user,
             v2_connection_details.protocol,
             v2_connection_details.min_version,
             v2_connection_details.max_version,
@@ -902,11 +2425,16 @@ impl v1::Handler for V2ToV1Translation {
             self.state,
             payload,
         );
-        let diff = payload.value() as u32;
+        // Targets are derived from integer difficulty, fractional difficulty is rounded up to 1
+        // and the value is saturated as casting of an out of range float is undefined
+        let diff = f64::from(payload.value())
+            .max(1.0)
+            .min(f64::from(std::u32::MAX)) as u32;
         self.v2_target = Some(Self::DIFF1_TARGET / diff);
+        self.v1_difficulty = diff;
         if self.v1_authorized && self.v1_extra_nonce1.is_some() {
             // Initial set difficulty finalizes open channel if all preconditions are met
-            if self.state == V2ToV1TranslationState::OpenStandardMiningChannelPending {
+            if self.is_session_pending() {
                 self.finalize_open_channel()
                     .map_err(|e| trace!("visit_set_difficulty: {}", e))
                     // Consume the error as there is no way to return anything from the visitor for now.
@@ -939,10 +2467,14 @@ impl v1::Handler for V2ToV1Translation {
         //   https://en.bitcoin.it/wiki/Stratum_mining_protocol#mining.set_extranonce
         self.v1_extra_nonce1 = Some(payload.extra_nonce_1().clone());
         self.v1_extra_nonce2_size = payload.extra_nonce_2_size().clone();
+        if self.state == V2ToV1TranslationState::Operational {
+            if let Err(e) = self.update_extended_channels() {
+                info!("Cannot update extranonce of extended channels: {}", e);
+            }
+        }
     }
 
-    /// Composes a new mining job and sends it downstream
-    /// TODO: Only 1 channel is supported
+    /// Composes a new mining job and sends it downstream to all channels
     async fn visit_notify(&mut self, id: &v1::MessageId, payload: &v1::messages::Notify) {
         trace!(
             "visit_notify() id={:?} state={:?} payload:{:?}",
@@ -1002,7 +2534,12 @@ impl v2::Handler for V2ToV1Translation {
             self.state,
             payload,
         );
-        if self.state != V2ToV1TranslationState::Init {
+        let downstream_id = self.v2_current_downstream;
+        let downstream = self
+            .v2_downstreams
+            .get_mut(&downstream_id)
+            .expect("BUG: missing current downstream");
+        if downstream.conn_details.is_some() {
             trace!("Cannot setup connection again, received: {:?}", payload);
             let err_msg = v2::messages::SetupConnectionError {
                 code: "Connection can be setup only once"
@@ -1010,31 +2547,34 @@ impl v2::Handler for V2ToV1Translation {
                     .expect("BUG: incorrect error message"),
                 flags: payload.flags, // TODO Flags indicating features causing an error
             };
-            if let Err(submit_err) = util::submit_message(&mut self.v2_tx, err_msg) {
+            if let Err(submit_err) = self.v2_submit_message(downstream_id, err_msg) {
                 info!("Cannot submit SetupConnectionError: {:?}", submit_err);
-                return;
             }
+            return;
         }
+        downstream.conn_details = Some(payload.clone());
 
-        self.v2_conn_details = Some(payload.clone());
-        let mut configure = v1::messages::Configure::new();
-        configure
-            .add_feature(v1::messages::VersionRolling::new(
-                ii_stratum::BIP320_N_VERSION_MASK,
-                ii_stratum::BIP320_N_VERSION_MAX_BITS,
-            ))
-            .expect("addfeature failed"); // FIXME: how to handle errors from configure.add_feature() ?
+        match self.state {
+            V2ToV1TranslationState::Init => (),
+            // Mining configuration is already in progress for another downstream connection
+            V2ToV1TranslationState::V1Configure => {
+                self.v2_pending_setups.push(downstream_id);
+                return;
+            }
+            // The upstream session has already been configured for another downstream connection
+            _ => {
+                if let Err(submit_err) = self.send_setup_connection_success(downstream_id) {
+                    info!("Cannot submit SetupConnectionSuccess: {:?}", submit_err);
+                }
+                return;
+            }
+        }
 
-        let v1_configure_message = self.v1_method_into_message(
-            configure,
-            Self::handle_configure_result,
-            Self::handle_configure_error,
-        );
-        if let Err(submit_err) = util::submit_message(&mut self.v1_tx, v1_configure_message) {
+        if let Err(submit_err) = self.send_configure() {
             info!("Cannot submit mining.configure: {:?}", submit_err);
             return;
         }
-        self.state = V2ToV1TranslationState::V1Configure;
+        self.v2_pending_setups.push(downstream_id);
     }
 
     /// Opening a channel is a 2 stage process when translating to  V1 ii_stratum, where
@@ -1056,94 +2596,81 @@ impl v2::Handler for V2ToV1Translation {
             self.state,
             payload,
         );
-        if self.state != V2ToV1TranslationState::ConnectionSetup
-            && self.state != V2ToV1TranslationState::V1SubscribeOrAuthorizeFail
-        {
-            trace!(
-                "Out of sequence OpenStandardMiningChannel message, received: {:?}",
-                payload
-            );
-            let err_msg = v2::messages::OpenStandardMiningChannelError {
+        let pending_channel = V2PendingChannel {
+            downstream_id: self.v2_current_downstream,
+            open_msg: payload.clone(),
+            min_extranonce_size: None,
+            v1_user: payload.user.to_string(),
+        };
+        self.open_channel(pending_channel);
+    }
+
+    /// Extended channel is opened the same way as the standard one. It is not a member of the
+    /// group, it receives jobs with the coinbase transaction and rolls part of the extra nonce 2.
+    async fn visit_open_extended_mining_channel(
+        &mut self,
+        header: &v2::framing::Header,
+        payload: &v2::messages::OpenExtendedMiningChannel,
+    ) {
+        trace!(
+            "visit_open_extended_mining_channel() header={:x?} state={:?} payload:{:?}",
+            header,
+            self.state,
+            payload,
+        );
+        let pending_channel = V2PendingChannel {
+            downstream_id: self.v2_current_downstream,
+            open_msg: v2::messages::OpenStandardMiningChannel {
                 req_id: payload.req_id,
-                code: "Out of sequence OpenStandardMiningChannel msg"
-                    .try_into()
-                    .expect("BUG: incorrect error message"),
-            };
+                user: payload.user.clone(),
+                nominal_hashrate: payload.nominal_hashrate,
+                max_target: payload.max_target,
+            },
+            min_extranonce_size: Some(payload.min_extranonce_size),
+            v1_user: payload.user.to_string(),
+        };
+        self.open_channel(pending_channel);
+    }
 
-            if let Err(submit_err) = util::submit_message(&mut self.v2_tx, err_msg) {
+    /// Releases the channel, the upstream session is released along with the last channel unless
+    /// it is shared by multiple downstream connections
+    async fn visit_close_channel(
+        &mut self,
+        header: &v2::framing::Header,
+        payload: &v2::messages::CloseChannel,
+    ) {
+        trace!(
+            "visit_close_channel() header={:x?} state={:?} payload:{:?}",
+            header,
+            self.state,
+            payload,
+        );
+        match self.current_downstream_channel(payload.channel_id) {
+            Some(v2_channel) => {
+                self.v2_channels.remove(&payload.channel_id);
                 info!(
-                    "Cannot send OpenStandardMiningChannelError message: {:?}",
-                    submit_err
-                );
-                return;
-            }
-        }
-        // Connection details are present by now
-        if let Some(conn_details) = self.v2_conn_details.as_ref() {
-            self.v2_channel_details = Some(payload.clone());
-            self.state = V2ToV1TranslationState::OpenStandardMiningChannelPending;
-
-            let hostname: String = conn_details
-                .endpoint_host
-                .clone()
-                .try_into()
-                .expect("BUG: Cannot convert to string from connection details");
-
-            let hostname_port = format!("{}:{}", hostname, conn_details.endpoint_port);
-            let subscribe = v1::messages::Subscribe(
-                Some(conn_details.device.fw_ver.to_string()),
-                None,
-                Some(hostname_port),
-                None,
-            );
-
-            let v1_subscribe_message = self.v1_method_into_message(
-                subscribe,
-                Self::handle_subscribe_result,
-                Self::handle_authorize_or_subscribe_error,
-            );
-
-            if let Err(submit_err) = util::submit_message(&mut self.v1_tx, v1_subscribe_message) {
-                info!("Cannot send V1 mining.subscribe: {:?}", submit_err);
-                return;
-            }
-
-            if self.options.try_enable_xnsub {
-                let extranonce_subscribe = v1::messages::ExtranonceSubscribe();
-                let v1_extranonce_subscribe = self.v1_method_into_message(
-                    extranonce_subscribe,
-                    Self::handle_extranonce_subscribe_result,
-                    Self::handle_extranonce_subscribe_error,
+                    "Channel {} closed by {}: {}",
+                    payload.channel_id,
+                    v2_channel.user,
+                    payload.reason_code.to_string()
                 );
-                if let Err(submit_err) =
-                    util::submit_message(&mut self.v1_tx, v1_extranonce_subscribe)
-                {
-                    info!(
-                        "Cannot send V1 mining.extranonce_subscribe: {:?}",
-                        submit_err
-                    );
-                    return;
+                if self.v2_channels.is_empty() && !self.v2_shared {
+                    self.state = V2ToV1TranslationState::Closed;
                 }
             }
-
-            let authorize = v1::messages::Authorize(payload.user.to_string(), "".to_string());
-            let v1_authorize_message = self.v1_method_into_message(
-                authorize,
-                Self::handle_authorize_result,
-                Self::handle_authorize_or_subscribe_error,
-            );
-            if let Err(submit_err) = util::submit_message(&mut self.v1_tx, v1_authorize_message) {
-                info!("Cannot send V1 mining.authorized: {:?}", submit_err);
-                return;
-            }
+            None => info!(
+                "CloseChannel: unrecognized channel ID {}",
+                payload.channel_id
+            ),
         }
     }
 
     /// The flow of share processing is as follows:
     ///
     /// - find corresponding job
-    /// - verify the share that it meets the target (NOTE: currently not implemented)
-    /// - emit V1 Submit message
+    /// - verify that the share meets the target and that it is not a duplicate (only when
+    ///   enabled by `validate_shares` option)
+    /// - emit V1 Submit message and remember the share until the V1 result arrives
     ///
     /// If any of the above points fail, reply with SubmitShareError + reasoning
     async fn visit_submit_shares_standard(
@@ -1158,62 +2685,68 @@ impl v2::Handler for V2ToV1Translation {
             payload,
         );
         // Report invalid channel ID
-        if payload.channel_id != Self::CHANNEL_ID {
-            self.reject_shares(
-                payload,
-                format!("Unrecognized channel ID {}", payload.channel_id),
-            );
-            return;
-        }
-
-        // Channel details must be filled by now, anything else is a bug, unfortunately, due to
-        // the 'expect' we have to clone them. TODO review this code
-        let v2_channel_details = &self
-            .v2_channel_details
-            .clone()
-            .expect("Missing channel details");
-        // TODO this is only here as we want to prevent locking up 'self' into multiple closures
-        // and causing borrow checker complains
-        let v1_extra_nonce2_size = self.v1_extra_nonce2_size;
-
-        // Check job ID validity
-        let v1_submit_template = self
-            .v2_to_v1_job_map
-            .get(&payload.job_id)
-            // convert missing job ID (None) into an error
-            .ok_or(crate::error::ErrorKind::General(format!(
-                "V2 Job ID not present {} in registry",
-                payload.job_id
-            )))
-            .map(|tmpl| tmpl.clone());
-        // TODO validate the job (recalculate the hash and compare the target)
-        // Submit upstream V1 job based on the found job ID in the map
-        match v1_submit_template {
-            Ok(v1_submit_template) => {
-                let submit = v1::messages::Submit::new(
-                    v2_channel_details.user.to_string(),
-                    v1_submit_template.job_id.clone(),
-                    Self::channel_to_extra_nonce2_bytes(Self::CHANNEL_ID, v1_extra_nonce2_size)
-                        .as_ref(),
-                    payload.ntime,
-                    payload.nonce,
-                    // ensure the version bits in the template follow BIP320
-                    payload.version & ii_stratum::BIP320_N_VERSION_MASK,
+        let extra_nonce2 = match self.current_downstream_channel(payload.channel_id) {
+            Some(v2_channel) if v2_channel.extended.is_none() => {
+                Self::channel_to_extra_nonce2_bytes(payload.channel_id, self.v1_extra_nonce2_size)
+            }
+            _ => {
+                self.reject_shares(
+                    payload,
+                    format!("Unrecognized channel ID {}", payload.channel_id),
                 );
-                // Convert the method into a message + provide handling methods
-                let v1_submit_message = self.v1_method_into_message(
-                    submit,
-                    Self::handle_submit_result,
-                    Self::handle_submit_error,
+                return;
+            }
+        };
+        self.submit_share(payload, extra_nonce2.as_ref());
+    }
+
+    /// Extended share is processed the same way as the standard one, the extra nonce 2 is
+    /// composed of the extranonce rolled by the channel and the suffix that identifies the channel
+    async fn visit_submit_shares_extended(
+        &mut self,
+        header: &v2::framing::Header,
+        payload: &v2::messages::SubmitSharesExtended,
+    ) {
+        trace!(
+            "visit_submit_shares_extended() header={:x?} state={:?} payload:{:?}",
+            header,
+            self.state,
+            payload,
+        );
+        let share = v2::messages::SubmitSharesStandard {
+            channel_id: payload.channel_id,
+            seq_num: payload.seq_num,
+            job_id: payload.job_id,
+            nonce: payload.nonce,
+            ntime: payload.ntime,
+            version: payload.version,
+        };
+        let extended = match self.current_downstream_channel(payload.channel_id) {
+            Some(V2Channel {
+                extended: Some(extended),
+                ..
+            }) => extended,
+            _ => {
+                self.reject_shares(
+                    &share,
+                    format!("Unrecognized channel ID {}", payload.channel_id),
                 );
-                if let Err(submit_err) = util::submit_message(&mut self.v1_tx, v1_submit_message) {
-                    info!(
-                        "SubmitSharesStandard: cannot send translated V1 message: {:?}",
-                        submit_err
-                    );
-                }
+                return;
             }
-            Err(e) => self.reject_shares(payload, format!("{}", e)),
+        };
+        if payload.extranonce.len() != extended.extranonce_size {
+            self.reject_shares(&share, "Invalid extranonce size".to_string());
+            return;
         }
+        let extra_nonce2_suffix = match self.extended_extra_nonce2_suffix(extended) {
+            Some(extra_nonce2_suffix) => extra_nonce2_suffix,
+            None => {
+                self.reject_shares(&share, "Extranonce size changed".to_string());
+                return;
+            }
+        };
+        let mut extra_nonce2 = payload.extranonce.to_vec();
+        extra_nonce2.extend_from_slice(&extra_nonce2_suffix);
+        self.submit_share(&share, &extra_nonce2);
     }
 }
@@ -21,6 +21,7 @@
 // contact us at opensource@braiins.com.
 
 use futures::stream::StreamExt;
+use std::str::FromStr;
 
 use ii_async_compat::tokio;
 
@@ -42,6 +43,22 @@ where
     msg.accept(translation).await;
 }
 
+/// Simulates incoming message from downstream connection `downstream_id` of a shared translation
+async fn v2_simulate_downstream_message<M>(
+    translation: &mut V2ToV1Translation,
+    downstream_id: DownstreamId,
+    message: M,
+) where
+    M: TryInto<v2::Frame, Error = ii_stratum::error::Error>,
+{
+    let frame: v2::Frame = message.try_into().expect("Could not serialize message");
+
+    let msg = v2::build_message_from_frame(frame).expect("Deserialization failed");
+    translation
+        .accept_downstream_message(downstream_id, msg)
+        .await;
+}
+
 async fn v1_simulate_incoming_message<M>(translation: &mut V2ToV1Translation, message: M)
 where
     M: TryInto<v1::Frame, Error = ii_stratum::error::Error>,
@@ -121,8 +138,13 @@ async fn test_setup_connection_translate() {
         test_utils::v1::build_set_difficulty_request_message(),
     )
     .await;
-    // Now we should have a successfully open channel
-    v2_verify_generated_response_message(&mut v2_rx).await;
+    // Now we should have a successfully open channel that is a member of the group channel
+    let success_msg: v2::messages::OpenStandardMiningChannelSuccess =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(
+        success_msg,
+        test_utils::v2::build_open_channel_success_with_group()
+    );
 
     v1_simulate_incoming_message(
         &mut translation,
@@ -131,13 +153,20 @@ async fn test_setup_connection_translate() {
     .await;
     // Expect NewMiningJob
     v2_verify_generated_response_message(&mut v2_rx).await;
-    // Expect SetNewPrevHash
-    v2_verify_generated_response_message(&mut v2_rx).await;
+    // Expect SetNewPrevHash broadcast to the group channel
+    let prev_hash_msg: v2::messages::SetNewPrevHash =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(
+        prev_hash_msg,
+        test_utils::v2::build_set_new_prev_hash_for_group()
+    );
     // Ensure that the V1 job has been registered
     let submit_template = V1SubmitTemplate {
         job_id: v1::messages::JobId::from_str(&test_utils::v1::MINING_NOTIFY_JOB_ID),
         time: test_utils::common::MINING_WORK_NTIME,
         version: test_utils::common::MINING_WORK_VERSION,
+        notify: test_utils::v1::build_mining_notify(),
+        submitted_shares: HashSet::new(),
     };
 
     let registered_submit_template = translation
@@ -165,6 +194,799 @@ async fn test_setup_connection_translate() {
     // });
 }
 
+/// Builds V1 response message with `true` result
+fn v1_build_ok_response_message(id: u32) -> v1::rpc::Rpc {
+    v1::rpc::Rpc::from_str(&format!(r#"{{"id":{},"error":null,"result":true}}"#, id))
+        .expect("Cannot parse response")
+}
+
+/// Opens the first channel the same way as in `test_setup_connection_translate` and delivers
+/// the first job to it
+async fn v2_open_operational_channel(
+    translation: &mut V2ToV1Translation,
+    v1_rx: &mut mpsc::Receiver<v1::Frame>,
+    v2_rx: &mut mpsc::Receiver<v2::Frame>,
+) {
+    v2_simulate_incoming_message(translation, test_utils::v2::build_setup_connection()).await;
+    v1_verify_generated_response_message(v1_rx).await;
+    v1_simulate_incoming_message(
+        translation,
+        test_utils::v1::build_configure_ok_response_message(),
+    )
+    .await;
+    v2_verify_generated_response_message(v2_rx).await;
+    v2_simulate_incoming_message(translation, test_utils::v2::build_open_channel()).await;
+    v1_verify_generated_response_message(v1_rx).await;
+    v1_verify_generated_response_message(v1_rx).await;
+    v1_simulate_incoming_message(
+        translation,
+        test_utils::v1::build_subscribe_ok_response_message(),
+    )
+    .await;
+    v1_simulate_incoming_message(
+        translation,
+        test_utils::v1::build_authorize_ok_response_message(),
+    )
+    .await;
+    v1_simulate_incoming_message(
+        translation,
+        test_utils::v1::build_set_difficulty_request_message(),
+    )
+    .await;
+    let success_msg: v2::messages::OpenStandardMiningChannelSuccess =
+        test_utils::v2::receive_message(v2_rx).await;
+    assert_eq!(
+        success_msg,
+        test_utils::v2::build_open_channel_success_with_group()
+    );
+    v1_simulate_incoming_message(
+        translation,
+        test_utils::v1::build_mining_notify_request_message(),
+    )
+    .await;
+    v2_verify_generated_response_message(v2_rx).await;
+    let prev_hash_msg: v2::messages::SetNewPrevHash = test_utils::v2::receive_message(v2_rx).await;
+    assert_eq!(
+        prev_hash_msg,
+        test_utils::v2::build_set_new_prev_hash_for_group()
+    );
+}
+
+/// Verifies that additional standard channels join the group channel of the V2 connection and
+/// that V1 jobs are fanned out to all of them while the new prevhash is sent only once
+#[tokio::test]
+async fn test_group_channel_job_fan_out() {
+    let (v1_tx, mut v1_rx) = mpsc::channel(10);
+    let (v2_tx, mut v2_rx) = mpsc::channel(10);
+    let mut translation = V2ToV1Translation::new(v1_tx, v2_tx, Default::default());
+
+    v2_open_operational_channel(&mut translation, &mut v1_rx, &mut v2_rx).await;
+
+    // Open the second channel, only authorize is needed for the upstream
+    let mut open_channel = test_utils::v2::build_open_channel();
+    open_channel.req_id = 11;
+    open_channel.user = "braiins.worker1".try_into().unwrap();
+    v2_simulate_incoming_message(&mut translation, open_channel).await;
+    let authorize_frame = v1_rx.next().await.expect("Missing V1 authorize");
+    let authorize = match v1::rpc::Rpc::try_from(authorize_frame).expect("Invalid V1 frame") {
+        v1::rpc::Rpc::Request(request) => {
+            assert_eq!(request.id, Some(3), "Unexpected V1 request ID");
+            v1::messages::Authorize::try_from(request).expect("Not an authorize request")
+        }
+        _ => panic!("V1 request expected"),
+    };
+    assert_eq!(authorize.name(), "braiins.worker1");
+    v1_simulate_incoming_message(&mut translation, v1_build_ok_response_message(3)).await;
+
+    let open_success: v2::messages::OpenStandardMiningChannelSuccess =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(open_success.req_id, 11);
+    assert_eq!(open_success.channel_id, 1);
+    assert_eq!(
+        open_success.group_channel_id,
+        test_utils::v2::GROUP_CHANNEL_ID
+    );
+    // The new channel immediately receives the current job
+    let job: v2::messages::NewMiningJob = test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!((job.channel_id, job.job_id), (1, 0));
+    let prev_hash: v2::messages::SetNewPrevHash = test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!((prev_hash.channel_id, prev_hash.job_id), (1, 0));
+
+    // Next V1 job results in one job per channel, each with a distinct merkle root, and a single
+    // prevhash for the whole group
+    v1_simulate_incoming_message(
+        &mut translation,
+        test_utils::v1::build_mining_notify_request_message(),
+    )
+    .await;
+    let job0: v2::messages::NewMiningJob = test_utils::v2::receive_message(&mut v2_rx).await;
+    let job1: v2::messages::NewMiningJob = test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!((job0.channel_id, job0.job_id), (0, 1));
+    assert_eq!((job1.channel_id, job1.job_id), (1, 1));
+    assert_ne!(job0.merkle_root, job1.merkle_root);
+    let prev_hash: v2::messages::SetNewPrevHash = test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(
+        (prev_hash.channel_id, prev_hash.job_id),
+        (test_utils::v2::GROUP_CHANNEL_ID, 1)
+    );
+
+    // Share from the second channel is submitted with its user and extra nonce 2
+    let mut submit_shares = test_utils::v2::build_submit_shares();
+    submit_shares.channel_id = 1;
+    submit_shares.job_id = 1;
+    v2_simulate_incoming_message(&mut translation, submit_shares).await;
+    let submit_frame = v1_rx.next().await.expect("Missing V1 submit");
+    let submit = match v1::rpc::Rpc::try_from(submit_frame).expect("Invalid V1 frame") {
+        v1::rpc::Rpc::Request(request) => {
+            v1::messages::Submit::try_from(request).expect("Not a submit request")
+        }
+        _ => panic!("V1 request expected"),
+    };
+    assert_eq!(submit.user_name(), "braiins.worker1");
+    assert_eq!(submit.extra_nonce_2(), &[1, 0, 0, 0]);
+    v1_simulate_incoming_message(&mut translation, v1_build_ok_response_message(4)).await;
+    let submit_success: v2::messages::SubmitSharesSuccess =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(submit_success.channel_id, 1);
+}
+
+/// Verifies that an extended channel gets the extranonce of the upstream session, receives jobs
+/// with the coinbase and that its shares are submitted with the rolled extranonce followed by the
+/// channel tag
+#[tokio::test]
+async fn test_extended_channel() {
+    let (v1_tx, mut v1_rx) = mpsc::channel(10);
+    let (v2_tx, mut v2_rx) = mpsc::channel(10);
+    let mut translation = V2ToV1Translation::new(v1_tx, v2_tx, Default::default());
+
+    v2_open_operational_channel(&mut translation, &mut v1_rx, &mut v2_rx).await;
+
+    // The upstream extra nonce 2 has 4 bytes, the last one is reserved for the channel tag
+    let mut open_channel = test_utils::v2::build_open_extended_channel();
+    open_channel.min_extranonce_size = 4;
+    v2_simulate_incoming_message(&mut translation, open_channel.clone()).await;
+    v1_rx.next().await.expect("Missing V1 authorize");
+    v1_simulate_incoming_message(&mut translation, v1_build_ok_response_message(3)).await;
+    let open_error: v2::messages::OpenExtendedMiningChannelError =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(open_error.req_id, open_channel.req_id);
+
+    open_channel.min_extranonce_size = 3;
+    v2_simulate_incoming_message(&mut translation, open_channel.clone()).await;
+    v1_rx.next().await.expect("Missing V1 authorize");
+    v1_simulate_incoming_message(&mut translation, v1_build_ok_response_message(4)).await;
+    let open_success: v2::messages::OpenExtendedMiningChannelSuccess =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(open_success.req_id, open_channel.req_id);
+    assert_eq!(open_success.channel_id, 1);
+    assert_eq!(open_success.extranonce_size, 3);
+    assert_eq!(
+        open_success.extranonce_prefix.as_ref(),
+        &[0x6c, 0x6f, 0x01, 0x00, 0x00, 0x00, 0x0c]
+    );
+
+    // The current job is delivered along with the coinbase, the suffix starts with the tag
+    let v1_job = test_utils::v1::build_mining_notify();
+    let job: v2::messages::NewExtendedMiningJob = test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!((job.channel_id, job.job_id), (1, 0));
+    assert_eq!(job.coinbase_tx_prefix.as_ref(), v1_job.coin_base_1());
+    assert_eq!(job.coinbase_tx_suffix[0], 1);
+    assert_eq!(&job.coinbase_tx_suffix[1..], v1_job.coin_base_2());
+    let prev_hash: v2::messages::SetNewPrevHash = test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!((prev_hash.channel_id, prev_hash.job_id), (1, 0));
+
+    // The next job is sent to both channels, the extended channel receives its own prevhash
+    v1_simulate_incoming_message(
+        &mut translation,
+        test_utils::v1::build_mining_notify_request_message(),
+    )
+    .await;
+    let job0: v2::messages::NewMiningJob = test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!((job0.channel_id, job0.job_id), (0, 1));
+    let job1: v2::messages::NewExtendedMiningJob =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!((job1.channel_id, job1.job_id), (1, 1));
+    let prev_hash: v2::messages::SetNewPrevHash = test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!((prev_hash.channel_id, prev_hash.job_id), (1, 1));
+    let prev_hash: v2::messages::SetNewPrevHash = test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(
+        (prev_hash.channel_id, prev_hash.job_id),
+        (test_utils::v2::GROUP_CHANNEL_ID, 1)
+    );
+
+    // Standard share is not accepted on the extended channel
+    let mut submit_shares = test_utils::v2::build_submit_shares();
+    submit_shares.channel_id = 1;
+    submit_shares.job_id = 1;
+    v2_simulate_incoming_message(&mut translation, submit_shares).await;
+    let submit_error: v2::messages::SubmitSharesError =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(submit_error.channel_id, 1);
+
+    let mut submit_shares = test_utils::v2::build_submit_shares_extended();
+    submit_shares.seq_num = 1;
+    submit_shares.job_id = 1;
+    submit_shares.extranonce = Bytes0_32::from_slice(&[0xaa, 0xbb, 0xcc]);
+    v2_simulate_incoming_message(&mut translation, submit_shares).await;
+    let submit_frame = v1_rx.next().await.expect("Missing V1 submit");
+    let submit = match v1::rpc::Rpc::try_from(submit_frame).expect("Invalid V1 frame") {
+        v1::rpc::Rpc::Request(request) => {
+            v1::messages::Submit::try_from(request).expect("Not a submit request")
+        }
+        _ => panic!("V1 request expected"),
+    };
+    assert_eq!(submit.extra_nonce_2(), &[0xaa, 0xbb, 0xcc, 0x01]);
+    v1_simulate_incoming_message(&mut translation, v1_build_ok_response_message(5)).await;
+    let submit_success: v2::messages::SubmitSharesSuccess =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(
+        (submit_success.channel_id, submit_success.last_seq_num),
+        (1, 1)
+    );
+}
+
+/// Verifies that closing the last channel marks the translation as closed and that a late V1
+/// response for the closed channel is not translated
+#[tokio::test]
+async fn test_close_channel() {
+    let (v1_tx, mut v1_rx) = mpsc::channel(10);
+    let (v2_tx, mut v2_rx) = mpsc::channel(10);
+    let mut translation = V2ToV1Translation::new(v1_tx, v2_tx, Default::default());
+
+    v2_open_operational_channel(&mut translation, &mut v1_rx, &mut v2_rx).await;
+    v2_simulate_incoming_message(&mut translation, test_utils::v2::build_submit_shares()).await;
+    v1_verify_generated_response_message(&mut v1_rx).await;
+
+    v2_simulate_incoming_message(&mut translation, test_utils::v2::build_close_channel()).await;
+    assert!(translation.is_closed(), "Translation not closed");
+
+    v1_simulate_incoming_message(
+        &mut translation,
+        test_utils::v1::build_mining_submit_ok_response_message(),
+    )
+    .await;
+    assert!(
+        v2_rx.try_next().is_err(),
+        "No message expected for closed channel"
+    );
+}
+
+/// Verifies that V1 results are paired with the original shares via the V1 request ID even if
+/// the upstream answers out of order
+#[tokio::test]
+async fn test_submit_results_pairing() {
+    let (v1_tx, mut v1_rx) = mpsc::channel(10);
+    let (v2_tx, mut v2_rx) = mpsc::channel(10);
+    let mut translation = V2ToV1Translation::new(v1_tx, v2_tx, Default::default());
+
+    v2_open_operational_channel(&mut translation, &mut v1_rx, &mut v2_rx).await;
+    for seq_num in 5..7 {
+        let mut submit_shares = test_utils::v2::build_submit_shares();
+        submit_shares.seq_num = seq_num;
+        v2_simulate_incoming_message(&mut translation, submit_shares).await;
+        v1_rx.next().await.expect("Missing V1 submit");
+    }
+
+    // The second share (V1 request ID 4) is rejected first
+    v1_simulate_incoming_message(
+        &mut translation,
+        v1::rpc::Rpc::from_str(
+            r#"{"id":4,"error":[23,"Low difficulty share",null],"result":null}"#,
+        )
+        .expect("Cannot parse response"),
+    )
+    .await;
+    let submit_error: v2::messages::SubmitSharesError =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!((submit_error.channel_id, submit_error.seq_num), (0, 6));
+    assert_eq!(
+        submit_error.code.to_string(),
+        V2ToV1Translation::LOW_DIFFICULTY_ERROR_CODE
+    );
+
+    v1_simulate_incoming_message(&mut translation, v1_build_ok_response_message(3)).await;
+    let submit_success: v2::messages::SubmitSharesSuccess =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(submit_success.channel_id, 0);
+    assert_eq!(submit_success.last_seq_num, 5);
+    assert_eq!(submit_success.new_submits_accepted_count, 1);
+    // Difficulty of the test upstream
+    assert_eq!(submit_success.new_shares_sum, 4);
+    assert!(translation.v1_pending_submits.is_empty());
+}
+
+/// Verifies that V1 share rejections are translated into error codes of SubmitSharesError
+#[test]
+fn test_submit_error_code() {
+    assert_eq!(
+        V2ToV1Translation::submit_error_code(21),
+        V2ToV1Translation::STALE_SHARE_ERROR_CODE
+    );
+    assert_eq!(
+        V2ToV1Translation::submit_error_code(22),
+        V2ToV1Translation::DUPLICATE_SHARE_ERROR_CODE
+    );
+    assert_eq!(
+        V2ToV1Translation::submit_error_code(23),
+        V2ToV1Translation::LOW_DIFFICULTY_ERROR_CODE
+    );
+    assert_eq!(
+        V2ToV1Translation::submit_error_code(20),
+        V2ToV1Translation::SHARE_REJECTED_ERROR_CODE
+    );
+}
+
+/// Verifies that shares without V1 result are reported as rejected after timeout and that the
+/// late result is ignored
+#[tokio::test]
+async fn test_submit_timeout() {
+    let (v1_tx, mut v1_rx) = mpsc::channel(10);
+    let (v2_tx, mut v2_rx) = mpsc::channel(10);
+    let mut translation = V2ToV1Translation::new(v1_tx, v2_tx, Default::default());
+
+    v2_open_operational_channel(&mut translation, &mut v1_rx, &mut v2_rx).await;
+    let mut submit_shares = test_utils::v2::build_submit_shares();
+    submit_shares.seq_num = 7;
+    v2_simulate_incoming_message(&mut translation, submit_shares).await;
+    v1_verify_generated_response_message(&mut v1_rx).await;
+
+    // Nothing is reported before the timeout elapses
+    translation.report_timed_out_submits(time::Instant::now());
+    assert!(v2_rx.try_next().is_err(), "Unexpected message");
+
+    translation
+        .report_timed_out_submits(time::Instant::now() + V2ToV1Translation::V1_SUBMIT_TIMEOUT);
+    let submit_error: v2::messages::SubmitSharesError =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!((submit_error.channel_id, submit_error.seq_num), (0, 7));
+    assert_eq!(
+        submit_error.code.to_string(),
+        V2ToV1Translation::SUBMIT_TIMEOUT_ERROR_CODE
+    );
+
+    v1_simulate_incoming_message(&mut translation, v1_build_ok_response_message(3)).await;
+    assert!(
+        v2_rx.try_next().is_err(),
+        "No message expected for timed out submit"
+    );
+}
+
+/// Verifies that shares which don't meet the channel target and duplicate shares are rejected
+/// without being submitted upstream
+#[tokio::test]
+async fn test_validate_shares() {
+    let (v1_tx, mut v1_rx) = mpsc::channel(10);
+    let (v2_tx, mut v2_rx) = mpsc::channel(10);
+    let options = V2ToV1TranslationOptions {
+        validate_shares: true,
+        ..Default::default()
+    };
+    let mut translation = V2ToV1Translation::new(v1_tx, v2_tx, options);
+
+    v2_open_operational_channel(&mut translation, &mut v1_rx, &mut v2_rx).await;
+    let mut submit_shares = test_utils::v2::build_submit_shares();
+    submit_shares.seq_num = 1;
+    v2_simulate_incoming_message(&mut translation, submit_shares.clone()).await;
+    let submit_error: v2::messages::SubmitSharesError =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!((submit_error.channel_id, submit_error.seq_num), (0, 1));
+    assert_eq!(
+        submit_error.code.to_string(),
+        V2ToV1Translation::LOW_DIFFICULTY_ERROR_CODE
+    );
+    assert!(v1_rx.try_next().is_err(), "Unexpected V1 submit");
+
+    // Any hash meets the maximum target, the share has already been seen, though
+    translation.v2_target = Some(uint::U256::MAX);
+    submit_shares.seq_num = 2;
+    v2_simulate_incoming_message(&mut translation, submit_shares.clone()).await;
+    let submit_error: v2::messages::SubmitSharesError =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(
+        submit_error.code.to_string(),
+        V2ToV1Translation::DUPLICATE_SHARE_ERROR_CODE
+    );
+    assert!(v1_rx.try_next().is_err(), "Unexpected V1 submit");
+
+    submit_shares.seq_num = 3;
+    submit_shares.nonce += 1;
+    v2_simulate_incoming_message(&mut translation, submit_shares).await;
+    v1_rx.next().await.expect("Missing V1 submit");
+    assert!(v2_rx.try_next().is_err(), "Unexpected V2 message");
+}
+
+/// Verifies that difficulty of an idle channel with variable difficulty is lowered and that
+/// the shares are credited with the channel difficulty
+#[tokio::test]
+async fn test_vardiff_retarget() {
+    let (v1_tx, mut v1_rx) = mpsc::channel(10);
+    let (v2_tx, mut v2_rx) = mpsc::channel(10);
+    let vardiff_options = VarDiffOptions::default();
+    let options = V2ToV1TranslationOptions {
+        vardiff: Some(vardiff_options),
+        ..Default::default()
+    };
+    let mut translation = V2ToV1Translation::new(v1_tx, v2_tx, options);
+
+    v2_open_operational_channel(&mut translation, &mut v1_rx, &mut v2_rx).await;
+    let now = time::Instant::now();
+    translation.retarget_channels(now);
+    assert!(v2_rx.try_next().is_err(), "Unexpected message");
+
+    // Difficulty of the test upstream is 4, it is lowered by the maximum adjustment
+    translation.retarget_channels(now + vardiff_options.retarget_interval);
+    let set_target: v2::messages::SetTarget = test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(set_target.channel_id, 0);
+    assert_eq!(
+        set_target.max_target,
+        Uint256Bytes::from(V2ToV1Translation::DIFF1_TARGET / 1)
+    );
+
+    // The share meets neither of the targets, hence it is rejected before reaching upstream
+    v2_simulate_incoming_message(&mut translation, test_utils::v2::build_submit_shares()).await;
+    let submit_error: v2::messages::SubmitSharesError =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(
+        submit_error.code.to_string(),
+        V2ToV1Translation::LOW_DIFFICULTY_ERROR_CODE
+    );
+    assert!(v1_rx.try_next().is_err(), "Unexpected V1 submit");
+}
+
+fn v1_build_set_difficulty_message(difficulty: f32) -> v1::rpc::Rpc {
+    v1::rpc::Rpc::from_str(&format!(
+        r#"{{"id":null,"method":"mining.set_difficulty","params":[{}]}}"#,
+        difficulty
+    ))
+    .expect("Cannot parse set difficulty")
+}
+
+/// Verifies that fractional upstream difficulty is rounded up to 1 instead of panicking
+#[tokio::test]
+async fn test_fractional_upstream_difficulty() {
+    let (v1_tx, mut v1_rx) = mpsc::channel(10);
+    let (v2_tx, mut v2_rx) = mpsc::channel(10);
+    let mut translation = V2ToV1Translation::new(v1_tx, v2_tx, Default::default());
+
+    v2_open_operational_channel(&mut translation, &mut v1_rx, &mut v2_rx).await;
+    v1_simulate_incoming_message(&mut translation, v1_build_set_difficulty_message(0.5)).await;
+    let set_target: v2::messages::SetTarget = test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(set_target.channel_id, 0);
+    assert_eq!(
+        set_target.max_target,
+        Uint256Bytes::from(V2ToV1Translation::DIFF1_TARGET / 1)
+    );
+}
+
+/// Verifies that channels with variable difficulty ignore changes of the upstream difficulty
+/// and that their difficulty may exceed the upstream difficulty
+#[tokio::test]
+async fn test_vardiff_above_upstream_difficulty() {
+    let (v1_tx, mut v1_rx) = mpsc::channel(10);
+    let (v2_tx, mut v2_rx) = mpsc::channel(10);
+    let vardiff_options = VarDiffOptions::default();
+    let options = V2ToV1TranslationOptions {
+        vardiff: Some(vardiff_options),
+        ..Default::default()
+    };
+    let mut translation = V2ToV1Translation::new(v1_tx, v2_tx, options);
+
+    v2_open_operational_channel(&mut translation, &mut v1_rx, &mut v2_rx).await;
+    v1_simulate_incoming_message(&mut translation, v1_build_set_difficulty_message(1.0)).await;
+    assert!(v2_rx.try_next().is_err(), "Unexpected message");
+    assert_eq!(translation.v1_difficulty, 1);
+    assert_eq!(
+        translation.channel_target(0),
+        Some(V2ToV1Translation::DIFF1_TARGET / 4)
+    );
+
+    // Flood of shares raises the difficulty by the maximum adjustment
+    for _ in 0..40 {
+        translation.record_vardiff_share(0);
+    }
+    let set_target: v2::messages::SetTarget = test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(set_target.channel_id, 0);
+    assert_eq!(
+        set_target.max_target,
+        Uint256Bytes::from(V2ToV1Translation::DIFF1_TARGET / 16)
+    );
+}
+
+/// Verifies that all downstream channels are closed when the upstream session is lost
+#[tokio::test]
+async fn test_close_all_channels() {
+    let (v1_tx, mut v1_rx) = mpsc::channel(10);
+    let (v2_tx, mut v2_rx) = mpsc::channel(10);
+    let mut translation = V2ToV1Translation::new(v1_tx, v2_tx, Default::default());
+
+    v2_open_operational_channel(&mut translation, &mut v1_rx, &mut v2_rx).await;
+    translation.close_all_channels(V2ToV1Translation::UPSTREAM_LOST_REASON);
+
+    let close_channel: v2::messages::CloseChannel =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(close_channel.channel_id, 0);
+    assert_eq!(
+        close_channel.reason_code.to_string(),
+        V2ToV1Translation::UPSTREAM_LOST_REASON
+    );
+    assert!(translation.is_closed(), "Translation not closed");
+}
+
+/// Verifies that channels of multiple downstream connections share a single upstream session and
+/// that all messages are routed to the downstream connection that owns the channel
+#[tokio::test]
+async fn test_shared_session() {
+    let (v1_tx, mut v1_rx) = mpsc::channel(10);
+    let (v2_tx, mut v2_rx) = mpsc::channel(10);
+    let (v2_tx_other, mut v2_rx_other) = mpsc::channel(10);
+    let mut translation = V2ToV1Translation::new_shared(v1_tx, Default::default());
+    translation.add_downstream(V2ToV1Translation::DEFAULT_DOWNSTREAM_ID, v2_tx);
+    translation.add_downstream(1, v2_tx_other);
+
+    v2_open_operational_channel(&mut translation, &mut v1_rx, &mut v2_rx).await;
+
+    // The upstream session has been configured already
+    v2_simulate_downstream_message(
+        &mut translation,
+        1,
+        test_utils::v2::build_setup_connection(),
+    )
+    .await;
+    let _: v2::messages::SetupConnectionSuccess =
+        test_utils::v2::receive_message(&mut v2_rx_other).await;
+    assert!(v1_rx.try_next().is_err(), "Unexpected upstream request");
+
+    // Only authorize is needed for joining the shared session
+    let mut open_channel = test_utils::v2::build_open_channel();
+    open_channel.req_id = 11;
+    v2_simulate_downstream_message(&mut translation, 1, open_channel).await;
+    v1_rx.next().await.expect("Missing V1 authorize");
+    v1_simulate_incoming_message(&mut translation, v1_build_ok_response_message(3)).await;
+    let open_success: v2::messages::OpenStandardMiningChannelSuccess =
+        test_utils::v2::receive_message(&mut v2_rx_other).await;
+    assert_eq!((open_success.req_id, open_success.channel_id), (11, 1));
+    let _: v2::messages::NewMiningJob = test_utils::v2::receive_message(&mut v2_rx_other).await;
+    let _: v2::messages::SetNewPrevHash = test_utils::v2::receive_message(&mut v2_rx_other).await;
+
+    // Each downstream receives the job for its channel and the group prevhash
+    v1_simulate_incoming_message(
+        &mut translation,
+        test_utils::v1::build_mining_notify_request_message(),
+    )
+    .await;
+    let job: v2::messages::NewMiningJob = test_utils::v2::receive_message(&mut v2_rx).await;
+    let job_other: v2::messages::NewMiningJob =
+        test_utils::v2::receive_message(&mut v2_rx_other).await;
+    assert_eq!((job.channel_id, job_other.channel_id), (0, 1));
+    assert_ne!(job.merkle_root, job_other.merkle_root);
+    for rx in [&mut v2_rx, &mut v2_rx_other].iter_mut() {
+        let prev_hash: v2::messages::SetNewPrevHash = test_utils::v2::receive_message(rx).await;
+        assert_eq!(prev_hash.channel_id, test_utils::v2::GROUP_CHANNEL_ID);
+    }
+
+    // The channel cannot be closed by a foreign downstream connection
+    let mut close_channel = test_utils::v2::build_close_channel();
+    close_channel.channel_id = 1;
+    v2_simulate_downstream_message(
+        &mut translation,
+        V2ToV1Translation::DEFAULT_DOWNSTREAM_ID,
+        close_channel,
+    )
+    .await;
+    assert!(translation.v2_channels.contains_key(&1));
+
+    // Removing the downstream connection releases its channels but keeps the session open
+    translation.remove_downstream(1);
+    assert!(!translation.v2_channels.contains_key(&1));
+    v2_simulate_downstream_message(
+        &mut translation,
+        V2ToV1Translation::DEFAULT_DOWNSTREAM_ID,
+        test_utils::v2::build_close_channel(),
+    )
+    .await;
+    assert!(translation.v2_channels.is_empty());
+    assert!(!translation.is_closed(), "Shared session closed");
+}
+
+/// Builds V1 response from test response `json` with its ID replaced by `id`
+fn v1_build_response_message_with_id(json: &str, id: u32) -> v1::rpc::Rpc {
+    let json = json.replacen(r#""id":0"#, &format!(r#""id":{}"#, id), 1);
+    let json = json.replacen(r#""id":1"#, &format!(r#""id":{}"#, id), 1);
+    v1::rpc::Rpc::from_str(&json).expect("Cannot parse response")
+}
+
+/// Verifies that an operational channel is moved onto a new upstream session without being
+/// reopened and that the share waiting for the result from the lost upstream is rejected
+#[tokio::test]
+async fn test_switch_upstream() {
+    let (v1_tx, mut v1_rx) = mpsc::channel(10);
+    let (v2_tx, mut v2_rx) = mpsc::channel(10);
+    let mut translation = V2ToV1Translation::new(v1_tx, v2_tx, Default::default());
+
+    v2_open_operational_channel(&mut translation, &mut v1_rx, &mut v2_rx).await;
+    v2_simulate_incoming_message(&mut translation, test_utils::v2::build_submit_shares()).await;
+    v1_verify_generated_response_message(&mut v1_rx).await;
+
+    let (v1_tx, mut v1_rx) = mpsc::channel(10);
+    translation.switch_upstream(v1_tx, "127.0.0.1:3334".parse().expect("Invalid address"));
+    let submit_error: v2::messages::SubmitSharesError =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(submit_error.channel_id, 0);
+    assert_eq!(
+        submit_error.code.to_string(),
+        V2ToV1Translation::UPSTREAM_LOST_REASON
+    );
+
+    // The new upstream is configured, subscribed and authorized for the existing channel
+    v1_rx.next().await.expect("Missing V1 configure");
+    v1_simulate_incoming_message(
+        &mut translation,
+        v1_build_response_message_with_id(test_utils::v1::MINING_CONFIGURE_OK_RESP_JSON, 4),
+    )
+    .await;
+    v1_rx.next().await.expect("Missing V1 subscribe");
+    v1_rx.next().await.expect("Missing V1 authorize");
+    v1_simulate_incoming_message(
+        &mut translation,
+        v1_build_response_message_with_id(test_utils::v1::MINING_SUBSCRIBE_OK_RESULT_JSON, 5),
+    )
+    .await;
+    v1_simulate_incoming_message(&mut translation, v1_build_ok_response_message(6)).await;
+    assert!(
+        v2_rx.try_next().is_err(),
+        "No message expected before the target is known"
+    );
+
+    // The channel receives the target of the new upstream instead of being reopened
+    v1_simulate_incoming_message(
+        &mut translation,
+        test_utils::v1::build_set_difficulty_request_message(),
+    )
+    .await;
+    let set_target: v2::messages::SetTarget = test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(set_target.channel_id, 0);
+
+    v1_simulate_incoming_message(
+        &mut translation,
+        test_utils::v1::build_mining_notify_request_message(),
+    )
+    .await;
+    let job: v2::messages::NewMiningJob = test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!((job.channel_id, job.job_id), (0, 1));
+    let prev_hash: v2::messages::SetNewPrevHash = test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(prev_hash.job_id, 1);
+
+    // Shares of the channel are submitted to the new upstream
+    let mut submit_shares = test_utils::v2::build_submit_shares();
+    submit_shares.job_id = 1;
+    v2_simulate_incoming_message(&mut translation, submit_shares).await;
+    v1_rx.next().await.expect("Missing V1 submit");
+    assert!(!translation.is_closed(), "Translation closed");
+}
+
+/// Builds translation options with routing rules that rewrite the test user and route users
+/// of the farm to other upstream servers
+fn build_routing_options() -> V2ToV1TranslationOptions {
+    let routing_rules = RoutingRules::try_from(
+        r#"
+        [[rule]]
+        user_prefix = "braiins."
+        account = "account"
+
+        [[rule]]
+        user_regex = '^farm-(\w+)$'
+        account = "farm"
+        worker = "$1"
+        upstream = ["pool.example.com:3333"]
+        "#
+        .to_string(),
+    )
+    .expect("BUG: cannot parse routing rules");
+    V2ToV1TranslationOptions {
+        routing_rules: Some(Arc::new(routing_rules)),
+        ..Default::default()
+    }
+}
+
+/// Receives the next V1 request generated by the translation and checks that it is an authorize
+/// request of `user`
+async fn v1_verify_authorize(v1_rx: &mut mpsc::Receiver<v1::Frame>, user: &str) {
+    let authorize_frame = v1_rx.next().await.expect("Missing V1 authorize");
+    let authorize = match v1::rpc::Rpc::try_from(authorize_frame).expect("Invalid V1 frame") {
+        v1::rpc::Rpc::Request(request) => {
+            v1::messages::Authorize::try_from(request).expect("Not an authorize request")
+        }
+        _ => panic!("V1 request expected"),
+    };
+    assert_eq!(authorize.name(), user);
+}
+
+/// Verifies that users are rewritten by the routing rules and that channels of unknown users
+/// are refused
+#[tokio::test]
+async fn test_routing_rules_rewrite_user() {
+    let (v1_tx, mut v1_rx) = mpsc::channel(10);
+    let (v2_tx, mut v2_rx) = mpsc::channel(10);
+    let mut translation = V2ToV1Translation::new(v1_tx, v2_tx, build_routing_options());
+
+    v2_simulate_incoming_message(&mut translation, test_utils::v2::build_setup_connection()).await;
+    v1_verify_generated_response_message(&mut v1_rx).await;
+    v1_simulate_incoming_message(
+        &mut translation,
+        test_utils::v1::build_configure_ok_response_message(),
+    )
+    .await;
+    v2_verify_generated_response_message(&mut v2_rx).await;
+
+    let mut open_channel = test_utils::v2::build_open_channel();
+    open_channel.user = "unknown.worker0".try_into().unwrap();
+    v2_simulate_incoming_message(&mut translation, open_channel).await;
+    let open_error: v2::messages::OpenStandardMiningChannelError =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(
+        open_error.code.to_string(),
+        V2ToV1Translation::UNKNOWN_USER_ERROR_CODE
+    );
+
+    v2_simulate_incoming_message(&mut translation, test_utils::v2::build_open_channel()).await;
+    v1_rx.next().await.expect("Missing V1 subscribe");
+    v1_verify_authorize(&mut v1_rx, "account.worker0").await;
+    assert!(
+        translation.take_upstream_request().is_none(),
+        "Channel routed to other upstream"
+    );
+}
+
+/// Verifies that the first channel routed to other upstream servers is opened only after the
+/// session has been moved to the servers
+#[tokio::test]
+async fn test_routing_rules_route_upstream() {
+    let (v1_tx, mut v1_rx) = mpsc::channel(10);
+    let (v2_tx, mut v2_rx) = mpsc::channel(10);
+    let mut translation = V2ToV1Translation::new(v1_tx, v2_tx, build_routing_options());
+
+    v2_simulate_incoming_message(&mut translation, test_utils::v2::build_setup_connection()).await;
+    v1_verify_generated_response_message(&mut v1_rx).await;
+    v1_simulate_incoming_message(
+        &mut translation,
+        test_utils::v1::build_configure_ok_response_message(),
+    )
+    .await;
+    v2_verify_generated_response_message(&mut v2_rx).await;
+
+    let mut open_channel = test_utils::v2::build_open_channel();
+    open_channel.user = "farm-rig1".try_into().unwrap();
+    v2_simulate_incoming_message(&mut translation, open_channel).await;
+    assert!(
+        v1_rx.try_next().is_err(),
+        "Channel opened with the original upstream"
+    );
+    assert_eq!(
+        translation.take_upstream_request(),
+        Some(vec![Address("pool.example.com".to_string(), 3333)])
+    );
+
+    // Channel of the default upstream cannot join the routed session
+    v2_simulate_incoming_message(&mut translation, test_utils::v2::build_open_channel()).await;
+    let open_error: v2::messages::OpenStandardMiningChannelError =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(
+        open_error.code.to_string(),
+        V2ToV1Translation::UPSTREAM_MISMATCH_ERROR_CODE
+    );
+
+    let (v1_tx, mut v1_rx) = mpsc::channel(10);
+    translation.switch_upstream(v1_tx, "127.0.0.1:3334".parse().expect("Invalid address"));
+    v1_rx.next().await.expect("Missing V1 configure");
+    v1_simulate_incoming_message(
+        &mut translation,
+        v1_build_response_message_with_id(test_utils::v1::MINING_CONFIGURE_OK_RESP_JSON, 1),
+    )
+    .await;
+    v1_rx.next().await.expect("Missing V1 subscribe");
+    v1_verify_authorize(&mut v1_rx, "farm.rig1").await;
+}
+
 #[test]
 fn test_diff_1_bitcoin_target() {
     // Difficulty 1 target in big-endian format
@@ -0,0 +1,391 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Connections to upstream servers. The servers are configured as a list in the order of
+//! preference, the first one is the primary server. A translation fails over to the next
+//! available server when its upstream V1 session is lost and it returns to a more preferred
+//! server as soon as the server recovers.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::channel::mpsc;
+use futures::stream::{SplitSink, SplitStream};
+use tokio::net::TcpStream;
+
+use ii_async_compat::prelude::*;
+use ii_async_compat::{futures, tokio};
+use ii_logging::macros::*;
+use ii_stratum::v1;
+use ii_wire::{Address, Backoff, Client, Connection};
+
+use crate::error::{Error, Result, ResultExt};
+use crate::limits::ConnectionLimits;
+use crate::metrics;
+use crate::routing::RoutingRules;
+use crate::server::ConnTranslation;
+use crate::translation::{V2ToV1Translation, V2ToV1TranslationOptions};
+use crate::vardiff::VarDiffOptions;
+
+#[cfg(test)]
+mod test;
+
+/// Exponential backoff between connection attempts. Each attempt targets the next server in the
+/// list so that the backup servers are tried without waiting for the primary server.
+#[derive(Debug)]
+struct UpstreamBackoff {
+    next_delay: Duration,
+}
+
+impl UpstreamBackoff {
+    const INITIAL_DELAY: Duration = Duration::from_millis(100);
+    const MAX_DELAY: Duration = Duration::from_secs(5);
+}
+
+impl Default for UpstreamBackoff {
+    fn default() -> Self {
+        Self {
+            next_delay: Self::INITIAL_DELAY,
+        }
+    }
+}
+
+impl Backoff for UpstreamBackoff {
+    fn next(&mut self) -> Duration {
+        let delay = self.next_delay;
+        self.next_delay = (delay * 2).min(Self::MAX_DELAY);
+        delay
+    }
+
+    fn reset(&mut self) {
+        self.next_delay = Self::INITIAL_DELAY;
+    }
+}
+
+/// Connection to one of the upstream servers, the framing depends on the upstream protocol
+pub struct UpstreamConnection {
+    pub conn: TcpStream,
+    pub peer_addr: SocketAddr,
+    /// Position of the server in the list of upstreams, the primary server has index 0
+    pub index: usize,
+}
+
+impl UpstreamConnection {
+    fn new(conn: TcpStream, index: usize) -> Result<Self> {
+        let peer_addr = conn.peer_addr().context("Upstream peer address")?;
+        Ok(Self {
+            conn,
+            peer_addr,
+            index,
+        })
+    }
+}
+
+/// Ordered list of upstream servers that the proxy connects to
+#[derive(Clone, Debug)]
+pub struct UpstreamConnector {
+    addrs: Arc<Vec<Address>>,
+    /// Rules that may route channels to other upstream servers
+    routing_rules: Option<Arc<RoutingRules>>,
+    /// Variable difficulty of the translated channels
+    vardiff: Option<VarDiffOptions>,
+    /// Try to enable extranonce subscription in the upstream sessions
+    try_enable_xnsub: bool,
+    /// Limits of the downstream connections served by the sessions
+    connection_limits: ConnectionLimits,
+    /// How often the servers preferred over the current server are checked for recovery
+    recovery_check_interval: Duration,
+}
+
+impl UpstreamConnector {
+    /// Connection attempt to a single server is abandoned after this timeout
+    const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+    /// Connecting fails when none of the servers can be reached within this time
+    const MAX_CONNECT_TIME: Duration = Duration::from_secs(30);
+    /// Default period of checking whether the preferred servers have recovered
+    const RECOVERY_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+    pub fn new(addrs: Vec<Address>) -> Result<Self> {
+        if addrs.is_empty() {
+            Err("No upstream server specified")?;
+        }
+        Ok(Self {
+            addrs: Arc::new(addrs),
+            routing_rules: None,
+            vardiff: None,
+            try_enable_xnsub: false,
+            connection_limits: ConnectionLimits::default(),
+            recovery_check_interval: Self::RECOVERY_CHECK_INTERVAL,
+        })
+    }
+
+    /// Translations onto the upstream servers rewrite users of their channels according to
+    /// `routing_rules`
+    pub fn with_routing_rules(mut self, routing_rules: RoutingRules) -> Self {
+        self.routing_rules = Some(Arc::new(routing_rules));
+        self
+    }
+
+    /// Translations onto the upstream servers adjust difficulty of each channel according to
+    /// `vardiff`
+    pub fn with_vardiff(mut self, vardiff: VarDiffOptions) -> Self {
+        self.vardiff = Some(vardiff);
+        self
+    }
+
+    /// Translations onto the upstream servers try to subscribe for extranonce changes
+    pub fn with_extranonce_subscribe(mut self) -> Self {
+        self.try_enable_xnsub = true;
+        self
+    }
+
+    /// Downstream connections served by sessions with the upstream servers are dropped when they
+    /// exceed `connection_limits`
+    pub fn with_connection_limits(mut self, connection_limits: ConnectionLimits) -> Self {
+        self.connection_limits = connection_limits;
+        self
+    }
+
+    pub fn connection_limits(&self) -> ConnectionLimits {
+        self.connection_limits
+    }
+
+    /// Builds options for translations onto the upstream servers
+    pub fn translation_options(&self) -> V2ToV1TranslationOptions {
+        V2ToV1TranslationOptions {
+            try_enable_xnsub: self.try_enable_xnsub,
+            routing_rules: self.routing_rules.clone(),
+            validate_shares: true,
+            vardiff: self.vardiff,
+        }
+    }
+
+    /// Connects to the first available server. The servers are attempted in the order of
+    /// preference, round after round, with an increasing delay between the attempts.
+    pub async fn connect(&self) -> Result<UpstreamConnection> {
+        let mut client = Client::with_backoff(self.addrs[0].clone(), UpstreamBackoff::default());
+        let start_time = Instant::now();
+        loop {
+            for (index, addr) in self.addrs.iter().enumerate() {
+                client.set_addr(addr.clone());
+                match client.next().timeout(Self::CONNECT_TIMEOUT).await {
+                    Ok(Ok(conn)) => return UpstreamConnection::new(conn, index),
+                    Ok(Err(e)) => info!("Cannot connect to upstream {}: {}", addr, e.error),
+                    Err(e) => info!("Cannot connect to upstream {}: {}", addr, e),
+                }
+                if start_time.elapsed() >= Self::MAX_CONNECT_TIME {
+                    Err(format!("No upstream server available ({})", self))?;
+                }
+            }
+        }
+    }
+
+    /// Attempts once to connect to each server that is preferred over the server at `index`
+    async fn connect_preferred(&self, index: usize) -> Option<UpstreamConnection> {
+        for (preferred_index, addr) in self.addrs[..index].iter().enumerate() {
+            if let Ok(Ok(conn)) = addr.connect().timeout(Self::CONNECT_TIMEOUT).await {
+                match UpstreamConnection::new(conn, preferred_index) {
+                    Ok(conn) => return Some(conn),
+                    Err(e) => info!("Cannot use recovered upstream {}: {}", addr, e),
+                }
+            }
+        }
+        None
+    }
+
+    /// Periodically checks whether any server preferred over the server at `index` has
+    /// recovered and provides the connection to it via `recovery_tx`. The check stops when the
+    /// receiving end is dropped.
+    async fn watch_recovery(self, index: usize, mut recovery_tx: mpsc::Sender<UpstreamConnection>) {
+        let mut recovery_check = tokio::time::interval(self.recovery_check_interval);
+        // The first tick completes immediately
+        recovery_check.tick().await;
+        while !recovery_tx.is_closed() {
+            recovery_check.tick().await;
+            if let Some(conn) = self.connect_preferred(index).await {
+                info!("Upstream {} has recovered", conn.peer_addr);
+                let _ = recovery_tx.send(conn).await;
+                return;
+            }
+        }
+    }
+}
+
+impl fmt::Display for UpstreamConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let addrs: Vec<String> = self.addrs.iter().map(|addr| addr.to_string()).collect();
+        write!(f, "{}", addrs.join(", "))
+    }
+}
+
+/// Upstream side of a translation. The connection is replaced when the upstream is lost or when
+/// a more preferred server recovers, the translation is moved onto the new connection.
+pub(crate) struct V1Upstream {
+    connector: UpstreamConnector,
+    /// Position of the current server in the list of upstreams
+    index: usize,
+    pub peer_addr: SocketAddr,
+    conn_tx: SplitSink<v1::Framed, v1::Frame>,
+    pub conn_rx: SplitStream<v1::Framed>,
+    /// Frames from the translation to be sent out via the current connection
+    pub translation_rx: mpsc::Receiver<v1::Frame>,
+    /// Connection to a recovered server that is preferred over the current one
+    pub recovery_rx: mpsc::Receiver<UpstreamConnection>,
+    translation_channel_size: usize,
+}
+
+impl V1Upstream {
+    /// Builds the upstream side on top of connection `conn`, the translation is expected to
+    /// send its frames via the returned sender
+    pub fn new(
+        connector: UpstreamConnector,
+        conn: UpstreamConnection,
+        translation_channel_size: usize,
+    ) -> (Self, mpsc::Sender<v1::Frame>) {
+        let (conn_tx, conn_rx) = Connection::<v1::Framing>::new(conn.conn)
+            .into_inner()
+            .split();
+        let (translation_tx, translation_rx) = mpsc::channel(translation_channel_size);
+        // The sender is dropped right away when there is no preferred server
+        let (recovery_tx, recovery_rx) = mpsc::channel(1);
+        if conn.index > 0 {
+            tokio::spawn(connector.clone().watch_recovery(conn.index, recovery_tx));
+        }
+
+        metrics::UPSTREAM_SESSIONS
+            .with_label_values(&[&conn.peer_addr.to_string()])
+            .inc();
+        let upstream = Self {
+            connector,
+            index: conn.index,
+            peer_addr: conn.peer_addr,
+            conn_tx,
+            conn_rx,
+            translation_rx,
+            recovery_rx,
+            translation_channel_size,
+        };
+        (upstream, translation_tx)
+    }
+
+    /// Moves `translation` onto connection `conn`, the current connection is closed
+    pub fn switch(&mut self, translation: &mut V2ToV1Translation, conn: UpstreamConnection) {
+        info!(
+            "Switching upstream V1 {} (#{}) -> {} (#{})",
+            self.peer_addr, self.index, conn.peer_addr, conn.index
+        );
+        let (upstream, v1_tx) =
+            Self::new(self.connector.clone(), conn, self.translation_channel_size);
+        *self = upstream;
+        translation.switch_upstream(v1_tx, self.peer_addr);
+    }
+
+    /// Moves `translation` onto the servers that the routing rules have assigned to its first
+    /// channel, if any. The servers replace the configured ones for the rest of the session.
+    pub async fn follow_route(&mut self, translation: &mut V2ToV1Translation) -> Result<()> {
+        let addrs = match translation.take_upstream_request() {
+            Some(addrs) => addrs,
+            None => return Ok(()),
+        };
+        let connector = UpstreamConnector::new(addrs)?;
+        match connector.connect().await {
+            Ok(conn) => {
+                self.connector = connector;
+                self.switch(translation, conn);
+                Ok(())
+            }
+            Err(e) => {
+                translation.close_all_channels(V2ToV1Translation::UPSTREAM_LOST_REASON);
+                Err(e)
+            }
+        }
+    }
+
+    /// Moves `translation` onto the first available server after the current connection has
+    /// failed with `err`. All channels of the translation are closed when no server is
+    /// available.
+    async fn fail_over(&mut self, translation: &mut V2ToV1Translation, err: Error) -> Result<()> {
+        info!("Upstream V1 {} lost: {}", self.peer_addr, err);
+        match self.connector.connect().await {
+            Ok(conn) => {
+                self.switch(translation, conn);
+                Ok(())
+            }
+            Err(e) => {
+                translation.close_all_channels(V2ToV1Translation::UPSTREAM_LOST_REASON);
+                Err(e)
+            }
+        }
+    }
+
+    /// Passes frame received via the current connection to `translation`. The translation fails
+    /// over to another server when the connection has been closed or it has timed out.
+    pub async fn handle_frame<E>(
+        &mut self,
+        translation: &mut V2ToV1Translation,
+        v1_frame: std::result::Result<
+            Option<std::result::Result<v1::Frame, E>>,
+            tokio::time::Elapsed,
+        >,
+    ) -> Result<()>
+    where
+        E: Into<Error>,
+    {
+        let v1_frame = match v1_frame {
+            Ok(Some(v1_frame)) => v1_frame.map_err(Into::into),
+            Ok(None) => Err(format!(
+                "Upstream V1 stratum connection dropped ({:?})",
+                self.peer_addr
+            )
+            .into()),
+            Err(e) => Err(e.into()),
+        };
+        match v1_frame {
+            Ok(v1_frame) => ConnTranslation::v1_handle_frame(translation, v1_frame).await,
+            Err(e) => self.fail_over(translation, e).await,
+        }
+    }
+
+    /// Sends out frame from `translation` via the current connection. The translation fails
+    /// over to another server when the connection has failed.
+    pub async fn send_frame(
+        &mut self,
+        translation: &mut V2ToV1Translation,
+        v1_frame: Option<v1::Frame>,
+    ) -> Result<()> {
+        let v1_frame = v1_frame.expect("BUG: translation dropped V1 queue");
+        match self.conn_tx.send(v1_frame).await {
+            Ok(()) => Ok(()),
+            Err(e) => self.fail_over(translation, e.into()).await,
+        }
+    }
+}
+
+impl Drop for V1Upstream {
+    fn drop(&mut self) {
+        metrics::UPSTREAM_SESSIONS
+            .with_label_values(&[&self.peer_addr.to_string()])
+            .dec();
+    }
+}
@@ -0,0 +1,145 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use tokio::net::TcpListener;
+
+use super::*;
+
+/// Listens on a free local port, the connections are accepted by the OS as long as the listener
+/// exists
+async fn listen() -> (TcpListener, Address) {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("BUG: cannot bind to a local port");
+    let addr = listener
+        .local_addr()
+        .expect("BUG: missing listener address");
+    (listener, Address(addr.ip().to_string(), addr.port()))
+}
+
+/// Local address that nobody listens on
+async fn unavailable_addr() -> Address {
+    let (_, addr) = listen().await;
+    addr
+}
+
+#[test]
+fn test_upstream_backoff() {
+    let mut backoff = UpstreamBackoff::default();
+    let delays: Vec<u64> = (0..8).map(|_| backoff.next().as_millis() as u64).collect();
+    assert_eq!(delays, vec![100, 200, 400, 800, 1600, 3200, 5000, 5000]);
+
+    backoff.reset();
+    assert_eq!(backoff.next(), UpstreamBackoff::INITIAL_DELAY);
+}
+
+#[tokio::test]
+async fn test_connect_in_preference_order() {
+    let (_primary, primary_addr) = listen().await;
+    let (_backup, backup_addr) = listen().await;
+
+    let connector = UpstreamConnector::new(vec![primary_addr.clone(), backup_addr.clone()])
+        .expect("BUG: cannot create connector");
+    let conn = connector.connect().await.expect("BUG: cannot connect");
+    assert_eq!(conn.index, 0);
+    assert_eq!(conn.peer_addr.port(), primary_addr.1);
+
+    // Unavailable servers are skipped
+    let connector = UpstreamConnector::new(vec![unavailable_addr().await, backup_addr.clone()])
+        .expect("BUG: cannot create connector");
+    let conn = connector.connect().await.expect("BUG: cannot connect");
+    assert_eq!(conn.index, 1);
+    assert_eq!(conn.peer_addr.port(), backup_addr.1);
+}
+
+#[tokio::test]
+async fn test_watch_recovery() {
+    let primary_addr = unavailable_addr().await;
+    let (_backup, backup_addr) = listen().await;
+    let mut connector = UpstreamConnector::new(vec![primary_addr.clone(), backup_addr])
+        .expect("BUG: cannot create connector");
+    connector.recovery_check_interval = Duration::from_millis(10);
+
+    let (recovery_tx, mut recovery_rx) = mpsc::channel(1);
+    tokio::spawn(connector.watch_recovery(1, recovery_tx));
+    // Nothing is provided while the primary server is down
+    assert!(recovery_rx
+        .next()
+        .timeout(Duration::from_millis(100))
+        .await
+        .is_err());
+
+    let _primary = TcpListener::bind(primary_addr.to_string())
+        .await
+        .expect("BUG: cannot bind to primary address");
+    let conn = recovery_rx
+        .next()
+        .timeout(Duration::from_secs(5))
+        .await
+        .expect("BUG: primary server recovery not detected")
+        .expect("BUG: recovery watch terminated");
+    assert_eq!(conn.index, 0);
+    assert_eq!(conn.peer_addr.port(), primary_addr.1);
+}
+
+#[tokio::test]
+async fn test_fail_over_and_return_to_primary() {
+    let (primary, primary_addr) = listen().await;
+    let (_backup, backup_addr) = listen().await;
+    let mut connector = UpstreamConnector::new(vec![primary_addr.clone(), backup_addr.clone()])
+        .expect("BUG: cannot create connector");
+    connector.recovery_check_interval = Duration::from_millis(10);
+
+    let (v1_tx, _v1_rx) = mpsc::channel(1);
+    let (v2_tx, _v2_rx) = mpsc::channel(1);
+    let mut translation = V2ToV1Translation::new(v1_tx, v2_tx, connector.translation_options());
+    let conn = connector.connect().await.expect("BUG: cannot connect");
+    let (mut upstream, _v1_tx) = V1Upstream::new(connector, conn, 1);
+    assert_eq!(upstream.index, 0);
+    // The primary server is not watched for recovery
+    assert!(upstream.recovery_rx.next().await.is_none());
+
+    // The translation moves to the backup server when the primary server goes down
+    drop(primary);
+    upstream
+        .fail_over(&mut translation, "Connection lost".into())
+        .await
+        .expect("BUG: fail over to backup server failed");
+    assert_eq!(upstream.index, 1);
+    assert_eq!(upstream.peer_addr.port(), backup_addr.1);
+
+    // And it returns to the primary server as soon as it recovers
+    let _primary = TcpListener::bind(primary_addr.to_string())
+        .await
+        .expect("BUG: cannot bind to primary address");
+    let conn = upstream
+        .recovery_rx
+        .next()
+        .timeout(Duration::from_secs(5))
+        .await
+        .expect("BUG: primary server recovery not detected")
+        .expect("BUG: recovery watch terminated");
+    upstream.switch(&mut translation, conn);
+    assert_eq!(upstream.index, 0);
+    assert_eq!(upstream.peer_addr.port(), primary_addr.1);
+    assert!(upstream.recovery_rx.next().await.is_none());
+}
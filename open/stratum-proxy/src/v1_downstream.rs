@@ -0,0 +1,1045 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Stratum V1 downstream connections. Each V1 miner is presented to the upstream as a V2 client
+//! with a single extended channel. A V2 upstream receives the V2 messages directly, a V1 upstream
+//! is reached via `V2ToV1Translation` that builds the jobs for the extended channel.
+
+use std::collections::{HashMap, VecDeque};
+use std::convert::{TryFrom, TryInto};
+use std::net::SocketAddr;
+use std::time;
+
+use async_trait::async_trait;
+use futures::channel::mpsc;
+use futures::stream::SplitSink;
+use tokio::net::TcpStream;
+
+use ii_async_compat::prelude::*;
+use ii_async_compat::{futures, select, tokio};
+use ii_logging::macros::*;
+use ii_stratum::v1;
+use ii_stratum::v2::{
+    self,
+    types::{Bytes0_32, DeviceInfo, Str0_255, Uint256Bytes},
+};
+use ii_wire::Connection;
+
+use crate::error::{Error, Result};
+use crate::passthrough;
+use crate::server::ConnTranslation;
+use crate::translation::{SeqId, V2ToV1Translation};
+use crate::upstream::{UpstreamConnection, UpstreamConnector, V1Upstream};
+use crate::util;
+
+#[cfg(test)]
+mod test;
+
+/// Describes how V1 downstream connections reach the upstream, each connection gets its own
+/// upstream session
+#[derive(Clone)]
+pub enum UpstreamMode {
+    /// The extended channel is translated onto a V1 session
+    V1,
+    /// The extended channel is opened with the V2 upstream, noise handshake authenticates the
+    /// upstream with any of the keys unless no key is provided
+    V2(Vec<v2::noise::auth::EncodedEd25519PublicKey>),
+}
+
+/// States of the translation setup
+#[derive(PartialEq, Debug)]
+enum V1ToV2TranslationState {
+    /// Waiting for mining.subscribe
+    Init,
+    /// SetupConnection has been sent upstream
+    ConnectionSetupPending,
+    /// Connection has been setup, the channel is opened by mining.authorize
+    ConnectionSetup,
+    /// OpenExtendedMiningChannel has been sent upstream
+    OpenChannelPending,
+    /// Channel is operational
+    Operational,
+    /// The upstream has refused the connection or closed the channel
+    Closed,
+}
+
+/// Extended channel that serves the V1 miner
+#[derive(Clone, Debug)]
+struct V2Channel {
+    channel_id: u32,
+    /// User that has been authorized by the miner
+    user: String,
+    extranonce_prefix: Vec<u8>,
+    /// Size of the extranonce rolled on the channel, the miner rolls only its trailing part
+    extranonce_size: usize,
+}
+
+/// Maps V2 job ID to the job received on the channel
+type JobMap = HashMap<u32, v2::messages::NewExtendedMiningJob>;
+
+/// Object capable of translating stratum V1 mining protocol into stratum V2 extended mining
+/// channel. The extranonce of the channel is composed of zero padding, extra nonce 1 and extra
+/// nonce 2 of the miner, the padding is passed to the miner as part of coinbase 1.
+pub struct V1ToV2Translation {
+    /// Statemachine tracking the translation setup
+    state: V1ToV2TranslationState,
+    /// Channel for sending out V1 messages to the miner
+    v1_tx: mpsc::Sender<v1::Frame>,
+    /// Channel for sending out V2 messages to the upstream
+    v2_tx: mpsc::Sender<v2::Frame>,
+    /// Upstream address that the connection is setup for
+    upstream_addr: SocketAddr,
+    /// Version mask negotiated via mining.configure, no version rolling without it
+    v1_version_mask: u32,
+    /// Pending mining.authorize along with the user, the channel is opened for it
+    v1_pending_authorize: Option<(u32, String)>,
+    /// Unique request ID generator
+    v2_req_id: SeqId,
+    v2_channel: Option<V2Channel>,
+    /// Jobs that can be submitted, future jobs wait for their SetNewPrevHash
+    v2_jobs: JobMap,
+    v2_future_jobs: JobMap,
+    /// Latest SetNewPrevHash, the jobs are mined on top of it
+    v2_prev_hash: Option<v2::messages::SetNewPrevHash>,
+    /// ID of the latest job sent to the miner
+    v2_last_job_id: Option<u32>,
+    /// Unique sequence number generator for submitted shares
+    v2_seq_num: SeqId,
+    /// Shares that wait for the upstream result in the order of submission, each share is
+    /// identified by its sequence number and V1 request ID
+    v1_pending_submits: VecDeque<(u32, u32)>,
+}
+
+impl V1ToV2Translation {
+    const PROTOCOL_VERSION: u16 = 2;
+    /// Extra nonce 1 assigned to the miner
+    const V1_EXTRA_NONCE1: &'static [u8] = &[0];
+    const V1_EXTRA_NONCE2_SIZE: usize = 2;
+    /// V1 miners don't report their hashrate, the upstream is expected to adjust the target as
+    /// the shares arrive
+    const V2_NOMINAL_HASHRATE: f32 = 1e12;
+    /// Error codes of V1 responses as used by V1 pools
+    const V1_OTHER_ERROR_CODE: i32 = 20;
+    const V1_JOB_NOT_FOUND_ERROR_CODE: i32 = 21;
+    const V1_UNAUTHORIZED_ERROR_CODE: i32 = 24;
+    const V1_NOT_SUBSCRIBED_ERROR_CODE: i32 = 25;
+
+    pub fn new(
+        v1_tx: mpsc::Sender<v1::Frame>,
+        v2_tx: mpsc::Sender<v2::Frame>,
+        upstream_addr: SocketAddr,
+    ) -> Self {
+        Self {
+            state: V1ToV2TranslationState::Init,
+            v1_tx,
+            v2_tx,
+            upstream_addr,
+            v1_version_mask: 0,
+            v1_pending_authorize: None,
+            v2_req_id: SeqId::new(),
+            v2_channel: None,
+            v2_jobs: JobMap::default(),
+            v2_future_jobs: JobMap::default(),
+            v2_prev_hash: None,
+            v2_last_job_id: None,
+            v2_seq_num: SeqId::new(),
+            v1_pending_submits: VecDeque::new(),
+        }
+    }
+
+    /// Indicates that the miner cannot be served by the upstream anymore
+    pub fn is_closed(&self) -> bool {
+        self.state == V1ToV2TranslationState::Closed
+    }
+
+    /// Processes frame received from the V1 miner. Messages that cannot be parsed are only
+    /// logged, miners tend to send methods that the proxy doesn't know.
+    pub async fn handle_downstream_frame(&mut self, frame: v1::Frame) {
+        match v1::build_message_from_frame(frame) {
+            Ok(v1_msg) => v1_msg.accept(self).await,
+            Err(e) => info!("Ignoring V1 message from miner: {}", e),
+        }
+    }
+
+    /// Processes frame received from the V2 upstream
+    pub async fn handle_upstream_frame(&mut self, frame: v2::Frame) -> Result<()> {
+        match frame.header.extension_type {
+            v2::extensions::BASE => {
+                let v2_msg = v2::build_message_from_frame(frame)?;
+                v2_msg.accept(self).await;
+            }
+            _ => warn!("Unsupported extension frame: {:x?} ", frame),
+        }
+        Ok(())
+    }
+
+    fn v1_submit_result<T>(&mut self, id: u32, result: T) -> Result<()>
+    where
+        T: TryInto<v1::rpc::ResponsePayload, Error = ii_stratum::error::Error>,
+    {
+        let response = v1::rpc::Response {
+            id,
+            payload: result.try_into()?,
+        };
+        util::submit_message(&mut self.v1_tx, v1::rpc::Rpc::from(response))
+    }
+
+    fn v1_submit_error(&mut self, id: u32, code: i32, err_msg: &str) -> Result<()> {
+        let response = v1::rpc::Response {
+            id,
+            payload: v1::rpc::ResponsePayload {
+                result: None,
+                error: Some(v1::rpc::StratumError(code, err_msg.to_string(), None)),
+            },
+        };
+        util::submit_message(&mut self.v1_tx, v1::rpc::Rpc::from(response))
+    }
+
+    /// Sends out V1 notification `method` to the miner
+    fn v1_submit_notification<M>(&mut self, method: M) -> Result<()>
+    where
+        M: TryInto<v1::rpc::RequestPayload, Error = ii_stratum::error::Error>,
+    {
+        let request = v1::rpc::Request {
+            id: None,
+            payload: method.try_into()?,
+        };
+        util::submit_message(&mut self.v1_tx, v1::rpc::Rpc::from(request))
+    }
+
+    fn v2_submit_message<T>(&mut self, msg: T) -> Result<()>
+    where
+        T: TryInto<v2::Frame, Error = ii_stratum::error::Error>,
+    {
+        util::submit_message(&mut self.v2_tx, msg)
+    }
+
+    /// Opens the extended channel for the pending authorize request
+    fn open_channel(&mut self) -> Result<()> {
+        let user = match self.v1_pending_authorize.as_ref() {
+            Some((_, user)) => user.clone(),
+            None => return Ok(()),
+        };
+        let msg = v2::messages::OpenExtendedMiningChannel {
+            req_id: self.v2_req_id.next(),
+            user: user
+                .as_str()
+                .try_into()
+                .map_err(|_| Error::from(format!("Invalid user name: {}", user)))?,
+            nominal_hashrate: Self::V2_NOMINAL_HASHRATE,
+            max_target: Uint256Bytes::from(V2ToV1Translation::DIFF1_TARGET),
+            min_extranonce_size: (Self::V1_EXTRA_NONCE1.len() + Self::V1_EXTRA_NONCE2_SIZE) as u16,
+        };
+        self.state = V1ToV2TranslationState::OpenChannelPending;
+        self.v2_submit_message(msg)
+    }
+
+    /// Completes the pending authorize request with `result`
+    fn finish_pending_authorize(&mut self, result: bool) -> Result<()> {
+        match self.v1_pending_authorize.take() {
+            Some((id, _)) => self.v1_submit_result(id, v1::messages::BooleanResult(result)),
+            None => Ok(()),
+        }
+    }
+
+    /// Translates `target` into mining.set_difficulty
+    fn send_set_difficulty(&mut self, target: Uint256Bytes) -> Result<()> {
+        let target: uint::U256 = target.into();
+        let difficulty = if target.is_zero() {
+            V2ToV1Translation::DIFF1_TARGET
+        } else {
+            V2ToV1Translation::DIFF1_TARGET / target
+        };
+        // Difficulty below 1 is not supported by V1 miners
+        let difficulty = difficulty.low_u64().max(1);
+        self.v1_submit_notification(v1::messages::SetDifficulty([difficulty as f32]))
+    }
+
+    /// Builds mining.notify for `job`. The extranonce prefix of the channel and the padding in
+    /// front of extra nonce 1 become part of coinbase 1.
+    fn build_notify(
+        v2_channel: &V2Channel,
+        job: &v2::messages::NewExtendedMiningJob,
+        prev_hash: &v2::messages::SetNewPrevHash,
+        clean_jobs: bool,
+    ) -> v1::messages::Notify {
+        let padding =
+            v2_channel.extranonce_size - Self::V1_EXTRA_NONCE1.len() - Self::V1_EXTRA_NONCE2_SIZE;
+        let mut coin_base_1 = job.coinbase_tx_prefix.to_vec();
+        coin_base_1.extend_from_slice(&v2_channel.extranonce_prefix);
+        coin_base_1.extend_from_slice(&vec![0; padding]);
+        let merkle_branch: Vec<&[u8]> = job
+            .merkle_path
+            .iter()
+            .map(|tx_hash| &tx_hash.as_ref()[..])
+            .collect();
+
+        v1::messages::Notify::new(
+            v1::messages::JobId::from_str(&job.job_id.to_string()),
+            prev_hash.prev_hash.as_ref(),
+            &coin_base_1,
+            &job.coinbase_tx_suffix,
+            &merkle_branch,
+            job.version,
+            prev_hash.nbits,
+            prev_hash.min_ntime,
+            clean_jobs,
+        )
+    }
+
+    /// Sends job `job_id` to the miner provided that the prevhash for it is known
+    fn send_notify(&mut self, job_id: u32, clean_jobs: bool) -> Result<()> {
+        let notify = match (
+            self.v2_channel.as_ref(),
+            self.v2_jobs.get(&job_id),
+            self.v2_prev_hash.as_ref(),
+        ) {
+            (Some(v2_channel), Some(job), Some(prev_hash)) => {
+                Self::build_notify(v2_channel, job, prev_hash, clean_jobs)
+            }
+            _ => return Ok(()),
+        };
+        self.v2_last_job_id = Some(job_id);
+        self.v1_submit_notification(notify)
+    }
+
+    /// Composes the extranonce of the channel from extra nonce 2 submitted by the miner
+    fn build_extranonce(v2_channel: &V2Channel, extra_nonce2: &[u8]) -> Bytes0_32 {
+        let padding =
+            v2_channel.extranonce_size - Self::V1_EXTRA_NONCE1.len() - Self::V1_EXTRA_NONCE2_SIZE;
+        let mut extranonce = vec![0; padding];
+        extranonce.extend_from_slice(Self::V1_EXTRA_NONCE1);
+        extranonce.extend_from_slice(extra_nonce2);
+        Bytes0_32::from_vec(extranonce)
+    }
+
+    /// Translates mining.submit into SubmitSharesExtended, the error message is reported to the
+    /// miner when the share cannot be submitted
+    fn submit_share(
+        &mut self,
+        id: u32,
+        payload: &v1::messages::Submit,
+    ) -> std::result::Result<(), (i32, &'static str)> {
+        let v2_channel = match self.v2_channel.as_ref() {
+            Some(v2_channel) if v2_channel.user == *payload.user_name() => v2_channel,
+            _ => return Err((Self::V1_UNAUTHORIZED_ERROR_CODE, "Unauthorized worker")),
+        };
+        if payload.extra_nonce_2().len() != Self::V1_EXTRA_NONCE2_SIZE {
+            return Err((Self::V1_OTHER_ERROR_CODE, "Invalid extra nonce 2 size"));
+        }
+        let job = payload
+            .job_id()
+            .parse::<u32>()
+            .ok()
+            .and_then(|job_id| self.v2_jobs.get(&job_id))
+            .ok_or((Self::V1_JOB_NOT_FOUND_ERROR_CODE, "Job not found"))?;
+        // Miner submits only the rolled version bits
+        let version =
+            (job.version & !self.v1_version_mask) | (payload.version() & self.v1_version_mask);
+
+        let mut msg = v2::messages::SubmitSharesExtended {
+            channel_id: v2_channel.channel_id,
+            seq_num: 0,
+            job_id: job.job_id,
+            nonce: payload.nonce(),
+            ntime: payload.time(),
+            version,
+            extranonce: Self::build_extranonce(v2_channel, payload.extra_nonce_2()),
+        };
+        let seq_num = self.v2_seq_num.next();
+        msg.seq_num = seq_num;
+        if let Err(e) = self.v2_submit_message(msg) {
+            info!("Cannot send SubmitSharesExtended: {}", e);
+            return Err((Self::V1_OTHER_ERROR_CODE, "Upstream not available"));
+        }
+        self.v1_pending_submits.push_back((seq_num, id));
+        Ok(())
+    }
+
+    /// Closes the translation, the miner is disconnected
+    fn close(&mut self, reason: &str) {
+        info!("Closing V1 miner connection: {}", reason);
+        self.state = V1ToV2TranslationState::Closed;
+    }
+}
+
+#[async_trait]
+impl v1::Handler for V1ToV2Translation {
+    /// Only version rolling is supported, the mask is limited to the bits allowed by BIP320
+    async fn visit_configure(&mut self, id: &v1::MessageId, payload: &v1::messages::Configure) {
+        trace!("visit_configure() id={:?} payload:{:?}", id, payload);
+        let id = match id {
+            Some(id) => *id,
+            None => return,
+        };
+        let mut result = serde_json::Map::new();
+        if payload.0.iter().any(|feature| feature == "version-rolling") {
+            let requested_mask: Option<v1::messages::VersionMask> =
+                serde_json::from_value(payload.1["version-rolling.mask"].clone()).ok();
+            self.v1_version_mask = requested_mask
+                .map(|mask| (mask.0).0)
+                .unwrap_or(ii_stratum::BIP320_N_VERSION_MASK)
+                & ii_stratum::BIP320_N_VERSION_MASK;
+            result.insert("version-rolling".to_string(), true.into());
+            result.insert(
+                "version-rolling.mask".to_string(),
+                format!("{:08x}", self.v1_version_mask).into(),
+            );
+        }
+        let result = v1::messages::ConfigureResult(serde_json::Value::Object(result));
+        if let Err(e) = self.v1_submit_result(id, result) {
+            info!("Cannot send mining.configure result: {}", e);
+        }
+    }
+
+    /// Subscription is answered right away, the connection is setup with the upstream on
+    /// behalf of the miner
+    async fn visit_subscribe(&mut self, id: &v1::MessageId, payload: &v1::messages::Subscribe) {
+        trace!("visit_subscribe() id={:?} payload:{:?}", id, payload);
+        let id = match id {
+            Some(id) => *id,
+            None => return,
+        };
+        let extra_nonce1_hex: String = Self::V1_EXTRA_NONCE1
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        let extra_nonce1 =
+            v1::HexBytes::try_from(extra_nonce1_hex.as_str()).expect("BUG: invalid extra nonce 1");
+        let subscribe_result = v1::messages::SubscribeResult(
+            vec![
+                v1::messages::Subscription("mining.set_difficulty".to_string(), "1".to_string()),
+                v1::messages::Subscription("mining.notify".to_string(), "1".to_string()),
+            ],
+            v1::ExtraNonce1(extra_nonce1),
+            Self::V1_EXTRA_NONCE2_SIZE,
+        );
+        if let Err(e) = self.v1_submit_result(id, subscribe_result) {
+            info!("Cannot send mining.subscribe result: {}", e);
+            return;
+        }
+        if self.state != V1ToV2TranslationState::Init {
+            return;
+        }
+
+        let agent = payload.agent_signature().cloned().unwrap_or_default();
+        let setup_connection = v2::messages::SetupConnection {
+            protocol: 0,
+            min_version: Self::PROTOCOL_VERSION,
+            max_version: Self::PROTOCOL_VERSION,
+            flags: 0,
+            endpoint_host: Str0_255::from_string(self.upstream_addr.ip().to_string()),
+            endpoint_port: self.upstream_addr.port(),
+            device: DeviceInfo {
+                vendor: Str0_255::new(),
+                hw_rev: Str0_255::new(),
+                fw_ver: Str0_255::try_from(agent).unwrap_or_default(),
+                dev_id: Str0_255::new(),
+            },
+        };
+        self.state = V1ToV2TranslationState::ConnectionSetupPending;
+        if let Err(e) = self.v2_submit_message(setup_connection) {
+            info!("Cannot send SetupConnection: {}", e);
+        }
+    }
+
+    async fn visit_extranonce_subscribe(
+        &mut self,
+        id: &v1::MessageId,
+        payload: &v1::messages::ExtranonceSubscribe,
+    ) {
+        trace!(
+            "visit_extranonce_subscribe() id={:?} payload:{:?}",
+            id,
+            payload
+        );
+        // Extra nonce 1 of the miner never changes
+        if let Some(id) = id {
+            if let Err(e) = self.v1_submit_result(*id, v1::messages::BooleanResult(false)) {
+                info!("Cannot send mining.extranonce.subscribe result: {}", e);
+            }
+        }
+    }
+
+    /// The channel is opened for the first authorized user, authorization of other users is
+    /// refused
+    async fn visit_authorize(&mut self, id: &v1::MessageId, payload: &v1::messages::Authorize) {
+        trace!("visit_authorize() id={:?} payload:{:?}", id, payload);
+        let id = match id {
+            Some(id) => *id,
+            None => return,
+        };
+        let result = match &self.state {
+            V1ToV2TranslationState::Init | V1ToV2TranslationState::ConnectionSetupPending
+                if self.v1_pending_authorize.is_none() =>
+            {
+                self.v1_pending_authorize = Some((id, payload.name().clone()));
+                return;
+            }
+            V1ToV2TranslationState::ConnectionSetup => {
+                self.v1_pending_authorize = Some((id, payload.name().clone()));
+                if let Err(e) = self.open_channel() {
+                    info!("Cannot open channel: {}", e);
+                    self.v1_pending_authorize = None;
+                    self.state = V1ToV2TranslationState::ConnectionSetup;
+                    false
+                } else {
+                    return;
+                }
+            }
+            V1ToV2TranslationState::Operational => self
+                .v2_channel
+                .as_ref()
+                .map(|v2_channel| v2_channel.user == *payload.name())
+                .unwrap_or(false),
+            _ => false,
+        };
+        if let Err(e) = self.v1_submit_result(id, v1::messages::BooleanResult(result)) {
+            info!("Cannot send mining.authorize result: {}", e);
+        }
+    }
+
+    async fn visit_submit(&mut self, id: &v1::MessageId, payload: &v1::messages::Submit) {
+        trace!("visit_submit() id={:?} payload:{:?}", id, payload);
+        let id = match id {
+            Some(id) => *id,
+            None => return,
+        };
+        let result = if self.state == V1ToV2TranslationState::Operational {
+            self.submit_share(id, payload)
+        } else {
+            Err((Self::V1_NOT_SUBSCRIBED_ERROR_CODE, "Not subscribed"))
+        };
+        if let Err((code, err_msg)) = result {
+            if let Err(e) = self.v1_submit_error(id, code, err_msg) {
+                info!("Cannot send mining.submit error: {}", e);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl v2::Handler for V1ToV2Translation {
+    async fn visit_setup_connection_success(
+        &mut self,
+        header: &v2::framing::Header,
+        payload: &v2::messages::SetupConnectionSuccess,
+    ) {
+        trace!(
+            "visit_setup_connection_success() header={:x?} state={:?} payload:{:?}",
+            header,
+            self.state,
+            payload,
+        );
+        if self.state != V1ToV2TranslationState::ConnectionSetupPending {
+            return;
+        }
+        self.state = V1ToV2TranslationState::ConnectionSetup;
+        if let Err(e) = self.open_channel() {
+            info!("Cannot open channel: {}", e);
+            self.state = V1ToV2TranslationState::ConnectionSetup;
+            if let Err(e) = self.finish_pending_authorize(false) {
+                info!("Cannot send mining.authorize result: {}", e);
+            }
+        }
+    }
+
+    async fn visit_setup_connection_error(
+        &mut self,
+        header: &v2::framing::Header,
+        payload: &v2::messages::SetupConnectionError,
+    ) {
+        trace!(
+            "visit_setup_connection_error() header={:x?} state={:?} payload:{:?}",
+            header,
+            self.state,
+            payload,
+        );
+        if let Err(e) = self.finish_pending_authorize(false) {
+            info!("Cannot send mining.authorize result: {}", e);
+        }
+        self.close(&format!(
+            "upstream refused connection setup: {}",
+            payload.code.to_string()
+        ));
+    }
+
+    async fn visit_open_extended_mining_channel_success(
+        &mut self,
+        header: &v2::framing::Header,
+        payload: &v2::messages::OpenExtendedMiningChannelSuccess,
+    ) {
+        trace!(
+            "visit_open_extended_mining_channel_success() header={:x?} state={:?} payload:{:?}",
+            header,
+            self.state,
+            payload,
+        );
+        if self.state != V1ToV2TranslationState::OpenChannelPending {
+            return;
+        }
+        let user = match self.v1_pending_authorize.as_ref() {
+            Some((_, user)) => user.clone(),
+            None => return,
+        };
+        let extranonce_size = payload.extranonce_size as usize;
+        if extranonce_size < Self::V1_EXTRA_NONCE1.len() + Self::V1_EXTRA_NONCE2_SIZE {
+            if let Err(e) = self.finish_pending_authorize(false) {
+                info!("Cannot send mining.authorize result: {}", e);
+            }
+            self.close("upstream extranonce too small");
+            return;
+        }
+        self.v2_channel = Some(V2Channel {
+            channel_id: payload.channel_id,
+            user,
+            extranonce_prefix: payload.extranonce_prefix.to_vec(),
+            extranonce_size,
+        });
+        self.state = V1ToV2TranslationState::Operational;
+        if let Err(e) = self
+            .finish_pending_authorize(true)
+            .and_then(|()| self.send_set_difficulty(payload.target))
+        {
+            info!("Cannot complete mining.authorize: {}", e);
+        }
+    }
+
+    async fn visit_open_extended_mining_channel_error(
+        &mut self,
+        header: &v2::framing::Header,
+        payload: &v2::messages::OpenExtendedMiningChannelError,
+    ) {
+        trace!(
+            "visit_open_extended_mining_channel_error() header={:x?} state={:?} payload:{:?}",
+            header,
+            self.state,
+            payload,
+        );
+        if self.state != V1ToV2TranslationState::OpenChannelPending {
+            return;
+        }
+        info!(
+            "Upstream refused to open channel: {}",
+            payload.code.to_string()
+        );
+        // The miner may attempt to authorize again
+        self.state = V1ToV2TranslationState::ConnectionSetup;
+        if let Err(e) = self.finish_pending_authorize(false) {
+            info!("Cannot send mining.authorize result: {}", e);
+        }
+    }
+
+    async fn visit_new_extended_mining_job(
+        &mut self,
+        header: &v2::framing::Header,
+        payload: &v2::messages::NewExtendedMiningJob,
+    ) {
+        trace!(
+            "visit_new_extended_mining_job() header={:x?} state={:?} payload:{:?}",
+            header,
+            self.state,
+            payload,
+        );
+        if payload.future_job {
+            self.v2_future_jobs.insert(payload.job_id, payload.clone());
+            return;
+        }
+        self.v2_jobs.insert(payload.job_id, payload.clone());
+        if let Err(e) = self.send_notify(payload.job_id, false) {
+            info!("Cannot send mining.notify: {}", e);
+        }
+    }
+
+    /// New prevhash activates the future job, all previous jobs become stale
+    async fn visit_set_new_prev_hash(
+        &mut self,
+        header: &v2::framing::Header,
+        payload: &v2::messages::SetNewPrevHash,
+    ) {
+        trace!(
+            "visit_set_new_prev_hash() header={:x?} state={:?} payload:{:?}",
+            header,
+            self.state,
+            payload,
+        );
+        self.v2_prev_hash = Some(payload.clone());
+        self.v2_jobs.clear();
+        match self.v2_future_jobs.remove(&payload.job_id) {
+            Some(job) => {
+                self.v2_future_jobs.clear();
+                self.v2_jobs.insert(job.job_id, job);
+                if let Err(e) = self.send_notify(payload.job_id, true) {
+                    info!("Cannot send mining.notify: {}", e);
+                }
+            }
+            None => info!("SetNewPrevHash for unknown job {}", payload.job_id),
+        }
+    }
+
+    async fn visit_set_target(
+        &mut self,
+        header: &v2::framing::Header,
+        payload: &v2::messages::SetTarget,
+    ) {
+        trace!(
+            "visit_set_target() header={:x?} state={:?} payload:{:?}",
+            header,
+            self.state,
+            payload,
+        );
+        if let Err(e) = self.send_set_difficulty(payload.max_target) {
+            info!("Cannot send mining.set_difficulty: {}", e);
+        }
+    }
+
+    /// The jobs already sent to the miner are built with the previous prefix, the current job is
+    /// resent so that the miner switches over immediately
+    async fn visit_set_extranonce_prefix(
+        &mut self,
+        header: &v2::framing::Header,
+        payload: &v2::messages::SetExtranoncePrefix,
+    ) {
+        trace!(
+            "visit_set_extranonce_prefix() header={:x?} state={:?} payload:{:?}",
+            header,
+            self.state,
+            payload,
+        );
+        if let Some(v2_channel) = self.v2_channel.as_mut() {
+            v2_channel.extranonce_prefix = payload.extranonce_prefix.to_vec();
+        }
+        if let Some(job_id) = self.v2_last_job_id {
+            self.v2_jobs.retain(|id, _| *id == job_id);
+            if let Err(e) = self.send_notify(job_id, true) {
+                info!("Cannot send mining.notify: {}", e);
+            }
+        }
+    }
+
+    /// Upstream acknowledges all shares up to the sequence number
+    async fn visit_submit_shares_success(
+        &mut self,
+        header: &v2::framing::Header,
+        payload: &v2::messages::SubmitSharesSuccess,
+    ) {
+        trace!(
+            "visit_submit_shares_success() header={:x?} state={:?} payload:{:?}",
+            header,
+            self.state,
+            payload,
+        );
+        while let Some((seq_num, id)) = self.v1_pending_submits.front().cloned() {
+            if seq_num > payload.last_seq_num {
+                break;
+            }
+            self.v1_pending_submits.pop_front();
+            if let Err(e) = self.v1_submit_result(id, v1::messages::BooleanResult(true)) {
+                info!("Cannot send mining.submit result: {}", e);
+            }
+        }
+    }
+
+    async fn visit_submit_shares_error(
+        &mut self,
+        header: &v2::framing::Header,
+        payload: &v2::messages::SubmitSharesError,
+    ) {
+        trace!(
+            "visit_submit_shares_error() header={:x?} state={:?} payload:{:?}",
+            header,
+            self.state,
+            payload,
+        );
+        let position = self
+            .v1_pending_submits
+            .iter()
+            .position(|(seq_num, _)| *seq_num == payload.seq_num);
+        if let Some((_, id)) =
+            position.and_then(|position| self.v1_pending_submits.remove(position))
+        {
+            let err_msg = payload.code.to_string();
+            if let Err(e) = self.v1_submit_error(id, Self::V1_OTHER_ERROR_CODE, &err_msg) {
+                info!("Cannot send mining.submit error: {}", e);
+            }
+        }
+    }
+
+    async fn visit_close_channel(
+        &mut self,
+        header: &v2::framing::Header,
+        payload: &v2::messages::CloseChannel,
+    ) {
+        trace!(
+            "visit_close_channel() header={:x?} state={:?} payload:{:?}",
+            header,
+            self.state,
+            payload,
+        );
+        self.close(&format!(
+            "channel closed by upstream: {}",
+            payload.reason_code.to_string()
+        ));
+    }
+
+    async fn visit_reconnect(
+        &mut self,
+        header: &v2::framing::Header,
+        payload: &v2::messages::Reconnect,
+    ) {
+        trace!(
+            "visit_reconnect() header={:x?} state={:?} payload:{:?}",
+            header,
+            self.state,
+            payload,
+        );
+        // The miner reconnects to the proxy that picks the upstream on its own
+        self.close("upstream requested reconnect");
+    }
+}
+
+/// Sends all frames from `translation_rx` to the V1 miner
+async fn v1_send_task(
+    mut conn_tx: SplitSink<v1::Framed, v1::Frame>,
+    mut translation_rx: mpsc::Receiver<v1::Frame>,
+    peer_addr: SocketAddr,
+) -> Result<()> {
+    while let Some(frame) = translation_rx.next().await {
+        if let Err(e) = conn_tx.send(frame).await {
+            info!("Send error: {} for (peer: {:?})", e, peer_addr);
+            Err(e)?;
+        }
+    }
+    Ok(())
+}
+
+/// Represents a single V1 miner translated onto its own upstream session
+struct V1ConnTranslation {
+    translation: V1ToV2Translation,
+    /// Downstream connection
+    v1_conn: v1::Framed,
+    v1_peer_addr: SocketAddr,
+    /// Frames from the translation to be sent out to the miner
+    v1_translation_rx: mpsc::Receiver<v1::Frame>,
+    /// Frames from the translation to be sent out to the upstream
+    v2_translation_rx: mpsc::Receiver<v2::Frame>,
+}
+
+impl V1ConnTranslation {
+    const MAX_TRANSLATION_CHANNEL_SIZE: usize = 10;
+    const V1_DOWNSTREAM_TIMEOUT: time::Duration = time::Duration::from_secs(60);
+    const V2_UPSTREAM_TIMEOUT: time::Duration = time::Duration::from_secs(60);
+
+    fn new(v1_conn: v1::Framed, v1_peer_addr: SocketAddr, upstream_addr: SocketAddr) -> Self {
+        let (v1_translation_tx, v1_translation_rx) =
+            mpsc::channel(Self::MAX_TRANSLATION_CHANNEL_SIZE);
+        let (v2_translation_tx, v2_translation_rx) =
+            mpsc::channel(Self::MAX_TRANSLATION_CHANNEL_SIZE);
+
+        Self {
+            translation: V1ToV2Translation::new(
+                v1_translation_tx,
+                v2_translation_tx,
+                upstream_addr,
+            ),
+            v1_conn,
+            v1_peer_addr,
+            v1_translation_rx,
+            v2_translation_rx,
+        }
+    }
+
+    /// Passes frame received from the miner to `translation`
+    async fn handle_v1_frame<E>(
+        translation: &mut V1ToV2Translation,
+        v1_frame: std::result::Result<
+            Option<std::result::Result<v1::Frame, E>>,
+            tokio::time::Elapsed,
+        >,
+        v1_peer_addr: SocketAddr,
+    ) -> Result<()>
+    where
+        E: Into<Error>,
+    {
+        match v1_frame? {
+            Some(v1_frame) => {
+                translation
+                    .handle_downstream_frame(v1_frame.map_err(Into::into)?)
+                    .await
+            }
+            None => Err(format!("V1 client disconnected ({:?})", v1_peer_addr))?,
+        }
+        if translation.is_closed() {
+            Err(format!("Upstream closed V1 client ({:?})", v1_peer_addr))?;
+        }
+        Ok(())
+    }
+
+    /// Runs the translation on top of upstream V1 session, the extended channel is translated
+    /// by `V2ToV1Translation`
+    async fn run_v1_upstream(
+        self,
+        upstream_conn: UpstreamConnection,
+        upstream_connector: UpstreamConnector,
+    ) -> Result<()> {
+        let mut translation = self.translation;
+        let mut v2_translation_rx = self.v2_translation_rx;
+        let translation_options = upstream_connector.translation_options();
+        let (mut v1, v1_upstream_translation_tx) = V1Upstream::new(
+            upstream_connector,
+            upstream_conn,
+            Self::MAX_TRANSLATION_CHANNEL_SIZE,
+        );
+        let (v2_upstream_translation_tx, mut v2_upstream_translation_rx) =
+            mpsc::channel(Self::MAX_TRANSLATION_CHANNEL_SIZE);
+        let mut upstream_translation = V2ToV1Translation::new(
+            v1_upstream_translation_tx,
+            v2_upstream_translation_tx,
+            translation_options,
+        );
+        upstream_translation.set_upstream_addr(v1.peer_addr);
+        upstream_translation
+            .set_downstream_peer_addr(V2ToV1Translation::DEFAULT_DOWNSTREAM_ID, self.v1_peer_addr);
+
+        let (v1_conn_tx, mut v1_conn_rx) = self.v1_conn.split();
+        tokio::spawn(v1_send_task(
+            v1_conn_tx,
+            self.v1_translation_rx,
+            self.v1_peer_addr,
+        ));
+
+        let mut submit_timeout_check =
+            tokio::time::interval(V2ToV1Translation::V1_SUBMIT_TIMEOUT / 2);
+
+        loop {
+            select! {
+                _ = submit_timeout_check.tick().fuse() => {
+                    upstream_translation.report_timed_out_submits(time::Instant::now());
+                    upstream_translation.retarget_channels(time::Instant::now());
+                },
+                v1_frame = v1.conn_rx.next().timeout(ConnTranslation::V1_UPSTREAM_TIMEOUT).fuse() => {
+                    v1.handle_frame(&mut upstream_translation, v1_frame).await?;
+                },
+                v1_frame = v1.translation_rx.next().fuse() => {
+                    v1.send_frame(&mut upstream_translation, v1_frame).await?;
+                },
+                v1_conn = v1.recovery_rx.select_next_some() => {
+                    v1.switch(&mut upstream_translation, v1_conn);
+                },
+                // V2 messages of the extended channel are passed between the translations
+                v2_frame = v2_translation_rx.select_next_some() => {
+                    ConnTranslation::v2_handle_frame(&mut upstream_translation, v2_frame).await?;
+                    v1.follow_route(&mut upstream_translation).await?;
+                },
+                v2_frame = v2_upstream_translation_rx.select_next_some() => {
+                    translation.handle_upstream_frame(v2_frame).await?;
+                    if translation.is_closed() {
+                        Err(format!("Upstream closed V1 client ({:?})", self.v1_peer_addr))?;
+                    }
+                },
+                v1_frame = v1_conn_rx.next().timeout(Self::V1_DOWNSTREAM_TIMEOUT).fuse() => {
+                    Self::handle_v1_frame(&mut translation, v1_frame, self.v1_peer_addr).await?;
+                }
+            }
+        }
+    }
+
+    /// Runs the translation on top of V2 upstream connection `upstream_conn`
+    async fn run_v2_upstream(
+        self,
+        upstream_conn: v2::Framed,
+        upstream_peer_addr: SocketAddr,
+    ) -> Result<()> {
+        let mut translation = self.translation;
+
+        let (v1_conn_tx, mut v1_conn_rx) = self.v1_conn.split();
+        tokio::spawn(v1_send_task(
+            v1_conn_tx,
+            self.v1_translation_rx,
+            self.v1_peer_addr,
+        ));
+        let (upstream_conn_tx, mut upstream_conn_rx) = upstream_conn.split();
+        tokio::spawn(ConnTranslation::v2_send_task(
+            upstream_conn_tx,
+            self.v2_translation_rx,
+            upstream_peer_addr,
+        ));
+
+        loop {
+            select! {
+                upstream_frame = upstream_conn_rx.next().timeout(Self::V2_UPSTREAM_TIMEOUT).fuse() => {
+                    match upstream_frame? {
+                        Some(upstream_frame) => {
+                            translation.handle_upstream_frame(upstream_frame?).await?;
+                            if translation.is_closed() {
+                                Err(format!(
+                                    "Upstream closed V1 client ({:?})",
+                                    self.v1_peer_addr
+                                ))?;
+                            }
+                        }
+                        None => Err(format!(
+                            "Upstream V2 stratum connection dropped ({:?})",
+                            upstream_peer_addr
+                        ))?,
+                    }
+                },
+                v1_frame = v1_conn_rx.next().timeout(Self::V1_DOWNSTREAM_TIMEOUT).fuse() => {
+                    Self::handle_v1_frame(&mut translation, v1_frame, self.v1_peer_addr).await?;
+                }
+            }
+        }
+    }
+}
+
+/// Translates V1 miner connection `v1_conn` from `v1_peer_addr` onto a dedicated session with
+/// the first available upstream server
+pub(crate) async fn handle_connection(
+    v1_conn: TcpStream,
+    v1_peer_addr: SocketAddr,
+    upstream_connector: UpstreamConnector,
+    upstream_mode: UpstreamMode,
+) -> Result<()> {
+    let upstream_conn = upstream_connector.connect().await?;
+    info!(
+        "Established connection with upstream {} for V1 peer: {}",
+        upstream_conn.peer_addr, v1_peer_addr
+    );
+    let upstream_peer_addr = upstream_conn.peer_addr;
+    let v1_conn = Connection::<v1::Framing>::new(v1_conn).into_inner();
+    let translation = V1ConnTranslation::new(v1_conn, v1_peer_addr, upstream_peer_addr);
+
+    match upstream_mode {
+        UpstreamMode::V1 => {
+            translation
+                .run_v1_upstream(upstream_conn, upstream_connector)
+                .await
+        }
+        UpstreamMode::V2(authority_public_keys) => {
+            let upstream_conn =
+                passthrough::connect_upstream(upstream_conn, authority_public_keys).await?;
+            translation
+                .run_v2_upstream(upstream_conn, upstream_peer_addr)
+                .await
+        }
+    }
+}
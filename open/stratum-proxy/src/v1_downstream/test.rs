@@ -0,0 +1,195 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use futures::stream::StreamExt;
+use std::str::FromStr;
+
+use ii_async_compat::tokio;
+
+use super::*;
+use ii_stratum::test_utils;
+
+/// Passes V1 message to the translation as if it arrived from the miner
+async fn v1_simulate_incoming_message(translation: &mut V1ToV2Translation, message: v1::rpc::Rpc) {
+    let frame: v1::Frame = message.try_into().expect("Could not serialize message");
+    translation.handle_downstream_frame(frame).await;
+}
+
+/// Passes V2 message to the translation as if it arrived from the upstream
+async fn v2_simulate_incoming_message<M>(translation: &mut V1ToV2Translation, message: M)
+where
+    M: TryInto<v2::Frame, Error = ii_stratum::error::Error>,
+{
+    let frame: v2::Frame = message.try_into().expect("Could not serialize message");
+    translation
+        .handle_upstream_frame(frame)
+        .await
+        .expect("Cannot handle upstream frame");
+}
+
+/// Receives the next V1 message generated by the translation
+async fn v1_receive_message(v1_rx: &mut mpsc::Receiver<v1::Frame>) -> v1::rpc::Rpc {
+    let frame = v1_rx.next().await.expect("At least 1 message was expected");
+    v1::rpc::Rpc::try_from(frame).expect("Invalid V1 frame")
+}
+
+async fn v1_receive_response(v1_rx: &mut mpsc::Receiver<v1::Frame>) -> v1::rpc::Response {
+    match v1_receive_message(v1_rx).await {
+        v1::rpc::Rpc::Response(response) => response,
+        _ => panic!("V1 response expected"),
+    }
+}
+
+async fn v1_receive_request(v1_rx: &mut mpsc::Receiver<v1::Frame>) -> v1::rpc::Request {
+    match v1_receive_message(v1_rx).await {
+        v1::rpc::Rpc::Request(request) => request,
+        _ => panic!("V1 request expected"),
+    }
+}
+
+fn v1_build_submit_message(id: u32, job_id: &str, extra_nonce2: &str) -> v1::rpc::Rpc {
+    v1::rpc::Rpc::from_str(&format!(
+        r#"{{"id":{},"method":"mining.submit","params":["{}","{}","{}","5d10bc0a","0443c37b","00000000"]}}"#,
+        id,
+        test_utils::common::USER_CREDENTIALS,
+        job_id,
+        extra_nonce2
+    ))
+    .expect("Cannot parse submit")
+}
+
+/// Walks a V1 miner through the whole session: the subscription and authorization result in
+/// an extended channel, the extended job is notified with the channel extranonce prefix in
+/// coinbase 1 and the submitted share is translated onto the extended channel
+#[tokio::test]
+async fn test_v1_miner_session() {
+    let (v1_tx, mut v1_rx) = mpsc::channel(10);
+    let (v2_tx, mut v2_rx) = mpsc::channel(10);
+    let upstream_addr = "127.0.0.1:3333".parse().expect("Invalid address");
+    let mut translation = V1ToV2Translation::new(v1_tx, v2_tx, upstream_addr);
+
+    v1_simulate_incoming_message(&mut translation, test_utils::v1::build_configure_request()).await;
+    let configure_result =
+        v1::messages::ConfigureResult::try_from(v1_receive_response(&mut v1_rx).await)
+            .expect("Not a configure result");
+    assert_eq!(
+        configure_result.0["version-rolling.mask"],
+        serde_json::Value::from("1fffe000")
+    );
+
+    // Subscription is answered locally and connection is setup with the upstream
+    v1_simulate_incoming_message(
+        &mut translation,
+        test_utils::v1::build_subscribe_request_frame(),
+    )
+    .await;
+    let subscribe_result =
+        v1::messages::SubscribeResult::try_from(v1_receive_response(&mut v1_rx).await)
+            .expect("Not a subscribe result");
+    assert_eq!(subscribe_result.extra_nonce_1().0.as_ref(), &vec![0]);
+    assert_eq!(subscribe_result.extra_nonce_2_size(), 2);
+    let setup_connection: v2::messages::SetupConnection =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(setup_connection.endpoint_port, 3333);
+
+    // Authorization waits for the connection setup and opens the extended channel
+    v1_simulate_incoming_message(
+        &mut translation,
+        test_utils::v1::build_authorize_request_message(),
+    )
+    .await;
+    v2_simulate_incoming_message(
+        &mut translation,
+        test_utils::v2::build_setup_connection_success(),
+    )
+    .await;
+    let open_channel: v2::messages::OpenExtendedMiningChannel =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!(open_channel.min_extranonce_size, 3);
+    v2_simulate_incoming_message(
+        &mut translation,
+        test_utils::v2::build_open_extended_channel_success(),
+    )
+    .await;
+    let authorize_result =
+        v1::messages::BooleanResult::try_from(v1_receive_response(&mut v1_rx).await)
+            .expect("Not an authorize result");
+    assert!(authorize_result.0);
+    let set_difficulty = v1_receive_request(&mut v1_rx).await;
+    assert_eq!(
+        set_difficulty.payload.method,
+        v1::rpc::Method::SetDifficulty
+    );
+
+    // Future job is notified once its prevhash arrives
+    v2_simulate_incoming_message(
+        &mut translation,
+        test_utils::v2::build_new_extended_mining_job(),
+    )
+    .await;
+    let mut prev_hash = test_utils::v2::build_set_new_prev_hash();
+    prev_hash.channel_id = 1;
+    v2_simulate_incoming_message(&mut translation, prev_hash).await;
+    let notify =
+        v1::messages::Notify::try_from(v1_receive_request(&mut v1_rx).await).expect("Not a notify");
+    let v1_job = test_utils::v1::build_mining_notify();
+    let mut coin_base_1 = v1_job.coin_base_1().to_vec();
+    coin_base_1.extend_from_slice(test_utils::v2::EXTENDED_CHANNEL_EXTRANONCE_PREFIX);
+    coin_base_1.push(0);
+    assert_eq!(notify.job_id(), "0");
+    assert_eq!(notify.coin_base_1(), coin_base_1.as_slice());
+    assert_eq!(notify.coin_base_2(), v1_job.coin_base_2());
+    assert_eq!(notify.merkle_branch(), v1_job.merkle_branch());
+    assert_eq!(notify.prev_hash(), v1_job.prev_hash());
+    assert!(notify.clean_jobs());
+
+    // Share is extended with the padding and extra nonce 1
+    v1_simulate_incoming_message(&mut translation, v1_build_submit_message(4, "0", "bbcc")).await;
+    let submit: v2::messages::SubmitSharesExtended =
+        test_utils::v2::receive_message(&mut v2_rx).await;
+    assert_eq!((submit.channel_id, submit.job_id), (1, 0));
+    assert_eq!(submit.extranonce.as_ref(), &[0x00, 0x00, 0xbb, 0xcc]);
+
+    // Share for an unknown job is rejected right away
+    v1_simulate_incoming_message(&mut translation, v1_build_submit_message(5, "7", "bbcc")).await;
+    let response = v1_receive_response(&mut v1_rx).await;
+    assert_eq!(response.id, 5);
+    assert!(response.payload.error.is_some());
+
+    v2_simulate_incoming_message(
+        &mut translation,
+        v2::messages::SubmitSharesSuccess {
+            channel_id: 1,
+            last_seq_num: submit.seq_num,
+            new_submits_accepted_count: 1,
+            new_shares_sum: 1,
+        },
+    )
+    .await;
+    let response = v1_receive_response(&mut v1_rx).await;
+    assert_eq!(response.id, 4);
+    assert!(
+        v1::messages::BooleanResult::try_from(response)
+            .expect("Not a submit result")
+            .0
+    );
+}
@@ -0,0 +1,138 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Variable difficulty keeps the share rate of each downstream channel around a configured
+//! number of shares per minute. The engine measures the share rate of the channel over the
+//! retarget interval and scales the difficulty by the ratio of the measured and the configured
+//! rate. A single adjustment is limited so that a burst of shares or an idle period doesn't
+//! throw the difficulty off completely.
+//!
+//! The difficulty of a channel is independent of the difficulty of the upstream V1 session. Only
+//! shares that meet the upstream target are forwarded upstream, shares that meet the channel
+//! difficulty but not the upstream one are accepted by the proxy and not forwarded.
+
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+mod test;
+
+/// Parameters of the variable difficulty engine
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct VarDiffOptions {
+    /// Share rate that the channels are kept at
+    pub shares_per_minute: f64,
+    /// Difficulty of the channels is never lowered below this value
+    pub min_difficulty: u32,
+    /// How long the share rate is measured before the difficulty is adjusted
+    pub retarget_interval: Duration,
+}
+
+impl Default for VarDiffOptions {
+    fn default() -> Self {
+        Self {
+            shares_per_minute: 10.0,
+            min_difficulty: 1,
+            retarget_interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Tracks the share rate of a single channel and adjusts its difficulty
+#[derive(Clone, Debug)]
+pub struct VarDiff {
+    options: VarDiffOptions,
+    difficulty: u32,
+    /// Shares that have met `difficulty` since `window_start`
+    shares: u32,
+    window_start: Instant,
+}
+
+impl VarDiff {
+    /// The difficulty changes at most by this factor in a single adjustment
+    const MAX_ADJUSTMENT: f64 = 4.0;
+    /// Difficulty is kept as long as the measured share rate doesn't deviate from the
+    /// configured rate by more than this fraction
+    const TOLERANCE: f64 = 0.25;
+
+    /// Starts at `difficulty` that is limited by the minimum difficulty only
+    pub fn new(options: VarDiffOptions, difficulty: u32, now: Instant) -> Self {
+        Self {
+            options,
+            difficulty: difficulty.max(options.min_difficulty).max(1),
+            shares: 0,
+            window_start: now,
+        }
+    }
+
+    pub fn difficulty(&self) -> u32 {
+        self.difficulty
+    }
+
+    /// Accounts a share that has met the current difficulty
+    pub fn record_share(&mut self) {
+        self.shares = self.shares.saturating_add(1);
+    }
+
+    /// Adjusts the difficulty once the retarget interval has elapsed. The adjustment happens
+    /// earlier when the channel submits shares so fast that the difficulty would be raised by
+    /// the maximum factor anyway.
+    /// Return:
+    ///  - new difficulty when it has changed
+    pub fn retarget(&mut self, now: Instant) -> Option<u32> {
+        let elapsed = now.saturating_duration_since(self.window_start);
+        let expected_shares = self.options.shares_per_minute * elapsed.as_secs_f64() / 60.0;
+        let flood_shares =
+            self.options.shares_per_minute * self.options.retarget_interval.as_secs_f64() / 60.0
+                * Self::MAX_ADJUSTMENT;
+        if elapsed < self.options.retarget_interval && f64::from(self.shares) < flood_shares {
+            return None;
+        }
+
+        let ratio = if expected_shares > 0.0 {
+            (f64::from(self.shares) / expected_shares)
+                .max(1.0 / Self::MAX_ADJUSTMENT)
+                .min(Self::MAX_ADJUSTMENT)
+        } else {
+            Self::MAX_ADJUSTMENT
+        };
+        self.restart(now);
+        if (ratio - 1.0).abs() <= Self::TOLERANCE {
+            return None;
+        }
+
+        // Saturate explicitly as casting of an out of range float is undefined
+        let difficulty = (f64::from(self.difficulty) * ratio)
+            .round()
+            .min(f64::from(std::u32::MAX)) as u32;
+        let difficulty = difficulty.max(self.options.min_difficulty).max(1);
+        if difficulty == self.difficulty {
+            return None;
+        }
+        self.difficulty = difficulty;
+        Some(difficulty)
+    }
+
+    fn restart(&mut self, now: Instant) {
+        self.shares = 0;
+        self.window_start = now;
+    }
+}
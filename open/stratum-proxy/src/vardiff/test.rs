@@ -0,0 +1,97 @@
+// Copyright (C) 2020  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use super::*;
+
+fn build_options() -> VarDiffOptions {
+    VarDiffOptions {
+        shares_per_minute: 10.0,
+        min_difficulty: 2,
+        retarget_interval: Duration::from_secs(60),
+    }
+}
+
+/// Verifies that the difficulty is lowered for idle channels and that it is kept when the share
+/// rate matches the configured one
+#[test]
+fn test_vardiff_lower_difficulty() {
+    let start = Instant::now();
+    let mut vardiff = VarDiff::new(build_options(), 64, start);
+    assert_eq!(vardiff.retarget(start + Duration::from_secs(59)), None);
+
+    // No shares at all, the difficulty is lowered by the maximum factor
+    let now = start + Duration::from_secs(60);
+    assert_eq!(vardiff.retarget(now), Some(16));
+
+    // 9 shares are close enough to the configured rate
+    for _ in 0..9 {
+        vardiff.record_share();
+    }
+    let now = now + Duration::from_secs(60);
+    assert_eq!(vardiff.retarget(now), None);
+
+    // 5 shares per minute, the difficulty is halved
+    for _ in 0..5 {
+        vardiff.record_share();
+    }
+    let now = now + Duration::from_secs(60);
+    assert_eq!(vardiff.retarget(now), Some(8));
+
+    // The minimum difficulty is respected
+    let now = now + Duration::from_secs(60);
+    assert_eq!(vardiff.retarget(now), Some(2));
+    let now = now + Duration::from_secs(60);
+    assert_eq!(vardiff.retarget(now), None);
+    assert_eq!(vardiff.difficulty(), 2);
+}
+
+/// Verifies that flood of shares raises the difficulty before the retarget interval elapses
+/// and that the difficulty isn't limited by the upstream difficulty
+#[test]
+fn test_vardiff_raise_difficulty() {
+    let start = Instant::now();
+    let mut vardiff = VarDiff::new(build_options(), 4, start);
+    for _ in 0..39 {
+        vardiff.record_share();
+    }
+    let now = start + Duration::from_secs(10);
+    assert_eq!(vardiff.retarget(now), None);
+    vardiff.record_share();
+    assert_eq!(vardiff.retarget(now), Some(16));
+
+    for _ in 0..40 {
+        vardiff.record_share();
+    }
+    assert_eq!(vardiff.retarget(now + Duration::from_secs(1)), Some(64));
+    assert_eq!(vardiff.difficulty(), 64);
+}
+
+/// Verifies that the difficulty saturates instead of overflowing
+#[test]
+fn test_vardiff_max_difficulty() {
+    let start = Instant::now();
+    let mut vardiff = VarDiff::new(build_options(), std::u32::MAX / 2, start);
+    for _ in 0..40 {
+        vardiff.record_share();
+    }
+    assert_eq!(vardiff.retarget(start), Some(std::u32::MAX));
+}
@@ -27,9 +27,11 @@
 //! NOTE: currently this test must be run with --nocapture flag as there is no reasonable way of
 //! communicating any failures/panics to the test harness.
 
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::net::SocketAddr;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 
 use ii_async_compat::prelude::*;
 
@@ -46,6 +48,8 @@ static ADDR: &'static str = "127.0.0.1";
 static PORT_V1: u16 = 9001;
 static PORT_V2: u16 = 9002;
 static PORT_V2_FULL: u16 = 9003;
+static PORT_V1_AGGREGATED: u16 = 9004;
+static PORT_V2_AGGREGATED: u16 = 9005;
 
 #[tokio::test]
 async fn test_v2server() {
@@ -229,9 +233,13 @@ async fn test_v2server_full() {
         .expect("BUG: cannot build stratum v1 address");
     let addr_v2 = Address(ADDR.into(), PORT_V2_FULL);
 
-    let v2server =
-        server::ProxyServer::listen(addr_v2.clone(), addr_v1, server::handle_connection, None)
-            .expect("BUG: Could not bind v2server");
+    let v2server = server::ProxyServer::listen(
+        addr_v2.clone(),
+        vec![addr_v1],
+        server::handle_connection,
+        None,
+    )
+    .expect("BUG: Could not bind v2server");
     let mut v2server_quit = v2server.quit_channel();
 
     tokio::spawn(v2server.run());
@@ -241,3 +249,114 @@ async fn test_v2server_full() {
     let _ = v2server_quit.try_send(());
     // TODO kill v1 test server
 }
+
+/// Minimal V1 pool that accepts any configuration, subscription and user. The number of accepted
+/// connections is stored in `connection_count`.
+fn v1_pool_task(addr: Address, connection_count: Arc<AtomicUsize>) -> impl Future<Output = ()> {
+    let mut server = Server::bind(&addr).expect("BUG: cannot bind to address");
+
+    async move {
+        while let Some(conn) = server.next().await {
+            connection_count.fetch_add(1, Ordering::SeqCst);
+            let mut conn = Connection::<v1::Framing>::new(
+                conn.expect("BUG server did not provide connection"),
+            );
+            tokio::spawn(async move {
+                while let Some(frame) = conn.next().await {
+                    let request = match v1::rpc::Rpc::try_from(frame.expect("Receiving failed")) {
+                        Ok(v1::rpc::Rpc::Request(request)) => request,
+                        _ => panic!("V1 request expected"),
+                    };
+                    let id = request.id.expect("V1 request without ID");
+                    let responses = match request.payload.method {
+                        v1::rpc::Method::Configure => vec![format!(
+                            r#"{{"id":{},"error":null,"result":{{"version-rolling":true,"version-rolling.mask":"1fffe000"}}}}"#,
+                            id
+                        )],
+                        v1::rpc::Method::Subscribe => vec![
+                            format!(
+                                r#"{{"id":{},"error":null,"result":[[["mining.notify","1"]],"6c6f010000000c",4]}}"#,
+                                id
+                            ),
+                            test_utils::v1::MINING_SET_DIFFICULTY_JSON.to_string(),
+                        ],
+                        _ => vec![format!(r#"{{"id":{},"error":null,"result":true}}"#, id)],
+                    };
+                    for response in responses {
+                        let response = v1::rpc::Rpc::from_str(&response).expect("Invalid response");
+                        conn.send(response.try_into().expect("BUG: Cannot convert to frame"))
+                            .await
+                            .expect("BUG: Could not send response");
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Receives V2 message `M` from connection `conn`
+async fn v2_receive_message<M>(conn: &mut Connection<v2::Framing>) -> M
+where
+    M: TryFrom<v2::Frame, Error = Error>,
+{
+    test_utils::v2::receive_message(&mut conn.map(|frame| frame.expect("Receiving failed"))).await
+}
+
+/// Verifies that channels of all V2 clients share a single upstream V1 connection when the proxy
+/// aggregates downstream connections
+#[tokio::test]
+async fn test_v2server_aggregation() {
+    let addr_v1 = Address(ADDR.into(), PORT_V1_AGGREGATED);
+    let addr_v2 = Address(ADDR.into(), PORT_V2_AGGREGATED);
+
+    let v1_connection_count = Arc::new(AtomicUsize::new(0));
+    tokio::spawn(v1_pool_task(addr_v1.clone(), v1_connection_count.clone()));
+
+    let v2server = server::ProxyServer::listen(
+        addr_v2.clone(),
+        vec![addr_v1],
+        server::handle_connection,
+        None,
+    )
+    .expect("BUG: Could not bind v2server")
+    .aggregate(1);
+    let mut v2server_quit = v2server.quit_channel();
+    tokio::spawn(v2server.run());
+
+    // Connections have to stay open until the end of the test
+    let mut v2_connections = vec![];
+    for &(req_id, expected_channel_id) in [(10, 0), (11, 1)].iter() {
+        let mut conn: Connection<v2::Framing> = utils::backoff(50, 4, || addr_v2.connect())
+            .await
+            .unwrap_or_else(|e| panic!("Could not connect to {}: {}", addr_v2, e))
+            .into();
+        conn.send(
+            test_utils::v2::build_setup_connection()
+                .try_into()
+                .expect("BUG: Cannot convert to frame"),
+        )
+        .await
+        .expect("BUG: Could not send message");
+        let _: v2::messages::SetupConnectionSuccess = v2_receive_message(&mut conn).await;
+
+        let mut open_channel = test_utils::v2::build_open_channel();
+        open_channel.req_id = req_id;
+        conn.send(
+            open_channel
+                .try_into()
+                .expect("BUG: Cannot convert to frame"),
+        )
+        .await
+        .expect("BUG: Could not send message");
+        let open_success: v2::messages::OpenStandardMiningChannelSuccess =
+            v2_receive_message(&mut conn).await;
+        assert_eq!(open_success.req_id, req_id);
+        assert_eq!(open_success.channel_id, expected_channel_id);
+
+        v2_connections.push(conn);
+    }
+    assert_eq!(v1_connection_count.load(Ordering::SeqCst), 1);
+
+    // Signal the server to shut down
+    let _ = v2server_quit.try_send(());
+}